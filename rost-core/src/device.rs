@@ -0,0 +1,165 @@
+//! The order devices start in
+//!
+//! Every device hangs off a parent bus and may depend on other devices, it is only set up
+//! once all of those are. `init_order` turns the names into an order to go through.
+
+use core::fmt;
+
+///A device as far as the ordering cares
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Node {
+    pub name: &'static str,
+    ///The bus the device is on, `None` for the root of the tree
+    pub parent: Option<&'static str>,
+    pub depends_on: &'static [&'static str],
+}
+
+impl Node {
+    ///Everything that has to be set up first
+    pub fn requires(&self) -> impl Iterator<Item = &'static str> {
+        self.parent
+            .into_iter()
+            .chain(self.depends_on.iter().copied())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OrderError {
+    ///`device` needs `missing` which isn't there
+    Unknown {
+        device: &'static str,
+        missing: &'static str,
+    },
+    ///`device` ends up depending on itself
+    Cycle(&'static str),
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            OrderError::Unknown { device, missing } => {
+                write!(f, "{} needs {} which isn't there", device, missing)
+            }
+            OrderError::Cycle(device) => write!(f, "{} depends on itself", device),
+        }
+    }
+}
+
+///Fills `order` with the indices of `nodes` so that every node comes after its parent and
+///what it depends on. Nodes that could go in either order keep the order they are in.
+///`order` has to be as long as `nodes`.
+pub fn init_order(nodes: &[Node], order: &mut [usize]) -> Result<(), OrderError> {
+    assert_eq!(
+        nodes.len(),
+        order.len(),
+        "the order is not as long as the nodes"
+    );
+
+    for node in nodes {
+        if let Some(missing) = node
+            .requires()
+            .find(|name| !nodes.iter().any(|other| other.name == *name))
+        {
+            return Err(OrderError::Unknown {
+                device: node.name,
+                missing,
+            });
+        }
+    }
+
+    for placed in 0..nodes.len() {
+        let is_placed = |name: &str| order[..placed].iter().any(|i| nodes[*i].name == name);
+        let next = (0..nodes.len())
+            .find(|i| !is_placed(nodes[*i].name) && nodes[*i].requires().all(&is_placed));
+        match next {
+            Some(next) => order[placed] = next,
+            None => {
+                let stuck = nodes.iter().find(|node| !is_placed(node.name)).unwrap();
+                return Err(OrderError::Cycle(stuck.name));
+            }
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    const fn node(
+        name: &'static str,
+        parent: Option<&'static str>,
+        depends_on: &'static [&'static str],
+    ) -> Node {
+        Node {
+            name,
+            parent,
+            depends_on,
+        }
+    }
+
+    #[test]
+    fn parents_and_dependencies_come_first() {
+        let nodes = [
+            node("mouse", Some("ps2"), &[]),
+            node("ps2", Some("isa"), &["pic"]),
+            node("isa", Some("platform"), &[]),
+            node("pic", Some("platform"), &[]),
+            node("platform", None, &[]),
+        ];
+        let mut order = [0; 5];
+        init_order(&nodes, &mut order).unwrap();
+        let names = order.map(|i| nodes[i].name);
+        assert_eq!(names, ["platform", "isa", "pic", "ps2", "mouse"]);
+    }
+
+    #[test]
+    fn errors() {
+        let mut order = [0; 2];
+        let unknown = [node("platform", None, &[]), node("mouse", Some("ps2"), &[])];
+        assert_eq!(
+            init_order(&unknown, &mut order),
+            Err(OrderError::Unknown {
+                device: "mouse",
+                missing: "ps2"
+            })
+        );
+
+        let cycle = [node("a", None, &["b"]), node("b", None, &["a"])];
+        assert_eq!(init_order(&cycle, &mut order), Err(OrderError::Cycle("a")));
+        assert_eq!(format!("{}", OrderError::Cycle("a")), "a depends on itself");
+    }
+
+    const NAMES: [&str; 8] = ["a", "b", "c", "d", "e", "f", "g", "h"];
+    //every node can only depend on the ones before it, so there is never a cycle
+    const DEPENDENCIES: [&[&str]; 8] = [
+        &[],
+        &["a"],
+        &["a", "b"],
+        &["c"],
+        &["b", "d"],
+        &["a", "e"],
+        &["f"],
+        &["c", "g"],
+    ];
+
+    proptest! {
+        #[test]
+        fn any_order_without_cycles_works(shuffle in Just((0..8).collect::<Vec<usize>>()).prop_shuffle()) {
+            let nodes: Vec<Node> = shuffle
+                .iter()
+                .map(|i| node(NAMES[*i], None, DEPENDENCIES[*i]))
+                .collect();
+            let mut order = vec![0; nodes.len()];
+            init_order(&nodes, &mut order).unwrap();
+
+            for (position, i) in order.iter().enumerate() {
+                for dependency in nodes[*i].depends_on {
+                    let before = order[..position].iter().any(|j| nodes[*j].name == *dependency);
+                    prop_assert!(before, "{} came before {}", nodes[*i].name, dependency);
+                }
+            }
+        }
+    }
+}
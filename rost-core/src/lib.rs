@@ -0,0 +1,20 @@
+//! The parts of rost that don't need the hardware
+//!
+//! Nothing in here touches a port or the screen directly, it goes through `port::PortIo`,
+//! `mmio::Mmio` and `vga::TextBuffer` which the kernel implements for the real hardware. That way
+//! `cargo test -p rost-core --target host-tuple` (or `cargo test-core`) runs the tests on
+//! the host against the fakes in `mock`, while the tests in QEMU only have to cover the
+//! hardware itself.
+
+#![cfg_attr(not(test), no_std)]
+
+pub mod device;
+pub mod mmio;
+#[cfg(any(test, feature = "mock"))]
+pub mod mock;
+pub mod pci;
+pub mod port;
+pub mod queue;
+pub mod register;
+pub mod resource;
+pub mod vga;
@@ -0,0 +1,22 @@
+//! Memory mapped I/O, device registers and memory that show up at an address
+//!
+//! A `Mmio` is a window of device memory counted from its start. Reads and writes are
+//! never merged, split or left out like ones to normal memory can be. The kernel maps
+//! the windows of the hardware, tests use `mock::MockMmio`.
+
+pub trait Mmio {
+    ///How many bytes the window has, accesses past it panic
+    fn len(&self) -> usize;
+    fn read_u8(&self, offset: usize) -> u8;
+    fn write_u8(&mut self, offset: usize, value: u8);
+    fn read_u16(&self, offset: usize) -> u16;
+    fn write_u16(&mut self, offset: usize, value: u16);
+    fn read_u32(&self, offset: usize) -> u32;
+    fn write_u32(&mut self, offset: usize, value: u32);
+    fn read_u64(&self, offset: usize) -> u64;
+    fn write_u64(&mut self, offset: usize, value: u64);
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
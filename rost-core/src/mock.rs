@@ -0,0 +1,508 @@
+//! Fake hardware for tests, with the `mock` feature outside of this crate
+//!
+//! `MockPorts` remembers what was written to each port and gives it back on reads, an
+//! address and data port pair can act as the indexed registers of a card. Reads a test
+//! expects the device to answer can be queued up front. Every access goes in a log the
+//! tests can check. `MockMmio` is a window of device memory, `MockConfigSpace` is the PCI
+//! configuration space of a few functions and `MockTextBuffer` is a text buffer in memory.
+
+use crate::mmio::Mmio;
+use crate::pci::{Address, ConfigSpace, BAR0};
+use crate::port::PortIo;
+use crate::queue::RingBuffer;
+use crate::vga::{ColorCode, ScreenChar, TextBuffer};
+
+///How many ports keep their values
+const PORT_COUNT: usize = 32;
+///How many queued reads there can be at once
+const QUEUE_LENGTH: usize = 128;
+///How many functions a configuration space can have
+const MAX_FUNCTIONS: usize = 16;
+///How many accesses the log keeps, older ones are dropped
+pub const LOG_LENGTH: usize = 256;
+///As many characters as the text buffer of the card has room for
+const MAX_CELLS: usize = 0x4000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Access {
+    Read { port: u16, value: u32 },
+    Write { port: u16, value: u32 },
+}
+
+//a write to `address` selects which of the registers `data` reads and writes
+#[derive(Debug, Clone, Copy)]
+struct Indexed {
+    address: u16,
+    data: u16,
+    index: u8,
+    registers: [u8; 256],
+}
+
+pub struct MockPorts {
+    values: [(u16, u32); PORT_COUNT],
+    value_count: usize,
+    indexed: Option<Indexed>,
+    queued: RingBuffer<(u16, u32), QUEUE_LENGTH>,
+    log: RingBuffer<Access, LOG_LENGTH>,
+}
+
+impl MockPorts {
+    pub const fn new() -> MockPorts {
+        MockPorts {
+            values: [(0, 0); PORT_COUNT],
+            value_count: 0,
+            indexed: None,
+            queued: RingBuffer::new((0, 0)),
+            log: RingBuffer::new(Access::Read { port: 0, value: 0 }),
+        }
+    }
+
+    ///Makes `address` and `data` a pair of index and data ports
+    pub const fn with_indexed(mut self, address: u16, data: u16) -> MockPorts {
+        self.indexed = Some(Indexed {
+            address,
+            data,
+            index: 0,
+            registers: [0; 256],
+        });
+        self
+    }
+
+    ///Sets what reads of a port give until something is written to it
+    pub fn set(&mut self, port: u16, value: u32) {
+        if let Some(indexed) = self.indexed.as_mut() {
+            if port == indexed.data {
+                indexed.registers[indexed.index as usize] = value as u8;
+                return;
+            }
+        }
+
+        let values = &mut self.values[..self.value_count];
+        match values.iter_mut().find(|(known, _)| *known == port) {
+            Some((_, known_value)) => *known_value = value,
+            None => {
+                assert!(self.value_count < PORT_COUNT, "too many ports");
+                self.values[self.value_count] = (port, value);
+                self.value_count += 1;
+            }
+        }
+        if let Some(indexed) = self.indexed.as_mut() {
+            if port == indexed.address {
+                indexed.index = value as u8;
+            }
+        }
+    }
+
+    ///Makes the next read of `port` give `value` instead of what the port holds, like a
+    ///device answering. Reads of a port take its queued values in order.
+    pub fn queue_read(&mut self, port: u16, value: u32) {
+        assert!(
+            self.queued.push((port, value)).is_ok(),
+            "too many queued reads"
+        );
+    }
+
+    ///How many queued reads haven't been read yet
+    pub fn queued_reads(&self) -> usize {
+        self.queued.len()
+    }
+
+    ///What a read of the port gives, zero for ports never written
+    pub fn get(&self, port: u16) -> u32 {
+        if let Some(indexed) = self.indexed.as_ref() {
+            if port == indexed.data {
+                return indexed.registers[indexed.index as usize] as u32;
+            }
+        }
+        self.values[..self.value_count]
+            .iter()
+            .find(|(known, _)| *known == port)
+            .map_or(0, |(_, value)| *value)
+    }
+
+    ///A register behind the index and data ports
+    pub fn indexed_register(&self, index: u8) -> u8 {
+        self.indexed
+            .as_ref()
+            .map_or(0, |indexed| indexed.registers[index as usize])
+    }
+
+    ///The accesses from the oldest to the newest
+    pub fn log(&self) -> impl Iterator<Item = Access> + '_ {
+        self.log.iter()
+    }
+
+    pub fn clear_log(&mut self) {
+        self.log.clear();
+    }
+
+    fn read(&mut self, port: u16) -> u32 {
+        let position = self.queued.iter().position(|(queued, _)| queued == port);
+        let value = match position {
+            Some(position) => self.take_queued(position),
+            None => self.get(port),
+        };
+        self.log.push_overwrite(Access::Read { port, value });
+        value
+    }
+
+    //the queue is small so it's rebuilt without the taken read
+    fn take_queued(&mut self, position: usize) -> u32 {
+        let mut rest = RingBuffer::new((0, 0));
+        let mut value = 0;
+        for (i, queued) in self.queued.iter().enumerate() {
+            if i == position {
+                value = queued.1;
+            } else {
+                let _ = rest.push(queued);
+            }
+        }
+        self.queued = rest;
+        value
+    }
+
+    fn write(&mut self, port: u16, value: u32) {
+        self.set(port, value);
+        self.log.push_overwrite(Access::Write { port, value });
+    }
+}
+
+impl Default for MockPorts {
+    fn default() -> Self {
+        MockPorts::new()
+    }
+}
+
+impl PortIo for MockPorts {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        self.read(port) as u8
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        self.write(port, value.into())
+    }
+
+    fn read_u16(&mut self, port: u16) -> u16 {
+        self.read(port) as u16
+    }
+
+    fn write_u16(&mut self, port: u16, value: u16) {
+        self.write(port, value.into())
+    }
+
+    fn read_u32(&mut self, port: u16) -> u32 {
+        self.read(port)
+    }
+
+    fn write_u32(&mut self, port: u16, value: u32) {
+        self.write(port, value)
+    }
+}
+
+///`N` bytes of device memory, in little endian like the hardware
+pub struct MockMmio<const N: usize> {
+    bytes: [u8; N],
+}
+
+impl<const N: usize> MockMmio<N> {
+    pub const fn new() -> MockMmio<N> {
+        MockMmio { bytes: [0; N] }
+    }
+
+    pub fn bytes(&self) -> &[u8; N] {
+        &self.bytes
+    }
+
+    pub fn bytes_mut(&mut self) -> &mut [u8; N] {
+        &mut self.bytes
+    }
+
+    fn get<const W: usize>(&self, offset: usize) -> [u8; W] {
+        self.bytes[offset..offset + W].try_into().unwrap()
+    }
+
+    fn put<const W: usize>(&mut self, offset: usize, bytes: [u8; W]) {
+        self.bytes[offset..offset + W].copy_from_slice(&bytes);
+    }
+}
+
+impl<const N: usize> Default for MockMmio<N> {
+    fn default() -> Self {
+        MockMmio::new()
+    }
+}
+
+impl<const N: usize> Mmio for MockMmio<N> {
+    fn len(&self) -> usize {
+        N
+    }
+
+    fn read_u8(&self, offset: usize) -> u8 {
+        self.bytes[offset]
+    }
+
+    fn write_u8(&mut self, offset: usize, value: u8) {
+        self.bytes[offset] = value;
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        u16::from_le_bytes(self.get(offset))
+    }
+
+    fn write_u16(&mut self, offset: usize, value: u16) {
+        self.put(offset, value.to_le_bytes())
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        u32::from_le_bytes(self.get(offset))
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        self.put(offset, value.to_le_bytes())
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        u64::from_le_bytes(self.get(offset))
+    }
+
+    fn write_u64(&mut self, offset: usize, value: u64) {
+        self.put(offset, value.to_le_bytes())
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct MockFunction {
+    address: Address,
+    bytes: [u8; 256],
+    bar_masks: [u32; 6],
+}
+
+///The configuration space of up to 16 functions, in little endian like the hardware.
+///Functions that weren't added read as all ones. The bars keep the bits that aren't in
+///their mask when written, like a function answers the sizing of its bars.
+pub struct MockConfigSpace {
+    functions: [Option<MockFunction>; MAX_FUNCTIONS],
+}
+
+impl MockConfigSpace {
+    pub const fn new() -> MockConfigSpace {
+        MockConfigSpace {
+            functions: [None; MAX_FUNCTIONS],
+        }
+    }
+
+    ///The configuration space of the function at `address`, it is added with all zeros
+    ///and bars that can't be written if it isn't there yet
+    pub fn function(&mut self, address: Address) -> &mut [u8; 256] {
+        let index = match self.find(address) {
+            Some(index) => index,
+            None => {
+                let index = self
+                    .functions
+                    .iter()
+                    .position(|slot| slot.is_none())
+                    .expect("too many functions");
+                self.functions[index] = Some(MockFunction {
+                    address,
+                    bytes: [0; 256],
+                    bar_masks: [0; 6],
+                });
+                index
+            }
+        };
+        &mut self.functions[index].as_mut().unwrap().bytes
+    }
+
+    ///Sets which bits of bar `index` can be written, the bits of the address the size
+    ///of the bar leaves to the function
+    pub fn set_bar_mask(&mut self, address: Address, index: u8, mask: u32) {
+        self.function(address);
+        let index = usize::from(index);
+        let function = self.functions[self.find(address).unwrap()]
+            .as_mut()
+            .unwrap();
+        function.bar_masks[index] = mask;
+    }
+
+    fn find(&self, address: Address) -> Option<usize> {
+        self.functions
+            .iter()
+            .position(|slot| slot.is_some_and(|function| function.address == address))
+    }
+
+    fn read<const W: usize>(&self, address: Address, offset: u16) -> Option<[u8; W]> {
+        let function = self.functions[self.find(address)?].as_ref().unwrap();
+        let offset = usize::from(offset);
+        Some(function.bytes[offset..offset + W].try_into().unwrap())
+    }
+
+    fn write<const W: usize>(&mut self, address: Address, offset: u16, bytes: [u8; W]) {
+        let function = match self.find(address) {
+            Some(index) => self.functions[index].as_mut().unwrap(),
+            None => return,
+        };
+        let offset = usize::from(offset);
+        let bar = offset.wrapping_sub(usize::from(BAR0)) / 4;
+        let old = function.bytes;
+        function.bytes[offset..offset + W].copy_from_slice(&bytes);
+
+        if let Some(mask) = function.bar_masks.get(bar) {
+            let start = usize::from(BAR0) + bar * 4;
+            let dword =
+                |bytes: &[u8; 256]| u32::from_le_bytes(bytes[start..start + 4].try_into().unwrap());
+            let value = dword(&function.bytes) & mask | dword(&old) & !mask;
+            function.bytes[start..start + 4].copy_from_slice(&value.to_le_bytes());
+        }
+    }
+}
+
+impl Default for MockConfigSpace {
+    fn default() -> Self {
+        MockConfigSpace::new()
+    }
+}
+
+impl ConfigSpace for MockConfigSpace {
+    fn read_u8(&mut self, address: Address, offset: u16) -> u8 {
+        self.read::<1>(address, offset)
+            .map_or(u8::MAX, |bytes| bytes[0])
+    }
+
+    fn read_u16(&mut self, address: Address, offset: u16) -> u16 {
+        self.read(address, offset)
+            .map_or(u16::MAX, u16::from_le_bytes)
+    }
+
+    fn read_u32(&mut self, address: Address, offset: u16) -> u32 {
+        self.read(address, offset)
+            .map_or(u32::MAX, u32::from_le_bytes)
+    }
+
+    fn write_u16(&mut self, address: Address, offset: u16, value: u16) {
+        self.write(address, offset, value.to_le_bytes())
+    }
+
+    fn write_u32(&mut self, address: Address, offset: u16, value: u32) {
+        self.write(address, offset, value.to_le_bytes())
+    }
+}
+
+///A text buffer in memory, every cell starts as a blank with the color code 0
+pub struct MockTextBuffer {
+    cells: [ScreenChar; MAX_CELLS],
+    width: usize,
+    height: usize,
+}
+
+impl MockTextBuffer {
+    pub fn new(width: usize, height: usize) -> MockTextBuffer {
+        assert!(width * height <= MAX_CELLS, "the buffer is too big");
+        MockTextBuffer {
+            cells: [ScreenChar {
+                ascii_character: b' ',
+                color_code: ColorCode(0),
+            }; MAX_CELLS],
+            width,
+            height,
+        }
+    }
+
+    ///Changes the size like a mode switch does, the cells keep their contents
+    pub fn resize(&mut self, width: usize, height: usize) {
+        assert!(width * height <= MAX_CELLS, "the buffer is too big");
+        self.width = width;
+        self.height = height;
+    }
+
+    ///The characters of a row, as Latin-1 so that every byte is a character
+    #[cfg(test)]
+    pub fn row_text(&self, row: usize) -> String {
+        (0..self.width)
+            .map(|col| char::from(self.read(row, col).ascii_character))
+            .collect()
+    }
+}
+
+impl TextBuffer for MockTextBuffer {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn read(&self, row: usize, col: usize) -> ScreenChar {
+        assert!(
+            self.contains(row, col),
+            "({}, {}) is off the screen",
+            row,
+            col
+        );
+        self.cells[row * self.width + col]
+    }
+
+    fn write(&mut self, row: usize, col: usize, character: ScreenChar) {
+        assert!(
+            self.contains(row, col),
+            "({}, {}) is off the screen",
+            row,
+            col
+        );
+        self.cells[row * self.width + col] = character;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ports_remember_writes() {
+        let mut ports = MockPorts::new().with_indexed(0x3C4, 0x3C5);
+        ports.set(0x60, 0xAA);
+        assert_eq!(ports.read_u8(0x60), 0xAA);
+        ports.write_u16(0x1F0, 0x1234);
+        assert_eq!(ports.read_u16(0x1F0), 0x1234);
+        assert_eq!(ports.read_u32(0x1F4), 0);
+
+        ports.clear_log();
+        ports.write_u8(0x3C4, 2);
+        ports.write_u8(0x3C5, 0x0F);
+        ports.write_u8(0x3C4, 4);
+        assert_eq!(ports.read_u8(0x3C5), 0);
+        assert_eq!(ports.indexed_register(2), 0x0F);
+        assert!(ports.log().eq([
+            Access::Write {
+                port: 0x3C4,
+                value: 2
+            },
+            Access::Write {
+                port: 0x3C5,
+                value: 0x0F
+            },
+            Access::Write {
+                port: 0x3C4,
+                value: 4
+            },
+            Access::Read {
+                port: 0x3C5,
+                value: 0
+            },
+        ]));
+    }
+
+    #[test]
+    fn queued_reads_come_first() {
+        let mut ports = MockPorts::new();
+        ports.set(0x64, 0x1C);
+        ports.queue_read(0x64, 0x1D);
+        ports.queue_read(0x60, 0xFA);
+        ports.queue_read(0x64, 0x1E);
+        assert_eq!(ports.read_u8(0x60), 0xFA);
+        assert_eq!(ports.read_u8(0x64), 0x1D);
+        assert_eq!(ports.read_u8(0x64), 0x1E);
+        assert_eq!(ports.read_u8(0x64), 0x1C);
+        assert_eq!(ports.queued_reads(), 0);
+    }
+}
@@ -0,0 +1,291 @@
+//! The ways to reach configuration space
+//!
+//! Configuration mechanism #1 selects a function and a register through the address port
+//! at 0xCF8 and moves the data through the four ports at 0xCFC, every PC has it but it
+//! only reaches the first 256 bytes. ECAM maps the whole 4096 bytes of every function
+//! into memory, the ACPI MCFG table says where.
+
+use super::{Address, ConfigSpace};
+use crate::bitfield;
+use crate::mmio::Mmio;
+use crate::port::PortIo;
+use crate::register::{PortRegister, ReadWrite};
+
+const CONFIG_ADDRESS: PortRegister<ConfigAddress, ReadWrite> = PortRegister::new(0xCF8);
+const CONFIG_DATA: u16 = 0xCFC;
+///How many bytes the mechanism #1 ports take up from `CONFIG_ADDRESS`
+pub const MECHANISM1_PORTS: u16 = 8;
+
+bitfield! {
+    struct ConfigAddress(u32) {
+        //the two low bits of the offset are picked with the data port
+        const OFFSET: u8 = 2..=7;
+        const FUNCTION: u8 = 8..=10;
+        const DEVICE: u8 = 11..=15;
+        const BUS: u8 = 16..=23;
+        const ENABLE: bool = 31;
+    }
+}
+
+///Configuration mechanism #1 through the ports
+pub struct Mechanism1<P> {
+    ports: P,
+}
+
+impl<P: PortIo> Mechanism1<P> {
+    pub const fn new(ports: P) -> Mechanism1<P> {
+        Mechanism1 { ports }
+    }
+
+    ///A host bridge with mechanism #1 keeps the enable bit written to the address port,
+    ///the port reads as all ones or zero when nothing is there. What the port held is put
+    ///back.
+    pub fn is_present(&mut self) -> bool {
+        let saved = CONFIG_ADDRESS.read(&mut self.ports);
+        let enabled = ConfigAddress::default().with(ConfigAddress::ENABLE, true);
+        CONFIG_ADDRESS.write(&mut self.ports, enabled);
+        let present = CONFIG_ADDRESS.read(&mut self.ports) == enabled;
+        CONFIG_ADDRESS.write(&mut self.ports, saved);
+        present
+    }
+
+    //selects the register and gives the data port the offset is at
+    fn select(&mut self, address: Address, offset: u16) -> u16 {
+        assert!(
+            offset < 0x100,
+            "{:#x} is past what mechanism #1 reaches",
+            offset
+        );
+        let selected = ConfigAddress::default()
+            .with(ConfigAddress::ENABLE, true)
+            .with(ConfigAddress::BUS, address.bus)
+            .with(ConfigAddress::DEVICE, address.device)
+            .with(ConfigAddress::FUNCTION, address.function)
+            .with(ConfigAddress::OFFSET, (offset >> 2) as u8);
+        CONFIG_ADDRESS.write(&mut self.ports, selected);
+        CONFIG_DATA + (offset & 0b11)
+    }
+}
+
+impl<P: PortIo> ConfigSpace for Mechanism1<P> {
+    fn read_u8(&mut self, address: Address, offset: u16) -> u8 {
+        let data = self.select(address, offset);
+        self.ports.read_u8(data)
+    }
+
+    fn read_u16(&mut self, address: Address, offset: u16) -> u16 {
+        let data = self.select(address, offset);
+        self.ports.read_u16(data)
+    }
+
+    fn read_u32(&mut self, address: Address, offset: u16) -> u32 {
+        let data = self.select(address, offset);
+        self.ports.read_u32(data)
+    }
+
+    fn write_u16(&mut self, address: Address, offset: u16, value: u16) {
+        let data = self.select(address, offset);
+        self.ports.write_u16(data, value)
+    }
+
+    fn write_u32(&mut self, address: Address, offset: u16, value: u32) {
+        let data = self.select(address, offset);
+        self.ports.write_u32(data, value)
+    }
+}
+
+///The configuration space of the buses `start_bus` to `end_bus` mapped in memory
+pub struct Ecam<M> {
+    mmio: M,
+    start_bus: u8,
+    end_bus: u8,
+}
+
+impl<M: Mmio> Ecam<M> {
+    ///`mmio` starts at the configuration space of `start_bus`, which is where an MCFG
+    ///entry has its `base`
+    pub const fn new(mmio: M, start_bus: u8, end_bus: u8) -> Ecam<M> {
+        Ecam {
+            mmio,
+            start_bus,
+            end_bus,
+        }
+    }
+
+    //the buses this doesn't have, or that don't fit in the window, read like missing
+    //functions
+    fn offset(&self, address: Address, offset: u16, width: usize) -> Option<usize> {
+        assert!(
+            offset < 0x1000,
+            "{:#x} is past the configuration space",
+            offset
+        );
+        if !(self.start_bus..=self.end_bus).contains(&address.bus) {
+            return None;
+        }
+        let offset = usize::from(address.bus - self.start_bus) << 20
+            | usize::from(address.device) << 15
+            | usize::from(address.function) << 12
+            | usize::from(offset);
+        Some(offset).filter(|offset| offset + width <= self.mmio.len())
+    }
+}
+
+impl<M: Mmio> ConfigSpace for Ecam<M> {
+    fn read_u8(&mut self, address: Address, offset: u16) -> u8 {
+        self.offset(address, offset, 1)
+            .map_or(u8::MAX, |offset| self.mmio.read_u8(offset))
+    }
+
+    fn read_u16(&mut self, address: Address, offset: u16) -> u16 {
+        self.offset(address, offset, 2)
+            .map_or(u16::MAX, |offset| self.mmio.read_u16(offset))
+    }
+
+    fn read_u32(&mut self, address: Address, offset: u16) -> u32 {
+        self.offset(address, offset, 4)
+            .map_or(u32::MAX, |offset| self.mmio.read_u32(offset))
+    }
+
+    fn write_u16(&mut self, address: Address, offset: u16, value: u16) {
+        if let Some(offset) = self.offset(address, offset, 2) {
+            self.mmio.write_u16(offset, value)
+        }
+    }
+
+    fn write_u32(&mut self, address: Address, offset: u16, value: u32) {
+        if let Some(offset) = self.offset(address, offset, 4) {
+            self.mmio.write_u32(offset, value)
+        }
+    }
+}
+
+///One of the ECAM windows the ACPI MCFG table lists
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct McfgEntry {
+    ///The physical address of the configuration space of `start_bus`
+    pub base: u64,
+    pub segment: u16,
+    pub start_bus: u8,
+    pub end_bus: u8,
+}
+
+impl McfgEntry {
+    //after the 36 byte header of every ACPI table and 8 reserved bytes
+    const FIRST: usize = 44;
+    const SIZE: usize = 16;
+
+    ///How many bytes the window of the buses takes up
+    pub fn size(&self) -> u64 {
+        (u64::from(self.end_bus) - u64::from(self.start_bus) + 1) << 20
+    }
+
+    ///The entries of an MCFG table, `table` starts with its ACPI header. Entries past the
+    ///length in the header or past `table` are left out.
+    pub fn parse(table: &[u8]) -> impl Iterator<Item = McfgEntry> + '_ {
+        let length = table.get(4..8).map_or(0, |length| {
+            u32::from_le_bytes(length.try_into().unwrap()) as usize
+        });
+        let table = &table[..length.min(table.len())];
+        table
+            .get(Self::FIRST..)
+            .unwrap_or(&[])
+            .chunks_exact(Self::SIZE)
+            .map(|entry| McfgEntry {
+                base: u64::from_le_bytes(entry[0..8].try_into().unwrap()),
+                segment: u16::from_le_bytes(entry[8..10].try_into().unwrap()),
+                start_bus: entry[10],
+                end_bus: entry[11],
+            })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, MockMmio, MockPorts};
+
+    #[test]
+    fn mechanism1_selects_then_moves_data() {
+        let mut config = Mechanism1::new(MockPorts::new());
+        let address = Address::new(1, 0x1F, 3);
+        config.ports.queue_read(0xCFE, 0x7000);
+        assert_eq!(config.read_u16(address, 0x02), 0x7000);
+        config.write_u32(address, 0x10, 0xFFFF_FFFF);
+
+        let log: Vec<Access> = config.ports.log().collect();
+        assert_eq!(
+            log,
+            [
+                Access::Write {
+                    port: 0xCF8,
+                    value: 0x8001_FB00
+                },
+                Access::Read {
+                    port: 0xCFE,
+                    value: 0x7000
+                },
+                Access::Write {
+                    port: 0xCF8,
+                    value: 0x8001_FB10
+                },
+                Access::Write {
+                    port: 0xCFC,
+                    value: 0xFFFF_FFFF
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn mechanism1_is_present() {
+        let mut config = Mechanism1::new(MockPorts::new());
+        assert!(config.is_present());
+        assert_eq!(config.ports.get(0xCF8), 0);
+
+        config.ports.queue_read(0xCF8, 0);
+        config.ports.queue_read(0xCF8, 0xFFFF_FFFF);
+        assert!(!config.is_present());
+    }
+
+    #[test]
+    fn ecam() {
+        let mut mmio = MockMmio::<0x10000>::new();
+        //device 1 function 2 of the first bus
+        mmio.bytes_mut()[0xA000..0xA004].copy_from_slice(&[0xF4, 0x1A, 0x00, 0x10]);
+        let mut config = Ecam::new(mmio, 4, 5);
+
+        assert_eq!(config.read_u32(Address::new(4, 1, 2), 0), 0x1000_1AF4);
+        assert_eq!(config.read_u8(Address::new(4, 1, 2), 1), 0x1A);
+        assert_eq!(config.read_u16(Address::new(3, 1, 2), 0), 0xFFFF);
+        //inside the buses but past the mapped window
+        assert_eq!(config.read_u16(Address::new(5, 0, 0), 0), 0xFFFF);
+        config.write_u16(Address::new(4, 0, 0), 0x804, 0x0107);
+        assert_eq!(config.read_u16(Address::new(4, 0, 0), 0x804), 0x0107);
+    }
+
+    #[test]
+    fn mcfg() {
+        let mut table = [0u8; 76];
+        table[0..4].copy_from_slice(b"MCFG");
+        table[4..8].copy_from_slice(&60u32.to_le_bytes());
+        table[44..52].copy_from_slice(&0xB000_0000u64.to_le_bytes());
+        table[54] = 0;
+        table[55] = 0xFF;
+        //past the length in the header
+        table[60..68].copy_from_slice(&0xC000_0000u64.to_le_bytes());
+
+        let entries: Vec<McfgEntry> = McfgEntry::parse(&table).collect();
+        assert_eq!(
+            entries,
+            [McfgEntry {
+                base: 0xB000_0000,
+                segment: 0,
+                start_bus: 0,
+                end_bus: 0xFF
+            }]
+        );
+        assert_eq!(entries[0].size(), 0x1000_0000);
+        assert_eq!(McfgEntry::parse(&table[..10]).count(), 0);
+    }
+}
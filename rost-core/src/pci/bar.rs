@@ -0,0 +1,357 @@
+//! Base address registers, where the memory and ports of a function are
+//!
+//! A bar holds the address the firmware put the function at. Writing all ones to it and
+//! reading it back gives ones only in the bits the function lets be changed, which says
+//! how big the window is. A 64-bit memory bar takes the next bar for its upper half.
+
+use core::fmt;
+
+use super::{Command, ConfigSpace, Function, BAR0};
+
+const IO_SPACE: u32 = 0b1;
+const MEMORY_TYPE_64: u32 = 0b10 << 1;
+const MEMORY_TYPE: u32 = 0b11 << 1;
+const PREFETCHABLE: u32 = 1 << 3;
+const IO_FLAGS: u32 = 0b11;
+const MEMORY_FLAGS: u32 = 0b1111;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Bar {
+    Memory {
+        address: u64,
+        size: u64,
+        ///Reads have no side effects, so they can be cached or merged
+        prefetchable: bool,
+        ///Takes up this bar and the next one
+        is_64bit: bool,
+    },
+    Io {
+        port: u32,
+        size: u32,
+    },
+}
+
+impl Bar {
+    pub fn size(&self) -> u64 {
+        match *self {
+            Bar::Memory { size, .. } => size,
+            Bar::Io { size, .. } => size.into(),
+        }
+    }
+}
+
+impl fmt::Display for Bar {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Bar::Memory {
+                address,
+                size,
+                prefetchable,
+                is_64bit,
+            } => {
+                write!(
+                    f,
+                    "memory at {:#x} ({}-bit",
+                    address,
+                    if is_64bit { 64 } else { 32 }
+                )?;
+                if prefetchable {
+                    write!(f, ", prefetchable")?;
+                }
+                write!(f, ") [size={}]", Size(size))
+            }
+            Bar::Io { port, size } => {
+                write!(f, "ports at {:#x} [size={}]", port, Size(size.into()))
+            }
+        }
+    }
+}
+
+//bars are always a power of two, so the largest unit that fits is exact
+struct Size(u64);
+
+impl fmt::Display for Size {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        const UNITS: [(u64, &str); 3] = [(1 << 30, "G"), (1 << 20, "M"), (1 << 10, "K")];
+        match UNITS
+            .iter()
+            .find(|(unit, _)| self.0 >= *unit && self.0 & (unit - 1) == 0)
+        {
+            Some((unit, name)) => write!(f, "{}{}", self.0 / unit, name),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+//writes all ones to the bar and gives back what it reads then, the bar is put back
+fn size_mask(config: &mut impl ConfigSpace, function: &Function, offset: u16) -> u32 {
+    let address = function.address;
+    let saved = config.read_u32(address, offset);
+    config.write_u32(address, offset, u32::MAX);
+    let mask = config.read_u32(address, offset);
+    config.write_u32(address, offset, saved);
+    mask
+}
+
+///Decodes bar `index` of `function`. Unused bars and the upper halves of 64-bit bars give
+///`None`. The function stops decoding its memory and ports while the bar is sized, so
+///nothing else should be using it then.
+pub fn read(config: &mut impl ConfigSpace, function: &Function, index: u8) -> Option<Bar> {
+    if index >= function.bar_count() || is_upper_half(config, function, index) {
+        return None;
+    }
+    decode(config, function, index)
+}
+
+fn decode(config: &mut impl ConfigSpace, function: &Function, index: u8) -> Option<Bar> {
+    let offset = BAR0 + u16::from(index) * 4;
+    let command = function.command(config);
+    function.set_command(
+        config,
+        command
+            .with(Command::IO_SPACE, false)
+            .with(Command::MEMORY_SPACE, false),
+    );
+    let bar = size(config, function, index, offset);
+    function.set_command(config, command);
+    bar
+}
+
+fn size(config: &mut impl ConfigSpace, function: &Function, index: u8, offset: u16) -> Option<Bar> {
+    let value = config.read_u32(function.address, offset);
+    let mask = size_mask(config, function, offset);
+
+    if value & IO_SPACE != 0 {
+        //the upper half of a port bar may read as zero on functions that only decode 64K
+        let mask = mask & !IO_FLAGS;
+        let mask = if mask & 0xFFFF_0000 == 0 {
+            mask | 0xFFFF_0000
+        } else {
+            mask
+        };
+        return match mask {
+            0xFFFF_0000 => None,
+            _ => Some(Bar::Io {
+                port: value & !IO_FLAGS,
+                size: (!mask).wrapping_add(1),
+            }),
+        };
+    }
+
+    let is_64bit = value & MEMORY_TYPE == MEMORY_TYPE_64 && index + 1 < function.bar_count();
+    let (address, mask) = if is_64bit {
+        let high = config.read_u32(function.address, offset + 4);
+        let high_mask = size_mask(config, function, offset + 4);
+        (
+            u64::from(high) << 32 | u64::from(value & !MEMORY_FLAGS),
+            u64::from(high_mask) << 32 | u64::from(mask & !MEMORY_FLAGS),
+        )
+    } else {
+        (
+            u64::from(value & !MEMORY_FLAGS),
+            0xFFFF_FFFF_0000_0000 | u64::from(mask & !MEMORY_FLAGS),
+        )
+    };
+    if mask == 0xFFFF_FFFF_0000_0000 || mask == 0 {
+        return None;
+    }
+    Some(Bar::Memory {
+        address,
+        size: (!mask).wrapping_add(1),
+        prefetchable: value & PREFETCHABLE != 0,
+        is_64bit,
+    })
+}
+
+//how many bars the one at `index` takes up, this is the same for unused bars
+fn width(config: &mut impl ConfigSpace, function: &Function, index: u8) -> u8 {
+    let value = config.read_u32(function.address, BAR0 + u16::from(index) * 4);
+    if value & IO_SPACE == 0
+        && value & MEMORY_TYPE == MEMORY_TYPE_64
+        && index + 1 < function.bar_count()
+    {
+        2
+    } else {
+        1
+    }
+}
+
+//an upper half can hold anything, so only walking from the first bar tells them apart
+fn is_upper_half(config: &mut impl ConfigSpace, function: &Function, index: u8) -> bool {
+    let mut bar = 0;
+    while bar < index {
+        bar += width(config, function, bar);
+    }
+    bar != index
+}
+
+///Every bar of `function` with its index
+pub fn bars<'a, C: ConfigSpace>(
+    config: &'a mut C,
+    function: &'a Function,
+) -> impl Iterator<Item = (u8, Bar)> + 'a {
+    let mut next = 0;
+    core::iter::from_fn(move || {
+        while next < function.bar_count() {
+            let index = next;
+            next += width(config, function, index);
+            if let Some(bar) = decode(config, function, index) {
+                return Some((index, bar));
+            }
+        }
+        None
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockConfigSpace;
+    use crate::pci::{Address, COMMAND};
+    use proptest::prelude::*;
+
+    const ADDRESS: Address = Address::new(0, 2, 0);
+
+    fn function(config: &mut MockConfigSpace, bars: &[(u32, u32)]) -> Function {
+        let bytes = config.function(ADDRESS);
+        bytes[0..4].copy_from_slice(&0x1111_1234u32.to_le_bytes());
+        bytes[COMMAND as usize] = 0b11;
+        for (index, (value, _)) in bars.iter().enumerate() {
+            let offset = BAR0 as usize + index * 4;
+            bytes[offset..offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        for (index, (_, mask)) in bars.iter().enumerate() {
+            config.set_bar_mask(ADDRESS, index as u8, *mask);
+        }
+        Function::read(config, ADDRESS).unwrap()
+    }
+
+    #[test]
+    fn kinds_of_bars() {
+        let mut config = MockConfigSpace::new();
+        let function = function(
+            &mut config,
+            &[
+                //16M of prefetchable memory
+                (0xFD00_0008, 0xFF00_0000),
+                (0, 0),
+                //4K of memory
+                (0xFEBF_0000, 0xFFFF_F000),
+                //32 ports, the upper half reads as zero
+                (0xC041, 0x0000_FFE0),
+                //16K of 64-bit memory above 4G
+                (0x0000_400C, 0xFFFF_C000),
+                (0x0000_0008, 0xFFFF_FFFF),
+            ],
+        );
+
+        let bars: Vec<(u8, Bar)> = bars(&mut config, &function).collect();
+        assert_eq!(
+            bars,
+            [
+                (
+                    0,
+                    Bar::Memory {
+                        address: 0xFD00_0000,
+                        size: 0x100_0000,
+                        prefetchable: true,
+                        is_64bit: false
+                    }
+                ),
+                (
+                    2,
+                    Bar::Memory {
+                        address: 0xFEBF_0000,
+                        size: 0x1000,
+                        prefetchable: false,
+                        is_64bit: false
+                    }
+                ),
+                (
+                    3,
+                    Bar::Io {
+                        port: 0xC040,
+                        size: 32
+                    }
+                ),
+                (
+                    4,
+                    Bar::Memory {
+                        address: 0x8_0000_4000,
+                        size: 0x4000,
+                        prefetchable: true,
+                        is_64bit: true
+                    }
+                ),
+            ]
+        );
+        assert_eq!(
+            format!("{}", bars[0].1),
+            "memory at 0xfd000000 (32-bit, prefetchable) [size=16M]"
+        );
+        assert_eq!(format!("{}", bars[2].1), "ports at 0xc040 [size=32]");
+    }
+
+    #[test]
+    fn upper_halves_that_look_like_bars() {
+        let mut config = MockConfigSpace::new();
+        let function = function(
+            &mut config,
+            &[
+                //16K of 64-bit memory at 16G, the upper half reads like a 64-bit bar
+                (0x0000_000C, 0xFFFF_C000),
+                (0x0000_0004, 0xFFFF_FFFF),
+                //4K of memory
+                (0xFEBF_0000, 0xFFFF_F000),
+            ],
+        );
+
+        let memory = Bar::Memory {
+            address: 0xFEBF_0000,
+            size: 0x1000,
+            prefetchable: false,
+            is_64bit: false,
+        };
+        assert_eq!(read(&mut config, &function, 1), None);
+        assert_eq!(read(&mut config, &function, 2), Some(memory));
+        let bars: Vec<(u8, Bar)> = bars(&mut config, &function).collect();
+        assert_eq!(
+            bars,
+            [
+                (
+                    0,
+                    Bar::Memory {
+                        address: 0x4_0000_0000,
+                        size: 0x4000,
+                        prefetchable: true,
+                        is_64bit: true
+                    }
+                ),
+                (2, memory),
+            ]
+        );
+    }
+
+    #[test]
+    fn sizing_puts_everything_back() {
+        let mut config = MockConfigSpace::new();
+        let function = function(&mut config, &[(0xFEBF_0000, 0xFFFF_F000)]);
+        let before = *config.function(ADDRESS);
+        read(&mut config, &function, 0).unwrap();
+        assert_eq!(*config.function(ADDRESS), before);
+        assert_eq!(read(&mut config, &function, 6), None);
+    }
+
+    proptest! {
+        #[test]
+        fn memory_bars_are_as_big_as_their_mask(bits in 4u32..32, slot in any::<u32>()) {
+            let size = 1u64 << bits;
+            let mask = !(size as u32 - 1);
+            let mut config = MockConfigSpace::new();
+            let function = function(&mut config, &[(slot & mask, mask)]);
+
+            let bar = read(&mut config, &function, 0).unwrap();
+            prop_assert_eq!(bar.size(), size);
+        }
+    }
+}
@@ -0,0 +1,302 @@
+//! The capability list, the optional features of a function
+//!
+//! A function that has one sets `Status::CAPABILITIES` and keeps the offset of the first
+//! capability at `CAPABILITIES`. Every capability starts with its id and the offset of
+//! the next one, zero ends the list.
+
+use core::fmt;
+
+use super::{ConfigSpace, Function, CAPABILITIES};
+use crate::bitfield;
+
+pub const POWER_MANAGEMENT: u8 = 0x01;
+pub const MSI: u8 = 0x05;
+pub const VENDOR_SPECIFIC: u8 = 0x09;
+pub const PCI_EXPRESS: u8 = 0x10;
+pub const MSI_X: u8 = 0x11;
+
+///The list can't be longer than fits in configuration space, one that is means a pointer
+///loops back
+const MAX_CAPABILITIES: usize = 48;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Capability {
+    pub id: u8,
+    ///Where the capability is in configuration space
+    pub offset: u8,
+}
+
+impl Capability {
+    pub fn name(&self) -> &'static str {
+        match self.id {
+            POWER_MANAGEMENT => "power management",
+            0x02 => "AGP",
+            0x03 => "vital product data",
+            0x04 => "slot identification",
+            MSI => "MSI",
+            0x08 => "HyperTransport",
+            VENDOR_SPECIFIC => "vendor specific",
+            0x0A => "debug port",
+            0x0D => "bridge subsystem id",
+            PCI_EXPRESS => "PCI Express",
+            MSI_X => "MSI-X",
+            0x12 => "SATA",
+            0x13 => "advanced features",
+            _ => "unknown",
+        }
+    }
+}
+
+impl fmt::Display for Capability {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} at {:#04x}", self.name(), self.offset)
+    }
+}
+
+///The capabilities of `function` in the order of the list
+pub fn capabilities<'a, C: ConfigSpace>(
+    config: &'a mut C,
+    function: &Function,
+) -> impl Iterator<Item = Capability> + 'a {
+    let address = function.address;
+    let mut next = if function.status(config).get(super::Status::CAPABILITIES) {
+        config.read_u8(address, CAPABILITIES)
+    } else {
+        0
+    };
+
+    (0..MAX_CAPABILITIES).map_while(move |_| {
+        //the two low bits are reserved, and the header comes before the first capability
+        let offset = next & !0b11;
+        if offset < 0x40 {
+            return None;
+        }
+        let header = config.read_u16(address, offset.into());
+        next = (header >> 8) as u8;
+        Some(Capability {
+            id: header as u8,
+            offset,
+        })
+    })
+}
+
+///The first capability of `function` with `id`
+pub fn find(config: &mut impl ConfigSpace, function: &Function, id: u8) -> Option<Capability> {
+    capabilities(config, function).find(|capability| capability.id == id)
+}
+
+bitfield! {
+    struct MsiControl(u16) {
+        const ENABLE: bool = 0;
+        //the vectors are powers of two, these are the exponents
+        const MULTIPLE_MESSAGE_CAPABLE: u8 = 1..=3;
+        const MULTIPLE_MESSAGE_ENABLE: u8 = 4..=6;
+        const ADDRESS_64: bool = 7;
+        const PER_VECTOR_MASKING: bool = 8;
+    }
+}
+
+///Message signalled interrupts, the function writes `data` to an address instead of
+///pulling an interrupt pin
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Msi {
+    pub offset: u8,
+    pub enabled: bool,
+    ///How many vectors the function can use
+    pub vectors: u8,
+    ///How many vectors it was told to use
+    pub enabled_vectors: u8,
+    ///The message address can be above 4G
+    pub is_64bit: bool,
+    pub per_vector_masking: bool,
+}
+
+impl Msi {
+    pub fn read(config: &mut impl ConfigSpace, function: &Function, capability: Capability) -> Msi {
+        let control =
+            MsiControl(config.read_u16(function.address, u16::from(capability.offset) + 2));
+        Msi {
+            offset: capability.offset,
+            enabled: control.get(MsiControl::ENABLE),
+            vectors: 1 << control.get(MsiControl::MULTIPLE_MESSAGE_CAPABLE).min(5),
+            enabled_vectors: 1 << control.get(MsiControl::MULTIPLE_MESSAGE_ENABLE).min(5),
+            is_64bit: control.get(MsiControl::ADDRESS_64),
+            per_vector_masking: control.get(MsiControl::PER_VECTOR_MASKING),
+        }
+    }
+}
+
+impl fmt::Display for Msi {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{}, {}/{} vectors enabled",
+            if self.is_64bit { "64-bit" } else { "32-bit" },
+            if self.enabled {
+                self.enabled_vectors
+            } else {
+                0
+            },
+            self.vectors
+        )?;
+        if self.per_vector_masking {
+            write!(f, ", maskable")?;
+        }
+        Ok(())
+    }
+}
+
+bitfield! {
+    struct MsiXControl(u16) {
+        //one less than the entries of the table
+        const TABLE_SIZE: u16 = 0..=10;
+        const FUNCTION_MASK: bool = 14;
+        const ENABLE: bool = 15;
+    }
+}
+
+bitfield! {
+    //where the table or the pending bits are, the bar is left out of the offset
+    struct MsiXLocation(u32) {
+        const BAR: u8 = 0..=2;
+    }
+}
+
+///The table of MSI-X vectors and their pending bits are in the memory of a bar
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MsiX {
+    pub offset: u8,
+    pub enabled: bool,
+    ///Every vector is masked no matter what its entry says
+    pub masked: bool,
+    pub table_size: u16,
+    pub table_bar: u8,
+    pub table_offset: u32,
+    pub pending_bar: u8,
+    pub pending_offset: u32,
+}
+
+impl MsiX {
+    pub fn read(
+        config: &mut impl ConfigSpace,
+        function: &Function,
+        capability: Capability,
+    ) -> MsiX {
+        let address = function.address;
+        let offset = u16::from(capability.offset);
+        let control = MsiXControl(config.read_u16(address, offset + 2));
+        let table = config.read_u32(address, offset + 4);
+        let pending = config.read_u32(address, offset + 8);
+        MsiX {
+            offset: capability.offset,
+            enabled: control.get(MsiXControl::ENABLE),
+            masked: control.get(MsiXControl::FUNCTION_MASK),
+            table_size: control.get(MsiXControl::TABLE_SIZE) + 1,
+            table_bar: MsiXLocation(table).get(MsiXLocation::BAR),
+            table_offset: table & !0b111,
+            pending_bar: MsiXLocation(pending).get(MsiXLocation::BAR),
+            pending_offset: pending & !0b111,
+        }
+    }
+}
+
+impl fmt::Display for MsiX {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "{} vectors, table in bar {} at {:#x}, pending bits in bar {} at {:#x}",
+            self.table_size,
+            self.table_bar,
+            self.table_offset,
+            self.pending_bar,
+            self.pending_offset
+        )?;
+        if self.enabled {
+            write!(f, ", enabled")?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockConfigSpace;
+    use crate::pci::{Address, STATUS};
+
+    const ADDRESS: Address = Address::new(0, 4, 0);
+
+    fn put(bytes: &mut [u8], offset: usize, value: &[u8]) {
+        bytes[offset..offset + value.len()].copy_from_slice(value);
+    }
+
+    #[test]
+    fn list_with_msi_and_msi_x() {
+        let mut config = MockConfigSpace::new();
+        let bytes = config.function(ADDRESS);
+        put(bytes, 0, &0x1000_1AF4u32.to_le_bytes());
+        put(bytes, STATUS as usize, &0x0010u16.to_le_bytes());
+        bytes[CAPABILITIES as usize] = 0x98;
+        //msi-x with 3 vectors, the table in bar 1 at 0 and the pending bits at 0x800
+        put(bytes, 0x98, &[MSI_X, 0x84, 0x02, 0x80]);
+        put(bytes, 0x9C, &0x0000_0001u32.to_le_bytes());
+        put(bytes, 0xA0, &0x0000_0801u32.to_le_bytes());
+        //64-bit msi that can use 4 vectors and has 1 enabled
+        put(bytes, 0x84, &[MSI, 0x70, 0x85, 0x00]);
+        put(bytes, 0x70, &[VENDOR_SPECIFIC, 0x00]);
+        let function = Function::read(&mut config, ADDRESS).unwrap();
+
+        let list: Vec<Capability> = capabilities(&mut config, &function).collect();
+        assert_eq!(
+            list.iter()
+                .map(|capability| capability.id)
+                .collect::<Vec<u8>>(),
+            [MSI_X, MSI, VENDOR_SPECIFIC]
+        );
+
+        let msi_x = MsiX::read(&mut config, &function, list[0]);
+        assert_eq!(
+            msi_x,
+            MsiX {
+                offset: 0x98,
+                enabled: true,
+                masked: false,
+                table_size: 3,
+                table_bar: 1,
+                table_offset: 0,
+                pending_bar: 1,
+                pending_offset: 0x800
+            }
+        );
+
+        let capability = find(&mut config, &function, MSI).unwrap();
+        let msi = Msi::read(&mut config, &function, capability);
+        assert_eq!((msi.vectors, msi.enabled_vectors), (4, 1));
+        assert!(msi.enabled && msi.is_64bit && !msi.per_vector_masking);
+        assert_eq!(format!("{}", msi), "64-bit, 1/4 vectors enabled");
+        assert_eq!(format!("{}", list[2]), "vendor specific at 0x70");
+    }
+
+    #[test]
+    fn broken_lists_end() {
+        let mut config = MockConfigSpace::new();
+        let bytes = config.function(ADDRESS);
+        put(bytes, 0, &0x1000_1AF4u32.to_le_bytes());
+        bytes[CAPABILITIES as usize] = 0x40;
+        //points at itself
+        put(bytes, 0x40, &[POWER_MANAGEMENT, 0x40]);
+        let function = Function::read(&mut config, ADDRESS).unwrap();
+
+        //without the status bit the pointer means nothing
+        assert_eq!(capabilities(&mut config, &function).count(), 0);
+        put(
+            config.function(ADDRESS),
+            STATUS as usize,
+            &0x0010u16.to_le_bytes(),
+        );
+        assert_eq!(
+            capabilities(&mut config, &function).count(),
+            MAX_CAPABILITIES
+        );
+    }
+}
@@ -0,0 +1,434 @@
+//! The PCI bus: configuration space, finding the functions and what they use
+//!
+//! Every function has 256 bytes of configuration space, 4096 with ECAM, that say what it
+//! is and how to reach it. `ConfigSpace` reads and writes them, `access` has the two ways
+//! the hardware offers. `enumerate` walks from bus 0 through every bridge, `bar` sizes
+//! the memory and ports a function decodes and `capability` reads its capability list.
+
+use core::fmt;
+
+use crate::bitfield;
+
+pub mod access;
+pub mod bar;
+pub mod capability;
+pub mod names;
+
+pub const VENDOR_ID: u16 = 0x00;
+pub const DEVICE_ID: u16 = 0x02;
+pub const COMMAND: u16 = 0x04;
+pub const STATUS: u16 = 0x06;
+pub const REVISION: u16 = 0x08;
+pub const PROG_IF: u16 = 0x09;
+pub const SUBCLASS: u16 = 0x0A;
+pub const CLASS: u16 = 0x0B;
+pub const HEADER_TYPE: u16 = 0x0E;
+pub const BAR0: u16 = 0x10;
+///The bus right behind a bridge
+pub const SECONDARY_BUS: u16 = 0x19;
+pub const CAPABILITIES: u16 = 0x34;
+pub const INTERRUPT_LINE: u16 = 0x3C;
+pub const INTERRUPT_PIN: u16 = 0x3D;
+
+///What reads of a function that isn't there give
+const NO_VENDOR: u16 = 0xFFFF;
+const DEVICES_PER_BUS: u8 = 32;
+const FUNCTIONS_PER_DEVICE: u8 = 8;
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_HOST_BRIDGE: u8 = 0x00;
+const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+bitfield! {
+    pub struct Command(u16) {
+        ///Lets the function answer accesses to its port bars
+        pub const IO_SPACE: bool = 0;
+        ///Lets the function answer accesses to its memory bars
+        pub const MEMORY_SPACE: bool = 1;
+        pub const BUS_MASTER: bool = 2;
+        pub const INTERRUPT_DISABLE: bool = 10;
+    }
+}
+
+bitfield! {
+    pub struct Status(u16) {
+        ///The capability pointer at `CAPABILITIES` is valid
+        pub const CAPABILITIES: bool = 4;
+    }
+}
+
+///Reads and writes the configuration space of the functions. Offsets of wider accesses
+///have to be aligned to their width, reading a function that isn't there gives all ones.
+pub trait ConfigSpace {
+    fn read_u8(&mut self, address: Address, offset: u16) -> u8;
+    fn read_u16(&mut self, address: Address, offset: u16) -> u16;
+    fn read_u32(&mut self, address: Address, offset: u16) -> u32;
+    fn write_u16(&mut self, address: Address, offset: u16, value: u16);
+    fn write_u32(&mut self, address: Address, offset: u16, value: u32);
+}
+
+impl<C: ConfigSpace + ?Sized> ConfigSpace for &mut C {
+    fn read_u8(&mut self, address: Address, offset: u16) -> u8 {
+        (**self).read_u8(address, offset)
+    }
+
+    fn read_u16(&mut self, address: Address, offset: u16) -> u16 {
+        (**self).read_u16(address, offset)
+    }
+
+    fn read_u32(&mut self, address: Address, offset: u16) -> u32 {
+        (**self).read_u32(address, offset)
+    }
+
+    fn write_u16(&mut self, address: Address, offset: u16, value: u16) {
+        (**self).write_u16(address, offset, value)
+    }
+
+    fn write_u32(&mut self, address: Address, offset: u16, value: u32) {
+        (**self).write_u32(address, offset, value)
+    }
+}
+
+///Where a function is, written like `00:1f.3`
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Address {
+    pub bus: u8,
+    ///0 to 31
+    pub device: u8,
+    ///0 to 7
+    pub function: u8,
+}
+
+impl Address {
+    pub const fn new(bus: u8, device: u8, function: u8) -> Address {
+        assert!(device < DEVICES_PER_BUS && function < FUNCTIONS_PER_DEVICE);
+        Address {
+            bus,
+            device,
+            function,
+        }
+    }
+}
+
+impl fmt::Display for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.device, self.function)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderType {
+    ///An endpoint with six bars
+    General,
+    ///A bridge to another PCI bus, with two bars
+    PciBridge,
+    CardBusBridge,
+    Unknown(u8),
+}
+
+impl HeaderType {
+    fn from_byte(byte: u8) -> HeaderType {
+        match byte & 0x7F {
+            0 => HeaderType::General,
+            1 => HeaderType::PciBridge,
+            2 => HeaderType::CardBusBridge,
+            other => HeaderType::Unknown(other),
+        }
+    }
+}
+
+///The header every function starts with
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Function {
+    pub address: Address,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    ///Narrows the class down further, like the interface of a usb controller
+    pub prog_if: u8,
+    pub revision: u8,
+    pub header_type: HeaderType,
+    ///Only meaningful on function 0, whether the device has functions past it
+    pub multifunction: bool,
+}
+
+impl Function {
+    ///The header of the function at `address`, if there is one
+    pub fn read(config: &mut impl ConfigSpace, address: Address) -> Option<Function> {
+        let vendor = config.read_u16(address, VENDOR_ID);
+        if vendor == NO_VENDOR {
+            return None;
+        }
+        let header_type = config.read_u8(address, HEADER_TYPE);
+        Some(Function {
+            address,
+            vendor,
+            device: config.read_u16(address, DEVICE_ID),
+            class: config.read_u8(address, CLASS),
+            subclass: config.read_u8(address, SUBCLASS),
+            prog_if: config.read_u8(address, PROG_IF),
+            revision: config.read_u8(address, REVISION),
+            header_type: HeaderType::from_byte(header_type),
+            multifunction: header_type & 0x80 != 0,
+        })
+    }
+
+    ///A bridge to another PCI bus that `enumerate` goes through
+    pub fn is_pci_bridge(&self) -> bool {
+        self.header_type == HeaderType::PciBridge
+            && self.class == CLASS_BRIDGE
+            && self.subclass == SUBCLASS_PCI_BRIDGE
+    }
+
+    ///How many bars the header has
+    pub fn bar_count(&self) -> u8 {
+        match self.header_type {
+            HeaderType::General => 6,
+            HeaderType::PciBridge => 2,
+            _ => 0,
+        }
+    }
+
+    pub fn command(&self, config: &mut impl ConfigSpace) -> Command {
+        Command(config.read_u16(self.address, COMMAND))
+    }
+
+    pub fn set_command(&self, config: &mut impl ConfigSpace, command: Command) {
+        config.write_u16(self.address, COMMAND, command.0)
+    }
+
+    pub fn status(&self, config: &mut impl ConfigSpace) -> Status {
+        Status(config.read_u16(self.address, STATUS))
+    }
+
+    ///The pin the function interrupts with, `A` to `D`, and the line the firmware routed
+    ///it to
+    pub fn interrupt(&self, config: &mut impl ConfigSpace) -> Option<(char, u8)> {
+        let pin = config.read_u8(self.address, INTERRUPT_PIN);
+        let line = config.read_u8(self.address, INTERRUPT_LINE);
+        match pin {
+            1..=4 => Some(((b'A' + pin - 1) as char, line)),
+            _ => None,
+        }
+    }
+}
+
+///Calls `found` with every function on bus 0 and the buses behind its bridges, a bridge
+///comes right before what is behind it
+pub fn enumerate(config: &mut impl ConfigSpace, mut found: impl FnMut(&Function)) {
+    let mut scanned = [false; 256];
+    let host = Function::read(config, Address::new(0, 0, 0));
+
+    match host {
+        //every function of a device with several host bridges is the bridge to the bus
+        //with its number
+        Some(host) if host.multifunction => {
+            for function in 0..FUNCTIONS_PER_DEVICE {
+                let address = Address::new(0, 0, function);
+                if let Some(bridge) = Function::read(config, address) {
+                    if bridge.class == CLASS_BRIDGE && bridge.subclass == SUBCLASS_HOST_BRIDGE {
+                        scan_bus(config, function, &mut scanned, &mut found);
+                    }
+                }
+            }
+        }
+        _ => scan_bus(config, 0, &mut scanned, &mut found),
+    }
+}
+
+//a bus is only scanned once, even when bridges that are set up wrong point to it again
+fn scan_bus(
+    config: &mut impl ConfigSpace,
+    bus: u8,
+    scanned: &mut [bool; 256],
+    found: &mut impl FnMut(&Function),
+) {
+    if scanned[bus as usize] {
+        return;
+    }
+    scanned[bus as usize] = true;
+
+    for device in 0..DEVICES_PER_BUS {
+        let first = match Function::read(config, Address::new(bus, device, 0)) {
+            Some(first) => first,
+            None => continue,
+        };
+        let functions = if first.multifunction {
+            FUNCTIONS_PER_DEVICE
+        } else {
+            1
+        };
+
+        for function in 0..functions {
+            let function = match Function::read(config, Address::new(bus, device, function)) {
+                Some(function) => function,
+                None => continue,
+            };
+            found(&function);
+            if function.is_pci_bridge() {
+                let secondary = config.read_u8(function.address, SECONDARY_BUS);
+                //a bridge the firmware hasn't given a bus yet has nothing reachable behind it
+                if secondary != 0 {
+                    scan_bus(config, secondary, scanned, found);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockConfigSpace;
+
+    fn add(
+        config: &mut MockConfigSpace,
+        address: Address,
+        id: (u16, u16),
+        class: (u8, u8),
+        header_type: u8,
+    ) {
+        let bytes = config.function(address);
+        bytes[0..2].copy_from_slice(&id.0.to_le_bytes());
+        bytes[2..4].copy_from_slice(&id.1.to_le_bytes());
+        bytes[CLASS as usize] = class.0;
+        bytes[SUBCLASS as usize] = class.1;
+        bytes[HEADER_TYPE as usize] = header_type;
+    }
+
+    #[test]
+    fn header() {
+        let mut config = MockConfigSpace::new();
+        let address = Address::new(0, 3, 0);
+        add(&mut config, address, (0x8086, 0x100E), (0x02, 0x00), 0);
+        config.function(address)[INTERRUPT_PIN as usize] = 1;
+        config.function(address)[INTERRUPT_LINE as usize] = 11;
+
+        let function = Function::read(&mut config, address).unwrap();
+        assert_eq!((function.vendor, function.device), (0x8086, 0x100E));
+        assert_eq!(function.header_type, HeaderType::General);
+        assert_eq!(function.bar_count(), 6);
+        assert_eq!(function.interrupt(&mut config), Some(('A', 11)));
+        assert_eq!(Function::read(&mut config, Address::new(0, 4, 0)), None);
+        assert_eq!(format!("{}", Address::new(0x10, 0x1F, 3)), "10:1f.3");
+    }
+
+    #[test]
+    fn enumerate_goes_through_bridges() {
+        let mut config = MockConfigSpace::new();
+        add(
+            &mut config,
+            Address::new(0, 0, 0),
+            (0x8086, 0x1237),
+            (0x06, 0x00),
+            0,
+        );
+        //a multifunction device with a hole at function 1
+        add(
+            &mut config,
+            Address::new(0, 1, 0),
+            (0x8086, 0x7000),
+            (0x06, 0x01),
+            0x80,
+        );
+        add(
+            &mut config,
+            Address::new(0, 1, 2),
+            (0x8086, 0x7020),
+            (0x0C, 0x03),
+            0,
+        );
+        add(
+            &mut config,
+            Address::new(0, 2, 0),
+            (0x1B36, 0x0001),
+            (0x06, 0x04),
+            1,
+        );
+        config.function(Address::new(0, 2, 0))[SECONDARY_BUS as usize] = 1;
+        add(
+            &mut config,
+            Address::new(1, 0, 0),
+            (0x1B36, 0x0001),
+            (0x06, 0x04),
+            1,
+        );
+        config.function(Address::new(1, 0, 0))[SECONDARY_BUS as usize] = 2;
+        add(
+            &mut config,
+            Address::new(2, 5, 0),
+            (0x1AF4, 0x1000),
+            (0x02, 0x00),
+            0,
+        );
+        //points back at bus 1, which must not be scanned again
+        add(
+            &mut config,
+            Address::new(2, 6, 0),
+            (0x1B36, 0x0001),
+            (0x06, 0x04),
+            1,
+        );
+        config.function(Address::new(2, 6, 0))[SECONDARY_BUS as usize] = 1;
+        add(
+            &mut config,
+            Address::new(0, 3, 0),
+            (0x1234, 0x1111),
+            (0x03, 0x00),
+            0,
+        );
+
+        let mut found = Vec::new();
+        enumerate(&mut config, |function| found.push(function.address));
+        assert_eq!(
+            found,
+            [
+                Address::new(0, 0, 0),
+                Address::new(0, 1, 0),
+                Address::new(0, 1, 2),
+                Address::new(0, 2, 0),
+                Address::new(1, 0, 0),
+                Address::new(2, 5, 0),
+                Address::new(2, 6, 0),
+                Address::new(0, 3, 0),
+            ]
+        );
+    }
+
+    #[test]
+    fn several_host_bridges() {
+        let mut config = MockConfigSpace::new();
+        add(
+            &mut config,
+            Address::new(0, 0, 0),
+            (0x1022, 0x1450),
+            (0x06, 0x00),
+            0x80,
+        );
+        add(
+            &mut config,
+            Address::new(0, 0, 1),
+            (0x1022, 0x1450),
+            (0x06, 0x00),
+            0,
+        );
+        add(
+            &mut config,
+            Address::new(1, 4, 0),
+            (0x1AF4, 0x1001),
+            (0x01, 0x00),
+            0,
+        );
+
+        let mut found = Vec::new();
+        enumerate(&mut config, |function| found.push(function.address));
+        assert_eq!(
+            found,
+            [
+                Address::new(0, 0, 0),
+                Address::new(0, 0, 1),
+                Address::new(1, 4, 0)
+            ]
+        );
+    }
+}
@@ -0,0 +1,133 @@
+//! Names for the vendors, devices and classes `lspci` shows
+//!
+//! Only the ones QEMU emulates and a few common vendors are here, not the whole PCI id
+//! database.
+
+const VENDORS: [(u16, &str); 14] = [
+    (0x1002, "AMD/ATI"),
+    (0x1013, "Cirrus Logic"),
+    (0x1022, "AMD"),
+    (0x1033, "NEC"),
+    (0x10DE, "NVIDIA"),
+    (0x10EC, "Realtek"),
+    (0x1234, "QEMU"),
+    (0x14E4, "Broadcom"),
+    (0x15AD, "VMware"),
+    (0x1AF4, "Red Hat virtio"),
+    (0x1B36, "Red Hat"),
+    (0x1D0F, "Amazon"),
+    (0x8086, "Intel"),
+    (0x80EE, "VirtualBox"),
+];
+
+const DEVICES: [(u16, u16, &str); 34] = [
+    (0x1013, 0x00B8, "GD 5446 VGA"),
+    (0x1033, 0x0194, "uPD720200 USB 3.0 controller"),
+    (0x10EC, 0x8139, "RTL-8139 ethernet"),
+    (0x1234, 0x1111, "standard VGA"),
+    (0x15AD, 0x0405, "SVGA II"),
+    (0x1AF4, 0x1000, "network device"),
+    (0x1AF4, 0x1001, "block device"),
+    (0x1AF4, 0x1002, "memory balloon"),
+    (0x1AF4, 0x1003, "console"),
+    (0x1AF4, 0x1004, "SCSI"),
+    (0x1AF4, 0x1005, "entropy source"),
+    (0x1AF4, 0x1041, "network device"),
+    (0x1AF4, 0x1042, "block device"),
+    (0x1AF4, 0x1050, "GPU"),
+    (0x1AF4, 0x1052, "input"),
+    (0x1B36, 0x0001, "PCI-PCI bridge"),
+    (0x1B36, 0x0005, "PCI test device"),
+    (0x1B36, 0x000C, "PCIe root port"),
+    (0x1B36, 0x000D, "XHCI host controller"),
+    (0x1B36, 0x0010, "NVMe controller"),
+    (0x8086, 0x100E, "82540EM gigabit ethernet"),
+    (0x8086, 0x10D3, "82574L gigabit ethernet"),
+    (0x8086, 0x1237, "440FX host bridge"),
+    (0x8086, 0x2415, "82801AA AC'97 audio"),
+    (0x8086, 0x2668, "82801FB HD audio"),
+    (0x8086, 0x2918, "ICH9 LPC interface"),
+    (0x8086, 0x2922, "ICH9 AHCI controller"),
+    (0x8086, 0x2930, "ICH9 SMBus controller"),
+    (0x8086, 0x29C0, "Q35 host bridge"),
+    (0x8086, 0x7000, "PIIX3 ISA bridge"),
+    (0x8086, 0x7010, "PIIX3 IDE"),
+    (0x8086, 0x7020, "PIIX3 USB controller"),
+    (0x8086, 0x7113, "PIIX4 ACPI"),
+    (0x80EE, 0xCAFE, "guest service"),
+];
+
+pub fn vendor(vendor: u16) -> Option<&'static str> {
+    VENDORS
+        .iter()
+        .find(|(id, _)| *id == vendor)
+        .map(|(_, name)| *name)
+}
+
+pub fn device(vendor: u16, device: u16) -> Option<&'static str> {
+    DEVICES
+        .iter()
+        .find(|(vendor_id, id, _)| *vendor_id == vendor && *id == device)
+        .map(|(_, _, name)| *name)
+}
+
+///The name of the subclass if there is one, otherwise of the class
+pub fn class(class: u8, subclass: u8) -> &'static str {
+    match (class, subclass) {
+        (0x00, _) => "unclassified device",
+        (0x01, 0x00) => "SCSI controller",
+        (0x01, 0x01) => "IDE controller",
+        (0x01, 0x05) => "ATA controller",
+        (0x01, 0x06) => "SATA controller",
+        (0x01, 0x08) => "non-volatile memory controller",
+        (0x01, _) => "mass storage controller",
+        (0x02, 0x00) => "ethernet controller",
+        (0x02, _) => "network controller",
+        (0x03, 0x00) => "VGA compatible controller",
+        (0x03, _) => "display controller",
+        (0x04, 0x01 | 0x03) => "audio device",
+        (0x04, _) => "multimedia controller",
+        (0x05, _) => "memory controller",
+        (0x06, 0x00) => "host bridge",
+        (0x06, 0x01) => "ISA bridge",
+        (0x06, 0x04) => "PCI bridge",
+        (0x06, _) => "bridge",
+        (0x07, 0x00) => "serial controller",
+        (0x07, _) => "communication controller",
+        (0x08, _) => "system peripheral",
+        (0x09, _) => "input device controller",
+        (0x0C, 0x03) => "USB controller",
+        (0x0C, 0x05) => "SMBus",
+        (0x0C, _) => "serial bus controller",
+        (0x0D, _) => "wireless controller",
+        (0x10, _) => "encryption controller",
+        (0x11, _) => "signal processing controller",
+        (0x12, _) => "processing accelerator",
+        (0xFF, _) => "unassigned class",
+        _ => "unknown class",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn names() {
+        assert_eq!(vendor(0x8086), Some("Intel"));
+        assert_eq!(vendor(0xDEAD), None);
+        assert_eq!(device(0x8086, 0x1237), Some("440FX host bridge"));
+        assert_eq!(device(0x1AF4, 0x1237), None);
+        assert_eq!(class(0x06, 0x01), "ISA bridge");
+        assert_eq!(class(0x06, 0x80), "bridge");
+        assert_eq!(class(0x42, 0x00), "unknown class");
+    }
+
+    #[test]
+    fn tables_are_sorted_without_duplicates() {
+        assert!(VENDORS.windows(2).all(|pair| pair[0].0 < pair[1].0));
+        assert!(DEVICES
+            .windows(2)
+            .all(|pair| (pair[0].0, pair[0].1) < (pair[1].0, pair[1].1)));
+    }
+}
@@ -0,0 +1,39 @@
+//! Port I/O, the `in` and `out` instructions of x86
+//!
+//! Drivers take a `PortIo` instead of creating ports themselves. The kernel passes one
+//! that runs the instructions, tests pass `mock::MockPorts`.
+
+pub trait PortIo {
+    fn read_u8(&mut self, port: u16) -> u8;
+    fn write_u8(&mut self, port: u16, value: u8);
+    fn read_u16(&mut self, port: u16) -> u16;
+    fn write_u16(&mut self, port: u16, value: u16);
+    fn read_u32(&mut self, port: u16) -> u32;
+    fn write_u32(&mut self, port: u16, value: u32);
+}
+
+impl<P: PortIo + ?Sized> PortIo for &mut P {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        (**self).read_u8(port)
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        (**self).write_u8(port, value)
+    }
+
+    fn read_u16(&mut self, port: u16) -> u16 {
+        (**self).read_u16(port)
+    }
+
+    fn write_u16(&mut self, port: u16, value: u16) {
+        (**self).write_u16(port, value)
+    }
+
+    fn read_u32(&mut self, port: u16) -> u32 {
+        (**self).read_u32(port)
+    }
+
+    fn write_u32(&mut self, port: u16, value: u32) {
+        (**self).write_u32(port, value)
+    }
+}
@@ -0,0 +1,235 @@
+//! Fixed size queues, there is no heap to grow them on
+
+///A fixed size first in first out queue
+#[derive(Debug, Clone, Copy)]
+pub struct RingBuffer<T: Copy, const N: usize> {
+    items: [T; N],
+    head: usize,
+    len: usize,
+}
+
+impl<T: Copy, const N: usize> RingBuffer<T, N> {
+    ///Creates an empty buffer, `fill` only initializes the unused slots
+    pub const fn new(fill: T) -> Self {
+        RingBuffer {
+            items: [fill; N],
+            head: 0,
+            len: 0,
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        self.head = 0;
+        self.len = 0;
+    }
+
+    ///Adds an item to the back, a full buffer gives the item back
+    pub fn push(&mut self, item: T) -> Result<(), T> {
+        if self.is_full() {
+            return Err(item);
+        }
+        self.items[(self.head + self.len) % N] = item;
+        self.len += 1;
+        Ok(())
+    }
+
+    ///Adds an item to the back, a full buffer drops its oldest item to make room
+    pub fn push_overwrite(&mut self, item: T) {
+        if self.is_full() {
+            self.pop();
+        }
+        let _ = self.push(item);
+    }
+
+    ///Takes the oldest item
+    pub fn pop(&mut self) -> Option<T> {
+        if self.is_empty() {
+            return None;
+        }
+        let item = self.items[self.head];
+        self.head = (self.head + 1) % N;
+        self.len -= 1;
+        Some(item)
+    }
+
+    ///The item `index` places after the oldest one
+    pub fn get(&self, index: usize) -> Option<T> {
+        if index < self.len {
+            Some(self.items[(self.head + index) % N])
+        } else {
+            None
+        }
+    }
+
+    ///Goes through the items from the oldest to the newest without removing them
+    pub fn iter(&self) -> impl Iterator<Item = T> + '_ {
+        (0..self.len).filter_map(move |i| self.get(i))
+    }
+}
+
+///The keys typed but not yet read, the newest key comes first
+#[derive(Debug, Clone, Copy)]
+pub struct InputBuffer<K: Copy, const N: usize> {
+    pub buffer: [K; N],
+    pub unread_char_count: usize,
+}
+
+pub struct InputReturn<K, const N: usize>(pub usize, pub [K; N]);
+
+impl<K: Copy, const N: usize> InputBuffer<K, N> {
+    ///Creates an empty buffer, `fill` only initializes the slots
+    pub const fn new(fill: K) -> Self {
+        InputBuffer {
+            buffer: [fill; N],
+            unread_char_count: 0,
+        }
+    }
+
+    ///appends to the front of the buffer deleting the last item in the buffer
+    pub fn write_key(&mut self, key: K) {
+        self.buffer[..].rotate_right(1);
+        self.buffer[0] = key;
+        self.unread_char_count = self.unread_char_count.saturating_add(1);
+    }
+
+    ///Returns a copy of the current INPUTBUFFER and sets the read index to 0
+    pub fn read(mut self) -> InputReturn<K, N> {
+        let return_object = InputReturn(self.unread_char_count, self.buffer);
+        self.unread_char_count = 0;
+        return_object
+    }
+
+    ///The keys written since the last read, newest first, and sets the read index to 0
+    pub fn read_unread(&mut self) -> &[K] {
+        let unread = self.unread_char_count.min(N);
+        self.unread_char_count = 0;
+        &self.buffer[..unread]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn ring_buffer_order() {
+        let mut ring = RingBuffer::<u8, 3>::new(0);
+        for i in 1..=3 {
+            ring.push(i).unwrap();
+        }
+        assert_eq!(ring.push(4), Err(4));
+        assert_eq!(ring.pop(), Some(1));
+
+        ring.push(4).unwrap();
+        ring.push_overwrite(5);
+        assert!(ring.iter().eq([3, 4, 5].into_iter()));
+        assert_eq!(ring.pop(), Some(3));
+        assert_eq!(ring.len(), 2);
+    }
+
+    #[test]
+    fn input_buffer_newest_first() {
+        let mut input = InputBuffer::<char, 4>::new('0');
+        for key in "abcde".chars() {
+            input.write_key(key);
+        }
+        assert_eq!(input.buffer, ['e', 'd', 'c', 'b']);
+        assert_eq!(input.unread_char_count, 5);
+    }
+
+    #[test]
+    fn input_buffer_read_unread() {
+        let mut input = InputBuffer::<char, 4>::new('0');
+        input.write_key('a');
+        input.write_key('b');
+        assert_eq!(input.read_unread(), &['b', 'a']);
+        assert!(input.read_unread().is_empty());
+
+        for key in "cdefg".chars() {
+            input.write_key(key);
+        }
+        assert_eq!(input.read_unread(), &['g', 'f', 'e', 'd']);
+    }
+
+    //what the ring buffer holds is always the last `N` items pushed with `push_overwrite`
+    //minus the ones popped since, like a `VecDeque` capped at `N`
+    #[derive(Debug, Clone)]
+    enum Operation {
+        Push(u8),
+        PushOverwrite(u8),
+        Pop,
+        Clear,
+    }
+
+    fn operation() -> impl Strategy<Value = Operation> {
+        prop_oneof![
+            any::<u8>().prop_map(Operation::Push),
+            any::<u8>().prop_map(Operation::PushOverwrite),
+            Just(Operation::Pop),
+            Just(Operation::Clear),
+        ]
+    }
+
+    proptest! {
+        #[test]
+        fn ring_buffer_matches_vec_deque(operations in prop::collection::vec(operation(), 0..200)) {
+            use std::collections::VecDeque;
+
+            let mut ring = RingBuffer::<u8, 8>::new(0);
+            let mut model = VecDeque::new();
+            for operation in operations {
+                match operation {
+                    Operation::Push(item) => {
+                        let expected = if model.len() < 8 {
+                            model.push_back(item);
+                            Ok(())
+                        } else {
+                            Err(item)
+                        };
+                        prop_assert_eq!(ring.push(item), expected);
+                    }
+                    Operation::PushOverwrite(item) => {
+                        if model.len() == 8 {
+                            model.pop_front();
+                        }
+                        model.push_back(item);
+                        ring.push_overwrite(item);
+                    }
+                    Operation::Pop => prop_assert_eq!(ring.pop(), model.pop_front()),
+                    Operation::Clear => {
+                        ring.clear();
+                        model.clear();
+                    }
+                }
+                prop_assert_eq!(ring.len(), model.len());
+                prop_assert_eq!(ring.is_full(), model.len() == 8);
+                prop_assert!(ring.iter().eq(model.iter().copied()));
+            }
+        }
+
+        #[test]
+        fn input_buffer_keeps_the_newest_keys(keys in prop::collection::vec(any::<u16>(), 0..40)) {
+            let mut input = InputBuffer::<u16, 16>::new(0);
+            for key in &keys {
+                input.write_key(*key);
+            }
+            prop_assert_eq!(input.unread_char_count, keys.len());
+            for (slot, key) in input.buffer.iter().zip(keys.iter().rev()) {
+                prop_assert_eq!(slot, key);
+            }
+        }
+    }
+}
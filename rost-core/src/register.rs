@@ -0,0 +1,576 @@
+//! Device registers with a type and an access, and bitfields that give the bits names
+//!
+//! A register is defined once as a constant and read or written through a `PortIo` or a
+//! `Mmio`. Reading a write only register or writing a read only one doesn't compile.
+//!
+//! ```ignore
+//! bitfield! {
+//!     ///The status register of the 8042
+//!     pub struct Status(u8) {
+//!         pub const OUTPUT_FULL: bool = 0;
+//!         pub const INPUT_FULL: bool = 1;
+//!     }
+//! }
+//!
+//! const STATUS: PortRegister<Status, ReadOnly> = PortRegister::new(0x64);
+//!
+//! if STATUS.read(&mut ports).get(Status::OUTPUT_FULL) {
+//!     ...
+//! }
+//! ```
+
+use core::fmt;
+use core::marker::PhantomData;
+
+use crate::mmio::Mmio;
+use crate::port::PortIo;
+
+///An unsigned integer a register or a bitfield is made of
+pub trait Raw: Copy + Eq + fmt::Debug {
+    const BITS: u32;
+    fn to_u64(self) -> u64;
+    ///Cuts off the bits that don't fit
+    fn from_u64(value: u64) -> Self;
+}
+
+///The widths a port can be read and written with
+pub trait PortWidth: Raw {
+    fn read_port(io: &mut impl PortIo, port: u16) -> Self;
+    fn write_port(io: &mut impl PortIo, port: u16, value: Self);
+}
+
+pub trait MmioWidth: Raw {
+    fn read_mmio(mmio: &impl Mmio, offset: usize) -> Self;
+    fn write_mmio(mmio: &mut impl Mmio, offset: usize, value: Self);
+}
+
+macro_rules! raw {
+    ($($raw:ty),*) => {
+        $(
+            impl Raw for $raw {
+                const BITS: u32 = <$raw>::BITS;
+
+                fn to_u64(self) -> u64 {
+                    self as u64
+                }
+
+                fn from_u64(value: u64) -> Self {
+                    value as $raw
+                }
+            }
+
+            impl RegisterValue for $raw {
+                type Raw = $raw;
+
+                fn from_raw(raw: $raw) -> Self {
+                    raw
+                }
+
+                fn to_raw(self) -> $raw {
+                    self
+                }
+            }
+
+            impl FieldValue for $raw {
+                fn from_bits(bits: u64) -> Self {
+                    bits as $raw
+                }
+
+                fn to_bits(self) -> u64 {
+                    self as u64
+                }
+            }
+        )*
+    };
+}
+
+raw!(u8, u16, u32, u64);
+
+macro_rules! port_width {
+    ($($raw:ty: $read:ident, $write:ident;)*) => {
+        $(
+            impl PortWidth for $raw {
+                fn read_port(io: &mut impl PortIo, port: u16) -> Self {
+                    io.$read(port)
+                }
+
+                fn write_port(io: &mut impl PortIo, port: u16, value: Self) {
+                    io.$write(port, value)
+                }
+            }
+        )*
+    };
+}
+
+port_width! {
+    u8: read_u8, write_u8;
+    u16: read_u16, write_u16;
+    u32: read_u32, write_u32;
+}
+
+macro_rules! mmio_width {
+    ($($raw:ty: $read:ident, $write:ident;)*) => {
+        $(
+            impl MmioWidth for $raw {
+                fn read_mmio(mmio: &impl Mmio, offset: usize) -> Self {
+                    mmio.$read(offset)
+                }
+
+                fn write_mmio(mmio: &mut impl Mmio, offset: usize, value: Self) {
+                    mmio.$write(offset, value)
+                }
+            }
+        )*
+    };
+}
+
+mmio_width! {
+    u8: read_u8, write_u8;
+    u16: read_u16, write_u16;
+    u32: read_u32, write_u32;
+    u64: read_u64, write_u64;
+}
+
+///What a register holds, a plain integer or a bitfield made with `bitfield!`
+pub trait RegisterValue: Copy {
+    type Raw: Raw;
+    fn from_raw(raw: Self::Raw) -> Self;
+    fn to_raw(self) -> Self::Raw;
+}
+
+///What a field of a bitfield holds
+pub trait FieldValue: Copy {
+    fn from_bits(bits: u64) -> Self;
+    fn to_bits(self) -> u64;
+}
+
+impl FieldValue for bool {
+    fn from_bits(bits: u64) -> Self {
+        bits != 0
+    }
+
+    fn to_bits(self) -> u64 {
+        self as u64
+    }
+}
+
+///The bits `low..=high` of the bitfield `B`, holding a `V`
+pub struct Field<B, V> {
+    low: u32,
+    high: u32,
+    _marker: PhantomData<fn() -> (B, V)>,
+}
+
+impl<B, V> Clone for Field<B, V> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<B, V> Copy for Field<B, V> {}
+
+impl<B, V> fmt::Debug for Field<B, V> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Field({}..={})", self.low, self.high)
+    }
+}
+
+impl<B, V: FieldValue> Field<B, V> {
+    pub const fn new(low: u32, high: u32) -> Field<B, V> {
+        assert!(
+            low <= high && high < 64,
+            "the bits of a field are out of order"
+        );
+        Field {
+            low,
+            high,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn low(&self) -> u32 {
+        self.low
+    }
+
+    pub const fn high(&self) -> u32 {
+        self.high
+    }
+
+    ///The bits of the field where they are in the bitfield
+    pub const fn mask(&self) -> u64 {
+        (u64::MAX >> (63 - (self.high - self.low))) << self.low
+    }
+
+    pub fn get(&self, raw: u64) -> V {
+        V::from_bits((raw & self.mask()) >> self.low)
+    }
+
+    ///`raw` with the field set to `value`, the bits of `value` that don't fit are left out
+    pub fn put(&self, raw: u64, value: V) -> u64 {
+        raw & !self.mask() | (value.to_bits() << self.low) & self.mask()
+    }
+}
+
+///Defines a register value with named fields, see the module documentation
+///
+///The struct gets `Debug`, `Default`, `Clone`, `Copy`, `PartialEq` and `Eq`, a field is
+///`pub const NAME: type = bit;` or `pub const NAME: type = low..=high;` with the type
+///`bool` or an unsigned integer.
+#[macro_export]
+macro_rules! bitfield {
+    (
+        $(#[$meta:meta])*
+        $vis:vis struct $name:ident($raw:ty) {
+            $(
+                $(#[$field_meta:meta])*
+                $field_vis:vis const $field:ident: $value:ty = $low:literal $(..= $high:literal)?;
+            )*
+        }
+    ) => {
+        $(#[$meta])*
+        #[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+        #[repr(transparent)]
+        $vis struct $name(pub $raw);
+
+        #[allow(dead_code)]
+        impl $name {
+            $(
+                $(#[$field_meta])*
+                $field_vis const $field: $crate::register::Field<$name, $value> =
+                    $crate::register::Field::new($low, $crate::__bitfield_high!($low $(, $high)?));
+            )*
+
+            pub fn get<V: $crate::register::FieldValue>(
+                self,
+                field: $crate::register::Field<$name, V>,
+            ) -> V {
+                field.get($crate::register::Raw::to_u64(self.0))
+            }
+
+            ///A copy with `field` set to `value`
+            pub fn with<V: $crate::register::FieldValue>(
+                self,
+                field: $crate::register::Field<$name, V>,
+                value: V,
+            ) -> $name {
+                let raw = field.put($crate::register::Raw::to_u64(self.0), value);
+                $name($crate::register::Raw::from_u64(raw))
+            }
+
+            pub fn set<V: $crate::register::FieldValue>(
+                &mut self,
+                field: $crate::register::Field<$name, V>,
+                value: V,
+            ) {
+                *self = self.with(field, value);
+            }
+        }
+
+        impl $crate::register::RegisterValue for $name {
+            type Raw = $raw;
+
+            fn from_raw(raw: $raw) -> Self {
+                $name(raw)
+            }
+
+            fn to_raw(self) -> $raw {
+                self.0
+            }
+        }
+    };
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __bitfield_high {
+    ($low:literal) => {
+        $low
+    };
+    ($low:literal, $high:literal) => {
+        $high
+    };
+}
+
+///Registers that can only be read
+#[derive(Debug)]
+pub struct ReadOnly;
+///Registers that can only be written, reading them gives something else or nothing
+#[derive(Debug)]
+pub struct WriteOnly;
+#[derive(Debug)]
+pub struct ReadWrite;
+
+pub trait Readable {}
+pub trait Writable {}
+
+impl Readable for ReadOnly {}
+impl Readable for ReadWrite {}
+impl Writable for WriteOnly {}
+impl Writable for ReadWrite {}
+
+///A register at an I/O port
+pub struct PortRegister<T, A = ReadWrite> {
+    port: u16,
+    _marker: PhantomData<fn() -> (T, A)>,
+}
+
+impl<T, A> Clone for PortRegister<T, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, A> Copy for PortRegister<T, A> {}
+
+impl<T, A> fmt::Debug for PortRegister<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "PortRegister({:#x})", self.port)
+    }
+}
+
+impl<T: RegisterValue, A> PortRegister<T, A>
+where
+    T::Raw: PortWidth,
+{
+    pub const fn new(port: u16) -> PortRegister<T, A> {
+        PortRegister {
+            port,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn port(&self) -> u16 {
+        self.port
+    }
+
+    ///How many ports the register takes up
+    pub const fn width(&self) -> u16 {
+        (<T::Raw as Raw>::BITS / 8) as u16
+    }
+}
+
+impl<T: RegisterValue, A: Readable> PortRegister<T, A>
+where
+    T::Raw: PortWidth,
+{
+    pub fn read(&self, io: &mut impl PortIo) -> T {
+        T::from_raw(T::Raw::read_port(io, self.port))
+    }
+}
+
+impl<T: RegisterValue, A: Writable> PortRegister<T, A>
+where
+    T::Raw: PortWidth,
+{
+    pub fn write(&self, io: &mut impl PortIo, value: T) {
+        T::Raw::write_port(io, self.port, value.to_raw())
+    }
+}
+
+impl<T: RegisterValue, A: Readable + Writable> PortRegister<T, A>
+where
+    T::Raw: PortWidth,
+{
+    ///Reads the register, changes the value and writes it back, returns what was written
+    pub fn modify(&self, io: &mut impl PortIo, f: impl FnOnce(T) -> T) -> T {
+        let value = f(self.read(io));
+        self.write(io, value);
+        value
+    }
+}
+
+///A register at an offset in a `Mmio` window
+pub struct MmioRegister<T, A = ReadWrite> {
+    offset: usize,
+    _marker: PhantomData<fn() -> (T, A)>,
+}
+
+impl<T, A> Clone for MmioRegister<T, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<T, A> Copy for MmioRegister<T, A> {}
+
+impl<T, A> fmt::Debug for MmioRegister<T, A> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "MmioRegister({:#x})", self.offset)
+    }
+}
+
+impl<T: RegisterValue, A> MmioRegister<T, A>
+where
+    T::Raw: MmioWidth,
+{
+    pub const fn new(offset: usize) -> MmioRegister<T, A> {
+        MmioRegister {
+            offset,
+            _marker: PhantomData,
+        }
+    }
+
+    pub const fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<T: RegisterValue, A: Readable> MmioRegister<T, A>
+where
+    T::Raw: MmioWidth,
+{
+    pub fn read(&self, mmio: &impl Mmio) -> T {
+        T::from_raw(T::Raw::read_mmio(mmio, self.offset))
+    }
+}
+
+impl<T: RegisterValue, A: Writable> MmioRegister<T, A>
+where
+    T::Raw: MmioWidth,
+{
+    pub fn write(&self, mmio: &mut impl Mmio, value: T) {
+        T::Raw::write_mmio(mmio, self.offset, value.to_raw())
+    }
+}
+
+impl<T: RegisterValue, A: Readable + Writable> MmioRegister<T, A>
+where
+    T::Raw: MmioWidth,
+{
+    ///Reads the register, changes the value and writes it back, returns what was written
+    pub fn modify(&self, mmio: &mut impl Mmio, f: impl FnOnce(T) -> T) -> T {
+        let value = f(self.read(mmio));
+        self.write(mmio, value);
+        value
+    }
+}
+
+///A bank of byte registers behind two ports, writing the index port picks which register
+///the data port reads and writes. The vga card and the cmos are made of these.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct IndexedPorts {
+    pub index: u16,
+    pub data: u16,
+}
+
+impl IndexedPorts {
+    pub const fn new(index: u16, data: u16) -> IndexedPorts {
+        IndexedPorts { index, data }
+    }
+
+    pub fn read(&self, io: &mut impl PortIo, index: u8) -> u8 {
+        io.write_u8(self.index, index);
+        io.read_u8(self.data)
+    }
+
+    pub fn write(&self, io: &mut impl PortIo, index: u8, value: u8) {
+        io.write_u8(self.index, index);
+        io.write_u8(self.data, value);
+    }
+
+    pub fn modify(&self, io: &mut impl PortIo, index: u8, f: impl FnOnce(u8) -> u8) -> u8 {
+        let value = f(self.read(io, index));
+        self.write(io, index, value);
+        value
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{Access, MockMmio, MockPorts};
+    use proptest::prelude::*;
+
+    crate::bitfield! {
+        struct Example(u8) {
+            const FLAG: bool = 0;
+            const MODE: u8 = 1..=3;
+            const TOP: u8 = 7;
+        }
+    }
+
+    const STATUS: PortRegister<Example, ReadOnly> = PortRegister::new(0x64);
+    const COMMAND: PortRegister<Example, WriteOnly> = PortRegister::new(0x64);
+    const WIDE: PortRegister<u32> = PortRegister::new(0xCFC);
+    const CONTROL: MmioRegister<u32> = MmioRegister::new(8);
+
+    #[test]
+    fn fields() {
+        let example = Example(0b1000_1011);
+        assert!(example.get(Example::FLAG));
+        assert_eq!(example.get(Example::MODE), 0b101);
+        assert_eq!(example.get(Example::TOP), 1);
+
+        let example = example
+            .with(Example::MODE, 0b010)
+            .with(Example::FLAG, false);
+        assert_eq!(example, Example(0b1000_0100));
+        assert_eq!(Example::MODE.mask(), 0b1110);
+        assert_eq!(format!("{:?}", Example::MODE), "Field(1..=3)");
+    }
+
+    #[test]
+    fn port_registers() {
+        let mut ports = MockPorts::new();
+        ports.queue_read(0x64, 0x0D);
+        assert_eq!(STATUS.read(&mut ports).get(Example::MODE), 0b110);
+        COMMAND.write(&mut ports, Example::default().with(Example::TOP, 1));
+        WIDE.modify(&mut ports, |value| value | 0x8000_0000);
+        assert_eq!(WIDE.width(), 4);
+        assert!(ports.log().eq([
+            Access::Read {
+                port: 0x64,
+                value: 0x0D
+            },
+            Access::Write {
+                port: 0x64,
+                value: 0x80
+            },
+            Access::Read {
+                port: 0xCFC,
+                value: 0
+            },
+            Access::Write {
+                port: 0xCFC,
+                value: 0x8000_0000
+            },
+        ]));
+    }
+
+    #[test]
+    fn mmio_registers() {
+        let mut mmio = MockMmio::<16>::new();
+        CONTROL.write(&mut mmio, 0x1234_5678);
+        assert_eq!(mmio.read_u8(8), 0x78);
+        assert_eq!(CONTROL.modify(&mut mmio, |value| value >> 4), 0x0123_4567);
+        assert_eq!(CONTROL.offset(), 8);
+    }
+
+    #[test]
+    fn indexed_ports() {
+        let crtc = IndexedPorts::new(0x3D4, 0x3D5);
+        let mut ports = MockPorts::new().with_indexed(0x3D4, 0x3D5);
+        crtc.write(&mut ports, 0x0E, 0x12);
+        crtc.modify(&mut ports, 0x0E, |value| value + 1);
+        assert_eq!(crtc.read(&mut ports, 0x0E), 0x13);
+        assert_eq!(ports.indexed_register(0x0E), 0x13);
+    }
+
+    proptest! {
+        #[test]
+        fn a_field_only_changes_its_own_bits(
+            raw in any::<u64>(),
+            value in any::<u64>(),
+            low in 0u32..64,
+            width in 0u32..64,
+        ) {
+            let high = (low + width).min(63);
+            let field = Field::<(), u64>::new(low, high);
+            let changed = field.put(raw, value);
+            prop_assert_eq!(changed & !field.mask(), raw & !field.mask());
+            let bits = high - low + 1;
+            let kept = if bits == 64 { value } else { value & ((1 << bits) - 1) };
+            prop_assert_eq!(field.get(changed), kept);
+        }
+    }
+}
@@ -0,0 +1,252 @@
+//! Who owns which I/O ports, IRQ lines and memory mapped regions
+//!
+//! A driver claims what it uses before touching it, a claim that overlaps one made
+//! earlier fails and names the owner. The kernel keeps one `ResourceTable` for the
+//! whole machine.
+
+use core::fmt;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Resource {
+    ///`count` ports starting at `start`
+    Ports { start: u16, count: u16 },
+    ///A line of the PIC
+    Irq(u8),
+    ///`size` bytes of physical memory starting at `start`
+    Memory { start: u64, size: u64 },
+}
+
+impl Resource {
+    pub const fn ports(start: u16, count: u16) -> Resource {
+        Resource::Ports { start, count }
+    }
+
+    pub const fn memory(start: u64, size: u64) -> Resource {
+        Resource::Memory { start, size }
+    }
+
+    ///Whether both take up some of the same ports, line or memory
+    pub fn overlaps(&self, other: &Resource) -> bool {
+        let (kind, start, size) = self.extent();
+        let (other_kind, other, others) = other.extent();
+        //empty ranges take up nothing and overlap nothing
+        kind == other_kind
+            && size != 0
+            && others != 0
+            && start < other.saturating_add(others)
+            && other < start.saturating_add(size)
+    }
+
+    ///Whether all of `other` is inside this one
+    pub fn contains(&self, other: &Resource) -> bool {
+        let (kind, start, size) = self.extent();
+        let (other_kind, other, others) = other.extent();
+        kind == other_kind
+            && start <= other
+            && other.saturating_add(others) <= start.saturating_add(size)
+    }
+
+    //ports, lines and memory are counted separately, an irq is a range of one line
+    fn extent(&self) -> (u8, u64, u64) {
+        match *self {
+            Resource::Ports { start, count } => (0, start.into(), count.into()),
+            Resource::Irq(line) => (1, line.into(), 1),
+            Resource::Memory { start, size } => (2, start, size),
+        }
+    }
+}
+
+impl fmt::Display for Resource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Resource::Ports { start, count: 1 } => write!(f, "port {:#06x}", start),
+            Resource::Ports { start, count } => write!(
+                f,
+                "ports {:#06x}-{:#06x}",
+                start,
+                u32::from(start) + u32::from(count).max(1) - 1
+            ),
+            Resource::Irq(line) => write!(f, "irq {}", line),
+            Resource::Memory { start, size } => write!(
+                f,
+                "memory {:#x}-{:#x}",
+                start,
+                start.saturating_add(size.max(1) - 1)
+            ),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Claim {
+    pub owner: &'static str,
+    pub resource: Resource,
+}
+
+///Names a claim so it can be given back, only the table that made it knows it
+#[derive(Debug, PartialEq, Eq)]
+pub struct ClaimId(usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ResourceError {
+    ///Some of what was asked for already belongs to `claim`
+    Conflict { wanted: Claim, owned: Claim },
+    ///The table has no room for another claim
+    Full,
+}
+
+impl fmt::Display for ResourceError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ResourceError::Conflict { wanted, owned } => write!(
+                f,
+                "{} wants {} but {} already has {}",
+                wanted.owner, wanted.resource, owned.owner, owned.resource
+            ),
+            ResourceError::Full => write!(f, "there is no room for another claim"),
+        }
+    }
+}
+
+pub struct ResourceTable<const N: usize> {
+    claims: [Option<Claim>; N],
+}
+
+impl<const N: usize> ResourceTable<N> {
+    pub const fn new() -> ResourceTable<N> {
+        ResourceTable { claims: [None; N] }
+    }
+
+    ///Gives `resource` to `owner` unless someone already has some of it
+    pub fn claim(
+        &mut self,
+        owner: &'static str,
+        resource: Resource,
+    ) -> Result<ClaimId, ResourceError> {
+        let wanted = Claim { owner, resource };
+        if let Some(owned) = self.iter().find(|claim| claim.resource.overlaps(&resource)) {
+            return Err(ResourceError::Conflict { wanted, owned });
+        }
+        let slot = self
+            .claims
+            .iter()
+            .position(|slot| slot.is_none())
+            .ok_or(ResourceError::Full)?;
+        self.claims[slot] = Some(wanted);
+        Ok(ClaimId(slot))
+    }
+
+    ///Gives a claim back, what it had can be claimed again
+    pub fn release(&mut self, id: ClaimId) -> Claim {
+        self.claims[id.0]
+            .take()
+            .expect("the claim was released twice")
+    }
+
+    pub fn get(&self, id: &ClaimId) -> Claim {
+        self.claims[id.0].expect("the claim was released")
+    }
+
+    ///The claim that has some of `resource`, if there is one
+    pub fn owner_of(&self, resource: &Resource) -> Option<Claim> {
+        self.iter().find(|claim| claim.resource.overlaps(resource))
+    }
+
+    ///The claims in no particular order
+    pub fn iter(&self) -> impl Iterator<Item = Claim> + '_ {
+        self.claims.iter().flatten().copied()
+    }
+
+    pub fn len(&self) -> usize {
+        self.iter().count()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl<const N: usize> Default for ResourceTable<N> {
+    fn default() -> Self {
+        ResourceTable::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use proptest::prelude::*;
+
+    #[test]
+    fn claims_conflict_until_released() {
+        let mut table = ResourceTable::<3>::new();
+        let ps2 = table.claim("ps2", Resource::ports(0x60, 1)).unwrap();
+        table.claim("pit", Resource::ports(0x40, 4)).unwrap();
+        table.claim("keyboard", Resource::Irq(1)).unwrap();
+
+        let error = table.claim("mouse", Resource::ports(0x5E, 4)).unwrap_err();
+        assert_eq!(
+            error,
+            ResourceError::Conflict {
+                wanted: Claim {
+                    owner: "mouse",
+                    resource: Resource::ports(0x5E, 4)
+                },
+                owned: Claim {
+                    owner: "ps2",
+                    resource: Resource::ports(0x60, 1)
+                },
+            }
+        );
+        assert_eq!(
+            format!("{}", error),
+            "mouse wants ports 0x005e-0x0061 but ps2 already has port 0x0060"
+        );
+
+        assert_eq!(table.release(ps2).owner, "ps2");
+        table.claim("mouse", Resource::ports(0x5E, 4)).unwrap();
+        assert_eq!(
+            table.claim("serial", Resource::Irq(4)),
+            Err(ResourceError::Full)
+        );
+        assert_eq!(table.owner_of(&Resource::Irq(1)).unwrap().owner, "keyboard");
+        assert_eq!(table.len(), 3);
+    }
+
+    #[test]
+    fn kinds_never_overlap() {
+        let mut table = ResourceTable::<4>::new();
+        table.claim("pic", Resource::ports(0x20, 2)).unwrap();
+        table.claim("timer", Resource::Irq(0x20)).unwrap();
+        table.claim("vga", Resource::memory(0x20, 2)).unwrap();
+        assert!(table.claim("empty", Resource::ports(0x20, 0)).is_ok());
+    }
+
+    #[test]
+    fn display() {
+        assert_eq!(
+            format!("{}", Resource::memory(0xA0000, 0x20000)),
+            "memory 0xa0000-0xbffff"
+        );
+        assert_eq!(format!("{}", Resource::Irq(12)), "irq 12");
+        assert_eq!(format!("{}", Resource::ports(0xFFFF, 1)), "port 0xffff");
+    }
+
+    proptest! {
+        #[test]
+        fn overlapping_is_sharing_a_port(
+            start in any::<u16>(),
+            count in 0u16..64,
+            other in any::<u16>(),
+            others in 0u16..64,
+        ) {
+            let ports = |start: u16, count: u16| {
+                u32::from(start)..u32::from(start) + u32::from(count)
+            };
+            let shared = ports(start, count).any(|port| ports(other, others).contains(&port));
+            let (a, b) = (Resource::ports(start, count), Resource::ports(other, others));
+            prop_assert_eq!(a.overlaps(&b), shared);
+            prop_assert_eq!(b.overlaps(&a), shared);
+        }
+    }
+}
@@ -0,0 +1,572 @@
+//! The vga text mode without the card: colors, the writer and the cursor math
+//!
+//! `TextWriter` writes to anything implementing `TextBuffer`, which for the kernel is the
+//! text buffer at 0xb8000. `Crtc` reads and moves the cursor through a `PortIo`.
+
+use core::fmt;
+
+use crate::port::PortIo;
+use crate::register::IndexedPorts;
+
+// 4 bit color table (using u8 since no u4 exsists)
+#[allow(dead_code)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Color {
+    Black = 0,
+    Blue = 1,
+    Green = 2,
+    Cyan = 3,
+    Red = 4,
+    Magenta = 5,
+    Brown = 6,
+    LightGray = 7,
+    DarkGray = 8,
+    LightBlue = 9,
+    LightGreen = 10,
+    LightCyan = 11,
+    LightRed = 12,
+    Pink = 13,
+    Yellow = 14,
+    White = 15,
+}
+
+impl Color {
+    ///The color of a 4 bit color index, the upper bits are ignored
+    pub fn from_index(index: u8) -> Color {
+        match index & 0x0F {
+            0 => Color::Black,
+            1 => Color::Blue,
+            2 => Color::Green,
+            3 => Color::Cyan,
+            4 => Color::Red,
+            5 => Color::Magenta,
+            6 => Color::Brown,
+            7 => Color::LightGray,
+            8 => Color::DarkGray,
+            9 => Color::LightBlue,
+            10 => Color::LightGreen,
+            11 => Color::LightCyan,
+            12 => Color::LightRed,
+            13 => Color::Pink,
+            14 => Color::Yellow,
+            _ => Color::White,
+        }
+    }
+}
+
+//A text character color code repsented as an u8 containing both the foreground and background color
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(transparent)]
+pub struct ColorCode(pub u8);
+
+impl ColorCode {
+    pub fn new(foreground: Color, background: Color) -> ColorCode {
+        ColorCode((background as u8) << 4 | (foreground as u8))
+    }
+
+    ///Packs a style, bit 7 is the blink bit while blinking is enabled and bit 3 is the intensity bit
+    pub fn from_style(style: TextStyle) -> ColorCode {
+        let mut foreground = style.foreground as u8;
+        let mut background = style.background as u8;
+
+        if style.underline {
+            // the card only underlines characters that look like the monochrome underline attribute
+            foreground = (foreground & 0x08) | 0x01;
+            background &= 0x08;
+        }
+        if style.bright {
+            foreground |= 0x08;
+        }
+        if style.blink {
+            background |= 0x08;
+        }
+
+        ColorCode(background << 4 | foreground)
+    }
+
+    pub fn foreground(self) -> Color {
+        Color::from_index(self.0)
+    }
+
+    pub fn background(self) -> Color {
+        Color::from_index(self.0 >> 4)
+    }
+
+    ///The same colors with a different foreground
+    pub fn with_foreground(self, foreground: Color) -> ColorCode {
+        ColorCode(self.0 & 0xF0 | foreground as u8)
+    }
+
+    ///Swaps the foreground and the background
+    pub fn inverted(self) -> ColorCode {
+        ColorCode(self.0.rotate_left(4))
+    }
+}
+
+///Colors together with the attribute bits the card can interpret in different ways
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TextStyle {
+    pub foreground: Color,
+    pub background: Color,
+    ///Forces the bright version of the foreground color
+    pub bright: bool,
+    ///Only blinks while `set_blink_enabled(true)`, it takes the place of the bright background bit
+    pub blink: bool,
+    ///Uses the monochrome underline attribute, which shows with the foreground of `Color::Blue`
+    ///(or `Color::LightBlue` when bright), only while `set_underline_enabled(true)`
+    pub underline: bool,
+}
+
+impl TextStyle {
+    pub const fn new(foreground: Color, background: Color) -> TextStyle {
+        TextStyle {
+            foreground,
+            background,
+            bright: false,
+            blink: false,
+            underline: false,
+        }
+    }
+
+    pub const fn bright(mut self) -> TextStyle {
+        self.bright = true;
+        self
+    }
+
+    pub const fn blink(mut self) -> TextStyle {
+        self.blink = true;
+        self
+    }
+
+    pub const fn underline(mut self) -> TextStyle {
+        self.underline = true;
+        self
+    }
+}
+
+//A vga text mode character with a colour and character code
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(C)]
+pub struct ScreenChar {
+    pub ascii_character: u8,
+    pub color_code: ColorCode,
+}
+
+///A position on the text screen given as (row, column)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Point(pub usize, pub usize);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CursorPosition {
+    pub x: u8,
+    pub y: u8,
+}
+
+///The cursor position of the offset the card keeps the cursor as, counted in characters
+///from the top left corner
+pub fn cursor_position(offset: u16, width: usize) -> CursorPosition {
+    let width = width as u16;
+    CursorPosition {
+        x: (offset % width) as u8,
+        y: (offset / width) as u8,
+    }
+}
+
+pub fn cursor_offset(position: CursorPosition, width: usize) -> u16 {
+    position.y as u16 * width as u16 + position.x as u16
+}
+
+///Where `move_cursor_by` puts the cursor, going left of the row wraps it around to the end
+pub fn moved_cursor(position: CursorPosition, x: i8, y: i8, width: usize) -> CursorPosition {
+    let mut new_x = position.x as i16 + x as i16;
+    let new_y = position.y as i16 + y as i16;
+
+    let width = width as i16;
+    if new_x < 0 || new_x > width + 1 {
+        new_x += width;
+    }
+
+    CursorPosition {
+        x: new_x as u8,
+        y: new_y as u8,
+    }
+}
+
+///Character cells laid out in rows, like the text buffer of the card
+pub trait TextBuffer {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+    fn read(&self, row: usize, col: usize) -> ScreenChar;
+    fn write(&mut self, row: usize, col: usize, character: ScreenChar);
+
+    fn contains(&self, row: usize, col: usize) -> bool {
+        row < self.height() && col < self.width()
+    }
+}
+
+///Writes text to the bottom row of a buffer and scrolls everything up at the end of a line
+pub struct TextWriter<B> {
+    buffer: B,
+    column_position: usize,
+    color_code: ColorCode,
+}
+
+impl<B: TextBuffer> TextWriter<B> {
+    pub const fn new(buffer: B, color_code: ColorCode) -> TextWriter<B> {
+        TextWriter {
+            buffer,
+            column_position: 0,
+            color_code,
+        }
+    }
+
+    pub fn buffer(&self) -> &B {
+        &self.buffer
+    }
+
+    ///Changing the size of the buffer should be followed by `clear`
+    pub fn buffer_mut(&mut self) -> &mut B {
+        &mut self.buffer
+    }
+
+    ///The column the next character goes to, it may be one past the last column
+    pub fn column_position(&self) -> usize {
+        self.column_position
+    }
+
+    pub fn color_code(&self) -> ColorCode {
+        self.color_code
+    }
+
+    pub fn set_color_code(&mut self, color_code: ColorCode) {
+        self.color_code = color_code;
+    }
+
+    ///The width of the screen in characters
+    pub fn width(&self) -> usize {
+        self.buffer.width()
+    }
+
+    ///The height of the screen in characters
+    pub fn height(&self) -> usize {
+        self.buffer.height()
+    }
+
+    ///Writes a single character to a point on the screen, points outside the screen are ignored
+    pub fn write_char_at(&mut self, point: Point, character: u8, color_code: ColorCode) {
+        if !self.buffer.contains(point.0, point.1) {
+            return;
+        }
+
+        self.buffer.write(
+            point.0,
+            point.1,
+            ScreenChar {
+                ascii_character: character,
+                color_code,
+            },
+        )
+    }
+
+    ///Returns the character currently shown at a point on the screen
+    pub fn char_at(&self, point: Point) -> Option<u8> {
+        if !self.buffer.contains(point.0, point.1) {
+            return None;
+        }
+
+        Some(self.buffer.read(point.0, point.1).ascii_character)
+    }
+
+    ///Returns the raw attribute byte of a point on the screen, background in the high nibble
+    pub fn attribute_at(&self, point: Point) -> Option<u8> {
+        if !self.buffer.contains(point.0, point.1) {
+            return None;
+        }
+
+        Some(self.buffer.read(point.0, point.1).color_code.0)
+    }
+
+    ///Swaps the foreground and background of a point on the screen, doing it twice puts the colors back
+    pub fn invert_at(&mut self, point: Point) {
+        if !self.buffer.contains(point.0, point.1) {
+            return;
+        }
+
+        let mut char = self.buffer.read(point.0, point.1);
+        char.color_code = char.color_code.inverted();
+        self.buffer.write(point.0, point.1, char);
+    }
+
+    ///Gives every character on the screen the same colors, the text stays
+    pub fn change_screen_color(&mut self, color_code: ColorCode) {
+        for row in 0..self.buffer.height() {
+            for col in 0..self.buffer.width() {
+                let char = self.buffer.read(row, col);
+                self.buffer.write(
+                    row,
+                    col,
+                    ScreenChar {
+                        ascii_character: char.ascii_character,
+                        color_code,
+                    },
+                )
+            }
+        }
+    }
+
+    // The handler for writing text
+    pub fn write_byte(&mut self, byte: u8) {
+        match byte {
+            b'\n' => self.new_line(),
+            byte => {
+                if self.column_position >= self.buffer.width() {
+                    self.new_line();
+                }
+
+                let row = self.buffer.height() - 1;
+                let col = self.column_position;
+
+                let color_code = self.color_code;
+                self.buffer.write(
+                    row,
+                    col,
+                    ScreenChar {
+                        ascii_character: byte,
+                        color_code,
+                    },
+                );
+                self.column_position += 1;
+            }
+        }
+    }
+
+    ///Writes a string to the bottom of the screen
+    pub fn write_string(&mut self, s: &str) {
+        for byte in s.bytes() {
+            match byte {
+                // printable ASCII byte or newline
+                0x20..=0x7e | b'\n' => self.write_byte(byte),
+                // like on a terminal backspace only moves left and carriage return goes to the start of the line
+                0x08 => self.column_position = self.column_position.saturating_sub(1),
+                b'\r' => self.column_position = 0,
+                // not part of printable ASCII range
+                _ => self.write_byte(0xfe),
+            }
+        }
+    }
+
+    ///Scrolls everything up a row and starts over on the blank bottom row
+    pub fn new_line(&mut self) {
+        for row in 1..self.buffer.height() {
+            for col in 0..self.buffer.width() {
+                let character = self.buffer.read(row, col);
+                self.buffer.write(row - 1, col, character);
+            }
+        }
+        self.clear_row(self.buffer.height() - 1);
+        self.column_position = 0;
+    }
+
+    ///Blanks the whole screen and starts writing from the beginning of the bottom row
+    pub fn clear(&mut self) {
+        for row in 0..self.buffer.height() {
+            self.clear_row(row);
+        }
+        self.column_position = 0;
+    }
+
+    pub fn clear_row(&mut self, row: usize) {
+        let blank = ScreenChar {
+            ascii_character: b' ',
+            color_code: self.color_code,
+        };
+        for col in 0..self.buffer.width() {
+            self.buffer.write(row, col, blank);
+        }
+    }
+}
+
+impl<B: TextBuffer> fmt::Write for TextWriter<B> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.write_string(s);
+        Ok(())
+    }
+}
+
+///The cathode ray tube controller of the card, which among other things keeps the cursor
+pub struct Crtc<P> {
+    ports: P,
+}
+
+impl<P: PortIo> Crtc<P> {
+    pub const REGISTERS: IndexedPorts = IndexedPorts::new(0x3D4, 0x3D5);
+    //the high and the low byte of the cursor offset
+    const CURSOR_HIGH: u8 = 0x0E;
+    const CURSOR_LOW: u8 = 0x0F;
+
+    pub const fn new(ports: P) -> Crtc<P> {
+        Crtc { ports }
+    }
+
+    pub fn ports(&mut self) -> &mut P {
+        &mut self.ports
+    }
+
+    ///Writes to a specified crtc data bus
+    pub fn write(&mut self, index: u8, value: u8) {
+        Self::REGISTERS.write(&mut self.ports, index, value)
+    }
+
+    ///Reads a specified crtc data bus and returns the value
+    pub fn read(&mut self, index: u8) -> u8 {
+        Self::REGISTERS.read(&mut self.ports, index)
+    }
+
+    pub fn cursor_offset(&mut self) -> u16 {
+        u16::from_be_bytes([self.read(Self::CURSOR_HIGH), self.read(Self::CURSOR_LOW)])
+    }
+
+    pub fn set_cursor_offset(&mut self, offset: u16) {
+        let [high, low] = offset.to_be_bytes();
+        self.write(Self::CURSOR_HIGH, high);
+        self.write(Self::CURSOR_LOW, low);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockPorts, MockTextBuffer};
+    use core::fmt::Write;
+    use proptest::prelude::*;
+
+    fn writer(width: usize, height: usize) -> TextWriter<MockTextBuffer> {
+        TextWriter::new(
+            MockTextBuffer::new(width, height),
+            ColorCode::new(Color::White, Color::Black),
+        )
+    }
+
+    #[test]
+    fn style_packing() {
+        let style = TextStyle::new(Color::Green, Color::Red);
+        assert_eq!(
+            ColorCode::from_style(style),
+            ColorCode::new(Color::Green, Color::Red)
+        );
+        assert_eq!(ColorCode::from_style(style.bright().blink()).0, 0xCA);
+        assert_eq!(
+            ColorCode::from_style(TextStyle::new(Color::Yellow, Color::Blue).underline()).0,
+            0x09
+        );
+    }
+
+    #[test]
+    fn writing_wraps_and_scrolls() {
+        let mut writer = writer(4, 3);
+        write!(writer, "abcdef\ngh\x01").unwrap();
+        assert_eq!(writer.buffer().row_text(0), "abcd");
+        assert_eq!(writer.buffer().row_text(1), "ef  ");
+        assert_eq!(writer.buffer().row_text(2), "gh\u{fe} ");
+        assert_eq!(writer.column_position(), 3);
+
+        write!(writer, "\r\x08x").unwrap();
+        assert_eq!(writer.buffer().row_text(2), "xh\u{fe} ");
+    }
+
+    #[test]
+    fn points_outside_are_ignored() {
+        let mut writer = writer(4, 3);
+        writer.write_char_at(Point(3, 0), b'x', ColorCode(0x12));
+        writer.invert_at(Point(0, 4));
+        assert_eq!(writer.char_at(Point(3, 0)), None);
+        assert_eq!(writer.attribute_at(Point(0, 4)), None);
+
+        writer.write_char_at(Point(2, 3), b'x', ColorCode(0x12));
+        writer.invert_at(Point(2, 3));
+        assert_eq!(writer.char_at(Point(2, 3)), Some(b'x'));
+        assert_eq!(writer.attribute_at(Point(2, 3)), Some(0x21));
+    }
+
+    #[test]
+    fn crtc_cursor() {
+        let mut crtc = Crtc::new(MockPorts::new().with_indexed(0x3D4, 0x3D5));
+        crtc.set_cursor_offset(0x0123);
+        assert_eq!(crtc.cursor_offset(), 0x0123);
+        assert_eq!(crtc.ports().indexed_register(0x0E), 0x01);
+        assert_eq!(crtc.ports().indexed_register(0x0F), 0x23);
+    }
+
+    #[test]
+    fn moving_the_cursor_wraps() {
+        let start = CursorPosition { x: 0, y: 5 };
+        assert_eq!(
+            moved_cursor(start, -1, 0, 80),
+            CursorPosition { x: 79, y: 5 }
+        );
+        assert_eq!(
+            moved_cursor(start, 3, -1, 80),
+            CursorPosition { x: 3, y: 4 }
+        );
+        assert_eq!(
+            moved_cursor(CursorPosition { x: 78, y: 5 }, 1, 0, 80),
+            CursorPosition { x: 79, y: 5 }
+        );
+    }
+
+    fn color() -> impl Strategy<Value = Color> {
+        (0u8..16).prop_map(Color::from_index)
+    }
+
+    proptest! {
+        #[test]
+        fn color_code_round_trips(foreground in color(), background in color()) {
+            let code = ColorCode::new(foreground, background);
+            prop_assert_eq!(code.foreground(), foreground);
+            prop_assert_eq!(code.background(), background);
+            prop_assert_eq!(code.inverted(), ColorCode::new(background, foreground));
+            prop_assert_eq!(code.inverted().inverted(), code);
+            prop_assert_eq!(code.with_foreground(background).background(), background);
+        }
+
+        #[test]
+        fn plain_style_packs_like_new(foreground in color(), background in color()) {
+            prop_assert_eq!(
+                ColorCode::from_style(TextStyle::new(foreground, background)),
+                ColorCode::new(foreground, background)
+            );
+        }
+
+        #[test]
+        fn cursor_math_round_trips(width in 1usize..=90, row in 0u16..50, col in any::<u16>()) {
+            let offset = row * width as u16 + col % width as u16;
+            let position = cursor_position(offset, width);
+            prop_assert_eq!(position.y as u16, row);
+            prop_assert!((position.x as usize) < width);
+            prop_assert_eq!(cursor_offset(position, width), offset);
+        }
+
+        #[test]
+        fn the_bottom_row_ends_with_what_was_written(
+            text in "[ -~]{0,300}",
+            width in 1usize..=90,
+            height in 1usize..=50,
+        ) {
+            let mut writer = writer(width, height);
+            writer.write_string(&text);
+
+            //the characters fill whole rows from the left, the last ones on the bottom row
+            let on_screen = (height - 1) * width + writer.column_position();
+            let shown = &text[text.len().saturating_sub(on_screen)..];
+            let mut cells = shown.bytes();
+            let skipped = on_screen - shown.len();
+            for i in 0..on_screen {
+                let point = Point(i / width, i % width);
+                let expected = if i < skipped { b' ' } else { cells.next().unwrap() };
+                prop_assert_eq!(writer.char_at(point), Some(expected));
+            }
+            prop_assert!(writer.column_position() <= width);
+        }
+    }
+}
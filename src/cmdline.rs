@@ -0,0 +1,66 @@
+//! The kernel command line, words separated by whitespace
+//!
+//! The bootloader has no way of passing one, so it is read from the fw_cfg file
+//! `opt/rost/cmdline`. With QEMU that is
+//! `-fw_cfg name=opt/rost/cmdline,string=words`, with commas in the words doubled.
+
+use spin::Once;
+
+pub const FW_CFG_FILE: &str = "opt/rost/cmdline";
+///Longer command lines are cut off at a character boundary
+pub const MAX_LENGTH: usize = 512;
+
+static CMDLINE: Once<Cmdline> = Once::new();
+
+struct Cmdline {
+    bytes: [u8; MAX_LENGTH],
+    len: usize,
+}
+
+impl Cmdline {
+    fn read() -> Cmdline {
+        let mut cmdline = Cmdline {
+            bytes: [0; MAX_LENGTH],
+            len: 0,
+        };
+        if let Some(file) = crate::fw_cfg::find(FW_CFG_FILE) {
+            cmdline.len = crate::fw_cfg::read(file, &mut cmdline.bytes);
+        }
+        cmdline
+    }
+
+    fn as_str(&self) -> &str {
+        let bytes = &self.bytes[..self.len];
+        let text = match core::str::from_utf8(bytes) {
+            Ok(text) => text,
+            //only the part before a character that got cut in half
+            Err(error) => core::str::from_utf8(&bytes[..error.valid_up_to()]).unwrap_or(""),
+        };
+        text.trim_end_matches('\0')
+    }
+}
+
+///The whole command line, empty if there is none
+pub fn get() -> &'static str {
+    CMDLINE.call_once(Cmdline::read).as_str()
+}
+
+pub fn args() -> impl Iterator<Item = &'static str> {
+    get().split_whitespace()
+}
+
+#[test_case]
+fn test_cmdline_text() {
+    let mut cmdline = Cmdline {
+        bytes: [0; MAX_LENGTH],
+        len: 0,
+    };
+    let text = "--list ü\0".as_bytes();
+    cmdline.bytes[..text.len()].copy_from_slice(text);
+    cmdline.len = text.len();
+    assert_eq!(cmdline.as_str(), "--list ü");
+
+    //the second byte of the ü is cut off
+    cmdline.len = 8;
+    assert_eq!(cmdline.as_str(), "--list ");
+}
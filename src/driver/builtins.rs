@@ -0,0 +1,379 @@
+//! The drivers the kernel comes with
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::{Driver, DriverError};
+use crate::ps2::{self, Channel};
+use crate::resource::{IrqLine, PortRange, PortSet, Reservation, Resource};
+use crate::serial::uart::ComPort;
+use crate::{
+    fw_cfg, interrupts, keyboard, mouse, pc_speaker, pci, pit, rtc, serial, vga_driver, watchdog,
+};
+
+pub const BUILTINS: [&dyn Driver; 14] = [
+    &Platform, &Isa, &Pci, &Pic, &Pit, &PcSpeaker, &Vga, &Com1, &Ps2, &Mouse, &Rtc, &FwCfg,
+    &Watchdog, &QemuExit,
+];
+
+//the registers are claimed by whatever prints to COM1 first
+const COM1_RESOURCES: [Resource; 1] = [Resource::Irq(ComPort::Com1.irq())];
+
+//the guards of what a driver lists in `resources` are always in its reservation
+fn take_ports(resources: &mut Reservation, start: u16) -> Result<PortRange, DriverError> {
+    resources
+        .take_ports(start)
+        .ok_or(DriverError::Failed("the ports weren't reserved"))
+}
+
+fn irq(resources: &Reservation, line: u8) -> Result<&IrqLine, DriverError> {
+    resources
+        .irq(line)
+        .ok_or(DriverError::Failed("the irq line wasn't reserved"))
+}
+
+///The root of the tree, the processor and whatever is always there
+struct Platform;
+
+impl Driver for Platform {
+    fn name(&self) -> &'static str {
+        "platform"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        None
+    }
+
+    fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+        Ok(())
+    }
+}
+
+///The legacy devices at their fixed ports
+struct Isa;
+
+impl Driver for Isa {
+    fn name(&self) -> &'static str {
+        "isa"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("platform")
+    }
+
+    fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+        Ok(())
+    }
+}
+
+struct Pci;
+
+impl Driver for Pci {
+    fn name(&self) -> &'static str {
+        "pci"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("platform")
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &pci::RESOURCES
+    }
+
+    fn probe(&self, resources: &mut Reservation) -> bool {
+        resources.ports(0xCF8).is_some_and(pci::is_present)
+    }
+
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        pci::init(take_ports(resources, 0xCF8)?);
+        Ok(())
+    }
+}
+
+struct Pic;
+
+impl Driver for Pic {
+    fn name(&self) -> &'static str {
+        "pic"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("platform")
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &interrupts::RESOURCES
+    }
+
+    fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+        unsafe { interrupts::PICS.lock().initialize() };
+        Ok(())
+    }
+}
+
+struct Pit;
+
+impl Driver for Pit {
+    fn name(&self) -> &'static str {
+        "pit"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("platform")
+    }
+
+    fn depends_on(&self) -> &'static [&'static str] {
+        &["pic"]
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &pit::RESOURCES
+    }
+
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        let channels = take_ports(resources, 0x40)?;
+        let speaker_control = take_ports(resources, 0x61)?;
+        pit::init(PortSet::new([channels, speaker_control]));
+        Ok(())
+    }
+}
+
+///Plays through the second channel of the pit, whose ports the pit already has
+struct PcSpeaker;
+
+impl Driver for PcSpeaker {
+    fn name(&self) -> &'static str {
+        "pc speaker"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("platform")
+    }
+
+    fn depends_on(&self) -> &'static [&'static str] {
+        &["pit"]
+    }
+
+    fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        pc_speaker::stop();
+        pc_speaker::stop_sound();
+    }
+}
+
+struct Vga;
+
+impl Driver for Vga {
+    fn name(&self) -> &'static str {
+        "vga"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("isa")
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &vga_driver::RESOURCES
+    }
+
+    fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+        vga_driver::init();
+        Ok(())
+    }
+}
+
+struct Com1;
+
+impl Driver for Com1 {
+    fn name(&self) -> &'static str {
+        ComPort::Com1.name()
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("isa")
+    }
+
+    fn depends_on(&self) -> &'static [&'static str] {
+        &["pic"]
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &COM1_RESOURCES
+    }
+
+    fn probe(&self, _: &mut Reservation) -> bool {
+        serial::is_present()
+    }
+
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        serial::init(irq(resources, ComPort::Com1.irq())?);
+        Ok(())
+    }
+
+    fn shutdown(&self) {
+        without_interrupts(|| serial::SERIAL1.lock().disable_interrupts());
+    }
+}
+
+///The controller and the keyboard on its first port
+struct Ps2;
+
+impl Driver for Ps2 {
+    fn name(&self) -> &'static str {
+        "ps2"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("isa")
+    }
+
+    fn depends_on(&self) -> &'static [&'static str] {
+        &["pic"]
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &ps2::RESOURCES
+    }
+
+    fn probe(&self, resources: &mut Reservation) -> bool {
+        resources.ports(0x64).is_some_and(ps2::is_present)
+    }
+
+    ///Switches the keyboard to scancode set 2. If that fails the firmware setup is put
+    ///back, which keeps working through the ports the driver holds.
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        let data = take_ports(resources, 0x60)?;
+        let status = take_ports(resources, 0x64)?;
+        let leds = keyboard::modifiers().leds();
+        match ps2::init(PortSet::new([data, status]), ps2::Typematic::DEFAULT, leds) {
+            Ok(()) => {
+                keyboard::set_scancode_set(ps2::ScancodeSet::Set2);
+            }
+            Err(error) => log::warn!("ps2 controller not set up: {:?}", error),
+        }
+        Ok(())
+    }
+}
+
+struct Mouse;
+
+impl Driver for Mouse {
+    fn name(&self) -> &'static str {
+        "mouse"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("ps2")
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &mouse::RESOURCES
+    }
+
+    ///The controller finds out what is on its second port when it is set up
+    fn probe(&self, _: &mut Reservation) -> bool {
+        ps2::device(Channel::Second).is_some()
+    }
+
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        match mouse::init(irq(resources, mouse::IRQ)?) {
+            Ok(_) => Ok(()),
+            Err(error) => {
+                log::info!("no ps2 mouse: {:?}", error);
+                Err(DriverError::Failed("the mouse didn't respond"))
+            }
+        }
+    }
+}
+
+struct Rtc;
+
+impl Driver for Rtc {
+    fn name(&self) -> &'static str {
+        "rtc"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("isa")
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &rtc::RESOURCES
+    }
+
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        rtc::init(take_ports(resources, 0x70)?);
+        Ok(())
+    }
+}
+
+///The qemu firmware configuration device
+struct FwCfg;
+
+impl Driver for FwCfg {
+    fn name(&self) -> &'static str {
+        "fw_cfg"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("isa")
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &fw_cfg::RESOURCES
+    }
+
+    fn probe(&self, resources: &mut Reservation) -> bool {
+        resources.ports(0x510).is_some_and(fw_cfg::is_present)
+    }
+
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        fw_cfg::init(take_ports(resources, 0x510)?);
+        Ok(())
+    }
+}
+
+///The ports the test watchdog uses to end a hung run
+struct Watchdog;
+
+impl Driver for Watchdog {
+    fn name(&self) -> &'static str {
+        "watchdog"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("isa")
+    }
+
+    fn resources(&self) -> &'static [Resource] {
+        &watchdog::RESOURCES
+    }
+
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError> {
+        let stop = take_ports(resources, 0x441)?;
+        let start = take_ports(resources, 0x443)?;
+        watchdog::init(PortSet::new([stop, start]));
+        Ok(())
+    }
+}
+
+struct QemuExit;
+
+impl Driver for QemuExit {
+    fn name(&self) -> &'static str {
+        "qemu exit"
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("isa")
+    }
+
+    ///Claims the isa-debug-exit ports `exit_qemu` writes to if nothing has exited yet
+    fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+        lazy_static::initialize(&crate::QEMU_EXIT);
+        Ok(())
+    }
+}
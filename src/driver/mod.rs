@@ -0,0 +1,411 @@
+//! Drivers and the tree of devices they drive
+//!
+//! Every device hangs off a bus: the platform at the root, the ISA bus with the legacy
+//! devices and the PCI bus. `init` goes through the registered drivers so that each one
+//! starts after its bus and whatever it depends on, claims the resources it lists, probes
+//! for its device and sets it up. `shutdown` stops them again in the opposite order.
+
+use core::fmt;
+
+use rost_core::device::{self, Node, OrderError};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::println;
+use crate::resource::{self, Reservation, Resource, ResourceError};
+use builtins::BUILTINS;
+
+pub mod builtins;
+
+pub const MAX_DEVICES: usize = 32;
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::with_builtins());
+
+pub trait Driver: Sync {
+    fn name(&self) -> &'static str;
+    ///The bus the device is on, only the platform itself has none
+    fn parent(&self) -> Option<&'static str>;
+    ///Devices other than the bus that have to be set up first
+    fn depends_on(&self) -> &'static [&'static str] {
+        &[]
+    }
+    ///Claimed before `probe` and held while the device is ready, a conflict keeps the driver
+    ///from starting
+    fn resources(&self) -> &'static [Resource] {
+        &[]
+    }
+    ///Whether the device is there, without setting it up, looking through the guards of
+    ///what `resources` lists
+    fn probe(&self, _: &mut Reservation) -> bool {
+        true
+    }
+    ///Sets the device up, the driver takes the guards it keeps using out of `resources`
+    fn init(&self, resources: &mut Reservation) -> Result<(), DriverError>;
+    ///Puts the device in a state it can be left in before the machine goes down
+    fn shutdown(&self) {}
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DriverError {
+    ///Another driver has some of the resources
+    Busy(ResourceError),
+    ///A device this one depends on didn't start
+    Dependency(&'static str),
+    Failed(&'static str),
+}
+
+impl fmt::Display for DriverError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DriverError::Busy(error) => write!(f, "{}", error),
+            DriverError::Dependency(name) => write!(f, "{} didn't start", name),
+            DriverError::Failed(reason) => write!(f, "{}", reason),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceState {
+    ///Registered and not started yet
+    Waiting,
+    ///The probe found nothing, or the bus it is on isn't there
+    Absent,
+    Ready,
+    Failed(DriverError),
+    ///Shut down
+    Stopped,
+}
+
+impl fmt::Display for DeviceState {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DeviceState::Waiting => write!(f, "waiting"),
+            DeviceState::Absent => write!(f, "absent"),
+            DeviceState::Ready => write!(f, "ready"),
+            DeviceState::Failed(error) => write!(f, "failed: {}", error),
+            DeviceState::Stopped => write!(f, "stopped"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    Full,
+    NameTaken,
+}
+
+#[derive(Clone, Copy)]
+pub struct Device {
+    pub driver: &'static dyn Driver,
+    pub state: DeviceState,
+}
+
+impl Device {
+    fn node(&self) -> Node {
+        Node {
+            name: self.driver.name(),
+            parent: self.driver.parent(),
+            depends_on: self.driver.depends_on(),
+        }
+    }
+}
+
+pub struct Registry {
+    devices: [Option<Device>; MAX_DEVICES],
+}
+
+impl Registry {
+    pub const fn new() -> Registry {
+        Registry {
+            devices: [None; MAX_DEVICES],
+        }
+    }
+
+    const fn with_builtins() -> Registry {
+        let mut registry = Registry::new();
+        let mut i = 0;
+        while i < BUILTINS.len() {
+            registry.devices[i] = Some(Device {
+                driver: BUILTINS[i],
+                state: DeviceState::Waiting,
+            });
+            i += 1;
+        }
+        registry
+    }
+
+    pub fn register(&mut self, driver: &'static dyn Driver) -> Result<(), RegistryError> {
+        if self.find(driver.name()).is_some() {
+            return Err(RegistryError::NameTaken);
+        }
+        let slot = self
+            .devices
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(RegistryError::Full)?;
+        *slot = Some(Device {
+            driver,
+            state: DeviceState::Waiting,
+        });
+        Ok(())
+    }
+
+    pub fn find(&self, name: &str) -> Option<Device> {
+        self.iter().find(|device| device.driver.name() == name)
+    }
+
+    fn set_state(&mut self, name: &str, state: DeviceState) {
+        let device = self
+            .devices
+            .iter_mut()
+            .flatten()
+            .find(|device| device.driver.name() == name);
+        if let Some(device) = device {
+            device.state = state;
+        }
+    }
+
+    ///The devices in the order they were registered
+    pub fn iter(&self) -> impl Iterator<Item = Device> + '_ {
+        self.devices.iter().flatten().copied()
+    }
+
+    ///Copies the devices out so drivers can run without the registry locked, returns how
+    ///many there are
+    fn snapshot(&self, devices: &mut [Option<Device>; MAX_DEVICES]) -> usize {
+        *devices = self.devices;
+        let mut count = 0;
+        for i in 0..MAX_DEVICES {
+            if let Some(device) = devices[i] {
+                devices[count] = Some(device);
+                count += 1;
+            }
+        }
+        count
+    }
+
+    ///The devices in the order they start in
+    fn init_order(&self, order: &mut [Option<Device>; MAX_DEVICES]) -> Result<usize, OrderError> {
+        let mut devices = [None; MAX_DEVICES];
+        let count = self.snapshot(&mut devices);
+        let mut nodes = [Node {
+            name: "",
+            parent: None,
+            depends_on: &[],
+        }; MAX_DEVICES];
+        for (node, device) in nodes.iter_mut().zip(devices.iter().flatten()) {
+            *node = device.node();
+        }
+
+        let mut indices = [0; MAX_DEVICES];
+        device::init_order(&nodes[..count], &mut indices[..count])?;
+        for (slot, i) in order.iter_mut().zip(&indices[..count]) {
+            *slot = devices[*i];
+        }
+        Ok(count)
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+///Adds a driver, it starts with the next `init`
+pub fn register(driver: &'static dyn Driver) -> Result<(), RegistryError> {
+    without_interrupts(|| REGISTRY.lock().register(driver))
+}
+
+pub fn state(name: &str) -> Option<DeviceState> {
+    without_interrupts(|| REGISTRY.lock().find(name)).map(|device| device.state)
+}
+
+fn set_state(name: &str, state: DeviceState) {
+    without_interrupts(|| REGISTRY.lock().set_state(name, state));
+}
+
+///Starts every driver that is still waiting, drivers registered later can be started by
+///calling it again
+pub fn init() {
+    //a bus registers the devices it finds while it starts, those start in the next pass
+    while start_waiting() > 0 {}
+}
+
+//returns how many drivers it tried to start
+fn start_waiting() -> usize {
+    let mut order = [None; MAX_DEVICES];
+    let count = match without_interrupts(|| REGISTRY.lock().init_order(&mut order)) {
+        Ok(count) => count,
+        Err(error) => {
+            log::error!("no driver was started: {}", error);
+            return 0;
+        }
+    };
+
+    let mut started = 0;
+    for device in order[..count].iter().flatten() {
+        if device.state != DeviceState::Waiting {
+            continue;
+        }
+        started += 1;
+        let name = device.driver.name();
+        let state = start(device);
+        set_state(name, state);
+        match state {
+            DeviceState::Ready => log::debug!("{} is ready", name),
+            DeviceState::Failed(error) => log::warn!("{} failed: {}", name, error),
+            _ => log::info!("{} is {}", name, state),
+        }
+    }
+    started
+}
+
+fn start(device: &Device) -> DeviceState {
+    let node = device.node();
+    if let Some(parent) = node.parent {
+        match state(parent) {
+            Some(DeviceState::Ready) => {}
+            //nothing on a bus that isn't there can be either
+            Some(DeviceState::Absent) => return DeviceState::Absent,
+            _ => return DeviceState::Failed(DriverError::Dependency(parent)),
+        }
+    }
+    for dependency in node.depends_on {
+        if state(dependency) != Some(DeviceState::Ready) {
+            return DeviceState::Failed(DriverError::Dependency(dependency));
+        }
+    }
+
+    let driver = device.driver;
+    let mut reservation = match resource::reserve(driver.name(), driver.resources()) {
+        Ok(reservation) => reservation,
+        Err(error) => return DeviceState::Failed(DriverError::Busy(error)),
+    };
+    //dropping the reservation gives the resources of a device that isn't set up back
+    if !driver.probe(&mut reservation) {
+        return DeviceState::Absent;
+    }
+    match driver.init(&mut reservation) {
+        Ok(()) => {
+            reservation.keep();
+            DeviceState::Ready
+        }
+        Err(error) => DeviceState::Failed(error),
+    }
+}
+
+///Shuts the devices down, the ones that depend on others first
+pub fn shutdown() {
+    let mut order = [None; MAX_DEVICES];
+    let count = match without_interrupts(|| REGISTRY.lock().init_order(&mut order)) {
+        Ok(count) => count,
+        Err(_) => return,
+    };
+
+    for device in order[..count].iter().rev().flatten() {
+        if device.state == DeviceState::Ready {
+            device.driver.shutdown();
+            set_state(device.driver.name(), DeviceState::Stopped);
+        }
+    }
+}
+
+///Prints the device tree with the state of every device
+pub fn list() {
+    let mut devices = [None; MAX_DEVICES];
+    let count = without_interrupts(|| REGISTRY.lock().snapshot(&mut devices));
+    list_children(&devices[..count], None, 0);
+}
+
+fn list_children(devices: &[Option<Device>], parent: Option<&str>, depth: usize) {
+    for device in devices.iter().flatten() {
+        if device.driver.parent() != parent {
+            continue;
+        }
+        let name = device.driver.name();
+        println!(
+            "{:indent$}{:<width$}{}",
+            "",
+            name,
+            device.state,
+            indent = depth * 2,
+            width = 16usize.saturating_sub(depth * 2)
+        );
+        list_children(devices, Some(name), depth + 1);
+    }
+}
+
+#[test_case]
+fn test_builtins_started() {
+    assert_eq!(state("platform"), Some(DeviceState::Ready));
+    assert_eq!(state("pit"), Some(DeviceState::Ready));
+    assert_eq!(state("ps2"), Some(DeviceState::Ready));
+    assert_eq!(state("com1"), Some(DeviceState::Ready));
+    assert_eq!(state("no such device"), None);
+}
+
+#[test_case]
+fn test_late_driver() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static STARTED: AtomicUsize = AtomicUsize::new(0);
+
+    struct Late;
+
+    impl Driver for Late {
+        fn name(&self) -> &'static str {
+            "late test device"
+        }
+
+        fn parent(&self) -> Option<&'static str> {
+            Some("isa")
+        }
+
+        fn depends_on(&self) -> &'static [&'static str] {
+            &["pit"]
+        }
+
+        fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+            STARTED.fetch_add(1, Ordering::SeqCst);
+            Ok(())
+        }
+    }
+
+    struct Missing;
+
+    impl Driver for Missing {
+        fn name(&self) -> &'static str {
+            "missing test device"
+        }
+
+        fn parent(&self) -> Option<&'static str> {
+            Some("isa")
+        }
+
+        fn resources(&self) -> &'static [Resource] {
+            &[Resource::Ports {
+                start: 0x2030,
+                count: 2,
+            }]
+        }
+
+        fn probe(&self, _: &mut Reservation) -> bool {
+            false
+        }
+
+        fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+            panic!("a device that isn't there was set up")
+        }
+    }
+
+    register(&Late).unwrap();
+    register(&Missing).unwrap();
+    assert_eq!(register(&Late), Err(RegistryError::NameTaken));
+    init();
+    init();
+    assert_eq!(STARTED.load(Ordering::SeqCst), 1);
+    assert_eq!(state("late test device"), Some(DeviceState::Ready));
+    assert_eq!(state("missing test device"), Some(DeviceState::Absent));
+    assert_eq!(resource::owner_of(Resource::ports(0x2030, 2)), None);
+}
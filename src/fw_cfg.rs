@@ -0,0 +1,163 @@
+//! QEMU's firmware configuration device, which hands files from the host to the guest
+//!
+//! QEMU started with `-fw_cfg name=opt/rost/example,string=text` has a file named
+//! `opt/rost/example` holding `text`. Files that are not QEMU's own go under `opt/`.
+
+use rost_core::port::PortIo;
+use rost_core::register::{PortRegister, ReadOnly, WriteOnly};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::resource::{PortRange, Resource};
+
+pub const RESOURCES: [Resource; 1] = [Resource::ports(0x510, 2)];
+
+const SELECTOR: PortRegister<u16, WriteOnly> = PortRegister::new(0x510);
+const DATA: PortRegister<u8, ReadOnly> = PortRegister::new(0x511);
+
+//selectors
+const SIGNATURE: u16 = 0x0000;
+const FILE_DIRECTORY: u16 = 0x0019;
+
+///The longest file name, including the nul that ends it
+pub const NAME_LENGTH: usize = 56;
+
+//set by `init`
+static FW_CFG: Mutex<Option<FwCfg<PortRange>>> = Mutex::new(None);
+
+///A file in the directory of the device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct File {
+    pub size: u32,
+    selector: u16,
+}
+
+pub struct FwCfg<P> {
+    ports: P,
+}
+
+impl<P: PortIo> FwCfg<P> {
+    pub const fn with_ports(ports: P) -> FwCfg<P> {
+        FwCfg { ports }
+    }
+
+    //reads start from the beginning of an item after selecting it
+    fn select(&mut self, selector: u16) {
+        SELECTOR.write(&mut self.ports, selector)
+    }
+
+    fn read_bytes(&mut self, buffer: &mut [u8]) {
+        for byte in buffer {
+            *byte = DATA.read(&mut self.ports);
+        }
+    }
+
+    //the directory is big endian unlike the rest of the device
+    fn read_u32_be(&mut self) -> u32 {
+        let mut bytes = [0; 4];
+        self.read_bytes(&mut bytes);
+        u32::from_be_bytes(bytes)
+    }
+
+    fn read_u16_be(&mut self) -> u16 {
+        let mut bytes = [0; 2];
+        self.read_bytes(&mut bytes);
+        u16::from_be_bytes(bytes)
+    }
+
+    pub fn is_present(&mut self) -> bool {
+        let mut signature = [0; 4];
+        self.select(SIGNATURE);
+        self.read_bytes(&mut signature);
+        &signature == b"QEMU"
+    }
+
+    pub fn find(&mut self, name: &str) -> Option<File> {
+        if !self.is_present() {
+            return None;
+        }
+
+        self.select(FILE_DIRECTORY);
+        let count = self.read_u32_be();
+        for _ in 0..count {
+            let size = self.read_u32_be();
+            let selector = self.read_u16_be();
+            let _reserved = self.read_u16_be();
+            let mut file_name = [0; NAME_LENGTH];
+            self.read_bytes(&mut file_name);
+
+            let length = file_name
+                .iter()
+                .position(|byte| *byte == 0)
+                .unwrap_or(NAME_LENGTH);
+            if &file_name[..length] == name.as_bytes() {
+                return Some(File { size, selector });
+            }
+        }
+        None
+    }
+
+    ///Reads the start of a file into `buffer`, returns how many bytes were read
+    pub fn read(&mut self, file: File, buffer: &mut [u8]) -> usize {
+        let length = buffer.len().min(file.size as usize);
+        self.select(file.selector);
+        self.read_bytes(&mut buffer[..length]);
+        length
+    }
+}
+
+///Takes the ports of the device
+pub fn init(ports: PortRange) {
+    without_interrupts(|| *FW_CFG.lock() = Some(FwCfg::with_ports(ports)));
+}
+
+///False when not running in QEMU, the ports are only looked at
+pub fn is_present(ports: &mut PortRange) -> bool {
+    FwCfg::with_ports(ports).is_present()
+}
+
+///`None` before `init` as well
+pub fn find(name: &str) -> Option<File> {
+    without_interrupts(|| FW_CFG.lock().as_mut()?.find(name))
+}
+
+///Reads the start of a file into `buffer`, returns how many bytes were read
+pub fn read(file: File, buffer: &mut [u8]) -> usize {
+    without_interrupts(|| {
+        FW_CFG
+            .lock()
+            .as_mut()
+            .map_or(0, |fw_cfg| fw_cfg.read(file, buffer))
+    })
+}
+
+#[test_case]
+fn test_fw_cfg() {
+    let present = without_interrupts(|| FW_CFG.lock().as_mut().map(FwCfg::is_present));
+    assert_eq!(present, Some(true));
+    assert_eq!(find("opt/rost/no-such-file"), None);
+}
+
+#[test_case]
+fn test_find_in_directory() {
+    use rost_core::mock::MockPorts;
+
+    let mut ports = MockPorts::new();
+    let count = 1u32.to_be_bytes();
+    let mut entry = [0; 8 + NAME_LENGTH];
+    entry[..4].copy_from_slice(&5u32.to_be_bytes());
+    entry[4..6].copy_from_slice(&0x0020u16.to_be_bytes());
+    entry[8..8 + 5].copy_from_slice(b"opt/a");
+    let bytes = b"QEMU".iter().chain(&count).chain(&entry);
+    for byte in bytes {
+        ports.queue_read(0x511, (*byte).into());
+    }
+
+    let mut fw_cfg = FwCfg::with_ports(ports);
+    let file = fw_cfg.find("opt/a");
+    assert_eq!(
+        file.map(|file| (file.size, file.selector)),
+        Some((5, 0x0020))
+    );
+    assert_eq!(fw_cfg.ports.queued_reads(), 0);
+}
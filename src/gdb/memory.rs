@@ -0,0 +1,128 @@
+//! Memory access for the debugger that survives bad addresses
+//!
+//! Every byte is read or written by a tiny assembly routine. When that access page faults
+//! the page fault handler asks `fixup` where to continue and the routine returns an error
+//! instead of the kernel crashing on whatever address gdb asked for.
+
+use core::arch::global_asm;
+
+use x86_64::registers::control::{Cr0, Cr0Flags};
+
+global_asm!(
+    ".global rost_probe_read",
+    ".global rost_probe_write",
+    ".global rost_probe_read_access",
+    ".global rost_probe_write_access",
+    ".global rost_probe_fault",
+    //u64 rost_probe_read(address: *const u8, out: *mut u8)
+    "rost_probe_read:",
+    "rost_probe_read_access:",
+    "    mov al, byte ptr [rdi]",
+    "    mov byte ptr [rsi], al",
+    "    xor eax, eax",
+    "    ret",
+    //u64 rost_probe_write(address: *mut u8, value: u8)
+    "rost_probe_write:",
+    "rost_probe_write_access:",
+    "    mov byte ptr [rdi], sil",
+    "    xor eax, eax",
+    "    ret",
+    "rost_probe_fault:",
+    "    mov eax, 1",
+    "    ret",
+);
+
+extern "C" {
+    fn rost_probe_read(address: *const u8, out: *mut u8) -> u64;
+    fn rost_probe_write(address: *mut u8, value: u8) -> u64;
+    static rost_probe_read_access: u8;
+    static rost_probe_write_access: u8;
+    static rost_probe_fault: u8;
+}
+
+///Where to continue after a page fault at `instruction`, only the two probe accesses have an answer
+pub fn fixup(instruction: u64) -> Option<u64> {
+    let (read, write, fault) = unsafe {
+        (
+            &rost_probe_read_access as *const u8 as u64,
+            &rost_probe_write_access as *const u8 as u64,
+            &rost_probe_fault as *const u8 as u64,
+        )
+    };
+
+    if instruction == read || instruction == write {
+        Some(fault)
+    } else {
+        None
+    }
+}
+
+//addresses in the hole between the lower and higher half always fault with a general
+//protection fault instead of a page fault, so they are turned away before trying
+fn is_canonical(address: u64) -> bool {
+    let top = address >> 47;
+    top == 0 || top == 0x1_FFFF
+}
+
+pub fn read_byte(address: u64) -> Option<u8> {
+    if !is_canonical(address) {
+        return None;
+    }
+
+    let mut value = 0;
+    match unsafe { rost_probe_read(address as *const u8, &mut value) } {
+        0 => Some(value),
+        _ => None,
+    }
+}
+
+///Writes a byte even if the page is read only like the kernel code is, needed for breakpoints
+pub fn write_byte(address: u64, value: u8) -> bool {
+    if !is_canonical(address) {
+        return false;
+    }
+
+    let cr0 = Cr0::read();
+    unsafe {
+        Cr0::write(cr0 - Cr0Flags::WRITE_PROTECT);
+        let result = rost_probe_write(address as *mut u8, value);
+        Cr0::write(cr0);
+        result == 0
+    }
+}
+
+///Reads as many bytes as possible, returns how many could be read
+pub fn read(address: u64, out: &mut [u8]) -> usize {
+    for (i, byte) in out.iter_mut().enumerate() {
+        match read_byte(address.wrapping_add(i as u64)) {
+            Some(value) => *byte = value,
+            None => return i,
+        }
+    }
+    out.len()
+}
+
+///Writes all of `bytes` or stops at the first address that can not be written
+pub fn write(address: u64, bytes: &[u8]) -> bool {
+    bytes
+        .iter()
+        .enumerate()
+        .all(|(i, byte)| write_byte(address.wrapping_add(i as u64), *byte))
+}
+
+#[test_case]
+fn test_probe_valid_memory() {
+    let mut value = 0x5Au8;
+    let address = &mut value as *mut u8 as u64;
+
+    assert_eq!(read_byte(address), Some(0x5A));
+    assert!(write_byte(address, 0xA5));
+    assert_eq!(unsafe { core::ptr::read_volatile(&value) }, 0xA5);
+}
+
+#[test_case]
+fn test_probe_bad_memory() {
+    assert_eq!(read_byte(0x0000_8000_0000_0000), None);
+    assert_eq!(read_byte(0xdead_beef), None);
+    assert!(!write_byte(0xdead_beef, 42));
+}
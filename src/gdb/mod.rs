@@ -0,0 +1,603 @@
+//! A gdb remote serial protocol stub on COM2
+//!
+//! After `init` every breakpoint and single step exception hands the kernel over to gdb.
+//! With qemu the second serial port can be given to gdb with
+//! `-serial stdio -serial tcp::1234,server,nowait` and `target remote :1234`. Once gdb is
+//! connected pressing Ctrl-C stops the kernel inside the COM2 interrupt handler.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use rost_core::port::PortIo;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::segmentation::{Segment, DS, ES, FS, GS};
+use x86_64::registers::rflags::RFlags;
+
+use crate::interrupts::TrapFrame;
+use crate::resource::{self, PortRange};
+use crate::serial::uart::{ComPort, LineConfig, Uart, UartError};
+use packet::{Response, MAX_PACKET};
+
+pub mod memory;
+pub mod packet;
+
+pub const PORT: ComPort = ComPort::Com2;
+pub const MAX_BREAKPOINTS: usize = 32;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+const INT3: u8 = 0xCC;
+//what gdb sends when Ctrl-C is pressed
+const INTERRUPT_BYTE: u8 = 0x03;
+//rax-r15 and rip are 8 bytes, eflags and the segment registers 4
+const REGISTER_COUNT: usize = 24;
+///Until there is a scheduler the whole kernel is a single thread
+const KERNEL_THREAD: u64 = 1;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static INTERRUPT_REQUESTED: AtomicBool = AtomicBool::new(false);
+static STUB: Mutex<Option<Stub>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy)]
+struct Breakpoint {
+    address: u64,
+    original: u8,
+}
+
+//what the kernel does once the current packet is answered
+enum Action {
+    Reply,
+    Resume,
+    Detach,
+}
+
+struct Stub<P = PortRange> {
+    uart: Uart<P>,
+    //gdb is waiting for a stop reply, only true between a continue or step and the next stop
+    running: bool,
+    breakpoints: [Option<Breakpoint>; MAX_BREAKPOINTS],
+}
+
+///Opens COM2 for gdb, the kernel keeps running until it hits a breakpoint
+pub fn init() -> Result<(), UartError> {
+    let mut uart = Uart::open(PORT, LineConfig::DEFAULT.with_baud_rate(115200))?;
+    let irq = resource::claim_irq("gdb", PORT.irq()).map_err(UartError::Busy)?;
+    uart.enable_receive_interrupt();
+
+    without_interrupts(|| {
+        *STUB.lock() = Some(Stub {
+            uart,
+            running: false,
+            breakpoints: [None; MAX_BREAKPOINTS],
+        });
+    });
+    ENABLED.store(true, Ordering::SeqCst);
+    //the stub stays until the machine is turned off
+    irq.unmask();
+    irq.keep();
+    Ok(())
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+///Stops in the debugger if one is set up, otherwise the breakpoint only gets logged
+pub fn breakpoint() {
+    x86_64::instructions::interrupts::int3();
+}
+
+///Called from the breakpoint and debug exceptions, returns false when the stub is not in use
+pub fn handle_trap(frame: &mut TrapFrame) -> bool {
+    if !is_enabled() {
+        return false;
+    }
+    let mut stub = match STUB.try_lock() {
+        Some(stub) => stub,
+        None => return false,
+    };
+    let stub = match stub.as_mut() {
+        Some(stub) => stub,
+        None => return false,
+    };
+
+    frame.rflags &= !RFlags::TRAP_FLAG.bits();
+    let signal = if INTERRUPT_REQUESTED.swap(false, Ordering::SeqCst) {
+        SIGINT
+    } else {
+        SIGTRAP
+    };
+
+    stub.run(frame, signal);
+    true
+}
+
+///Called from the COM2 interrupt, returns true when gdb wants the kernel stopped
+pub fn handle_interrupt() -> bool {
+    let mut stub = STUB.lock();
+    let stub = match stub.as_mut() {
+        Some(stub) => stub,
+        None => return false,
+    };
+
+    while let Some(byte) = stub.uart.try_receive() {
+        if byte == INTERRUPT_BYTE {
+            INTERRUPT_REQUESTED.store(true, Ordering::SeqCst);
+        }
+    }
+    INTERRUPT_REQUESTED.load(Ordering::SeqCst)
+}
+
+impl<P: PortIo> Stub<P> {
+    fn run(&mut self, frame: &mut TrapFrame, signal: u8) {
+        if self.running {
+            let mut response = Response::new();
+            stop_reply(&mut response, signal);
+            self.send(response.as_bytes());
+            self.running = false;
+        }
+
+        let mut packet = [0; MAX_PACKET];
+        loop {
+            let len = self.receive(&mut packet);
+            let mut response = Response::new();
+
+            match self.command(&packet[..len], frame, signal, &mut response) {
+                Action::Reply => self.send(response.as_bytes()),
+                Action::Resume => {
+                    self.running = true;
+                    return;
+                }
+                Action::Detach => {
+                    self.remove_all_breakpoints();
+                    return;
+                }
+            }
+        }
+    }
+
+    fn command(
+        &mut self,
+        packet: &[u8],
+        frame: &mut TrapFrame,
+        signal: u8,
+        response: &mut Response,
+    ) -> Action {
+        let (&kind, arguments) = match packet.split_first() {
+            Some(split) => split,
+            None => return Action::Reply,
+        };
+
+        match kind {
+            b'?' => stop_reply(response, signal),
+            b'g' => {
+                for register in 0..REGISTER_COUNT {
+                    let (value, size) = read_register(frame, register);
+                    response.push_hex_le(value, size);
+                }
+            }
+            b'G' => {
+                let mut offset = 0;
+                for register in 0..REGISTER_COUNT {
+                    let size = register_size(register);
+                    match arguments.get(offset..offset + size * 2).and_then(parse_le) {
+                        Some(value) => write_register(frame, register, value),
+                        None => break,
+                    }
+                    offset += size * 2;
+                }
+                response.push_str("OK");
+            }
+            b'p' => match packet::parse_hex(arguments).map(|n| n as usize) {
+                Some(register) if register < REGISTER_COUNT => {
+                    let (value, size) = read_register(frame, register);
+                    response.push_hex_le(value, size);
+                }
+                _ => response.push_str("E00"),
+            },
+            b'P' => {
+                let parsed = packet::split_once(arguments, b'=').and_then(|(register, value)| {
+                    Some((packet::parse_hex(register)? as usize, parse_le(value)?))
+                });
+                match parsed {
+                    Some((register, value)) if register < REGISTER_COUNT => {
+                        write_register(frame, register, value);
+                        response.push_str("OK");
+                    }
+                    _ => response.push_str("E00"),
+                }
+            }
+            b'm' => self.read_memory(arguments, response),
+            b'M' => self.write_memory(arguments, response),
+            b'Z' | b'z' => self.breakpoint_command(kind == b'Z', arguments, response),
+            b'c' | b's' => {
+                if let Some(address) = packet::parse_hex(arguments) {
+                    frame.rip = address;
+                }
+                if kind == b's' {
+                    frame.rflags |= RFlags::TRAP_FLAG.bits();
+                }
+                return Action::Resume;
+            }
+            b'D' => {
+                self.send(b"OK");
+                return Action::Detach;
+            }
+            b'k' => return Action::Detach,
+            b'H' => response.push_str("OK"),
+            b'T' => match packet::parse_hex(arguments) {
+                Some(KERNEL_THREAD) => response.push_str("OK"),
+                _ => response.push_str("E01"),
+            },
+            b'q' => query(arguments, response),
+            _ => (),
+        }
+        Action::Reply
+    }
+
+    fn read_memory(&mut self, arguments: &[u8], response: &mut Response) {
+        let (address, len) = match address_and_len(arguments) {
+            Some(parsed) => parsed,
+            None => return response.push_str("E01"),
+        };
+
+        let mut bytes = [0; MAX_PACKET / 2];
+        let len = len.min(bytes.len());
+        let read = memory::read(address, &mut bytes[..len]);
+
+        if read == 0 && len != 0 {
+            return response.push_str("E14");
+        }
+        for byte in &bytes[..read] {
+            response.push_hex_byte(*byte);
+        }
+    }
+
+    fn write_memory(&mut self, arguments: &[u8], response: &mut Response) {
+        let mut bytes = [0; MAX_PACKET / 2];
+        let parsed = packet::split_once(arguments, b':').and_then(|(range, data)| {
+            let (address, len) = address_and_len(range)?;
+            let decoded = packet::decode_hex_bytes(data, &mut bytes)?;
+            (decoded == len).then_some((address, len))
+        });
+
+        match parsed {
+            Some((address, len)) if memory::write(address, &bytes[..len]) => {
+                response.push_str("OK")
+            }
+            Some(_) => response.push_str("E14"),
+            None => response.push_str("E01"),
+        }
+    }
+
+    //only software breakpoints are supported, gdb falls back to them for everything else
+    fn breakpoint_command(&mut self, insert: bool, arguments: &[u8], response: &mut Response) {
+        let mut fields = arguments.split(|byte| *byte == b',');
+        let kind = fields.next();
+        let address = fields.next().and_then(packet::parse_hex);
+
+        let address = match (kind, address) {
+            (Some(b"0"), Some(address)) => address,
+            (Some(b"0"), None) => return response.push_str("E01"),
+            _ => return,
+        };
+
+        let done = if insert {
+            self.insert_breakpoint(address)
+        } else {
+            self.remove_breakpoint(address)
+        };
+        response.push_str(if done { "OK" } else { "E14" });
+    }
+
+    fn insert_breakpoint(&mut self, address: u64) -> bool {
+        if self.find_breakpoint(address).is_some() {
+            return true;
+        }
+        let slot = match self.breakpoints.iter().position(Option::is_none) {
+            Some(slot) => slot,
+            None => return false,
+        };
+
+        match memory::read_byte(address) {
+            Some(original) if memory::write_byte(address, INT3) => {
+                self.breakpoints[slot] = Some(Breakpoint { address, original });
+                true
+            }
+            _ => false,
+        }
+    }
+
+    fn remove_breakpoint(&mut self, address: u64) -> bool {
+        match self.find_breakpoint(address) {
+            Some(slot) => {
+                let breakpoint = self.breakpoints[slot].take().unwrap();
+                memory::write_byte(breakpoint.address, breakpoint.original)
+            }
+            None => true,
+        }
+    }
+
+    fn remove_all_breakpoints(&mut self) {
+        for slot in self.breakpoints.iter_mut() {
+            if let Some(breakpoint) = slot.take() {
+                memory::write_byte(breakpoint.address, breakpoint.original);
+            }
+        }
+    }
+
+    fn find_breakpoint(&self, address: u64) -> Option<usize> {
+        self.breakpoints
+            .iter()
+            .position(|slot| matches!(slot, Some(breakpoint) if breakpoint.address == address))
+    }
+
+    ///Waits for a packet with a good checksum and acknowledges it
+    fn receive(&mut self, buffer: &mut [u8; MAX_PACKET]) -> usize {
+        loop {
+            while self.uart.receive() != b'$' {}
+
+            let mut len = 0;
+            let mut sum: u8 = 0;
+            loop {
+                let byte = self.uart.receive();
+                if byte == b'#' {
+                    break;
+                }
+                sum = sum.wrapping_add(byte);
+                //packets that are too long are cut off, gdb was told the limit
+                if len < buffer.len() {
+                    buffer[len] = byte;
+                    len += 1;
+                }
+            }
+
+            let high = packet::hex_value(self.uart.receive());
+            let low = packet::hex_value(self.uart.receive());
+            match (high, low) {
+                (Some(high), Some(low)) if high << 4 | low == sum => {
+                    self.uart.send(b'+');
+                    return len;
+                }
+                _ => self.uart.send(b'-'),
+            }
+        }
+    }
+
+    ///Sends a packet until gdb acknowledges it
+    fn send(&mut self, data: &[u8]) {
+        let sum = packet::checksum(data);
+
+        loop {
+            self.uart.send(b'$');
+            for byte in data {
+                self.uart.send(*byte);
+            }
+            self.uart.send(b'#');
+            self.uart.send(packet::hex_digit(sum >> 4));
+            self.uart.send(packet::hex_digit(sum));
+
+            loop {
+                match self.uart.receive() {
+                    b'+' => return,
+                    b'-' => break,
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+fn stop_reply(response: &mut Response, signal: u8) {
+    response.push(b'T');
+    response.push_hex_byte(signal);
+    response.push_str("thread:");
+    response.push_hex_byte(KERNEL_THREAD as u8);
+    response.push(b';');
+}
+
+fn query(query: &[u8], response: &mut Response) {
+    if query.starts_with(b"Supported") {
+        let _ = core::fmt::write(response, format_args!("PacketSize={:x}", MAX_PACKET));
+    } else if query == b"Attached" {
+        response.push_str("1");
+    } else if query == b"C" {
+        response.push_str("QC");
+        response.push_hex_byte(KERNEL_THREAD as u8);
+    } else if query == b"fThreadInfo" {
+        response.push(b'm');
+        response.push_hex_byte(KERNEL_THREAD as u8);
+    } else if query == b"sThreadInfo" {
+        response.push(b'l');
+    } else if query.starts_with(b"ThreadExtraInfo") {
+        for byte in b"kernel" {
+            response.push_hex_byte(*byte);
+        }
+    }
+}
+
+fn address_and_len(arguments: &[u8]) -> Option<(u64, usize)> {
+    let (address, len) = packet::split_once(arguments, b',')?;
+    Some((
+        packet::parse_hex(address)?,
+        packet::parse_hex(len)? as usize,
+    ))
+}
+
+//register values are sent in target byte order
+fn parse_le(digits: &[u8]) -> Option<u64> {
+    let mut bytes = [0; 8];
+    packet::decode_hex_bytes(digits, &mut bytes)?;
+    Some(u64::from_le_bytes(bytes))
+}
+
+fn register_size(register: usize) -> usize {
+    if register <= 16 {
+        8
+    } else {
+        4
+    }
+}
+
+//the order of the amd64 registers in gdb
+fn general_register(frame: &mut TrapFrame, register: usize) -> Option<&mut u64> {
+    Some(match register {
+        0 => &mut frame.rax,
+        1 => &mut frame.rbx,
+        2 => &mut frame.rcx,
+        3 => &mut frame.rdx,
+        4 => &mut frame.rsi,
+        5 => &mut frame.rdi,
+        6 => &mut frame.rbp,
+        7 => &mut frame.rsp,
+        8 => &mut frame.r8,
+        9 => &mut frame.r9,
+        10 => &mut frame.r10,
+        11 => &mut frame.r11,
+        12 => &mut frame.r12,
+        13 => &mut frame.r13,
+        14 => &mut frame.r14,
+        15 => &mut frame.r15,
+        16 => &mut frame.rip,
+        17 => &mut frame.rflags,
+        _ => return None,
+    })
+}
+
+fn read_register(frame: &mut TrapFrame, register: usize) -> (u64, usize) {
+    let value = match register {
+        18 => frame.cs,
+        19 => frame.ss,
+        20 => DS::get_reg().0 as u64,
+        21 => ES::get_reg().0 as u64,
+        22 => FS::get_reg().0 as u64,
+        23 => GS::get_reg().0 as u64,
+        _ => general_register(frame, register).map_or(0, |value| *value),
+    };
+    (value, register_size(register))
+}
+
+//the segment registers are left alone, the kernel only has one code and data segment
+fn write_register(frame: &mut TrapFrame, register: usize, value: u64) {
+    if let Some(general) = general_register(frame, register) {
+        *general = value;
+    }
+}
+
+#[cfg(test)]
+pub(crate) fn test_frame() -> TrapFrame {
+    TrapFrame {
+        r15: 15,
+        r14: 14,
+        r13: 13,
+        r12: 12,
+        r11: 11,
+        r10: 10,
+        r9: 9,
+        r8: 8,
+        rbp: 6,
+        rdi: 5,
+        rsi: 4,
+        rdx: 3,
+        rcx: 2,
+        rbx: 1,
+        rax: 0,
+        vector: 3,
+        error_code: 0,
+        rip: 0xFFFF_8000_0000_1000,
+        cs: 8,
+        rflags: 0x202,
+        rsp: 0x1000,
+        ss: 0,
+    }
+}
+
+#[test_case]
+fn test_register_order() {
+    let mut frame = test_frame();
+
+    assert_eq!(read_register(&mut frame, 15), (15, 8));
+    assert_eq!(read_register(&mut frame, 16), (0xFFFF_8000_0000_1000, 8));
+    assert_eq!(read_register(&mut frame, 17), (0x202, 4));
+    assert_eq!(read_register(&mut frame, 18), (8, 4));
+
+    write_register(&mut frame, 7, 0x2000);
+    write_register(&mut frame, 18, 0x10);
+    assert_eq!(frame.rsp, 0x2000);
+    assert_eq!(frame.cs, 8);
+}
+
+#[test_case]
+fn test_register_packets() {
+    use rost_core::mock::MockPorts;
+
+    let mut stub = Stub {
+        uart: Uart::with_ports(MockPorts::new(), PORT.base()),
+        running: false,
+        breakpoints: [None; MAX_BREAKPOINTS],
+    };
+    let mut frame = test_frame();
+
+    let mut response = Response::new();
+    stub.command(b"p10", &mut frame, SIGTRAP, &mut response);
+    assert_eq!(response.as_bytes(), b"00100000000080ff");
+
+    let mut response = Response::new();
+    stub.command(b"P0=2a00000000000000", &mut frame, SIGTRAP, &mut response);
+    assert_eq!(response.as_bytes(), b"OK");
+    assert_eq!(frame.rax, 42);
+
+    let mut response = Response::new();
+    stub.command(b"g", &mut frame, SIGTRAP, &mut response);
+    assert_eq!(response.as_bytes().len(), (17 * 8 + 7 * 4) * 2);
+    assert!(response.as_bytes().starts_with(b"2a00000000000000"));
+}
+
+#[test_case]
+fn test_memory_packets() {
+    use rost_core::mock::MockPorts;
+
+    let mut stub = Stub {
+        uart: Uart::with_ports(MockPorts::new(), PORT.base()),
+        running: false,
+        breakpoints: [None; MAX_BREAKPOINTS],
+    };
+    let mut frame = test_frame();
+    let mut target = [0x11u8, 0x22, 0x33, 0x44];
+    let address = target.as_mut_ptr() as u64;
+
+    let mut command = Response::new();
+    let _ = core::fmt::write(&mut command, format_args!("M{:x},2:abcd", address));
+    let mut response = Response::new();
+    stub.command(command.as_bytes(), &mut frame, SIGTRAP, &mut response);
+    assert_eq!(response.as_bytes(), b"OK");
+
+    let mut command = Response::new();
+    let _ = core::fmt::write(&mut command, format_args!("m{:x},4", address));
+    let mut response = Response::new();
+    stub.command(command.as_bytes(), &mut frame, SIGTRAP, &mut response);
+    assert_eq!(response.as_bytes(), b"abcd3344");
+
+    let mut response = Response::new();
+    stub.command(b"mdeadbeef,4", &mut frame, SIGTRAP, &mut response);
+    assert_eq!(response.as_bytes(), b"E14");
+}
+
+#[test_case]
+fn test_software_breakpoints() {
+    use rost_core::mock::MockPorts;
+
+    let mut stub = Stub {
+        uart: Uart::with_ports(MockPorts::new(), PORT.base()),
+        running: false,
+        breakpoints: [None; MAX_BREAKPOINTS],
+    };
+    let mut target = [0x90u8; 2];
+    let address = target.as_mut_ptr() as u64;
+
+    assert!(stub.insert_breakpoint(address));
+    assert_eq!(memory::read_byte(address), Some(INT3));
+    assert!(stub.remove_breakpoint(address));
+    assert_eq!(memory::read_byte(address), Some(0x90));
+    assert_eq!(stub.find_breakpoint(address), None);
+}
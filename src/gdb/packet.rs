@@ -0,0 +1,138 @@
+//! Framing and hex encoding of remote serial protocol packets
+//!
+//! A packet is sent as `$data#cc` where `cc` is the modulo 256 sum of the data bytes in hex.
+//! The receiver answers with `+` or asks for the packet again with `-`.
+
+use core::fmt;
+
+///The largest packet the stub takes, reported to gdb in `qSupported`
+pub const MAX_PACKET: usize = 1024;
+
+const HEX_DIGITS: &[u8; 16] = b"0123456789abcdef";
+
+pub fn checksum(data: &[u8]) -> u8 {
+    data.iter().fold(0, |sum: u8, byte| sum.wrapping_add(*byte))
+}
+
+pub fn hex_digit(value: u8) -> u8 {
+    HEX_DIGITS[(value & 0x0F) as usize]
+}
+
+pub fn hex_value(digit: u8) -> Option<u8> {
+    match digit {
+        b'0'..=b'9' => Some(digit - b'0'),
+        b'a'..=b'f' => Some(digit - b'a' + 10),
+        b'A'..=b'F' => Some(digit - b'A' + 10),
+        _ => None,
+    }
+}
+
+///Parses a big endian hex number like the addresses and lengths in packets
+pub fn parse_hex(digits: &[u8]) -> Option<u64> {
+    if digits.is_empty() || digits.len() > 16 {
+        return None;
+    }
+    digits.iter().try_fold(0u64, |value, digit| {
+        Some(value << 4 | hex_value(*digit)? as u64)
+    })
+}
+
+///Decodes pairs of hex digits into bytes, returns how many bytes were written
+pub fn decode_hex_bytes(digits: &[u8], out: &mut [u8]) -> Option<usize> {
+    if !digits.len().is_multiple_of(2) || digits.len() / 2 > out.len() {
+        return None;
+    }
+    for (byte, pair) in out.iter_mut().zip(digits.chunks(2)) {
+        *byte = hex_value(pair[0])? << 4 | hex_value(pair[1])?;
+    }
+    Some(digits.len() / 2)
+}
+
+///Splits `data` at the first `separator`
+pub fn split_once(data: &[u8], separator: u8) -> Option<(&[u8], &[u8])> {
+    let position = data.iter().position(|byte| *byte == separator)?;
+    Some((&data[..position], &data[position + 1..]))
+}
+
+///An outgoing packet being built, anything past `MAX_PACKET` is dropped
+pub struct Response {
+    data: [u8; MAX_PACKET],
+    len: usize,
+}
+
+impl Response {
+    pub fn new() -> Response {
+        Response {
+            data: [0; MAX_PACKET],
+            len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.data[..self.len]
+    }
+
+    pub fn push(&mut self, byte: u8) {
+        if self.len < MAX_PACKET {
+            self.data[self.len] = byte;
+            self.len += 1;
+        }
+    }
+
+    pub fn push_str(&mut self, text: &str) {
+        for byte in text.bytes() {
+            self.push(byte);
+        }
+    }
+
+    pub fn push_hex_byte(&mut self, byte: u8) {
+        self.push(hex_digit(byte >> 4));
+        self.push(hex_digit(byte));
+    }
+
+    ///Writes a value as target (little endian) bytes the way register contents are sent
+    pub fn push_hex_le(&mut self, value: u64, size: usize) {
+        for byte in value.to_le_bytes().iter().take(size) {
+            self.push_hex_byte(*byte);
+        }
+    }
+}
+
+impl Default for Response {
+    fn default() -> Self {
+        Response::new()
+    }
+}
+
+impl fmt::Write for Response {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_checksum() {
+    assert_eq!(checksum(b"OK"), 0x9A);
+    assert_eq!(checksum(b""), 0);
+}
+
+#[test_case]
+fn test_parse_hex() {
+    assert_eq!(parse_hex(b"ffffffff80001000"), Some(0xFFFF_FFFF_8000_1000));
+    assert_eq!(parse_hex(b"1A"), Some(0x1A));
+    assert_eq!(parse_hex(b""), None);
+    assert_eq!(parse_hex(b"12g"), None);
+}
+
+#[test_case]
+fn test_hex_bytes_round_trip() {
+    let mut response = Response::new();
+    response.push_hex_le(0x1122_3344, 4);
+    assert_eq!(response.as_bytes(), b"44332211");
+
+    let mut bytes = [0; 4];
+    assert_eq!(decode_hex_bytes(response.as_bytes(), &mut bytes), Some(4));
+    assert_eq!(u32::from_le_bytes(bytes), 0x1122_3344);
+    assert_eq!(decode_hex_bytes(b"123", &mut bytes), None);
+}
@@ -1,8 +1,15 @@
-use crate::{gdt, print, println, vga_driver};
+use crate::gdt;
+use core::arch::global_asm;
+use core::time::Duration;
 use lazy_static::lazy_static;
 use pic8259::ChainedPics;
 use spin;
-use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame};
+use x86_64::registers::control::Cr2;
+use x86_64::registers::rflags::RFlags;
+use x86_64::structures::idt::{InterruptDescriptorTable, InterruptStackFrame, PageFaultErrorCode};
+use x86_64::VirtAddr;
+
+use crate::resource::Resource;
 
 pub const PIC_1_OFFSET: u8 = 32;
 pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
@@ -12,6 +19,11 @@ pub const PIC_2_OFFSET: u8 = PIC_1_OFFSET + 8;
 pub enum InterruptIndex {
     Timer = PIC_1_OFFSET,
     Keyboard,
+    Serial2 = PIC_1_OFFSET + 3,
+    Serial1 = PIC_1_OFFSET + crate::serial::COM1_IRQ,
+    Mouse = PIC_1_OFFSET + crate::mouse::IRQ,
+    ///What the primary PIC raises when an interrupt went away before it got delivered
+    Spurious = PIC_1_OFFSET + 7,
 }
 
 impl InterruptIndex {
@@ -28,20 +40,163 @@ lazy_static! {
     pub static ref TICKS: spin::RwLock<u64> = spin::RwLock::new(0);
 }
 
+///The input clock of the PIT in hertz
+pub const PIT_FREQUENCY: u64 = crate::pit::FREQUENCY as u64;
+///`pit::init` sets the timer to about a thousand ticks a second
+pub const PIT_DIVISOR: u64 = crate::pit::TIMER_DIVISOR as u64;
+
+///Time since the timer interrupt was enabled
+pub fn uptime() -> Duration {
+    let ticks = x86_64::instructions::interrupts::without_interrupts(|| *TICKS.read());
+    ticks_to_duration(ticks)
+}
+
+fn ticks_to_duration(ticks: u64) -> Duration {
+    let nanos = ticks as u128 * PIT_DIVISOR as u128 * 1_000_000_000 / PIT_FREQUENCY as u128;
+    Duration::from_nanos(nanos as u64)
+}
+
+///Waits at least `duration` with the cpu halted, interrupts have to be enabled
+pub fn sleep(duration: Duration) {
+    let end = uptime() + duration;
+    while uptime() < end {
+        x86_64::instructions::hlt();
+    }
+}
+
+///The ports of both PICs and the line the secondary one is chained to
+pub const RESOURCES: [Resource; 3] = [
+    Resource::ports(0x20, 2),
+    Resource::ports(0xA0, 2),
+    Resource::Irq(2),
+];
+
 pub static PICS: spin::Mutex<ChainedPics> =
     spin::Mutex::new(unsafe { ChainedPics::new(PIC_1_OFFSET, PIC_2_OFFSET) });
 
+///Everything the trap entry stubs save of the interrupted code, changes made to it
+///are restored when the handler returns
+#[derive(Debug, Clone, Copy)]
+#[repr(C)]
+pub struct TrapFrame {
+    pub r15: u64,
+    pub r14: u64,
+    pub r13: u64,
+    pub r12: u64,
+    pub r11: u64,
+    pub r10: u64,
+    pub r9: u64,
+    pub r8: u64,
+    pub rbp: u64,
+    pub rdi: u64,
+    pub rsi: u64,
+    pub rdx: u64,
+    pub rcx: u64,
+    pub rbx: u64,
+    pub rax: u64,
+    pub vector: u64,
+    pub error_code: u64,
+    pub rip: u64,
+    pub cs: u64,
+    pub rflags: u64,
+    pub rsp: u64,
+    pub ss: u64,
+}
+
+//the x86-interrupt calling convention only gives handlers the stack frame, the debugger
+//and the watchdog need every register so these entries save them by hand
+global_asm!(
+    ".global rost_breakpoint_entry",
+    ".global rost_debug_entry",
+    ".global rost_nmi_entry",
+    ".global rost_timer_entry",
+    "rost_breakpoint_entry:",
+    "    push 0",
+    "    push 3",
+    "    jmp 2f",
+    "rost_debug_entry:",
+    "    push 0",
+    "    push 1",
+    "    jmp 2f",
+    "rost_nmi_entry:",
+    "    push 0",
+    "    push 2",
+    "    jmp 2f",
+    "rost_timer_entry:",
+    "    push 0",
+    "    push {timer}",
+    "2:",
+    "    push rax",
+    "    push rbx",
+    "    push rcx",
+    "    push rdx",
+    "    push rsi",
+    "    push rdi",
+    "    push rbp",
+    "    push r8",
+    "    push r9",
+    "    push r10",
+    "    push r11",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov rdi, rsp",
+    "    cld",
+    "    call {handler}",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop r11",
+    "    pop r10",
+    "    pop r9",
+    "    pop r8",
+    "    pop rbp",
+    "    pop rdi",
+    "    pop rsi",
+    "    pop rdx",
+    "    pop rcx",
+    "    pop rbx",
+    "    pop rax",
+    // the vector and error code
+    "    add rsp, 16",
+    "    iretq",
+    handler = sym trap_handler,
+    timer = const InterruptIndex::Timer as u8,
+);
+
+extern "C" {
+    fn rost_breakpoint_entry();
+    fn rost_debug_entry();
+    fn rost_nmi_entry();
+    fn rost_timer_entry();
+}
+
 lazy_static! {
     static ref IDT: InterruptDescriptorTable = {
         let mut idt = InterruptDescriptorTable::new();
-        idt.breakpoint.set_handler_fn(breakpoint_handler);
+        unsafe {
+            idt.breakpoint
+                .set_handler_addr(VirtAddr::from_ptr(rost_breakpoint_entry as *const ()));
+            idt.debug
+                .set_handler_addr(VirtAddr::from_ptr(rost_debug_entry as *const ()));
+            idt.non_maskable_interrupt
+                .set_handler_addr(VirtAddr::from_ptr(rost_nmi_entry as *const ()));
+            idt[InterruptIndex::Timer.as_usize()]
+                .set_handler_addr(VirtAddr::from_ptr(rost_timer_entry as *const ()));
+        }
+        idt.page_fault.set_handler_fn(page_fault_handler);
         unsafe {
             idt.double_fault
                 .set_handler_fn(double_fault_handler)
                 .set_stack_index(gdt::DOUBLE_FAULT_IST_INDEX);
         }
-        idt[InterruptIndex::Timer.as_usize()].set_handler_fn(timer_interrupt_handler);
         idt[InterruptIndex::Keyboard.as_usize()].set_handler_fn(keyboard_interrupt_handler);
+        idt[InterruptIndex::Serial1.as_usize()].set_handler_fn(serial_interrupt_handler);
+        idt[InterruptIndex::Serial2.as_usize()].set_handler_fn(serial2_interrupt_handler);
+        idt[InterruptIndex::Mouse.as_usize()].set_handler_fn(mouse_interrupt_handler);
+        idt[InterruptIndex::Spurious.as_usize()].set_handler_fn(spurious_interrupt_handler);
 
         idt
     };
@@ -51,6 +206,22 @@ pub fn init_idt() {
     IDT.load();
 }
 
+///Lets an irq line of the PICs through, the line to the secondary PIC is opened as well when needed.
+///Drivers unmask the lines they claimed with `IrqLine::unmask`.
+pub(crate) fn unmask_irq(irq: u8) {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut pics = PICS.lock();
+        let mut masks = unsafe { pics.read_masks() };
+
+        masks[usize::from(irq / 8)] &= !(1 << (irq % 8));
+        if irq >= 8 {
+            masks[0] &= !(1 << 2);
+        }
+
+        unsafe { pics.write_masks(masks[0], masks[1]) };
+    });
+}
+
 extern "x86-interrupt" fn double_fault_handler(
     stack_frame: InterruptStackFrame,
     _error_code: u64,
@@ -58,74 +229,117 @@ extern "x86-interrupt" fn double_fault_handler(
     panic!("EXCEPTION: DOUBLE FAULT\n{:#?}", stack_frame);
 }
 
-extern "x86-interrupt" fn breakpoint_handler(stack_frame: InterruptStackFrame) {
-    println!("EXCEPTION: BREAKPOINT\n{:#?}", stack_frame);
+extern "x86-interrupt" fn page_fault_handler(
+    mut stack_frame: InterruptStackFrame,
+    error_code: PageFaultErrorCode,
+) {
+    //the debugger reading an address that is not mapped
+    if let Some(resume) = crate::gdb::memory::fixup(stack_frame.instruction_pointer.as_u64()) {
+        unsafe {
+            stack_frame
+                .as_mut()
+                .update(|frame| frame.instruction_pointer = VirtAddr::new(resume));
+        }
+        return;
+    }
+
+    panic!(
+        "EXCEPTION: PAGE FAULT\nAccessed Address: {:?}\nError Code: {:?}\n{:#?}",
+        Cr2::read(),
+        error_code,
+        stack_frame
+    );
+}
+
+extern "C" fn trap_handler(frame: &mut TrapFrame) {
+    match frame.vector {
+        1 => debug_handler(frame),
+        2 => crate::watchdog::handle_nmi(frame),
+        3 => breakpoint_handler(frame),
+        _ => timer_handler(frame),
+    }
+}
+
+//software breakpoints, the debugger takes over when it is set up
+fn breakpoint_handler(frame: &mut TrapFrame) {
+    if !crate::gdb::handle_trap(frame) {
+        log::info!("EXCEPTION: BREAKPOINT\n{:#x?}", frame);
+    }
+}
+
+//raised after every instruction while the trap flag is set
+fn debug_handler(frame: &mut TrapFrame) {
+    if !crate::gdb::handle_trap(frame) {
+        frame.rflags &= !RFlags::TRAP_FLAG.bits();
+    }
 }
 
-extern "x86-interrupt" fn timer_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    match TICKS.try_write() {
-        None => (),
+fn timer_handler(frame: &TrapFrame) {
+    let ticks = match TICKS.try_write() {
+        None => None,
         Some(mut ticks) => {
             *ticks += 1;
+            Some(*ticks)
         }
+    };
+    if let Some(ticks) = ticks {
+        crate::pc_speaker::timer_tick(ticks_to_duration(ticks));
     }
 
     unsafe {
         PICS.lock()
             .notify_end_of_interrupt(InterruptIndex::Timer.as_u8());
     }
+
+    //these may end a test or the kernel, so they have to come after the end of interrupt
+    if let Some(ticks) = ticks {
+        crate::watchdog::check(ticks_to_duration(ticks), frame);
+        crate::testing::check_timeout(ticks_to_duration(ticks));
+    }
 }
 
-//Handles the keyboard interrupt
-extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
-    use crate::vga_driver::{change_screen_color, Color};
-    use pc_keyboard::{layouts, DecodedKey, HandleControl, KeyCode, Keyboard, ScancodeSet1};
-    use spin::Mutex;
-    use x86_64::instructions::port::Port;
+extern "x86-interrupt" fn serial_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    crate::serial::handle_interrupt();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial1.as_u8());
+    }
+}
+
+extern "x86-interrupt" fn mouse_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    if let Some(byte) = crate::ps2::interrupt_byte() {
+        crate::mouse::handle_byte(byte);
+    }
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Mouse.as_u8());
+    }
+}
 
-    lazy_static! {
-        static ref KEYBOARD: Mutex<Keyboard<layouts::Us104Key, ScancodeSet1>> = Mutex::new(
-            Keyboard::new(layouts::Us104Key, ScancodeSet1, HandleControl::Ignore)
-        );
+extern "x86-interrupt" fn serial2_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    let stop = crate::gdb::handle_interrupt();
+
+    unsafe {
+        PICS.lock()
+            .notify_end_of_interrupt(InterruptIndex::Serial2.as_u8());
     }
 
-    let mut keyboard = KEYBOARD.lock();
-    let mut port = Port::new(0x60);
+    //gdb asked to stop the kernel, it will see it stopped in here
+    if stop {
+        x86_64::instructions::interrupts::int3();
+    }
+}
+
+//Handles the keyboard interrupt
+extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStackFrame) {
+    use crate::vga_driver::{change_screen_color, Color};
 
     change_screen_color(Color::Green, Color::Blue);
 
-    /*
-                        KeyCode::ArrowLeft => {
-
-                        },
-                        KeyCode::ArrowRight => {},
-                        KeyCode::ArrowUp => {},
-                        KeyCode::ArrowDown => {},
-    */
-
-    let scancode: u8 = unsafe { port.read() };
-    if let Ok(Some(key_event)) = keyboard.add_byte(scancode) {
-        if let Some(key) = keyboard.process_keyevent(key_event) {
-            /*match key {
-                DecodedKey::Unicode(character) => print!("{}", character),
-                DecodedKey::RawKey(key) => match key {
-                    KeyCode::ArrowLeft => {
-                        vga_driver::move_cursor_by(-1, 0);
-                    },
-                    KeyCode::ArrowRight => {
-                        vga_driver::move_cursor_by(1, 0);
-                    },
-                    KeyCode::ArrowUp => {
-                        vga_driver::move_cursor_by(0, -1);
-                    },
-                    KeyCode::ArrowDown => {
-                        vga_driver::move_cursor_by(0, 1);
-                    },
-                    _ => {},
-                },
-            }*/
-            crate::io::INPUTBUFFER.write().write_key(key);
-        }
+    if let Some(scancode) = crate::ps2::interrupt_byte() {
+        crate::keyboard::handle_scancode(scancode);
     }
 
     unsafe {
@@ -133,3 +347,18 @@ extern "x86-interrupt" fn keyboard_interrupt_handler(_stack_frame: InterruptStac
             .notify_end_of_interrupt(InterruptIndex::Keyboard.as_u8());
     }
 }
+
+//nothing to do, a spurious interrupt must not get an end of interrupt
+extern "x86-interrupt" fn spurious_interrupt_handler(_stack_frame: InterruptStackFrame) {}
+
+#[cfg(test)]
+fn bench_interrupt_dispatch(bencher: &mut crate::testing::bench::Bencher) {
+    //the cheapest way into and out of the kernel's interrupt handlers
+    bencher.iter(|| unsafe {
+        core::arch::asm!("int {}", const InterruptIndex::Spurious as u8, options(nomem, nostack))
+    });
+}
+
+#[test_case]
+const BENCH_INTERRUPT_DISPATCH: crate::testing::bench::BenchDescriptor =
+    crate::bench_descriptor!(bench_interrupt_dispatch);
@@ -4,41 +4,15 @@ use spin::RwLock;
 
 use crate::serial_println;
 
-lazy_static! {
-    pub static ref INPUTBUFFER: RwLock<InputBuffer> = RwLock::new(InputBuffer {
-        buffer: [DecodedKey::Unicode('0'); 255],
-        unread_char_count: 0,
-    });
-}
+pub use rost_core::queue::RingBuffer;
 
-#[derive(Debug, Clone, Copy)]
-pub struct InputBuffer {
-    pub buffer: [DecodedKey; 255],
-    pub unread_char_count: usize,
+lazy_static! {
+    pub static ref INPUTBUFFER: RwLock<InputBuffer> =
+        RwLock::new(InputBuffer::new(DecodedKey::Unicode('0')));
 }
 
-pub struct InputReturn(usize, [DecodedKey; 255]);
-
-impl InputBuffer {
-    ///appends to the front of the buffer deleting the last item in the buffer
-    pub fn write_key(&mut self, key: DecodedKey) {
-        self.buffer[..].rotate_right(1);
-        self.buffer[0] = key;
-        self.unread_char_count = self.unread_char_count.saturating_add(1);
-    }
-
-    ///Returns a copy of the current INPUTBUFFER and sets the read index to 0
-    pub fn read(mut self) -> InputReturn {
-        let return_object = InputReturn(self.unread_char_count.clone(), self.buffer.clone());
-        self.unread_char_count = 0;
-        return_object
-    }
-
-    pub fn read_unread(self) {
-        //This can be added if we ever decide to add such a thing known as a heap
-        todo!();
-    }
-}
+pub type InputBuffer = rost_core::queue::InputBuffer<DecodedKey, 255>;
+pub type InputReturn = rost_core::queue::InputReturn<DecodedKey, 255>;
 
 #[test_case]
 fn test_write_unicode() {
@@ -76,3 +50,14 @@ fn test_read_index() {
     }
     assert_eq!(input_buffer.unread_char_count, 4);
 }
+
+#[cfg(test)]
+fn bench_write_key(bencher: &mut crate::testing::bench::Bencher) {
+    //a copy, the keys written would otherwise show up as typed
+    let mut input_buffer = *INPUTBUFFER.read();
+    bencher.iter(|| input_buffer.write_key(DecodedKey::Unicode('k')));
+}
+
+#[test_case]
+const BENCH_WRITE_KEY: crate::testing::bench::BenchDescriptor =
+    crate::bench_descriptor!(bench_write_key);
@@ -0,0 +1,226 @@
+//! Key presses and releases as they happen, for programs that need more than the typed text
+//!
+//! Every key the keyboard reports, modifiers and releases included, becomes a `KeyEvent`
+//! that is copied to each subscriber. Which keys are held right now can be asked from
+//! `keyboard::is_pressed`.
+
+use core::ops::BitOr;
+use core::time::Duration;
+
+use pc_keyboard::{KeyCode, KeyState};
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::io::RingBuffer;
+
+pub const MAX_SUBSCRIBERS: usize = 8;
+///How many events a subscriber can fall behind before the oldest are dropped
+pub const QUEUE_LENGTH: usize = 64;
+
+type Queue = RingBuffer<KeyEvent, QUEUE_LENGTH>;
+
+const EMPTY_EVENT: KeyEvent = KeyEvent {
+    code: KeyCode::Escape,
+    state: KeyState::Up,
+    modifiers: ModifierMask::NONE,
+    timestamp: Duration::ZERO,
+    repeat: false,
+};
+
+static SUBSCRIBERS: Mutex<[Option<Queue>; MAX_SUBSCRIBERS]> = Mutex::new([None; MAX_SUBSCRIBERS]);
+
+///The modifiers held and the lock keys on when a key event happened
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct ModifierMask(u8);
+
+impl ModifierMask {
+    pub const NONE: ModifierMask = ModifierMask(0);
+    pub const SHIFT: ModifierMask = ModifierMask(1 << 0);
+    pub const CTRL: ModifierMask = ModifierMask(1 << 1);
+    pub const ALT: ModifierMask = ModifierMask(1 << 2);
+    pub const ALT_GR: ModifierMask = ModifierMask(1 << 3);
+    pub const CAPS_LOCK: ModifierMask = ModifierMask(1 << 4);
+    pub const NUM_LOCK: ModifierMask = ModifierMask(1 << 5);
+    pub const SCROLL_LOCK: ModifierMask = ModifierMask(1 << 6);
+
+    pub const fn bits(&self) -> u8 {
+        self.0
+    }
+
+    ///True if every modifier in `other` is in this mask too
+    pub const fn contains(&self, other: ModifierMask) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    ///Adds `other` if `condition` holds
+    pub const fn with_if(self, other: ModifierMask, condition: bool) -> ModifierMask {
+        if condition {
+            ModifierMask(self.0 | other.0)
+        } else {
+            self
+        }
+    }
+}
+
+impl BitOr for ModifierMask {
+    type Output = ModifierMask;
+
+    fn bitor(self, other: ModifierMask) -> ModifierMask {
+        ModifierMask(self.0 | other.0)
+    }
+}
+
+///One key going down or up
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub state: KeyState,
+    ///The modifiers right after the key was handled, so a shift press has `SHIFT` set
+    pub modifiers: ModifierMask,
+    ///The uptime when the key arrived
+    pub timestamp: Duration,
+    ///Set for the presses the keyboard repeats while a key is held down
+    pub repeat: bool,
+}
+
+impl KeyEvent {
+    pub fn is_press(&self) -> bool {
+        self.state == KeyState::Down
+    }
+}
+
+///Which keys are held down, one bit per key code
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct KeyStates([u64; 4]);
+
+impl KeyStates {
+    pub const fn new() -> KeyStates {
+        KeyStates([0; 4])
+    }
+
+    pub fn is_pressed(&self, code: KeyCode) -> bool {
+        let index = code as usize;
+        self.0[index / 64] & 1 << (index % 64) != 0
+    }
+
+    ///Marks a key pressed or released, returns whether it was pressed before
+    pub fn set(&mut self, code: KeyCode, pressed: bool) -> bool {
+        let index = code as usize;
+        let was_pressed = self.is_pressed(code);
+        if pressed {
+            self.0[index / 64] |= 1 << (index % 64);
+        } else {
+            self.0[index / 64] &= !(1 << (index % 64));
+        }
+        was_pressed
+    }
+
+    pub fn any_pressed(&self) -> bool {
+        self.0.iter().any(|bits| *bits != 0)
+    }
+
+    pub fn clear(&mut self) {
+        self.0 = [0; 4];
+    }
+}
+
+///A queue of the key events since subscribing, unsubscribes when dropped
+#[derive(Debug)]
+pub struct Subscription {
+    slot: usize,
+}
+
+impl Subscription {
+    ///Takes the oldest event if there is one
+    pub fn try_next(&self) -> Option<KeyEvent> {
+        interrupts::without_interrupts(|| {
+            SUBSCRIBERS.lock()[self.slot]
+                .as_mut()
+                .and_then(|queue| queue.pop())
+        })
+    }
+
+    ///Waits for the next event, interrupts have to be enabled
+    pub fn next(&self) -> KeyEvent {
+        loop {
+            interrupts::disable();
+            let event = SUBSCRIBERS.lock()[self.slot]
+                .as_mut()
+                .and_then(|queue| queue.pop());
+            if let Some(event) = event {
+                interrupts::enable();
+                return event;
+            }
+            // enabling and halting together means the interrupt can not slip in between
+            interrupts::enable_and_hlt();
+        }
+    }
+
+    ///Throws away the events nobody read yet
+    pub fn clear(&self) {
+        interrupts::without_interrupts(|| {
+            if let Some(queue) = SUBSCRIBERS.lock()[self.slot].as_mut() {
+                queue.clear();
+            }
+        });
+    }
+}
+
+impl Drop for Subscription {
+    fn drop(&mut self) {
+        interrupts::without_interrupts(|| SUBSCRIBERS.lock()[self.slot] = None);
+    }
+}
+
+///Starts receiving key events, `None` when there are `MAX_SUBSCRIBERS` already
+pub fn subscribe() -> Option<Subscription> {
+    interrupts::without_interrupts(|| {
+        let mut subscribers = SUBSCRIBERS.lock();
+        let slot = subscribers.iter().position(Option::is_none)?;
+        subscribers[slot] = Some(Queue::new(EMPTY_EVENT));
+        Some(Subscription { slot })
+    })
+}
+
+///Hands an event to every subscriber, called from the keyboard interrupt
+pub(super) fn publish(event: KeyEvent) {
+    for queue in SUBSCRIBERS.lock().iter_mut().flatten() {
+        queue.push_overwrite(event);
+    }
+}
+
+#[test_case]
+fn test_key_states() {
+    let mut states = KeyStates::new();
+
+    assert!(!states.set(KeyCode::A, true));
+    assert!(states.set(KeyCode::A, true));
+    assert!(states.is_pressed(KeyCode::A));
+    assert!(!states.is_pressed(KeyCode::B));
+    assert!(states.set(KeyCode::A, false));
+    assert!(!states.any_pressed());
+}
+
+#[test_case]
+fn test_subscriptions() {
+    let first = subscribe().unwrap();
+    let event = KeyEvent {
+        code: KeyCode::Q,
+        state: KeyState::Down,
+        modifiers: ModifierMask::SHIFT | ModifierMask::NUM_LOCK,
+        ..EMPTY_EVENT
+    };
+    interrupts::without_interrupts(|| publish(event));
+
+    let second = subscribe().unwrap();
+    assert_eq!(first.try_next(), Some(event));
+    assert_eq!(first.try_next(), None);
+    assert_eq!(second.try_next(), None);
+
+    let slot = first.slot;
+    drop(first);
+    assert_eq!(
+        subscribe().map(|subscription| subscription.slot),
+        Some(slot)
+    );
+}
@@ -0,0 +1,484 @@
+//! What the character keys type on the supported layouts
+//!
+//! Keys are named after what they type on a US keyboard. Every layout only lists the keys
+//! that differ from the US layout, all the others fall back to it.
+
+use pc_keyboard::KeyCode;
+
+///pc_keyboard has no name for the extra key left of Z on ISO keyboards, this one stands in for it
+pub const ISO_KEY: KeyCode = KeyCode::HashTilde;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Layout {
+    Us,
+    Uk,
+    German,
+    FinnishSwedish,
+    Dvorak,
+    Colemak,
+    Azerty,
+}
+
+impl Layout {
+    pub const ALL: [Layout; 7] = [
+        Layout::Us,
+        Layout::Uk,
+        Layout::German,
+        Layout::FinnishSwedish,
+        Layout::Dvorak,
+        Layout::Colemak,
+        Layout::Azerty,
+    ];
+
+    ///The short name used to pick the layout from the shell
+    pub fn name(&self) -> &'static str {
+        match self {
+            Layout::Us => "us",
+            Layout::Uk => "uk",
+            Layout::German => "de",
+            Layout::FinnishSwedish => "fi",
+            Layout::Dvorak => "dvorak",
+            Layout::Colemak => "colemak",
+            Layout::Azerty => "fr",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Layout> {
+        match name {
+            "se" => Some(Layout::FinnishSwedish),
+            "azerty" => Some(Layout::Azerty),
+            _ => Layout::ALL
+                .iter()
+                .copied()
+                .find(|layout| layout.name() == name),
+        }
+    }
+
+    ///Whether the right alt key is AltGr instead of a second alt
+    pub fn has_alt_gr(&self) -> bool {
+        matches!(
+            self,
+            Layout::Uk | Layout::German | Layout::FinnishSwedish | Layout::Azerty
+        )
+    }
+
+    ///What a key types, None for keys that are not character keys
+    pub fn key_map(&self, key: KeyCode) -> Option<KeyMap> {
+        let mapped = match self {
+            Layout::Us => None,
+            Layout::Uk => uk(key),
+            Layout::German => german(key),
+            Layout::FinnishSwedish => finnish_swedish(key),
+            Layout::Dvorak => dvorak(key),
+            Layout::Colemak => colemak(key),
+            Layout::Azerty => azerty(key),
+        };
+        mapped.or_else(|| us(key))
+    }
+}
+
+///A key that only puts an accent on the next key
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeadKey {
+    Grave,
+    Acute,
+    Circumflex,
+    Tilde,
+    Diaeresis,
+}
+
+impl DeadKey {
+    ///The accent on its own, typed when the next key can not take it
+    pub fn spacing(&self) -> char {
+        match self {
+            DeadKey::Grave => '`',
+            DeadKey::Acute => '´',
+            DeadKey::Circumflex => '^',
+            DeadKey::Tilde => '~',
+            DeadKey::Diaeresis => '¨',
+        }
+    }
+
+    pub fn compose(&self, base: char) -> Option<char> {
+        let composed = match (self, base) {
+            (DeadKey::Grave, 'a') => 'à',
+            (DeadKey::Grave, 'e') => 'è',
+            (DeadKey::Grave, 'i') => 'ì',
+            (DeadKey::Grave, 'o') => 'ò',
+            (DeadKey::Grave, 'u') => 'ù',
+            (DeadKey::Grave, 'A') => 'À',
+            (DeadKey::Grave, 'E') => 'È',
+            (DeadKey::Grave, 'I') => 'Ì',
+            (DeadKey::Grave, 'O') => 'Ò',
+            (DeadKey::Grave, 'U') => 'Ù',
+            (DeadKey::Acute, 'a') => 'á',
+            (DeadKey::Acute, 'e') => 'é',
+            (DeadKey::Acute, 'i') => 'í',
+            (DeadKey::Acute, 'o') => 'ó',
+            (DeadKey::Acute, 'u') => 'ú',
+            (DeadKey::Acute, 'y') => 'ý',
+            (DeadKey::Acute, 'A') => 'Á',
+            (DeadKey::Acute, 'E') => 'É',
+            (DeadKey::Acute, 'I') => 'Í',
+            (DeadKey::Acute, 'O') => 'Ó',
+            (DeadKey::Acute, 'U') => 'Ú',
+            (DeadKey::Acute, 'Y') => 'Ý',
+            (DeadKey::Circumflex, 'a') => 'â',
+            (DeadKey::Circumflex, 'e') => 'ê',
+            (DeadKey::Circumflex, 'i') => 'î',
+            (DeadKey::Circumflex, 'o') => 'ô',
+            (DeadKey::Circumflex, 'u') => 'û',
+            (DeadKey::Circumflex, 'A') => 'Â',
+            (DeadKey::Circumflex, 'E') => 'Ê',
+            (DeadKey::Circumflex, 'I') => 'Î',
+            (DeadKey::Circumflex, 'O') => 'Ô',
+            (DeadKey::Circumflex, 'U') => 'Û',
+            (DeadKey::Tilde, 'a') => 'ã',
+            (DeadKey::Tilde, 'n') => 'ñ',
+            (DeadKey::Tilde, 'o') => 'õ',
+            (DeadKey::Tilde, 'A') => 'Ã',
+            (DeadKey::Tilde, 'N') => 'Ñ',
+            (DeadKey::Tilde, 'O') => 'Õ',
+            (DeadKey::Diaeresis, 'a') => 'ä',
+            (DeadKey::Diaeresis, 'e') => 'ë',
+            (DeadKey::Diaeresis, 'i') => 'ï',
+            (DeadKey::Diaeresis, 'o') => 'ö',
+            (DeadKey::Diaeresis, 'u') => 'ü',
+            (DeadKey::Diaeresis, 'y') => 'ÿ',
+            (DeadKey::Diaeresis, 'A') => 'Ä',
+            (DeadKey::Diaeresis, 'E') => 'Ë',
+            (DeadKey::Diaeresis, 'I') => 'Ï',
+            (DeadKey::Diaeresis, 'O') => 'Ö',
+            (DeadKey::Diaeresis, 'U') => 'Ü',
+            (DeadKey::Diaeresis, 'Y') => 'Ÿ',
+            _ => return None,
+        };
+        Some(composed)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Output {
+    Char(char),
+    Dead(DeadKey),
+    Nothing,
+}
+
+impl From<char> for Output {
+    fn from(character: char) -> Self {
+        Output::Char(character)
+    }
+}
+
+impl From<DeadKey> for Output {
+    fn from(dead_key: DeadKey) -> Self {
+        Output::Dead(dead_key)
+    }
+}
+
+///What a key types on its own, with shift and with AltGr
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct KeyMap {
+    pub normal: Output,
+    pub shifted: Output,
+    pub alt_gr: Output,
+}
+
+impl KeyMap {
+    pub fn with_alt_gr(mut self, alt_gr: impl Into<Output>) -> KeyMap {
+        self.alt_gr = alt_gr.into();
+        self
+    }
+
+    ///Caps lock works like shift only on letters
+    pub fn is_letter(&self) -> bool {
+        match (self.normal, self.shifted) {
+            (Output::Char(normal), Output::Char(shifted)) => {
+                normal.is_alphabetic() && normal.to_uppercase().eq(core::iter::once(shifted))
+            }
+            _ => false,
+        }
+    }
+}
+
+fn keys(normal: impl Into<Output>, shifted: impl Into<Output>) -> KeyMap {
+    KeyMap {
+        normal: normal.into(),
+        shifted: shifted.into(),
+        alt_gr: Output::Nothing,
+    }
+}
+
+fn letter(normal: char) -> KeyMap {
+    keys(normal, normal.to_ascii_uppercase())
+}
+
+fn us(key: KeyCode) -> Option<KeyMap> {
+    use KeyCode::*;
+
+    Some(match key {
+        BackTick => keys('`', '~'),
+        Key1 => keys('1', '!'),
+        Key2 => keys('2', '@'),
+        Key3 => keys('3', '#'),
+        Key4 => keys('4', '$'),
+        Key5 => keys('5', '%'),
+        Key6 => keys('6', '^'),
+        Key7 => keys('7', '&'),
+        Key8 => keys('8', '*'),
+        Key9 => keys('9', '('),
+        Key0 => keys('0', ')'),
+        Minus => keys('-', '_'),
+        Equals => keys('=', '+'),
+        Q => letter('q'),
+        W => letter('w'),
+        E => letter('e'),
+        R => letter('r'),
+        T => letter('t'),
+        Y => letter('y'),
+        U => letter('u'),
+        I => letter('i'),
+        O => letter('o'),
+        P => letter('p'),
+        BracketSquareLeft => keys('[', '{'),
+        BracketSquareRight => keys(']', '}'),
+        BackSlash => keys('\\', '|'),
+        A => letter('a'),
+        S => letter('s'),
+        D => letter('d'),
+        F => letter('f'),
+        G => letter('g'),
+        H => letter('h'),
+        J => letter('j'),
+        K => letter('k'),
+        L => letter('l'),
+        SemiColon => keys(';', ':'),
+        Quote => keys('\'', '"'),
+        ISO_KEY => keys('\\', '|'),
+        Z => letter('z'),
+        X => letter('x'),
+        C => letter('c'),
+        V => letter('v'),
+        B => letter('b'),
+        N => letter('n'),
+        M => letter('m'),
+        Comma => keys(',', '<'),
+        Fullstop => keys('.', '>'),
+        Slash => keys('/', '?'),
+        Spacebar => keys(' ', ' '),
+        _ => return None,
+    })
+}
+
+fn uk(key: KeyCode) -> Option<KeyMap> {
+    use KeyCode::*;
+
+    Some(match key {
+        BackTick => keys('`', '¬').with_alt_gr('¦'),
+        Key2 => keys('2', '"'),
+        Key3 => keys('3', '£'),
+        Key4 => keys('4', '$').with_alt_gr('€'),
+        Quote => keys('\'', '@'),
+        BackSlash => keys('#', '~'),
+        A => letter('a').with_alt_gr('á'),
+        E => letter('e').with_alt_gr('é'),
+        I => letter('i').with_alt_gr('í'),
+        O => letter('o').with_alt_gr('ó'),
+        U => letter('u').with_alt_gr('ú'),
+        _ => return None,
+    })
+}
+
+fn german(key: KeyCode) -> Option<KeyMap> {
+    use KeyCode::*;
+
+    Some(match key {
+        BackTick => keys(DeadKey::Circumflex, '°'),
+        Key2 => keys('2', '"').with_alt_gr('²'),
+        Key3 => keys('3', '§').with_alt_gr('³'),
+        Key6 => keys('6', '&'),
+        Key7 => keys('7', '/').with_alt_gr('{'),
+        Key8 => keys('8', '(').with_alt_gr('['),
+        Key9 => keys('9', ')').with_alt_gr(']'),
+        Key0 => keys('0', '=').with_alt_gr('}'),
+        Minus => keys('ß', '?').with_alt_gr('\\'),
+        Equals => keys(DeadKey::Acute, DeadKey::Grave),
+        Q => letter('q').with_alt_gr('@'),
+        E => letter('e').with_alt_gr('€'),
+        Y => letter('z'),
+        BracketSquareLeft => keys('ü', 'Ü'),
+        BracketSquareRight => keys('+', '*').with_alt_gr('~'),
+        SemiColon => keys('ö', 'Ö'),
+        Quote => keys('ä', 'Ä'),
+        BackSlash => keys('#', '\''),
+        ISO_KEY => keys('<', '>').with_alt_gr('|'),
+        Z => letter('y'),
+        M => letter('m').with_alt_gr('µ'),
+        Comma => keys(',', ';'),
+        Fullstop => keys('.', ':'),
+        Slash => keys('-', '_'),
+        _ => return None,
+    })
+}
+
+fn finnish_swedish(key: KeyCode) -> Option<KeyMap> {
+    use KeyCode::*;
+
+    Some(match key {
+        BackTick => keys('§', '½'),
+        Key2 => keys('2', '"').with_alt_gr('@'),
+        Key3 => keys('3', '#').with_alt_gr('£'),
+        Key4 => keys('4', '¤').with_alt_gr('$'),
+        Key5 => keys('5', '%').with_alt_gr('€'),
+        Key6 => keys('6', '&'),
+        Key7 => keys('7', '/').with_alt_gr('{'),
+        Key8 => keys('8', '(').with_alt_gr('['),
+        Key9 => keys('9', ')').with_alt_gr(']'),
+        Key0 => keys('0', '=').with_alt_gr('}'),
+        Minus => keys('+', '?').with_alt_gr('\\'),
+        Equals => keys(DeadKey::Acute, DeadKey::Grave),
+        E => letter('e').with_alt_gr('€'),
+        BracketSquareLeft => keys('å', 'Å'),
+        BracketSquareRight => {
+            keys(DeadKey::Diaeresis, DeadKey::Circumflex).with_alt_gr(DeadKey::Tilde)
+        }
+        SemiColon => keys('ö', 'Ö'),
+        Quote => keys('ä', 'Ä'),
+        BackSlash => keys('\'', '*'),
+        ISO_KEY => keys('<', '>').with_alt_gr('|'),
+        M => letter('m').with_alt_gr('µ'),
+        Comma => keys(',', ';'),
+        Fullstop => keys('.', ':'),
+        Slash => keys('-', '_'),
+        _ => return None,
+    })
+}
+
+fn dvorak(key: KeyCode) -> Option<KeyMap> {
+    use KeyCode::*;
+
+    Some(match key {
+        Minus => keys('[', '{'),
+        Equals => keys(']', '}'),
+        Q => keys('\'', '"'),
+        W => keys(',', '<'),
+        E => keys('.', '>'),
+        R => letter('p'),
+        T => letter('y'),
+        Y => letter('f'),
+        U => letter('g'),
+        I => letter('c'),
+        O => letter('r'),
+        P => letter('l'),
+        BracketSquareLeft => keys('/', '?'),
+        BracketSquareRight => keys('=', '+'),
+        A => letter('a'),
+        S => letter('o'),
+        D => letter('e'),
+        F => letter('u'),
+        G => letter('i'),
+        H => letter('d'),
+        J => letter('h'),
+        K => letter('t'),
+        L => letter('n'),
+        SemiColon => letter('s'),
+        Quote => keys('-', '_'),
+        Z => keys(';', ':'),
+        X => letter('q'),
+        C => letter('j'),
+        V => letter('k'),
+        B => letter('x'),
+        N => letter('b'),
+        Comma => letter('w'),
+        Fullstop => letter('v'),
+        Slash => letter('z'),
+        _ => return None,
+    })
+}
+
+///Caps lock is a second backspace on colemak, that is handled with the other special keys
+fn colemak(key: KeyCode) -> Option<KeyMap> {
+    use KeyCode::*;
+
+    Some(match key {
+        E => letter('f'),
+        R => letter('p'),
+        T => letter('g'),
+        Y => letter('j'),
+        U => letter('l'),
+        I => letter('u'),
+        O => letter('y'),
+        P => keys(';', ':'),
+        S => letter('r'),
+        D => letter('s'),
+        F => letter('t'),
+        G => letter('d'),
+        J => letter('n'),
+        K => letter('e'),
+        L => letter('i'),
+        SemiColon => letter('o'),
+        N => letter('k'),
+        _ => return None,
+    })
+}
+
+fn azerty(key: KeyCode) -> Option<KeyMap> {
+    use KeyCode::*;
+
+    Some(match key {
+        BackTick => keys('²', Output::Nothing),
+        Key1 => keys('&', '1'),
+        Key2 => keys('é', '2').with_alt_gr(DeadKey::Tilde),
+        Key3 => keys('"', '3').with_alt_gr('#'),
+        Key4 => keys('\'', '4').with_alt_gr('{'),
+        Key5 => keys('(', '5').with_alt_gr('['),
+        Key6 => keys('-', '6').with_alt_gr('|'),
+        Key7 => keys('è', '7').with_alt_gr(DeadKey::Grave),
+        Key8 => keys('_', '8').with_alt_gr('\\'),
+        Key9 => keys('ç', '9').with_alt_gr('^'),
+        Key0 => keys('à', '0').with_alt_gr('@'),
+        Minus => keys(')', '°').with_alt_gr(']'),
+        Equals => keys('=', '+').with_alt_gr('}'),
+        Q => letter('a'),
+        W => letter('z'),
+        E => letter('e').with_alt_gr('€'),
+        BracketSquareLeft => keys(DeadKey::Circumflex, DeadKey::Diaeresis),
+        BracketSquareRight => keys('$', '£').with_alt_gr('¤'),
+        BackSlash => keys('*', 'µ'),
+        A => letter('q'),
+        SemiColon => letter('m'),
+        Quote => keys('ù', '%'),
+        ISO_KEY => keys('<', '>'),
+        Z => letter('w'),
+        M => keys(',', '?'),
+        Comma => keys(';', '.'),
+        Fullstop => keys(':', '/'),
+        Slash => keys('!', '§'),
+        _ => return None,
+    })
+}
+
+#[test_case]
+fn test_layout_names() {
+    for layout in Layout::ALL {
+        assert_eq!(Layout::from_name(layout.name()), Some(layout));
+    }
+    assert_eq!(Layout::from_name("se"), Some(Layout::FinnishSwedish));
+    assert_eq!(Layout::from_name("xx"), None);
+}
+
+#[test_case]
+fn test_layout_fallback() {
+    assert_eq!(Layout::German.key_map(KeyCode::Y), Some(letter('z')));
+    assert_eq!(Layout::German.key_map(KeyCode::A), Some(letter('a')));
+    assert_eq!(Layout::Dvorak.key_map(KeyCode::Enter), None);
+    assert!(Layout::German.key_map(KeyCode::Quote).unwrap().is_letter());
+    assert!(!Layout::Azerty.key_map(KeyCode::Key2).unwrap().is_letter());
+}
+
+#[test_case]
+fn test_dead_key_compose() {
+    assert_eq!(DeadKey::Diaeresis.compose('o'), Some('ö'));
+    assert_eq!(DeadKey::Circumflex.compose('E'), Some('Ê'));
+    assert_eq!(DeadKey::Tilde.compose('x'), None);
+}
@@ -0,0 +1,440 @@
+//! The PS/2 keyboard, from scancodes to the keys in the input buffer
+//!
+//! Scancodes are decoded into key presses, the modifier keys are tracked here and the
+//! current layout decides what the character keys type. The layout can be changed at any
+//! time with `set_layout`. The lock keys are shown on the keyboard leds once the PS/2
+//! controller is set up. Besides the typed keys every press and release is sent to the
+//! subscribers in `event`.
+
+use core::time::Duration;
+
+use pc_keyboard::{DecodedKey, KeyCode, KeyEvent as RawKeyEvent, KeyState};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+pub mod event;
+pub mod layouts;
+pub mod scancode;
+
+use crate::ps2::{Leds, ScancodeSet};
+use event::KeyStates;
+pub use event::{subscribe, KeyEvent, ModifierMask, Subscription};
+pub use layouts::Layout;
+use layouts::{DeadKey, Output};
+use scancode::Decoder;
+
+static KEYBOARD: Mutex<Keyboard> = Mutex::new(Keyboard::new(Layout::Us));
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Modifiers {
+    pub left_shift: bool,
+    pub right_shift: bool,
+    pub left_ctrl: bool,
+    pub right_ctrl: bool,
+    pub alt: bool,
+    pub alt_gr: bool,
+    pub caps_lock: bool,
+    pub num_lock: bool,
+    pub scroll_lock: bool,
+}
+
+impl Modifiers {
+    pub fn shift(&self) -> bool {
+        self.left_shift || self.right_shift
+    }
+
+    pub fn ctrl(&self) -> bool {
+        self.left_ctrl || self.right_ctrl
+    }
+
+    pub fn leds(&self) -> Leds {
+        Leds {
+            scroll_lock: self.scroll_lock,
+            num_lock: self.num_lock,
+            caps_lock: self.caps_lock,
+        }
+    }
+
+    pub fn mask(&self) -> ModifierMask {
+        ModifierMask::NONE
+            .with_if(ModifierMask::SHIFT, self.shift())
+            .with_if(ModifierMask::CTRL, self.ctrl())
+            .with_if(ModifierMask::ALT, self.alt)
+            .with_if(ModifierMask::ALT_GR, self.alt_gr)
+            .with_if(ModifierMask::CAPS_LOCK, self.caps_lock)
+            .with_if(ModifierMask::NUM_LOCK, self.num_lock)
+            .with_if(ModifierMask::SCROLL_LOCK, self.scroll_lock)
+    }
+}
+
+#[derive(Debug)]
+pub struct Keyboard {
+    decoder: Decoder,
+    modifiers: Modifiers,
+    layout: Layout,
+    dead_key: Option<DeadKey>,
+    pressed: KeyStates,
+}
+
+impl Keyboard {
+    pub const fn new(layout: Layout) -> Keyboard {
+        Keyboard {
+            decoder: Decoder::Set1(scancode::ScancodeSet1::new()),
+            modifiers: Modifiers {
+                left_shift: false,
+                right_shift: false,
+                left_ctrl: false,
+                right_ctrl: false,
+                alt: false,
+                alt_gr: false,
+                caps_lock: false,
+                num_lock: true,
+                scroll_lock: false,
+            },
+            layout,
+            dead_key: None,
+            pressed: KeyStates::new(),
+        }
+    }
+
+    pub fn layout(&self) -> Layout {
+        self.layout
+    }
+
+    ///Switches the layout, a half typed accent is thrown away
+    pub fn set_layout(&mut self, layout: Layout) {
+        self.layout = layout;
+        self.dead_key = None;
+    }
+
+    pub fn modifiers(&self) -> Modifiers {
+        self.modifiers
+    }
+
+    ///Starts decoding another scancode set, returns false for a set that can't be decoded
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) -> bool {
+        match Decoder::new(set) {
+            Some(decoder) => {
+                self.decoder = decoder;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn is_pressed(&self, code: KeyCode) -> bool {
+        self.pressed.is_pressed(code)
+    }
+
+    ///Decodes a scancode byte, `emit` gets every key it types. Returns the press or
+    ///release the byte completed, stamped with `timestamp`.
+    pub fn add_byte(
+        &mut self,
+        byte: u8,
+        timestamp: Duration,
+        emit: impl FnMut(DecodedKey),
+    ) -> Option<KeyEvent> {
+        let event = self.decoder.add_byte(byte).ok()??;
+        let was_pressed = self.pressed.set(event.code, event.state == KeyState::Down);
+        self.process_event(event.clone(), emit);
+
+        Some(KeyEvent {
+            code: event.code,
+            state: event.state,
+            modifiers: self.modifiers.mask(),
+            timestamp,
+            repeat: was_pressed && event.state == KeyState::Down,
+        })
+    }
+
+    ///A dead key followed by a key that can not take its accent gives two keys at once
+    pub fn process_event(&mut self, event: RawKeyEvent, mut emit: impl FnMut(DecodedKey)) {
+        if self.update_modifiers(&event) || event.state == KeyState::Up {
+            return;
+        }
+
+        let key = match self.layout.key_map(event.code) {
+            Some(key_map) => {
+                let shifted =
+                    self.modifiers.shift() ^ (self.modifiers.caps_lock && key_map.is_letter());
+                if self.modifiers.alt_gr && key_map.alt_gr != Output::Nothing {
+                    key_map.alt_gr
+                } else if shifted {
+                    key_map.shifted
+                } else {
+                    key_map.normal
+                }
+            }
+            None => {
+                self.dead_key = None;
+                if let Some(key) = self.special_key(event.code) {
+                    emit(key);
+                }
+                return;
+            }
+        };
+
+        match (key, self.dead_key.take()) {
+            (Output::Dead(dead_key), None) => self.dead_key = Some(dead_key),
+            (Output::Dead(dead_key), Some(pending)) => {
+                emit(DecodedKey::Unicode(pending.spacing()));
+                if dead_key != pending {
+                    self.dead_key = Some(dead_key);
+                }
+            }
+            (Output::Char(' '), Some(pending)) => emit(DecodedKey::Unicode(pending.spacing())),
+            (Output::Char(character), Some(pending)) => match pending.compose(character) {
+                Some(composed) => emit(DecodedKey::Unicode(composed)),
+                None => {
+                    emit(DecodedKey::Unicode(pending.spacing()));
+                    emit(DecodedKey::Unicode(character));
+                }
+            },
+            (Output::Char(character), None) => emit(DecodedKey::Unicode(character)),
+            (Output::Nothing, pending) => self.dead_key = pending,
+        }
+    }
+
+    //returns true for the modifier keys, they never type anything
+    fn update_modifiers(&mut self, event: &RawKeyEvent) -> bool {
+        let down = event.state == KeyState::Down;
+        let modifiers = &mut self.modifiers;
+
+        match event.code {
+            KeyCode::ShiftLeft => modifiers.left_shift = down,
+            KeyCode::ShiftRight => modifiers.right_shift = down,
+            KeyCode::ControlLeft => modifiers.left_ctrl = down,
+            KeyCode::ControlRight => modifiers.right_ctrl = down,
+            KeyCode::AltLeft => modifiers.alt = down,
+            KeyCode::AltRight if self.layout.has_alt_gr() => modifiers.alt_gr = down,
+            KeyCode::AltRight => modifiers.alt = down,
+            // colemak puts backspace where caps lock is
+            KeyCode::CapsLock if self.layout == Layout::Colemak => return false,
+            KeyCode::CapsLock if down => modifiers.caps_lock = !modifiers.caps_lock,
+            KeyCode::NumpadLock if down => modifiers.num_lock = !modifiers.num_lock,
+            KeyCode::ScrollLock if down => modifiers.scroll_lock = !modifiers.scroll_lock,
+            KeyCode::CapsLock | KeyCode::NumpadLock | KeyCode::ScrollLock => (),
+            _ => return false,
+        }
+        true
+    }
+
+    //the keys that type the same on every layout
+    fn special_key(&self, code: KeyCode) -> Option<DecodedKey> {
+        use KeyCode::*;
+
+        let num_lock = self.modifiers.num_lock;
+        let character = match code {
+            Enter | NumpadEnter => '\n',
+            Backspace | CapsLock => '\u{8}',
+            Tab => '\t',
+            Escape => '\u{1b}',
+            Delete => '\u{7f}',
+            NumpadSlash => '/',
+            NumpadStar => '*',
+            NumpadMinus => '-',
+            NumpadPlus => '+',
+            Numpad0 if num_lock => '0',
+            Numpad1 if num_lock => '1',
+            Numpad2 if num_lock => '2',
+            Numpad3 if num_lock => '3',
+            Numpad4 if num_lock => '4',
+            Numpad5 if num_lock => '5',
+            Numpad6 if num_lock => '6',
+            Numpad7 if num_lock => '7',
+            Numpad8 if num_lock => '8',
+            Numpad9 if num_lock => '9',
+            NumpadPeriod if num_lock => '.',
+            _ => return Some(DecodedKey::RawKey(navigation_key(code))),
+        };
+        Some(DecodedKey::Unicode(character))
+    }
+}
+
+//the numpad doubles as the navigation keys with num lock off
+fn navigation_key(code: KeyCode) -> KeyCode {
+    match code {
+        KeyCode::Numpad0 => KeyCode::Insert,
+        KeyCode::Numpad1 => KeyCode::End,
+        KeyCode::Numpad2 => KeyCode::ArrowDown,
+        KeyCode::Numpad3 => KeyCode::PageDown,
+        KeyCode::Numpad4 => KeyCode::ArrowLeft,
+        KeyCode::Numpad6 => KeyCode::ArrowRight,
+        KeyCode::Numpad7 => KeyCode::Home,
+        KeyCode::Numpad8 => KeyCode::ArrowUp,
+        KeyCode::Numpad9 => KeyCode::PageUp,
+        KeyCode::NumpadPeriod => KeyCode::Delete,
+        other => other,
+    }
+}
+
+///Called with every byte from the keyboard, finished keys go into the input buffer
+pub fn handle_scancode(byte: u8) {
+    //the answers to led updates come in on the same port as the scancodes
+    if crate::ps2::handle_keyboard_response(byte) {
+        return;
+    }
+
+    let mut keyboard = KEYBOARD.lock();
+    let event = keyboard.add_byte(byte, crate::interrupts::uptime(), |key| {
+        crate::io::INPUTBUFFER.write().write_key(key)
+    });
+    if let Some(event) = event {
+        event::publish(event);
+    }
+    crate::ps2::update_leds(keyboard.modifiers().leds());
+}
+
+///Whether a key is held down right now
+pub fn is_pressed(code: KeyCode) -> bool {
+    without_interrupts(|| KEYBOARD.lock().is_pressed(code))
+}
+
+///Has to match what the keyboard sends, `ps2::init` switches it to set 2
+pub fn set_scancode_set(set: ScancodeSet) -> bool {
+    without_interrupts(|| KEYBOARD.lock().set_scancode_set(set))
+}
+
+pub fn set_layout(layout: Layout) {
+    without_interrupts(|| KEYBOARD.lock().set_layout(layout));
+}
+
+pub fn layout() -> Layout {
+    without_interrupts(|| KEYBOARD.lock().layout())
+}
+
+pub fn modifiers() -> Modifiers {
+    without_interrupts(|| KEYBOARD.lock().modifiers())
+}
+
+#[cfg(test)]
+fn type_keys(
+    keyboard: &mut Keyboard,
+    codes: &[(KeyCode, KeyState)],
+    out: &mut [Option<DecodedKey>],
+) {
+    let mut count = 0;
+    for (code, state) in codes {
+        keyboard.process_event(RawKeyEvent::new(*code, *state), |key| {
+            out[count] = Some(key);
+            count += 1;
+        });
+    }
+}
+
+#[test_case]
+fn test_shift_and_caps_lock() {
+    use KeyState::*;
+
+    let mut keyboard = Keyboard::new(Layout::German);
+    let mut out = [None; 4];
+    type_keys(
+        &mut keyboard,
+        &[
+            (KeyCode::CapsLock, Down),
+            (KeyCode::CapsLock, Up),
+            (KeyCode::Quote, Down),
+            (KeyCode::Key1, Down),
+            (KeyCode::ShiftLeft, Down),
+            (KeyCode::Y, Down),
+        ],
+        &mut out,
+    );
+    assert_eq!(out[0], Some(DecodedKey::Unicode('Ä')));
+    assert_eq!(out[1], Some(DecodedKey::Unicode('1')));
+    assert_eq!(out[2], Some(DecodedKey::Unicode('z')));
+}
+
+#[test_case]
+fn test_alt_gr() {
+    use KeyState::*;
+
+    let mut keyboard = Keyboard::new(Layout::German);
+    let mut out = [None; 2];
+    type_keys(
+        &mut keyboard,
+        &[
+            (KeyCode::AltRight, Down),
+            (KeyCode::Q, Down),
+            (KeyCode::AltRight, Up),
+            (KeyCode::Q, Down),
+        ],
+        &mut out,
+    );
+    assert_eq!(
+        out,
+        [
+            Some(DecodedKey::Unicode('@')),
+            Some(DecodedKey::Unicode('q'))
+        ]
+    );
+}
+
+#[test_case]
+fn test_dead_keys() {
+    use KeyState::*;
+
+    let mut keyboard = Keyboard::new(Layout::FinnishSwedish);
+    let mut out = [None; 4];
+    type_keys(
+        &mut keyboard,
+        &[
+            (KeyCode::BracketSquareRight, Down),
+            (KeyCode::O, Down),
+            (KeyCode::Equals, Down),
+            (KeyCode::X, Down),
+            (KeyCode::Equals, Down),
+            (KeyCode::Spacebar, Down),
+        ],
+        &mut out,
+    );
+    assert_eq!(out[0], Some(DecodedKey::Unicode('ö')));
+    assert_eq!(out[1], Some(DecodedKey::Unicode('´')));
+    assert_eq!(out[2], Some(DecodedKey::Unicode('x')));
+    assert_eq!(out[3], Some(DecodedKey::Unicode('´')));
+}
+
+#[test_case]
+fn test_special_keys() {
+    let mut keyboard = Keyboard::new(Layout::Colemak);
+    let mut out = [None; 3];
+    type_keys(
+        &mut keyboard,
+        &[
+            (KeyCode::CapsLock, KeyState::Down),
+            (KeyCode::NumpadLock, KeyState::Down),
+            (KeyCode::Numpad8, KeyState::Down),
+            (KeyCode::K, KeyState::Down),
+        ],
+        &mut out,
+    );
+    assert_eq!(out[0], Some(DecodedKey::Unicode('\u{8}')));
+    assert_eq!(out[1], Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
+    assert_eq!(out[2], Some(DecodedKey::Unicode('e')));
+}
+
+#[test_case]
+fn test_key_events() {
+    let mut keyboard = Keyboard::new(Layout::Us);
+    let time = Duration::from_millis(5);
+    let mut events = [0x2A, 0x1E, 0x1E, 0x9E]
+        .iter()
+        .filter_map(|byte| keyboard.add_byte(*byte, time, |_| ()));
+
+    let shift = events.next().unwrap();
+    assert_eq!(shift.code, KeyCode::ShiftLeft);
+    assert!(shift
+        .modifiers
+        .contains(ModifierMask::SHIFT | ModifierMask::NUM_LOCK));
+    assert_eq!(shift.timestamp, time);
+
+    let press = events.next().unwrap();
+    assert!(press.is_press() && !press.repeat);
+    assert!(events.next().unwrap().repeat);
+    let release = events.next().unwrap();
+    assert_eq!((release.code, release.state), (KeyCode::A, KeyState::Up));
+    drop(events);
+
+    assert!(keyboard.is_pressed(KeyCode::ShiftLeft));
+    assert!(!keyboard.is_pressed(KeyCode::A));
+}
@@ -0,0 +1,517 @@
+//! Turning the bytes the keyboard controller sends into key presses and releases
+
+use pc_keyboard::{Error, KeyCode, KeyEvent, KeyState};
+
+use super::layouts::ISO_KEY;
+use crate::ps2::ScancodeSet;
+
+const EXTENDED: u8 = 0xE0;
+//only the pause key sends this, as E1 1D 45 E1 9D C5 in set 1
+const PAUSE: u8 = 0xE1;
+const PAUSE_LENGTH: u8 = 6;
+const RELEASED: u8 = 0x80;
+//the fake shift presses some keyboards wrap around print screen and the numpad keys
+const FAKE_SHIFT: [u8; 2] = [0x2A, 0x36];
+
+//set 2 sends releases as the make code after this
+const SET2_RELEASE: u8 = 0xF0;
+//E1 14 77 E1 F0 14 F0 77
+const SET2_PAUSE_LENGTH: u8 = 8;
+const SET2_FAKE_SHIFT: [u8; 2] = [0x12, 0x59];
+
+///The decoder for whichever scancode set the keyboard is sending
+#[derive(Debug, Clone, Copy)]
+pub enum Decoder {
+    Set1(ScancodeSet1),
+    Set2(ScancodeSet2),
+}
+
+impl Decoder {
+    ///`None` for scancode set 3, nothing uses it
+    pub const fn new(set: ScancodeSet) -> Option<Decoder> {
+        match set {
+            ScancodeSet::Set1 => Some(Decoder::Set1(ScancodeSet1::new())),
+            ScancodeSet::Set2 => Some(Decoder::Set2(ScancodeSet2::new())),
+            ScancodeSet::Set3 => None,
+        }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error> {
+        match self {
+            Decoder::Set1(decoder) => decoder.add_byte(byte),
+            Decoder::Set2(decoder) => decoder.add_byte(byte),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Start,
+    Extended,
+    //bytes of the pause sequence seen so far
+    Pause(u8),
+}
+
+///A decoder for scancode set 1, the set the controller translates everything to by default
+#[derive(Debug, Clone, Copy)]
+pub struct ScancodeSet1 {
+    state: State,
+}
+
+impl ScancodeSet1 {
+    pub const fn new() -> ScancodeSet1 {
+        ScancodeSet1 {
+            state: State::Start,
+        }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error> {
+        match self.state {
+            State::Start => match byte {
+                EXTENDED => {
+                    self.state = State::Extended;
+                    Ok(None)
+                }
+                PAUSE => {
+                    self.state = State::Pause(1);
+                    Ok(None)
+                }
+                _ => event(byte, map_scancode),
+            },
+            State::Extended => {
+                self.state = State::Start;
+                if FAKE_SHIFT.contains(&(byte & !RELEASED)) {
+                    return Ok(None);
+                }
+                event(byte, map_extended)
+            }
+            State::Pause(seen) => {
+                if seen + 1 < PAUSE_LENGTH {
+                    self.state = State::Pause(seen + 1);
+                    return Ok(None);
+                }
+                self.state = State::Start;
+                Ok(Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Down)))
+            }
+        }
+    }
+}
+
+impl Default for ScancodeSet1 {
+    fn default() -> Self {
+        ScancodeSet1::new()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Set2State {
+    Start,
+    Extended,
+    Release,
+    ExtendedRelease,
+    Pause(u8),
+}
+
+///A decoder for scancode set 2, what the keyboard sends with the controller translation off
+#[derive(Debug, Clone, Copy)]
+pub struct ScancodeSet2 {
+    state: Set2State,
+}
+
+impl ScancodeSet2 {
+    pub const fn new() -> ScancodeSet2 {
+        ScancodeSet2 {
+            state: Set2State::Start,
+        }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Result<Option<KeyEvent>, Error> {
+        let (code, state) = match (self.state, byte) {
+            (Set2State::Start, EXTENDED) => {
+                self.state = Set2State::Extended;
+                return Ok(None);
+            }
+            (Set2State::Start, PAUSE) => {
+                self.state = Set2State::Pause(1);
+                return Ok(None);
+            }
+            (Set2State::Start, SET2_RELEASE) => {
+                self.state = Set2State::Release;
+                return Ok(None);
+            }
+            (Set2State::Extended, SET2_RELEASE) => {
+                self.state = Set2State::ExtendedRelease;
+                return Ok(None);
+            }
+            (Set2State::Pause(seen), _) => {
+                if seen + 1 < SET2_PAUSE_LENGTH {
+                    self.state = Set2State::Pause(seen + 1);
+                    return Ok(None);
+                }
+                self.state = Set2State::Start;
+                return Ok(Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Down)));
+            }
+            (Set2State::Start, _) => (map_set2(byte), KeyState::Down),
+            (Set2State::Release, _) => (map_set2(byte), KeyState::Up),
+            (Set2State::Extended, _) => (map_set2_extended(byte), KeyState::Down),
+            (Set2State::ExtendedRelease, _) => (map_set2_extended(byte), KeyState::Up),
+        };
+        let extended = matches!(self.state, Set2State::Extended | Set2State::ExtendedRelease);
+        self.state = Set2State::Start;
+
+        if extended && SET2_FAKE_SHIFT.contains(&byte) {
+            return Ok(None);
+        }
+        let code = code.ok_or(Error::UnknownKeyCode)?;
+        Ok(Some(KeyEvent::new(code, state)))
+    }
+}
+
+impl Default for ScancodeSet2 {
+    fn default() -> Self {
+        ScancodeSet2::new()
+    }
+}
+
+fn event(byte: u8, map: fn(u8) -> Option<KeyCode>) -> Result<Option<KeyEvent>, Error> {
+    let state = if byte & RELEASED != 0 {
+        KeyState::Up
+    } else {
+        KeyState::Down
+    };
+    let code = map(byte & !RELEASED).ok_or(Error::UnknownKeyCode)?;
+    Ok(Some(KeyEvent::new(code, state)))
+}
+
+fn map_scancode(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => Escape,
+        0x02 => Key1,
+        0x03 => Key2,
+        0x04 => Key3,
+        0x05 => Key4,
+        0x06 => Key5,
+        0x07 => Key6,
+        0x08 => Key7,
+        0x09 => Key8,
+        0x0A => Key9,
+        0x0B => Key0,
+        0x0C => Minus,
+        0x0D => Equals,
+        0x0E => Backspace,
+        0x0F => Tab,
+        0x10 => Q,
+        0x11 => W,
+        0x12 => E,
+        0x13 => R,
+        0x14 => T,
+        0x15 => Y,
+        0x16 => U,
+        0x17 => I,
+        0x18 => O,
+        0x19 => P,
+        0x1A => BracketSquareLeft,
+        0x1B => BracketSquareRight,
+        0x1C => Enter,
+        0x1D => ControlLeft,
+        0x1E => A,
+        0x1F => S,
+        0x20 => D,
+        0x21 => F,
+        0x22 => G,
+        0x23 => H,
+        0x24 => J,
+        0x25 => K,
+        0x26 => L,
+        0x27 => SemiColon,
+        0x28 => Quote,
+        0x29 => BackTick,
+        0x2A => ShiftLeft,
+        0x2B => BackSlash,
+        0x2C => Z,
+        0x2D => X,
+        0x2E => C,
+        0x2F => V,
+        0x30 => B,
+        0x31 => N,
+        0x32 => M,
+        0x33 => Comma,
+        0x34 => Fullstop,
+        0x35 => Slash,
+        0x36 => ShiftRight,
+        0x37 => NumpadStar,
+        0x38 => AltLeft,
+        0x39 => Spacebar,
+        0x3A => CapsLock,
+        0x3B => F1,
+        0x3C => F2,
+        0x3D => F3,
+        0x3E => F4,
+        0x3F => F5,
+        0x40 => F6,
+        0x41 => F7,
+        0x42 => F8,
+        0x43 => F9,
+        0x44 => F10,
+        0x45 => NumpadLock,
+        0x46 => ScrollLock,
+        0x47 => Numpad7,
+        0x48 => Numpad8,
+        0x49 => Numpad9,
+        0x4A => NumpadMinus,
+        0x4B => Numpad4,
+        0x4C => Numpad5,
+        0x4D => Numpad6,
+        0x4E => NumpadPlus,
+        0x4F => Numpad1,
+        0x50 => Numpad2,
+        0x51 => Numpad3,
+        0x52 => Numpad0,
+        0x53 => NumpadPeriod,
+        0x56 => ISO_KEY,
+        0x57 => F11,
+        0x58 => F12,
+        _ => return None,
+    })
+}
+
+fn map_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x10 => PrevTrack,
+        0x19 => NextTrack,
+        0x1C => NumpadEnter,
+        0x1D => ControlRight,
+        0x20 => Mute,
+        0x21 => Calculator,
+        0x22 => Play,
+        0x24 => Stop,
+        0x2E => VolumeDown,
+        0x30 => VolumeUp,
+        0x32 => WWWHome,
+        0x35 => NumpadSlash,
+        0x37 => PrintScreen,
+        0x38 => AltRight,
+        0x47 => Home,
+        0x48 => ArrowUp,
+        0x49 => PageUp,
+        0x4B => ArrowLeft,
+        0x4D => ArrowRight,
+        0x4F => End,
+        0x50 => ArrowDown,
+        0x51 => PageDown,
+        0x52 => Insert,
+        0x53 => Delete,
+        0x5B => WindowsLeft,
+        0x5C => WindowsRight,
+        0x5D => Menus,
+        _ => return None,
+    })
+}
+
+fn map_set2(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x01 => F9,
+        0x03 => F5,
+        0x04 => F3,
+        0x05 => F1,
+        0x06 => F2,
+        0x07 => F12,
+        0x09 => F10,
+        0x0A => F8,
+        0x0B => F6,
+        0x0C => F4,
+        0x0D => Tab,
+        0x0E => BackTick,
+        0x11 => AltLeft,
+        0x12 => ShiftLeft,
+        0x14 => ControlLeft,
+        0x15 => Q,
+        0x16 => Key1,
+        0x1A => Z,
+        0x1B => S,
+        0x1C => A,
+        0x1D => W,
+        0x1E => Key2,
+        0x21 => C,
+        0x22 => X,
+        0x23 => D,
+        0x24 => E,
+        0x25 => Key4,
+        0x26 => Key3,
+        0x29 => Spacebar,
+        0x2A => V,
+        0x2B => F,
+        0x2C => T,
+        0x2D => R,
+        0x2E => Key5,
+        0x31 => N,
+        0x32 => B,
+        0x33 => H,
+        0x34 => G,
+        0x35 => Y,
+        0x36 => Key6,
+        0x3A => M,
+        0x3B => J,
+        0x3C => U,
+        0x3D => Key7,
+        0x3E => Key8,
+        0x41 => Comma,
+        0x42 => K,
+        0x43 => I,
+        0x44 => O,
+        0x45 => Key0,
+        0x46 => Key9,
+        0x49 => Fullstop,
+        0x4A => Slash,
+        0x4B => L,
+        0x4C => SemiColon,
+        0x4D => P,
+        0x4E => Minus,
+        0x52 => Quote,
+        0x54 => BracketSquareLeft,
+        0x55 => Equals,
+        0x58 => CapsLock,
+        0x59 => ShiftRight,
+        0x5A => Enter,
+        0x5B => BracketSquareRight,
+        0x5D => BackSlash,
+        0x61 => ISO_KEY,
+        0x66 => Backspace,
+        0x69 => Numpad1,
+        0x6B => Numpad4,
+        0x6C => Numpad7,
+        0x70 => Numpad0,
+        0x71 => NumpadPeriod,
+        0x72 => Numpad2,
+        0x73 => Numpad5,
+        0x74 => Numpad6,
+        0x75 => Numpad8,
+        0x76 => Escape,
+        0x77 => NumpadLock,
+        0x78 => F11,
+        0x79 => NumpadPlus,
+        0x7A => Numpad3,
+        0x7B => NumpadMinus,
+        0x7C => NumpadStar,
+        0x7D => Numpad9,
+        0x7E => ScrollLock,
+        0x83 => F7,
+        _ => return None,
+    })
+}
+
+fn map_set2_extended(code: u8) -> Option<KeyCode> {
+    use KeyCode::*;
+
+    Some(match code {
+        0x11 => AltRight,
+        0x14 => ControlRight,
+        0x15 => PrevTrack,
+        0x1F => WindowsLeft,
+        0x21 => VolumeDown,
+        0x23 => Mute,
+        0x27 => WindowsRight,
+        0x2B => Calculator,
+        0x2F => Menus,
+        0x32 => VolumeUp,
+        0x34 => Play,
+        0x3A => WWWHome,
+        0x3B => Stop,
+        0x4A => NumpadSlash,
+        0x4D => NextTrack,
+        0x5A => NumpadEnter,
+        0x69 => End,
+        0x6B => ArrowLeft,
+        0x6C => Home,
+        0x70 => Insert,
+        0x71 => Delete,
+        0x72 => ArrowDown,
+        0x74 => ArrowRight,
+        0x75 => ArrowUp,
+        0x7A => PageDown,
+        0x7C => PrintScreen,
+        0x7D => PageUp,
+        _ => return None,
+    })
+}
+
+#[test_case]
+fn test_set1_decoding() {
+    let mut decoder = ScancodeSet1::new();
+
+    assert_eq!(
+        decoder.add_byte(0x1E),
+        Ok(Some(KeyEvent::new(KeyCode::A, KeyState::Down)))
+    );
+    assert_eq!(
+        decoder.add_byte(0x9E),
+        Ok(Some(KeyEvent::new(KeyCode::A, KeyState::Up)))
+    );
+    assert_eq!(decoder.add_byte(0xE0), Ok(None));
+    assert_eq!(
+        decoder.add_byte(0x38),
+        Ok(Some(KeyEvent::new(KeyCode::AltRight, KeyState::Down)))
+    );
+    assert_eq!(decoder.add_byte(0x7F), Err(Error::UnknownKeyCode));
+}
+
+#[test_case]
+fn test_set1_special_sequences() {
+    let mut decoder = ScancodeSet1::new();
+
+    // print screen with its fake shift
+    assert_eq!(decoder.add_byte(0xE0), Ok(None));
+    assert_eq!(decoder.add_byte(0x2A), Ok(None));
+    assert_eq!(decoder.add_byte(0xE0), Ok(None));
+    assert_eq!(
+        decoder.add_byte(0x37),
+        Ok(Some(KeyEvent::new(KeyCode::PrintScreen, KeyState::Down)))
+    );
+
+    let mut events = [0xE1, 0x1D, 0x45, 0xE1, 0x9D, 0xC5]
+        .iter()
+        .filter_map(|byte| decoder.add_byte(*byte).unwrap());
+    assert_eq!(
+        events.next(),
+        Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Down))
+    );
+    assert_eq!(events.next(), None);
+}
+
+#[test_case]
+fn test_set2_decoding() {
+    let mut decoder = ScancodeSet2::new();
+    let mut events = [
+        0x1C, 0xF0, 0x1C, // a
+        0xE0, 0x12, 0xE0, 0x7C, // print screen with its fake shift
+        0xE0, 0xF0, 0x11, // right alt released
+        0xE1, 0x14, 0x77, 0xE1, 0xF0, 0x14, 0xF0, 0x77, // pause
+    ]
+    .iter()
+    .filter_map(|byte| decoder.add_byte(*byte).unwrap());
+
+    assert_eq!(
+        events.next(),
+        Some(KeyEvent::new(KeyCode::A, KeyState::Down))
+    );
+    assert_eq!(events.next(), Some(KeyEvent::new(KeyCode::A, KeyState::Up)));
+    assert_eq!(
+        events.next(),
+        Some(KeyEvent::new(KeyCode::PrintScreen, KeyState::Down))
+    );
+    assert_eq!(
+        events.next(),
+        Some(KeyEvent::new(KeyCode::AltRight, KeyState::Up))
+    );
+    assert_eq!(
+        events.next(),
+        Some(KeyEvent::new(KeyCode::PauseBreak, KeyState::Down))
+    );
+    assert_eq!(events.next(), None);
+}
@@ -15,52 +15,136 @@ pub mod vga_driver;
 pub mod interrupts;
 pub mod gdt;
 pub mod pc_speaker;
+pub mod pit;
 pub mod io;
+pub mod tui;
+pub mod logger;
+pub mod gdb;
+pub mod keyboard;
+pub mod ps2;
+pub mod mouse;
+pub mod memory;
+pub mod power;
+pub mod rtc;
+pub mod shell;
+pub mod testing;
+pub mod fw_cfg;
+pub mod cmdline;
+pub mod watchdog;
+pub mod tsc;
+pub mod mmio;
+pub mod resource;
+pub mod driver;
+pub mod pci;
 
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
 #[repr(u32)]
 pub enum QemuExitCode {
     Success = 0x10,
     Failed = 0x11,   
+    ///The watchdog caught something that stopped making progress
+    Hung = 0x12,
 }
 
 pub trait Testable {
+    fn name(&self) -> &'static str;
     fn run(&self) -> ();
+    ///Plain test functions only pass when they return
+    fn options(&self) -> testing::TestOptions {
+        testing::TestOptions::DEFAULT
+    }
+    ///The function `--bench` times, `None` for tests
+    fn bench(&self) -> Option<fn(&mut testing::bench::Bencher)> {
+        None
+    }
 }
 
 impl<T> Testable for T
 where
     T: Fn(),
 {
+    fn name(&self) -> &'static str {
+        core::any::type_name::<T>()
+    }
+
     fn run(&self) {
-        serial_print!("{}...\t", core::any::type_name::<T>());
         self();
-        serial_println!("[ok]");
     }
 }
 
 pub fn test_runner(tests: &[&dyn Testable]) {
-    serial_println!("Running {} tests", tests.len());
-    for test in tests {
-        test.run();
+    let args = match testing::args::TestArgs::parse(cmdline::args()) {
+        Ok(args) => args,
+        Err(error) => {
+            serial_println!("error: {}", error);
+            exit_qemu(QemuExitCode::Failed);
+            hlt_loop();
+        }
+    };
+    let selected = || tests.iter().filter(|test| args.matches(test.name()));
+    let count = selected().count();
+
+    if args.list {
+        let benchmarks = selected().filter(|test| test.bench().is_some()).count();
+        for test in selected() {
+            let kind = if test.bench().is_some() {
+                "bench"
+            } else {
+                "test"
+            };
+            serial_println!("{}: {}", test.name(), kind);
+        }
+        serial_println!();
+        serial_println!("{} tests, {} benchmarks", count - benchmarks, benchmarks);
+        exit_qemu(QemuExitCode::Success);
+        hlt_loop();
+    }
+
+    let mut report = testing::report::Report::start(count, tests.len() - count);
+    for test in selected() {
+        report.test_started(test.name());
+        watchdog::arm(test.name(), test.options().watchdog_budget());
+        //like libtest only the benchmarks run with `--bench`
+        let outcome = match (args.bench, test.bench().is_some()) {
+            (false, _) => testing::run_test(*test),
+            (true, true) => testing::bench::run_bench(*test),
+            (true, false) => testing::Outcome::Ignored,
+        };
+        watchdog::disarm();
+        testing::with_panic_message(|message| report.test_finished(test.name(), outcome, message));
+    }
+
+    if report.finish() {
+        exit_qemu(QemuExitCode::Success);
+    } else {
+        exit_qemu(QemuExitCode::Failed);
     }
-    exit_qemu(QemuExitCode::Success);
 }
 
+///Ends the running test if there is one, otherwise the whole test kernel
 pub fn test_panic_handler(info: &PanicInfo) -> ! {
+    testing::abandon_test(info);
     serial_println!("[failed]\n");
     serial_println!("Error: {}\n", info);
     exit_qemu(QemuExitCode::Failed);
     hlt_loop();
 }
 
+lazy_static::lazy_static! {
+    //the isa-debug-exit device from the test-args, claimed by the first exit or when the
+    //drivers start
+    pub(crate) static ref QEMU_EXIT: spin::Mutex<resource::PortRange> = spin::Mutex::new(
+        resource::claim_ports("qemu exit", 0xf4, 4).expect("the qemu exit ports are already claimed")
+    );
+}
+
 pub fn exit_qemu(exit_code: QemuExitCode){
-    use x86_64::instructions::port::Port;
+    use rost_core::register::{PortRegister, WriteOnly};
 
-    unsafe {
-        let mut port = Port::new(0xf4);
-        port.write(exit_code as u32)
-    }
+    const EXIT: PortRegister<u32, WriteOnly> = PortRegister::new(0xf4);
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        EXIT.write(&mut *QEMU_EXIT.lock(), exit_code as u32)
+    })
 }
 
 pub fn colorchg(foreground_color: Color, background_color: Color){
@@ -69,9 +153,11 @@ pub fn colorchg(foreground_color: Color, background_color: Color){
 
 //Does initialization stuff
 pub fn init() {
+    // only fails when a test binary already installed the logger
+    let _ = logger::init();
     gdt::init();
     interrupts::init_idt();
-    unsafe { interrupts::PICS.lock().initialize() };
+    driver::init();
     x86_64::instructions::interrupts::enable(); 
 }
 
@@ -0,0 +1,425 @@
+//! The kernel logger behind the `log` crate macros
+//!
+//! Every record is formatted once as `[seconds.micros] LEVEL target: message` and handed to
+//! the sinks whose level lets it through. The memory sink keeps the latest records around so
+//! they can be read back with `dmesg` even when nothing was watching the screen or serial port.
+
+use core::fmt::{self, Write};
+
+use log::{Level, LevelFilter, Log, Metadata, Record};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::interrupts::uptime;
+use crate::io::RingBuffer;
+use crate::serial::SERIAL1;
+use crate::vga_driver::{Color, WRITER};
+
+///Longer lines are cut off
+pub const MAX_LINE: usize = 256;
+pub const MEMORY_SIZE: usize = 16 * 1024;
+pub const MAX_MODULE_FILTERS: usize = 16;
+const MAX_MODULE_NAME: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Sink {
+    Vga,
+    Serial,
+    ///The buffer `dmesg` reads
+    Memory,
+}
+
+impl Sink {
+    pub const ALL: [Sink; 3] = [Sink::Vga, Sink::Serial, Sink::Memory];
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoggerError {
+    AlreadyInitialized,
+    TooManyFilters,
+    ModuleNameTooLong,
+}
+
+#[derive(Debug, Clone, Copy)]
+struct ModuleFilter {
+    name: [u8; MAX_MODULE_NAME],
+    len: usize,
+    level: LevelFilter,
+}
+
+impl ModuleFilter {
+    fn name(&self) -> &str {
+        core::str::from_utf8(&self.name[..self.len]).unwrap_or("")
+    }
+
+    //a filter for `rost::serial` also covers `rost::serial::uart` but not `rost::serials`
+    fn matches(&self, module: &str) -> bool {
+        let name = self.name();
+        module.starts_with(name)
+            && (module.len() == name.len() || module[name.len()..].starts_with("::"))
+    }
+}
+
+struct Config {
+    default_level: LevelFilter,
+    modules: [Option<ModuleFilter>; MAX_MODULE_FILTERS],
+    sinks: [LevelFilter; 3],
+}
+
+impl Config {
+    ///The level of the most specific module filter or the default level
+    fn level_for(&self, module: &str) -> LevelFilter {
+        self.modules
+            .iter()
+            .flatten()
+            .filter(|filter| filter.matches(module))
+            .max_by_key(|filter| filter.len)
+            .map_or(self.default_level, |filter| filter.level)
+    }
+
+    //lets the log macros skip formatting anything no sink would take
+    fn update_max_level(&self) {
+        let module_max = self
+            .modules
+            .iter()
+            .flatten()
+            .map(|filter| filter.level)
+            .fold(self.default_level, Ord::max);
+        let sink_max = self.sinks.iter().copied().fold(LevelFilter::Off, Ord::max);
+
+        log::set_max_level(module_max.min(sink_max));
+    }
+}
+
+static CONFIG: Mutex<Config> = Mutex::new(Config {
+    default_level: LevelFilter::Info,
+    modules: [None; MAX_MODULE_FILTERS],
+    //the serial port also carries test results so only problems go there by default
+    sinks: [LevelFilter::Info, LevelFilter::Warn, LevelFilter::Trace],
+});
+
+static MEMORY: Mutex<RingBuffer<u8, MEMORY_SIZE>> = Mutex::new(RingBuffer::new(0));
+
+static LOGGER: KernelLogger = KernelLogger;
+
+struct KernelLogger;
+
+impl Log for KernelLogger {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        without_interrupts(|| metadata.level() <= CONFIG.lock().level_for(metadata.target()))
+    }
+
+    fn log(&self, record: &Record) {
+        let module = record.module_path().unwrap_or(record.target());
+        if record.level() > module_level(module) {
+            return;
+        }
+
+        let mut line = Line::new();
+        let timestamp = uptime();
+        let _ = writeln!(
+            line,
+            "[{:5}.{:06}] {:<5} {}: {}",
+            timestamp.as_secs(),
+            timestamp.subsec_micros(),
+            record.level(),
+            record.target(),
+            record.args()
+        );
+
+        without_interrupts(|| {
+            let config = CONFIG.lock();
+            for (sink, level) in Sink::ALL.iter().zip(config.sinks) {
+                if record.level() <= level {
+                    write_to_sink(*sink, record.level(), line.as_str());
+                }
+            }
+        });
+    }
+
+    fn flush(&self) {}
+}
+
+fn write_to_sink(sink: Sink, level: Level, line: &str) {
+    match sink {
+        Sink::Vga => {
+            WRITER
+                .lock()
+                .with_foreground(level_color(level), |writer| writer.write_string(line));
+        }
+        Sink::Serial => {
+            let mut serial = SERIAL1.lock();
+            for byte in line.bytes() {
+                if byte == b'\n' {
+                    serial.send(b'\r');
+                }
+                serial.send(byte);
+            }
+        }
+        Sink::Memory => {
+            let mut memory = MEMORY.lock();
+            //drop whole records so the oldest one left is never cut in half
+            while MEMORY_SIZE - memory.len() < line.len() {
+                while let Some(byte) = memory.pop() {
+                    if byte == b'\n' {
+                        break;
+                    }
+                }
+            }
+            for byte in line.bytes() {
+                let _ = memory.push(byte);
+            }
+        }
+    }
+}
+
+fn level_color(level: Level) -> Color {
+    match level {
+        Level::Error => Color::LightRed,
+        Level::Warn => Color::Yellow,
+        Level::Info => Color::White,
+        Level::Debug => Color::LightGray,
+        Level::Trace => Color::DarkGray,
+    }
+}
+
+//a record formatted into a fixed buffer, whatever does not fit is dropped
+struct Line {
+    bytes: [u8; MAX_LINE],
+    len: usize,
+}
+
+impl Line {
+    fn new() -> Line {
+        Line {
+            bytes: [0; MAX_LINE],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+}
+
+impl Write for Line {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        //keep room for the newline at the end
+        let room = MAX_LINE - 1 - self.len;
+        let mut end = s.len().min(room);
+        while !s.is_char_boundary(end) {
+            end -= 1;
+        }
+
+        self.bytes[self.len..self.len + end].copy_from_slice(&s.as_bytes()[..end]);
+        self.len += end;
+
+        if end < s.len() && s.ends_with('\n') {
+            self.bytes[self.len] = b'\n';
+            self.len += 1;
+        }
+        Ok(())
+    }
+}
+
+///Installs the logger, everything at info level or above gets logged and warnings and
+///errors are also sent to the serial port
+pub fn init() -> Result<(), LoggerError> {
+    log::set_logger(&LOGGER).map_err(|_| LoggerError::AlreadyInitialized)?;
+    without_interrupts(|| CONFIG.lock().update_max_level());
+    Ok(())
+}
+
+///Sets the level used for modules without a filter of their own
+pub fn set_level(level: LevelFilter) {
+    without_interrupts(|| {
+        let mut config = CONFIG.lock();
+        config.default_level = level;
+        config.update_max_level();
+    });
+}
+
+///Sets the level of a module and its submodules, the longest matching module path wins
+pub fn set_module_level(module: &str, level: LevelFilter) -> Result<(), LoggerError> {
+    if module.len() > MAX_MODULE_NAME {
+        return Err(LoggerError::ModuleNameTooLong);
+    }
+
+    without_interrupts(|| {
+        let mut config = CONFIG.lock();
+        let slot = match config
+            .modules
+            .iter()
+            .position(|filter| matches!(filter, Some(filter) if filter.name() == module))
+        {
+            Some(index) => index,
+            None => config
+                .modules
+                .iter()
+                .position(Option::is_none)
+                .ok_or(LoggerError::TooManyFilters)?,
+        };
+
+        let mut filter = ModuleFilter {
+            name: [0; MAX_MODULE_NAME],
+            len: module.len(),
+            level,
+        };
+        filter.name[..module.len()].copy_from_slice(module.as_bytes());
+        config.modules[slot] = Some(filter);
+        config.update_max_level();
+        Ok(())
+    })
+}
+
+///Makes a module use the default level again
+pub fn clear_module_level(module: &str) {
+    without_interrupts(|| {
+        let mut config = CONFIG.lock();
+        for filter in config.modules.iter_mut() {
+            if matches!(filter, Some(existing) if existing.name() == module) {
+                *filter = None;
+            }
+        }
+        config.update_max_level();
+    });
+}
+
+///The level that applies to a module with all the filters taken into account
+pub fn module_level(module: &str) -> LevelFilter {
+    without_interrupts(|| CONFIG.lock().level_for(module))
+}
+
+pub fn set_sink_level(sink: Sink, level: LevelFilter) {
+    without_interrupts(|| {
+        let mut config = CONFIG.lock();
+        config.sinks[sink as usize] = level;
+        config.update_max_level();
+    });
+}
+
+pub fn sink_level(sink: Sink) -> LevelFilter {
+    without_interrupts(|| CONFIG.lock().sinks[sink as usize])
+}
+
+///Writes every record still in the memory sink, oldest first
+pub fn write_dmesg(out: &mut impl Write) -> fmt::Result {
+    let mut chunk = [0; 64];
+    let mut carried = 0;
+    let mut position = 0;
+
+    loop {
+        //copying a chunk at a time keeps the lock away from whatever `out` does
+        let read = without_interrupts(|| {
+            let memory = MEMORY.lock();
+            let mut read = 0;
+            while carried + read < chunk.len() {
+                match memory.get(position + read) {
+                    Some(byte) => chunk[carried + read] = byte,
+                    None => break,
+                }
+                read += 1;
+            }
+            read
+        });
+        position += read;
+
+        let len = carried + read;
+        if read == 0 {
+            return Ok(());
+        }
+
+        //a character can be split between two chunks, the rest of it is carried over
+        let valid = match core::str::from_utf8(&chunk[..len]) {
+            Ok(text) => text.len(),
+            Err(error) => error.valid_up_to(),
+        };
+        out.write_str(core::str::from_utf8(&chunk[..valid]).unwrap_or(""))?;
+
+        //only possible if records were dropped while reading, skip the broken byte
+        let skip = if valid == 0 && len == chunk.len() {
+            1
+        } else {
+            valid
+        };
+        chunk.copy_within(skip..len, 0);
+        carried = len - skip;
+    }
+}
+
+///Prints the memory sink to the screen
+pub fn dmesg() {
+    let _ = write_dmesg(&mut Printer);
+}
+
+pub fn clear_dmesg() {
+    without_interrupts(|| MEMORY.lock().clear());
+}
+
+struct Printer;
+
+impl Write for Printer {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        crate::print!("{}", s);
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_module_filters() {
+    set_module_level("rost::test_filters", LevelFilter::Trace).unwrap();
+    set_module_level("rost::test_filters::quiet", LevelFilter::Error).unwrap();
+
+    assert_eq!(module_level("rost::test_filters::loud"), LevelFilter::Trace);
+    assert_eq!(
+        module_level("rost::test_filters::quiet::x"),
+        LevelFilter::Error
+    );
+    assert_eq!(module_level("rost::test_filtersx"), module_level("rost"));
+
+    clear_module_level("rost::test_filters");
+    clear_module_level("rost::test_filters::quiet");
+    assert_eq!(
+        module_level("rost::test_filters::loud"),
+        module_level("rost")
+    );
+}
+
+#[cfg(test)]
+struct Collect {
+    bytes: [u8; MEMORY_SIZE],
+    len: usize,
+}
+
+#[cfg(test)]
+impl Write for Collect {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.bytes[self.len..self.len + s.len()].copy_from_slice(s.as_bytes());
+        self.len += s.len();
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_memory_sink() {
+    let vga = sink_level(Sink::Vga);
+    let serial = sink_level(Sink::Serial);
+    set_sink_level(Sink::Vga, LevelFilter::Off);
+    set_sink_level(Sink::Serial, LevelFilter::Off);
+    clear_dmesg();
+
+    log::warn!("memory sink ä");
+    log::trace!("not logged at the default level");
+
+    let mut collected = Collect {
+        bytes: [0; MEMORY_SIZE],
+        len: 0,
+    };
+    write_dmesg(&mut collected).unwrap();
+    let text = core::str::from_utf8(&collected.bytes[..collected.len]).unwrap();
+    assert!(text.ends_with("] WARN  rost::logger: memory sink ä\n"));
+    assert_eq!(text.lines().count(), 1);
+
+    set_sink_level(Sink::Vga, vga);
+    set_sink_level(Sink::Serial, serial);
+}
@@ -4,13 +4,10 @@
 #![test_runner(rost::test_runner)]
 #![reexport_test_harness_main = "test_main"]
 
+use bootloader::BootInfo;
 use core::panic::PanicInfo;
 use rost::colorchg;
-use rost::interrupts::TICKS;
-use rost::io::INPUTBUFFER;
-use rost::print;
 use rost::println;
-use rost::serial_println;
 use rost::vga_driver;
 use rost::vga_driver::code_page_737_definitions::Symbols::*;
 use rost::Color::*;
@@ -31,8 +28,14 @@ fn panic(info: &PanicInfo) -> ! {
 }
 
 #[no_mangle]
-pub extern "C" fn _start() -> ! {
+pub extern "C" fn _start(boot_info: &'static BootInfo) -> ! {
+    rost::memory::init(&boot_info.memory_map);
     rost::init();
+    rost::serial::console::set_enabled(true);
+    //gdb can attach on the second serial port whenever the kernel hits a breakpoint
+    if rost::gdb::init().is_ok() {
+        log::info!("gdb stub listening on COM2");
+    }
     //runs tests if built in test mode
     #[cfg(test)]
     test_main();
@@ -43,25 +46,10 @@ pub extern "C" fn _start() -> ! {
     vga_driver::WRITER
         .lock()
         .draw_symbol(Point, vga_driver::Point(10, 10));
+    rost::mouse::set_pointer_visible(true);
 
     //rost::pc_speaker::play_sound(1000);
-    loop {
-        x86_64::instructions::hlt();
-        let current_tick = *TICKS.read();
-        let mut io = INPUTBUFFER.write();
-
-        if io.unread_char_count != 0 {
-            use pc_keyboard::DecodedKey;
-
-            for i in &io.buffer[..io.unread_char_count] {
-                match i {
-                    DecodedKey::Unicode(char) => print!("{}", char),
-                    DecodedKey::RawKey(raw_key) => print!("{:#?}", raw_key),
-                }
-            }
-            io.unread_char_count = 0;
-        }
-    }
+    rost::shell::run()
 }
 
 #[test_case]
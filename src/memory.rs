@@ -0,0 +1,72 @@
+//! What the bootloader found out about physical memory
+
+use bootloader::bootinfo::{MemoryMap, MemoryRegion, MemoryRegionType};
+use spin::Mutex;
+
+static MEMORY_MAP: Mutex<Option<&'static MemoryMap>> = Mutex::new(None);
+
+///Bytes of physical memory by what they are used for
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct MemoryStats {
+    ///Free for the kernel to use
+    pub usable: u64,
+    ///Taken by the kernel, its stack, the page tables and what the bootloader left behind
+    pub in_use: u64,
+    ///Reserved by the firmware or broken
+    pub reserved: u64,
+}
+
+impl MemoryStats {
+    pub fn total(&self) -> u64 {
+        self.usable + self.in_use + self.reserved
+    }
+
+    pub fn from_regions(regions: &[MemoryRegion]) -> MemoryStats {
+        let mut stats = MemoryStats::default();
+        for region in regions {
+            let size = region.range.end_addr() - region.range.start_addr();
+            match region.region_type {
+                MemoryRegionType::Usable => stats.usable += size,
+                MemoryRegionType::Reserved
+                | MemoryRegionType::AcpiNvs
+                | MemoryRegionType::BadMemory => stats.reserved += size,
+                MemoryRegionType::Empty => (),
+                _ => stats.in_use += size,
+            }
+        }
+        stats
+    }
+}
+
+///Keeps the memory map the bootloader passed to the kernel
+pub fn init(memory_map: &'static MemoryMap) {
+    *MEMORY_MAP.lock() = Some(memory_map);
+}
+
+///`None` until `init` is called
+pub fn memory_map() -> Option<&'static MemoryMap> {
+    *MEMORY_MAP.lock()
+}
+
+pub fn stats() -> Option<MemoryStats> {
+    memory_map().map(|memory_map| MemoryStats::from_regions(memory_map))
+}
+
+#[test_case]
+fn test_memory_stats() {
+    use bootloader::bootinfo::FrameRange;
+
+    let region = |start, end, region_type| MemoryRegion {
+        range: FrameRange::new(start, end),
+        region_type,
+    };
+    let stats = MemoryStats::from_regions(&[
+        region(0, 0x1000, MemoryRegionType::FrameZero),
+        region(0x1000, 0x9F000, MemoryRegionType::Usable),
+        region(0x9F000, 0x100000, MemoryRegionType::Reserved),
+        region(0x100000, 0x400000, MemoryRegionType::Kernel),
+    ]);
+    assert_eq!(stats.usable, 0x9E000);
+    assert_eq!(stats.in_use, 0x301000);
+    assert_eq!(stats.total(), 0x400000);
+}
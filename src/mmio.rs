@@ -0,0 +1,83 @@
+//! Memory mapped I/O on the hardware for the drivers written against `rost_core::mmio::Mmio`
+
+use core::mem::size_of;
+use core::ptr::{read_volatile, write_volatile};
+
+use rost_core::mmio::Mmio;
+
+///A window of device memory, every access is a single volatile read or write
+#[derive(Debug)]
+pub struct MmioRegion {
+    base: usize,
+    len: usize,
+}
+
+impl MmioRegion {
+    ///# Safety
+    ///
+    ///The `len` bytes from `base` have to be mapped device memory that nothing else uses
+    pub const unsafe fn new(base: usize, len: usize) -> MmioRegion {
+        MmioRegion { base, len }
+    }
+
+    pub fn base(&self) -> usize {
+        self.base
+    }
+
+    fn address<T>(&self, offset: usize) -> *mut T {
+        assert!(
+            offset + size_of::<T>() <= self.len,
+            "{:#x} is outside of the {:#x} bytes at {:#x}",
+            offset,
+            self.len,
+            self.base
+        );
+        (self.base + offset) as *mut T
+    }
+
+    fn read<T>(&self, offset: usize) -> T {
+        unsafe { read_volatile(self.address(offset)) }
+    }
+
+    fn write<T>(&mut self, offset: usize, value: T) {
+        unsafe { write_volatile(self.address(offset), value) }
+    }
+}
+
+impl Mmio for MmioRegion {
+    fn len(&self) -> usize {
+        self.len
+    }
+
+    fn read_u8(&self, offset: usize) -> u8 {
+        self.read(offset)
+    }
+
+    fn write_u8(&mut self, offset: usize, value: u8) {
+        self.write(offset, value)
+    }
+
+    fn read_u16(&self, offset: usize) -> u16 {
+        self.read(offset)
+    }
+
+    fn write_u16(&mut self, offset: usize, value: u16) {
+        self.write(offset, value)
+    }
+
+    fn read_u32(&self, offset: usize) -> u32 {
+        self.read(offset)
+    }
+
+    fn write_u32(&mut self, offset: usize, value: u32) {
+        self.write(offset, value)
+    }
+
+    fn read_u64(&self, offset: usize) -> u64 {
+        self.read(offset)
+    }
+
+    fn write_u64(&mut self, offset: usize, value: u64) {
+        self.write(offset, value)
+    }
+}
@@ -0,0 +1,401 @@
+//! The PS/2 mouse on the second port of the controller
+//!
+//! The mouse sends its state in packets of three bytes, or four with a scroll wheel. The
+//! packets are turned into events in a queue and move a pointer over the text screen,
+//! drawn by swapping the colors of the character under it.
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::io::RingBuffer;
+use crate::ps2::{DeviceType, Ps2Error};
+use crate::resource::{IrqLine, Resource};
+use crate::vga_driver::{Point, WRITER};
+
+///The PIC line the second PS/2 port raises
+pub const IRQ: u8 = 12;
+
+pub const RESOURCES: [Resource; 1] = [Resource::Irq(IRQ)];
+
+///How far the mouse has to move for the pointer to move a column, rows are twice as tall
+const COLUMN_WIDTH: i32 = 8;
+const ROW_HEIGHT: i32 = 16;
+
+//first packet byte
+const LEFT: u8 = 1 << 0;
+const RIGHT: u8 = 1 << 1;
+const MIDDLE: u8 = 1 << 2;
+const ALWAYS_SET: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+//fourth packet byte of a mouse with five buttons
+const FOURTH: u8 = 1 << 4;
+const FIFTH: u8 = 1 << 5;
+
+static MOUSE: Mutex<Mouse> = Mutex::new(Mouse::new());
+static EVENTS: Mutex<RingBuffer<MouseEvent, 64>> =
+    Mutex::new(RingBuffer::new(MouseEvent::Scrolled(0)));
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseButton {
+    Left,
+    Right,
+    Middle,
+    Fourth,
+    Fifth,
+}
+
+impl MouseButton {
+    pub const ALL: [MouseButton; 5] = [
+        MouseButton::Left,
+        MouseButton::Right,
+        MouseButton::Middle,
+        MouseButton::Fourth,
+        MouseButton::Fifth,
+    ];
+}
+
+///The buttons held down, one bit per button in the order of `MouseButton`
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Buttons(u8);
+
+impl Buttons {
+    pub fn is_pressed(&self, button: MouseButton) -> bool {
+        self.0 & 1 << button as u8 != 0
+    }
+
+    fn set(&mut self, button: MouseButton, pressed: bool) {
+        if pressed {
+            self.0 |= 1 << button as u8;
+        } else {
+            self.0 &= !(1 << button as u8);
+        }
+    }
+}
+
+///Movement is in mouse counts with y growing downwards like on the screen, the wheel
+///is positive when scrolled towards the user
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MouseEvent {
+    Moved { dx: i16, dy: i16 },
+    ButtonDown(MouseButton),
+    ButtonUp(MouseButton),
+    Scrolled(i8),
+}
+
+///One decoded packet
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Packet {
+    pub buttons: Buttons,
+    pub dx: i16,
+    pub dy: i16,
+    pub wheel: i8,
+}
+
+///Collects the bytes of a packet, the packet size follows the kind of mouse
+#[derive(Debug, Clone, Copy)]
+pub struct PacketDecoder {
+    device: DeviceType,
+    bytes: [u8; 4],
+    len: usize,
+}
+
+impl PacketDecoder {
+    pub const fn new(device: DeviceType) -> PacketDecoder {
+        PacketDecoder {
+            device,
+            bytes: [0; 4],
+            len: 0,
+        }
+    }
+
+    pub fn packet_size(&self) -> usize {
+        match self.device {
+            DeviceType::ScrollMouse | DeviceType::FiveButtonMouse => 4,
+            _ => 3,
+        }
+    }
+
+    pub fn add_byte(&mut self, byte: u8) -> Option<Packet> {
+        //a first byte without the always set bit means a byte got lost, skip until one has it
+        if self.len == 0 && byte & ALWAYS_SET == 0 {
+            return None;
+        }
+        self.bytes[self.len] = byte;
+        self.len += 1;
+        if self.len < self.packet_size() {
+            return None;
+        }
+        self.len = 0;
+        Some(self.decode())
+    }
+
+    fn decode(&self) -> Packet {
+        let [flags, x, y, extra] = self.bytes;
+        let mut buttons = Buttons::default();
+        buttons.set(MouseButton::Left, flags & LEFT != 0);
+        buttons.set(MouseButton::Right, flags & RIGHT != 0);
+        buttons.set(MouseButton::Middle, flags & MIDDLE != 0);
+
+        //the movement is a 9 bit number with the sign in the first byte
+        let movement = |value: u8, sign: u8, overflow: u8| -> i16 {
+            if flags & overflow != 0 {
+                0
+            } else if flags & sign != 0 {
+                value as i16 - 0x100
+            } else {
+                value as i16
+            }
+        };
+
+        let wheel = match self.device {
+            DeviceType::ScrollMouse => extra as i8,
+            DeviceType::FiveButtonMouse => {
+                buttons.set(MouseButton::Fourth, extra & FOURTH != 0);
+                buttons.set(MouseButton::Fifth, extra & FIFTH != 0);
+                //sign extends the low four bits
+                ((extra << 4) as i8) >> 4
+            }
+            _ => 0,
+        };
+
+        Packet {
+            buttons,
+            dx: movement(x, X_SIGN, X_OVERFLOW),
+            //the mouse counts up when moved away from the user
+            dy: -movement(y, Y_SIGN, Y_OVERFLOW),
+            wheel,
+        }
+    }
+}
+
+///The pointer on the text screen
+#[derive(Debug, Clone, Copy)]
+struct Pointer {
+    visible: bool,
+    //where it is drawn and the attribute it left there
+    drawn: Option<(usize, usize, u8)>,
+}
+
+impl Pointer {
+    fn hide(&mut self) {
+        if let Some((column, row, attribute)) = self.drawn.take() {
+            let mut writer = WRITER.lock();
+            //something else was written there since, leave it alone
+            if writer.attribute_at(Point(row, column)) == Some(attribute) {
+                writer.invert_at(Point(row, column));
+            }
+        }
+    }
+
+    fn show(&mut self, column: usize, row: usize) {
+        self.hide();
+        if !self.visible {
+            return;
+        }
+        let mut writer = WRITER.lock();
+        writer.invert_at(Point(row, column));
+        if let Some(attribute) = writer.attribute_at(Point(row, column)) {
+            self.drawn = Some((column, row, attribute));
+        }
+    }
+}
+
+pub struct Mouse {
+    decoder: PacketDecoder,
+    buttons: Buttons,
+    //the position in mouse counts, a column is `COLUMN_WIDTH` counts wide
+    x: i32,
+    y: i32,
+    pointer: Pointer,
+}
+
+impl Mouse {
+    pub const fn new() -> Mouse {
+        Mouse {
+            decoder: PacketDecoder::new(DeviceType::Mouse),
+            buttons: Buttons(0),
+            x: 0,
+            y: 0,
+            pointer: Pointer {
+                visible: false,
+                drawn: None,
+            },
+        }
+    }
+
+    pub fn buttons(&self) -> Buttons {
+        self.buttons
+    }
+
+    ///The column and row of the screen the pointer is on
+    pub fn position(&self) -> (usize, usize) {
+        (
+            (self.x / COLUMN_WIDTH) as usize,
+            (self.y / ROW_HEIGHT) as usize,
+        )
+    }
+
+    ///Applies a packet, `emit` gets the events it caused. The pointer stays inside a
+    ///screen of `width` by `height` characters.
+    pub fn process_packet(
+        &mut self,
+        packet: Packet,
+        (width, height): (usize, usize),
+        mut emit: impl FnMut(MouseEvent),
+    ) {
+        if packet.dx != 0 || packet.dy != 0 {
+            self.x = (self.x + packet.dx as i32).clamp(0, width as i32 * COLUMN_WIDTH - 1);
+            self.y = (self.y + packet.dy as i32).clamp(0, height as i32 * ROW_HEIGHT - 1);
+            emit(MouseEvent::Moved {
+                dx: packet.dx,
+                dy: packet.dy,
+            });
+        }
+
+        for button in MouseButton::ALL {
+            let pressed = packet.buttons.is_pressed(button);
+            if pressed != self.buttons.is_pressed(button) {
+                emit(if pressed {
+                    MouseEvent::ButtonDown(button)
+                } else {
+                    MouseEvent::ButtonUp(button)
+                });
+            }
+        }
+        self.buttons = packet.buttons;
+
+        if packet.wheel != 0 {
+            emit(MouseEvent::Scrolled(packet.wheel));
+        }
+    }
+}
+
+impl Default for Mouse {
+    fn default() -> Self {
+        Mouse::new()
+    }
+}
+
+///Turns on the mouse if the controller found one, call it with interrupts disabled
+pub fn init(irq: &IrqLine) -> Result<DeviceType, Ps2Error> {
+    let device = crate::ps2::init_mouse()?;
+    MOUSE.lock().decoder = PacketDecoder::new(device);
+    irq.unmask();
+    Ok(device)
+}
+
+///Called with every byte the mouse sends
+pub fn handle_byte(byte: u8) {
+    let mut mouse = MOUSE.lock();
+    if let Some(packet) = mouse.decoder.add_byte(byte) {
+        let size = {
+            let writer = WRITER.lock();
+            (writer.width(), writer.height())
+        };
+        mouse.process_packet(packet, size, |event| {
+            EVENTS.lock().push_overwrite(event);
+        });
+        let (column, row) = mouse.position();
+        mouse.pointer.show(column, row);
+    }
+}
+
+///Takes the oldest event from the queue, old events are dropped when nobody reads them
+pub fn poll_event() -> Option<MouseEvent> {
+    without_interrupts(|| EVENTS.lock().pop())
+}
+
+pub fn position() -> (usize, usize) {
+    without_interrupts(|| MOUSE.lock().position())
+}
+
+pub fn buttons() -> Buttons {
+    without_interrupts(|| MOUSE.lock().buttons())
+}
+
+///Shows or hides the pointer, hide it while redrawing the screen under it
+pub fn set_pointer_visible(visible: bool) {
+    without_interrupts(|| {
+        let mut mouse = MOUSE.lock();
+        let (column, row) = mouse.position();
+        mouse.pointer.visible = visible;
+        mouse.pointer.show(column, row);
+    });
+}
+
+#[test_case]
+fn test_three_byte_packets() {
+    let mut decoder = PacketDecoder::new(DeviceType::Mouse);
+
+    //a stray byte before the packet is skipped
+    assert_eq!(decoder.add_byte(0x05), None);
+    assert_eq!(decoder.add_byte(0x09), None);
+    assert_eq!(decoder.add_byte(0x10), None);
+    let packet = decoder.add_byte(0x20).unwrap();
+    assert!(packet.buttons.is_pressed(MouseButton::Left));
+    assert!(!packet.buttons.is_pressed(MouseButton::Right));
+    assert_eq!((packet.dx, packet.dy), (0x10, -0x20));
+
+    //moving left and down
+    decoder.add_byte(0x38);
+    decoder.add_byte(0xFE);
+    let packet = decoder.add_byte(0xFB).unwrap();
+    assert_eq!((packet.dx, packet.dy), (-2, 5));
+    assert_eq!(packet.wheel, 0);
+}
+
+#[test_case]
+fn test_wheel_packets() {
+    let mut decoder = PacketDecoder::new(DeviceType::ScrollMouse);
+    let packet = [0x08, 0, 0, 0xFF]
+        .iter()
+        .find_map(|byte| decoder.add_byte(*byte))
+        .unwrap();
+    assert_eq!(packet.wheel, -1);
+
+    let mut decoder = PacketDecoder::new(DeviceType::FiveButtonMouse);
+    let packet = [0x08, 0, 0, 0x12]
+        .iter()
+        .find_map(|byte| decoder.add_byte(*byte))
+        .unwrap();
+    assert_eq!(packet.wheel, 2);
+    assert!(packet.buttons.is_pressed(MouseButton::Fourth));
+}
+
+#[test_case]
+fn test_mouse_events() {
+    let mut mouse = Mouse::new();
+    let mut events = [None; 4];
+    let mut count = 0;
+    let mut pressed = Buttons::default();
+    pressed.set(MouseButton::Right, true);
+
+    mouse.process_packet(
+        Packet {
+            buttons: pressed,
+            dx: 100,
+            dy: -5,
+            wheel: 1,
+        },
+        (10, 5),
+        |event| {
+            events[count] = Some(event);
+            count += 1;
+        },
+    );
+    assert_eq!(
+        events,
+        [
+            Some(MouseEvent::Moved { dx: 100, dy: -5 }),
+            Some(MouseEvent::ButtonDown(MouseButton::Right)),
+            Some(MouseEvent::Scrolled(1)),
+            None
+        ]
+    );
+    //clamped to the last column and the first row
+    assert_eq!(mouse.position(), (9, 0));
+}
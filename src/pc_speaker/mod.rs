@@ -0,0 +1,261 @@
+//! The pc speaker, a square wave from channel 2 of the PIT let through by port 0x61
+//!
+//! `beep` plays one tone and waits for it to end. `play` queues notes that the timer
+//! interrupt goes through in the background, melodies can be written down in RTTTL.
+
+use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::io::RingBuffer;
+use crate::pit::{self, Channel, Mode, SpeakerControl};
+
+pub mod rtttl;
+
+///The lowest and highest tones played, about what people can hear
+pub const MIN_FREQUENCY: u32 = 20;
+pub const MAX_FREQUENCY: u32 = 20_000;
+///How many notes can wait to be played
+pub const QUEUE_LENGTH: usize = 128;
+
+static PLAYER: Mutex<Player> = Mutex::new(Player::new());
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpeakerError {
+    FrequencyOutOfRange(u32),
+    ///The melody does not fit in what is left of the queue
+    QueueFull,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Note {
+    ///In hertz, `None` is a rest
+    pub frequency: Option<u32>,
+    pub duration: Duration,
+}
+
+impl Note {
+    pub const fn tone(frequency: u32, duration: Duration) -> Note {
+        Note {
+            frequency: Some(frequency),
+            duration,
+        }
+    }
+
+    pub const fn rest(duration: Duration) -> Note {
+        Note {
+            frequency: None,
+            duration,
+        }
+    }
+}
+
+//what the speaker has to do after a timer tick
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Change {
+    Play(Note),
+    Silence,
+}
+
+//the queued notes and when the one playing now ends
+struct Player {
+    queue: RingBuffer<Note, QUEUE_LENGTH>,
+    ends_at: Option<Duration>,
+}
+
+impl Player {
+    const fn new() -> Player {
+        Player {
+            queue: RingBuffer::new(Note::rest(Duration::ZERO)),
+            ends_at: None,
+        }
+    }
+
+    fn queue(&mut self, notes: &[Note]) -> Result<(), SpeakerError> {
+        if let Some(note) = notes.iter().find(|note| !in_range(note.frequency)) {
+            return Err(SpeakerError::FrequencyOutOfRange(
+                note.frequency.unwrap_or(0),
+            ));
+        }
+        if QUEUE_LENGTH - self.queue.len() < notes.len() {
+            return Err(SpeakerError::QueueFull);
+        }
+        for note in notes {
+            let _ = self.queue.push(*note);
+        }
+        Ok(())
+    }
+
+    fn is_playing(&self) -> bool {
+        self.ends_at.is_some() || !self.queue.is_empty()
+    }
+
+    fn advance(&mut self, now: Duration) -> Option<Change> {
+        if matches!(self.ends_at, Some(end) if now < end) {
+            return None;
+        }
+        match self.queue.pop() {
+            Some(note) => {
+                //the next note starts where the last one ended so late ticks do not add up
+                let start = self.ends_at.unwrap_or(now);
+                self.ends_at = Some(start + note.duration);
+                Some(Change::Play(note))
+            }
+            None => self.ends_at.take().map(|_| Change::Silence),
+        }
+    }
+
+    fn clear(&mut self) {
+        self.queue.clear();
+        self.ends_at = None;
+    }
+}
+
+fn in_range(frequency: Option<u32>) -> bool {
+    frequency.is_none_or(|frequency| (MIN_FREQUENCY..=MAX_FREQUENCY).contains(&frequency))
+}
+
+//the speaker is silent while the pit isn't set up
+fn control() -> SpeakerControl {
+    pit::speaker_control().unwrap_or_default()
+}
+
+fn set_gate(open: bool) {
+    pit::modify_speaker_control(|control| {
+        control
+            .with(SpeakerControl::GATE, open)
+            .with(SpeakerControl::DATA, open)
+    });
+}
+
+///Starts a tone that plays until `stop_sound`
+pub fn play_sound(frequency: u32) -> Result<(), SpeakerError> {
+    if !in_range(Some(frequency)) {
+        return Err(SpeakerError::FrequencyOutOfRange(frequency));
+    }
+    let divisor =
+        pit::divisor_for(frequency).ok_or(SpeakerError::FrequencyOutOfRange(frequency))?;
+    pit::set_divisor(Channel::Speaker, Mode::SquareWave, divisor);
+    set_gate(true);
+    Ok(())
+}
+
+pub fn stop_sound() {
+    set_gate(false);
+}
+
+///True while the timer is connected to the speaker
+pub fn is_sounding() -> bool {
+    let control = control();
+    control.get(SpeakerControl::GATE) && control.get(SpeakerControl::DATA)
+}
+
+///Plays a tone for `duration` and waits for it, stops a melody that was playing
+pub fn beep(frequency: u32, duration: Duration) -> Result<(), SpeakerError> {
+    stop();
+    play_sound(frequency)?;
+    crate::interrupts::sleep(duration);
+    stop_sound();
+    Ok(())
+}
+
+///Adds notes to the end of the melody, nothing is queued if any of them can't be played
+pub fn play(notes: &[Note]) -> Result<(), SpeakerError> {
+    without_interrupts(|| PLAYER.lock().queue(notes))
+}
+
+///True until the last queued note has ended
+pub fn is_playing() -> bool {
+    without_interrupts(|| PLAYER.lock().is_playing())
+}
+
+///Stops the melody and throws away the rest of it
+pub fn stop() {
+    without_interrupts(|| {
+        let mut player = PLAYER.lock();
+        if player.is_playing() {
+            player.clear();
+            stop_sound();
+        }
+    });
+}
+
+///Moves the melody along, called from the timer interrupt
+pub(crate) fn timer_tick(now: Duration) {
+    let change = PLAYER.lock().advance(now);
+    match change {
+        Some(Change::Play(Note {
+            frequency: Some(frequency),
+            ..
+        })) => {
+            //the frequency was checked when the note was queued
+            let _ = play_sound(frequency);
+        }
+        Some(Change::Play(_)) | Some(Change::Silence) => stop_sound(),
+        None => (),
+    }
+}
+
+#[test_case]
+fn test_play_sound() {
+    play_sound(1000).unwrap();
+}
+
+#[test_case]
+fn test_stop_sound() {
+    stop_sound();
+    assert!(!is_sounding());
+}
+
+#[test_case]
+fn test_frequency_range() {
+    assert_eq!(play_sound(0), Err(SpeakerError::FrequencyOutOfRange(0)));
+    assert_eq!(
+        play_sound(25_000),
+        Err(SpeakerError::FrequencyOutOfRange(25_000))
+    );
+    assert!(!is_sounding());
+}
+
+#[test_case]
+fn test_speaker_hardware_state() {
+    play_sound(440).unwrap();
+    let (status, count) = pit::read_back(Channel::Speaker).unwrap();
+    assert_eq!(status.mode(), Some(Mode::SquareWave));
+    assert!(status.low_high_access());
+    assert!(count <= pit::divisor_for(440).unwrap());
+    assert!(control().get(SpeakerControl::GATE));
+    assert!(control().get(SpeakerControl::DATA));
+
+    stop_sound();
+    assert!(!control().get(SpeakerControl::GATE));
+    assert!(!control().get(SpeakerControl::DATA));
+}
+
+#[test_case]
+fn test_player() {
+    let ms = Duration::from_millis;
+    let a = Note::tone(440, ms(100));
+    let rest = Note::rest(ms(50));
+    let mut player = Player::new();
+
+    assert_eq!(
+        player.queue(&[a, Note::tone(5, ms(10))]),
+        Err(SpeakerError::FrequencyOutOfRange(5))
+    );
+    assert!(!player.is_playing());
+
+    player.queue(&[a, rest]).unwrap();
+    assert_eq!(player.advance(ms(1000)), Some(Change::Play(a)));
+    assert_eq!(player.advance(ms(1099)), None);
+    //a late tick does not push the next note back
+    assert_eq!(player.advance(ms(1120)), Some(Change::Play(rest)));
+    assert_eq!(player.advance(ms(1149)), None);
+    assert_eq!(player.advance(ms(1150)), Some(Change::Silence));
+    assert_eq!(player.advance(ms(1200)), None);
+    assert!(!player.is_playing());
+
+    let long = [a; QUEUE_LENGTH + 1];
+    assert_eq!(player.queue(&long), Err(SpeakerError::QueueFull));
+}
@@ -0,0 +1,225 @@
+//! Ring tone text transfer language, the melody format of old phones
+//!
+//! A melody is a name, the defaults and the notes separated by colons, like
+//! `scale:d=4,o=5,b=120:c,d,e,f,g,a,b,c6`. The defaults are the duration, the octave and
+//! the beats per minute. A note is an optional duration, a letter or `p` for a pause, an
+//! optional `#`, an optional octave and an optional dot that makes it half again as long.
+
+use core::time::Duration;
+
+use super::Note;
+
+///The frequencies of the eighth octave from C up, lower octaves halve them
+const OCTAVE_8: [u32; 12] = [
+    4186, 4435, 4699, 4978, 5274, 5588, 5920, 6272, 6645, 7040, 7459, 7902,
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RtttlError {
+    ///The text is not three parts separated by colons
+    Format,
+    ///A default is not known or its value can't be used
+    Default,
+    ///The note at this index, counted from zero, can't be read
+    Note(usize),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Melody<'a> {
+    pub name: &'a str,
+    duration: u32,
+    octave: u32,
+    beats_per_minute: u32,
+    notes: &'a str,
+}
+
+impl<'a> Melody<'a> {
+    ///Reads the name and the defaults, the notes are read by `notes`
+    pub fn parse(text: &'a str) -> Result<Melody<'a>, RtttlError> {
+        let mut parts = text.trim().splitn(3, ':');
+        let (Some(name), Some(defaults), Some(notes)) = (parts.next(), parts.next(), parts.next())
+        else {
+            return Err(RtttlError::Format);
+        };
+
+        //the defaults the standard gives when a melody leaves them out
+        let mut melody = Melody {
+            name: name.trim(),
+            duration: 4,
+            octave: 6,
+            beats_per_minute: 63,
+            notes,
+        };
+        for default in defaults.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let (key, value) = default.split_once('=').ok_or(RtttlError::Default)?;
+            let value = value.trim().parse().map_err(|_| RtttlError::Default)?;
+            match key.trim() {
+                "d" | "D" if is_duration(value) => melody.duration = value,
+                "o" | "O" if is_octave(value) => melody.octave = value,
+                "b" | "B" if (1..=900).contains(&value) => melody.beats_per_minute = value,
+                _ => return Err(RtttlError::Default),
+            }
+        }
+        Ok(melody)
+    }
+
+    pub fn notes(&self) -> Notes<'a> {
+        Notes {
+            melody: *self,
+            parts: self.notes.split(','),
+            index: 0,
+            broken: false,
+        }
+    }
+
+    fn parse_note(&self, text: &str) -> Option<Note> {
+        let (duration, rest) = take_number(text.as_bytes());
+        let duration = match duration {
+            Some(duration) if is_duration(duration) => duration,
+            Some(_) => return None,
+            None => self.duration,
+        };
+
+        let (letter, mut rest) = rest.split_first()?;
+        let semitone = match letter.to_ascii_lowercase() {
+            b'p' => None,
+            b'c' => Some(0),
+            b'd' => Some(2),
+            b'e' => Some(4),
+            b'f' => Some(5),
+            b'g' => Some(7),
+            b'a' => Some(9),
+            //some melodies use the german name for b
+            b'b' | b'h' => Some(11),
+            _ => return None,
+        };
+        let mut sharp = false;
+        if let [b'#', after @ ..] = rest {
+            sharp = true;
+            rest = after;
+        }
+        let mut dotted = false;
+        if let [b'.', after @ ..] = rest {
+            dotted = true;
+            rest = after;
+        }
+        let (octave, mut rest) = take_number(rest);
+        let octave = match octave {
+            Some(octave) if is_octave(octave) => octave,
+            Some(_) => return None,
+            None => self.octave,
+        };
+        //the dot is allowed after the octave too
+        if let [b'.', after @ ..] = rest {
+            dotted = true;
+            rest = after;
+        }
+        if !rest.is_empty() {
+            return None;
+        }
+
+        //a whole note is four beats
+        let mut micros = 240_000_000 / (self.beats_per_minute * duration) as u64;
+        if dotted {
+            micros += micros / 2;
+        }
+        let duration = Duration::from_micros(micros);
+
+        Some(match semitone {
+            None => Note::rest(duration),
+            Some(semitone) => {
+                let semitone = semitone + sharp as usize;
+                //b sharp is the c of the next octave
+                let (semitone, octave) = (semitone % 12, octave + semitone as u32 / 12);
+                let shift = 8u32.saturating_sub(octave);
+                let frequency = (OCTAVE_8[semitone] + (1 << shift) / 2) >> shift;
+                Note::tone(frequency, duration)
+            }
+        })
+    }
+}
+
+///The notes of a melody in order, stops after the first one that can't be read
+#[derive(Debug, Clone)]
+pub struct Notes<'a> {
+    melody: Melody<'a>,
+    parts: core::str::Split<'a, char>,
+    index: usize,
+    broken: bool,
+}
+
+impl Iterator for Notes<'_> {
+    type Item = Result<Note, RtttlError>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.broken {
+            return None;
+        }
+        let text = self.parts.by_ref().map(str::trim).find(|s| !s.is_empty())?;
+        let index = self.index;
+        self.index += 1;
+        match self.melody.parse_note(text) {
+            Some(note) => Some(Ok(note)),
+            None => {
+                //nothing after a broken note is trusted
+                self.broken = true;
+                Some(Err(RtttlError::Note(index)))
+            }
+        }
+    }
+}
+
+fn is_duration(value: u32) -> bool {
+    matches!(value, 1 | 2 | 4 | 8 | 16 | 32)
+}
+
+//the standard only has octaves 4 to 7, lower and higher ones are easy enough to play
+fn is_octave(value: u32) -> bool {
+    (1..=8).contains(&value)
+}
+
+//the number at the start of the bytes, if there is one, and the bytes after it
+fn take_number(bytes: &[u8]) -> (Option<u32>, &[u8]) {
+    let digits = bytes
+        .iter()
+        .take_while(|byte| byte.is_ascii_digit())
+        .count();
+    if digits == 0 || digits > 3 {
+        return (None, &bytes[digits..]);
+    }
+    let number = bytes[..digits]
+        .iter()
+        .fold(0, |number, digit| number * 10 + (digit - b'0') as u32);
+    (Some(number), &bytes[digits..])
+}
+
+#[test_case]
+fn test_parse_melody() {
+    let melody = Melody::parse("test:d=4,o=5,b=120:8e6,p,c#.,2a4, 16b#5").unwrap();
+    assert_eq!(melody.name, "test");
+
+    let ms = Duration::from_millis;
+    let mut notes = melody.notes();
+    assert_eq!(notes.next(), Some(Ok(Note::tone(1319, ms(250)))));
+    assert_eq!(notes.next(), Some(Ok(Note::rest(ms(500)))));
+    assert_eq!(notes.next(), Some(Ok(Note::tone(554, ms(750)))));
+    assert_eq!(notes.next(), Some(Ok(Note::tone(440, ms(1000)))));
+    assert_eq!(
+        notes.next(),
+        Some(Ok(Note::tone(1047, Duration::from_micros(125_000))))
+    );
+    assert_eq!(notes.next(), None);
+}
+
+#[test_case]
+fn test_rtttl_errors() {
+    assert_eq!(Melody::parse("no colons").err(), Some(RtttlError::Format));
+    assert_eq!(Melody::parse("x:d=3:c").err(), Some(RtttlError::Default));
+    assert_eq!(Melody::parse("x:q=1:c").err(), Some(RtttlError::Default));
+
+    let melody = Melody::parse("x::c,q,d").unwrap();
+    let mut notes = melody.notes();
+    assert!(matches!(notes.next(), Some(Ok(_))));
+    assert_eq!(notes.next(), Some(Err(RtttlError::Note(1))));
+    assert_eq!(notes.next(), None);
+}
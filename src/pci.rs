@@ -0,0 +1,220 @@
+//! The PCI bus through configuration mechanism #1
+//!
+//! Mechanism #1 is on every PC but only reaches the first 256 bytes of each function.
+//! ECAM reaches all of it, but rost has no ACPI to find the MCFG table with yet, so the
+//! kernel doesn't use `rost_core::pci::access::Ecam`.
+
+use core::fmt::Write;
+
+use rost_core::pci::access::{Mechanism1, MECHANISM1_PORTS};
+use rost_core::pci::capability::{self, Msi, MsiX};
+use rost_core::pci::{bar, names, Function};
+use spin::{Mutex, Once};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::driver::{self, Driver, DriverError};
+use crate::println;
+use crate::resource::{PortRange, Reservation, Resource};
+use crate::shell::line::Text;
+
+pub use rost_core::pci::{Address, ConfigSpace};
+
+pub const RESOURCES: [Resource; 1] = [Resource::ports(0xCF8, MECHANISM1_PORTS)];
+///How many functions `scan` keeps, QEMU has well under this
+pub const MAX_FUNCTIONS: usize = 64;
+//an address is `bb:dd.f`
+const NAME_LENGTH: usize = 7;
+
+//an access is two port accesses that must not be split up, lock it with interrupts
+//disabled. Set by `init`.
+static CONFIG: Mutex<Option<Mechanism1<PortRange>>> = Mutex::new(None);
+
+//the addresses of the functions `init` found, the names of their devices
+static NAMES: Once<[Text<NAME_LENGTH>; MAX_FUNCTIONS]> = Once::new();
+static FUNCTIONS: [FunctionDevice; MAX_FUNCTIONS] = function_devices();
+
+///A function on the bus in the device tree, named after its address. No function has a
+///driver of its own yet, so there is nothing to set up.
+#[derive(Clone, Copy)]
+struct FunctionDevice(usize);
+
+impl Driver for FunctionDevice {
+    fn name(&self) -> &'static str {
+        NAMES.r#try().map_or("", |names| names[self.0].as_str())
+    }
+
+    fn parent(&self) -> Option<&'static str> {
+        Some("pci")
+    }
+
+    fn init(&self, _: &mut Reservation) -> Result<(), DriverError> {
+        Ok(())
+    }
+}
+
+const fn function_devices() -> [FunctionDevice; MAX_FUNCTIONS] {
+    let mut devices = [FunctionDevice(0); MAX_FUNCTIONS];
+    let mut i = 0;
+    while i < MAX_FUNCTIONS {
+        devices[i] = FunctionDevice(i);
+        i += 1;
+    }
+    devices
+}
+
+///Runs `f` with configuration space to itself, `None` before `init`
+pub fn with_config<T>(f: impl FnOnce(&mut Mechanism1<PortRange>) -> T) -> Option<T> {
+    without_interrupts(|| CONFIG.lock().as_mut().map(f))
+}
+
+///Whether there is a host bridge with mechanism #1 behind `ports`
+pub fn is_present(ports: &mut PortRange) -> bool {
+    Mechanism1::new(ports).is_present()
+}
+
+///Fills `functions` with the functions on the bus, returns how many there are. Those
+///past the end of `functions` are left out.
+pub fn scan(functions: &mut [Option<Function>; MAX_FUNCTIONS]) -> usize {
+    let mut count = 0;
+    with_config(|config| {
+        rost_core::pci::enumerate(config, |function| {
+            if let Some(slot) = functions.get_mut(count) {
+                *slot = Some(*function);
+                count += 1;
+            }
+        })
+    });
+    count
+}
+
+///Takes the ports of mechanism #1 and registers a device for every function on the bus
+pub fn init(ports: PortRange) {
+    without_interrupts(|| *CONFIG.lock() = Some(Mechanism1::new(ports)));
+
+    let mut functions = [None; MAX_FUNCTIONS];
+    let count = scan(&mut functions);
+    NAMES.call_once(|| {
+        let mut names = [Text::new(); MAX_FUNCTIONS];
+        for (name, function) in names.iter_mut().zip(functions[..count].iter().flatten()) {
+            let _ = write!(name, "{}", function.address);
+        }
+        names
+    });
+
+    for (device, function) in FUNCTIONS.iter().zip(functions[..count].iter().flatten()) {
+        log::debug!(
+            "{} {} [{:04x}:{:04x}]",
+            function.address,
+            names::class(function.class, function.subclass),
+            function.vendor,
+            function.device
+        );
+        if let Err(error) = driver::register(device) {
+            log::warn!("{} isn't in the device tree: {:?}", function.address, error);
+        }
+    }
+    log::info!("{} pci functions", count);
+}
+
+///Prints the functions on the bus like `00:01.0 ISA bridge: Intel PIIX3 ISA bridge`,
+///`verbose` adds the bars, the interrupt and the capabilities
+pub fn list(verbose: bool) {
+    let mut functions = [None; MAX_FUNCTIONS];
+    let count = scan(&mut functions);
+
+    for function in functions[..count].iter().flatten() {
+        print_function(function);
+        if verbose {
+            with_config(|config| print_details(config, function));
+        }
+    }
+}
+
+fn print_function(function: &Function) {
+    let class = names::class(function.class, function.subclass);
+    let vendor = names::vendor(function.vendor);
+    let device = names::device(function.vendor, function.device);
+    let id = (function.vendor, function.device);
+    match (vendor, device) {
+        (Some(vendor), Some(device)) => println!(
+            "{} {}: {} {} [{:04x}:{:04x}]",
+            function.address, class, vendor, device, id.0, id.1
+        ),
+        (Some(vendor), None) => println!(
+            "{} {}: {} device [{:04x}:{:04x}]",
+            function.address, class, vendor, id.0, id.1
+        ),
+        (None, _) => println!(
+            "{} {}: [{:04x}:{:04x}]",
+            function.address, class, id.0, id.1
+        ),
+    }
+}
+
+fn print_details(config: &mut impl ConfigSpace, function: &Function) {
+    if let Some((pin, line)) = function.interrupt(config) {
+        println!("  interrupt pin {} on irq {}", pin, line);
+    }
+    for (index, bar) in bar::bars(config, function) {
+        println!("  bar {}: {}", index, bar);
+    }
+    let mut capabilities = [None; 16];
+    for (slot, capability) in capabilities
+        .iter_mut()
+        .zip(capability::capabilities(config, function))
+    {
+        *slot = Some(capability);
+    }
+    for capability in capabilities.iter().flatten() {
+        match capability.id {
+            capability::MSI => println!(
+                "  {}: {}",
+                capability,
+                Msi::read(config, function, *capability)
+            ),
+            capability::MSI_X => println!(
+                "  {}: {}",
+                capability,
+                MsiX::read(config, function, *capability)
+            ),
+            _ => println!("  {}", capability),
+        }
+    }
+}
+
+#[test_case]
+fn test_host_bridge() {
+    let mut functions = [None; MAX_FUNCTIONS];
+    let count = scan(&mut functions);
+    assert!(count > 0);
+
+    let host = functions[0].unwrap();
+    assert_eq!(host.address, Address::new(0, 0, 0));
+    assert_eq!((host.class, host.subclass), (0x06, 0x00));
+    //both machines QEMU emulates have an Intel chipset
+    assert_eq!(host.vendor, 0x8086);
+}
+
+#[test_case]
+fn test_functions_in_device_tree() {
+    //the host bridge is always the first function
+    assert_eq!(driver::state("00:00.0"), Some(driver::DeviceState::Ready));
+}
+
+#[test_case]
+fn test_bars_of_the_vga() {
+    let mut functions = [None; MAX_FUNCTIONS];
+    let count = scan(&mut functions);
+    let vga = functions[..count]
+        .iter()
+        .flatten()
+        .find(|function| function.class == 0x03);
+
+    //the framebuffer of a card is memory, and sizing it leaves it where it was
+    if let Some(vga) = vga {
+        let first = with_config(|config| bar::read(config, vga, 0)).flatten();
+        let again = with_config(|config| bar::read(config, vga, 0)).flatten();
+        assert!(matches!(first, Some(bar::Bar::Memory { .. })));
+        assert_eq!(first, again);
+    }
+}
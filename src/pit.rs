@@ -0,0 +1,294 @@
+//! The programmable interval timer
+//!
+//! Channel 0 raises the timer interrupt and channel 2 drives the pc speaker. Each channel
+//! counts its divisor down at `FREQUENCY` and starts over when it reaches zero.
+
+use rost_core::bitfield;
+use rost_core::port::PortIo;
+use rost_core::register::{PortRegister, ReadWrite, WriteOnly};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::resource::{PortSet, Resource};
+
+///The input clock in hertz
+pub const FREQUENCY: u32 = 1_193_182;
+///Makes the timer interrupt come about a thousand times a second
+pub const TIMER_DIVISOR: u16 = 1193;
+
+///The channels, the command port, the speaker control port and the timer interrupt
+pub const RESOURCES: [Resource; 3] = [
+    Resource::ports(0x40, 4),
+    Resource::ports(0x61, 1),
+    Resource::Irq(0),
+];
+
+//the channels with the command port and the speaker control port, set by `init`
+static PIT: Mutex<Option<Pit<PortSet<2>>>> = Mutex::new(None);
+
+const COMMAND: PortRegister<Command, WriteOnly> = PortRegister::new(0x43);
+///The gate of channel 2 and its output are in the pc speaker control port
+const SPEAKER_CONTROL: PortRegister<SpeakerControl, ReadWrite> = PortRegister::new(0x61);
+
+//the access field of a command
+const ACCESS_LOW_HIGH: u8 = 0b11;
+//a command to the channel 3 that isn't there is a read back command
+const READ_BACK: u8 = 3;
+
+bitfield! {
+    struct Command(u8) {
+        const MODE: u8 = 1..=3;
+        const ACCESS: u8 = 4..=5;
+        const CHANNEL: u8 = 6..=7;
+        //a read back latches the count and the status of the channels whose bits are set,
+        //the latch bits are inverted
+        const READ_BACK_CHANNELS: u8 = 1..=3;
+    }
+}
+
+bitfield! {
+    pub struct SpeakerControl(u8) {
+        ///Lets channel 2 count
+        pub const GATE: bool = 0;
+        ///Lets the output of channel 2 through to the speaker
+        pub const DATA: bool = 1;
+        ///The output of channel 2, read only
+        pub const OUTPUT: bool = 5;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Channel {
+    Timer = 0,
+    Speaker = 2,
+}
+
+impl Channel {
+    fn data(self) -> PortRegister<u8> {
+        PortRegister::new(0x40 + self as u16)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum Mode {
+    ///The output goes high once the count runs out and stays there
+    InterruptOnTerminalCount = 0,
+    ///A short pulse every time the count runs out, what the timer interrupt wants
+    RateGenerator = 2,
+    ///High for half of the count and low for the other half, what the speaker wants
+    SquareWave = 3,
+}
+
+bitfield! {
+    ///The status byte of a channel from a read back command
+    pub struct Status(u8) {
+        pub const MODE: u8 = 1..=3;
+        pub const ACCESS: u8 = 4..=5;
+        ///Set until a newly written divisor has been loaded into the counter
+        pub const NULL_COUNT: bool = 6;
+        ///The level of the output pin
+        pub const OUTPUT: bool = 7;
+    }
+}
+
+impl Status {
+    ///The level of the output pin
+    pub fn output(&self) -> bool {
+        self.get(Status::OUTPUT)
+    }
+
+    ///Set until a newly written divisor has been loaded into the counter
+    pub fn null_count(&self) -> bool {
+        self.get(Status::NULL_COUNT)
+    }
+
+    ///True if the divisor is written as a low and a high byte
+    pub fn low_high_access(&self) -> bool {
+        self.get(Status::ACCESS) == ACCESS_LOW_HIGH
+    }
+
+    pub fn mode(&self) -> Option<Mode> {
+        //modes 6 and 7 are other names for 2 and 3
+        match self.get(Status::MODE) & 0b11 {
+            0 => Some(Mode::InterruptOnTerminalCount),
+            2 => Some(Mode::RateGenerator),
+            3 => Some(Mode::SquareWave),
+            _ => None,
+        }
+    }
+}
+
+pub struct Pit<P> {
+    ports: P,
+}
+
+impl<P: PortIo> Pit<P> {
+    pub const fn with_ports(ports: P) -> Pit<P> {
+        Pit { ports }
+    }
+
+    ///Starts a channel over with a new divisor
+    pub fn set_divisor(&mut self, channel: Channel, mode: Mode, divisor: u16) {
+        let command = Command::default()
+            .with(Command::CHANNEL, channel as u8)
+            .with(Command::ACCESS, ACCESS_LOW_HIGH)
+            .with(Command::MODE, mode as u8);
+        let [low, high] = divisor.to_le_bytes();
+
+        //the three writes must not be split by someone else programming the timer
+        without_interrupts(|| {
+            COMMAND.write(&mut self.ports, command);
+            channel.data().write(&mut self.ports, low);
+            channel.data().write(&mut self.ports, high);
+        });
+    }
+
+    ///Reads the status and the current count of a channel
+    pub fn read_back(&mut self, channel: Channel) -> (Status, u16) {
+        let command = Command::default()
+            .with(Command::CHANNEL, READ_BACK)
+            .with(Command::READ_BACK_CHANNELS, 1 << channel as u8);
+
+        without_interrupts(|| {
+            COMMAND.write(&mut self.ports, command);
+            //the status comes first when both were latched
+            let status = Status(channel.data().read(&mut self.ports));
+            let low = channel.data().read(&mut self.ports);
+            let high = channel.data().read(&mut self.ports);
+            (status, u16::from_le_bytes([low, high]))
+        })
+    }
+
+    pub fn speaker_control(&mut self) -> SpeakerControl {
+        SPEAKER_CONTROL.read(&mut self.ports)
+    }
+
+    pub fn modify_speaker_control(&mut self, f: impl FnOnce(SpeakerControl) -> SpeakerControl) {
+        SPEAKER_CONTROL.modify(&mut self.ports, f);
+    }
+
+    ///Spins for `count` ticks of the input clock on channel 2
+    pub fn busy_wait(&mut self, count: u16) {
+        without_interrupts(|| {
+            let saved = SPEAKER_CONTROL.read(&mut self.ports);
+            //the gate lets the channel count, the speaker stays quiet
+            let counting = saved
+                .with(SpeakerControl::DATA, false)
+                .with(SpeakerControl::GATE, true);
+            SPEAKER_CONTROL.write(&mut self.ports, counting);
+            self.set_divisor(Channel::Speaker, Mode::InterruptOnTerminalCount, count);
+            while !SPEAKER_CONTROL
+                .read(&mut self.ports)
+                .get(SpeakerControl::OUTPUT)
+            {
+                core::hint::spin_loop();
+            }
+            SPEAKER_CONTROL.write(&mut self.ports, saved);
+        });
+    }
+}
+
+///The divisor that comes closest to `frequency`, `None` if no divisor gets there
+pub fn divisor_for(frequency: u32) -> Option<u16> {
+    if frequency == 0 {
+        return None;
+    }
+    let divisor = (FREQUENCY + frequency / 2) / frequency;
+    //a divisor of one does not count at all in the square wave mode
+    if divisor < 2 {
+        return None;
+    }
+    u16::try_from(divisor).ok()
+}
+
+//runs `f` on the pit, `None` until `init` got it the ports
+fn with_pit<T>(f: impl FnOnce(&mut Pit<PortSet<2>>) -> T) -> Option<T> {
+    without_interrupts(|| PIT.lock().as_mut().map(f))
+}
+
+///Starts a channel over with a new divisor, changing the timer channel throws off `uptime`
+pub fn set_divisor(channel: Channel, mode: Mode, divisor: u16) {
+    with_pit(|pit| pit.set_divisor(channel, mode, divisor));
+}
+
+///Reads the status and the current count of a channel
+pub fn read_back(channel: Channel) -> Option<(Status, u16)> {
+    with_pit(|pit| pit.read_back(channel))
+}
+
+///Spins for `count` ticks of the input clock on channel 2, which stops a tone the pc
+///speaker was playing
+pub fn busy_wait(count: u16) {
+    with_pit(|pit| pit.busy_wait(count));
+}
+
+pub fn speaker_control() -> Option<SpeakerControl> {
+    with_pit(|pit| pit.speaker_control())
+}
+
+pub fn modify_speaker_control(f: impl FnOnce(SpeakerControl) -> SpeakerControl) {
+    with_pit(|pit| pit.modify_speaker_control(f));
+}
+
+///Takes the ports of the channels and of the speaker control and sets the timer interrupt
+///to `TIMER_DIVISOR`, call it before enabling interrupts
+pub fn init(ports: PortSet<2>) {
+    without_interrupts(|| *PIT.lock() = Some(Pit::with_ports(ports)));
+    set_divisor(Channel::Timer, Mode::RateGenerator, TIMER_DIVISOR);
+}
+
+#[test_case]
+fn test_divisor_for() {
+    assert_eq!(divisor_for(1000), Some(1193));
+    assert_eq!(divisor_for(440), Some(2712));
+    assert_eq!(divisor_for(0), None);
+    assert_eq!(divisor_for(18), None);
+    assert_eq!(divisor_for(FREQUENCY), None);
+}
+
+#[test_case]
+fn test_read_back_timer() {
+    let (status, count) = read_back(Channel::Timer).unwrap();
+    assert_eq!(status.mode(), Some(Mode::RateGenerator));
+    assert!(status.low_high_access());
+    assert!(count <= TIMER_DIVISOR);
+}
+
+#[test_case]
+fn test_set_divisor() {
+    use rost_core::mock::{Access, MockPorts};
+
+    let mut pit = Pit::with_ports(MockPorts::new());
+    pit.set_divisor(Channel::Speaker, Mode::SquareWave, 2712);
+    assert!(pit.ports.log().eq([
+        Access::Write {
+            port: 0x43,
+            value: 0b1011_0110
+        },
+        Access::Write {
+            port: 0x42,
+            value: 0x98
+        },
+        Access::Write {
+            port: 0x42,
+            value: 0x0A
+        },
+    ]));
+
+    pit.ports.clear_log();
+    pit.ports.queue_read(0x40, 0b0011_0100);
+    pit.ports.queue_read(0x40, 0x34);
+    pit.ports.queue_read(0x40, 0x02);
+    let (status, count) = pit.read_back(Channel::Timer);
+    assert_eq!(status.mode(), Some(Mode::RateGenerator));
+    assert_eq!(count, 0x234);
+    assert_eq!(
+        pit.ports.log().next(),
+        Some(Access::Write {
+            port: 0x43,
+            value: 0b1100_0010
+        })
+    );
+}
@@ -0,0 +1,48 @@
+//! Rebooting and turning the machine off
+
+use rost_core::register::{PortRegister, WriteOnly};
+use x86_64::instructions::interrupts;
+
+use crate::resource;
+
+//writing the value to the register turns the machine off: qemu, older qemu and bochs,
+//virtualbox
+const SHUTDOWN: [(PortRegister<u16, WriteOnly>, u16); 3] = [
+    (PortRegister::new(0x604), 0x2000),
+    (PortRegister::new(0xB004), 0x2000),
+    (PortRegister::new(0x4004), 0x3400),
+];
+
+///Restarts the machine through the keyboard controller reset line, or with a triple
+///fault if the controller did nothing
+pub fn reboot() -> ! {
+    crate::driver::shutdown();
+    interrupts::disable();
+    crate::ps2::pulse_reset_line();
+
+    //with an empty interrupt table the breakpoint faults until the processor resets
+    let empty = x86_64::structures::DescriptorTablePointer {
+        limit: 0,
+        base: x86_64::VirtAddr::zero(),
+    };
+    unsafe { x86_64::instructions::tables::lidt(&empty) };
+    interrupts::int3();
+    crate::hlt_loop();
+}
+
+///Turns the machine off. Only virtual machines are supported, real hardware needs acpi
+///which rost doesn't have yet, so there the machine is just halted.
+pub fn shutdown() -> ! {
+    crate::driver::shutdown();
+    interrupts::disable();
+
+    //a port some driver has is something else on this machine
+    for (register, value) in SHUTDOWN {
+        if let Ok(mut ports) = resource::claim_ports("power", register.port(), register.width()) {
+            register.write(&mut ports, value);
+        }
+    }
+
+    crate::println!("It is now safe to turn off your computer");
+    crate::hlt_loop();
+}
@@ -0,0 +1,748 @@
+//! The 8042 PS/2 controller and the devices behind its two ports
+//!
+//! `init` takes the controller over from the firmware: it tests the controller and its
+//! ports, resets the devices and switches the keyboard to scancode set 2 with the
+//! controller translation turned off. Every wait on the controller gives up after
+//! `TIMEOUT` polls so a missing or stuck controller can not hang the boot.
+
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use rost_core::bitfield;
+use rost_core::port::PortIo;
+use rost_core::register::{PortRegister, ReadOnly, WriteOnly};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::resource::{PortRange, PortSet, Resource};
+
+///The controller ports and the line of the keyboard, the mouse claims its own
+pub const RESOURCES: [Resource; 3] = [
+    Resource::ports(0x60, 1),
+    Resource::ports(0x64, 1),
+    Resource::Irq(1),
+];
+
+const DATA: PortRegister<u8> = PortRegister::new(0x60);
+const STATUS: PortRegister<Status, ReadOnly> = PortRegister::new(0x64);
+const COMMAND: PortRegister<u8, WriteOnly> = PortRegister::new(0x64);
+
+///How many times the status register is polled before a wait gives up
+const TIMEOUT: usize = 100_000;
+///A device self test can take most of a second on real hardware
+const RESET_TIMEOUT: usize = 10 * TIMEOUT;
+///How many times a command the device asked for again is resent
+const RETRIES: usize = 3;
+
+bitfield! {
+    pub struct Status(u8) {
+        ///There is a byte to read in the data port
+        pub const OUTPUT_FULL: bool = 0;
+        ///The controller hasn't taken the last byte written yet
+        pub const INPUT_FULL: bool = 1;
+        ///The byte to read came from the second port
+        pub const FROM_PORT2: bool = 5;
+        pub const TIMEOUT_ERROR: bool = 6;
+        pub const PARITY_ERROR: bool = 7;
+    }
+}
+
+bitfield! {
+    ///The configuration byte of the controller
+    pub struct Config(u8) {
+        pub const PORT1_INTERRUPT: bool = 0;
+        pub const PORT2_INTERRUPT: bool = 1;
+        ///Set once the firmware passed its self test
+        pub const SYSTEM: bool = 2;
+        pub const PORT1_CLOCK_DISABLED: bool = 4;
+        pub const PORT2_CLOCK_DISABLED: bool = 5;
+        ///The controller turns what the keyboard sends into scancode set 1
+        pub const TRANSLATION: bool = 6;
+    }
+}
+
+//controller commands
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_PORT2: u8 = 0xA7;
+const ENABLE_PORT2: u8 = 0xA8;
+const TEST_PORT2: u8 = 0xA9;
+const SELF_TEST: u8 = 0xAA;
+const TEST_PORT1: u8 = 0xAB;
+const DISABLE_PORT1: u8 = 0xAD;
+const ENABLE_PORT1: u8 = 0xAE;
+const WRITE_PORT2: u8 = 0xD4;
+const PULSE_RESET: u8 = 0xFE;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+//device commands
+const SET_LEDS: u8 = 0xED;
+const SCANCODE_SET: u8 = 0xF0;
+const IDENTIFY: u8 = 0xF2;
+const SET_TYPEMATIC: u8 = 0xF3;
+//the same command as the typematic one, mice take it as their sample rate
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const ENABLE_SCANNING: u8 = 0xF4;
+const DISABLE_SCANNING: u8 = 0xF5;
+const SET_DEFAULTS: u8 = 0xF6;
+const RESET: u8 = 0xFF;
+
+///What a device answers to a command it took
+pub const ACK: u8 = 0xFA;
+///What a device answers to a command it wants sent again
+pub const RESEND: u8 = 0xFE;
+const RESET_PASSED: u8 = 0xAA;
+
+//the data and the status ports, set by `init`
+static CONTROLLER: Mutex<Option<Controller<PortSet<2>>>> = Mutex::new(None);
+static LEDS: Mutex<LedSender> = Mutex::new(LedSender::new());
+static INITIALIZED: AtomicBool = AtomicBool::new(false);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Ps2Error {
+    Timeout,
+    SelfTestFailed(u8),
+    PortTestFailed(Channel, u8),
+    NoDevice(Channel),
+    ResetFailed(Channel, u8),
+    //the device kept asking for the command again
+    Resend(u8),
+    UnexpectedResponse(u8),
+    UnsupportedScancodeSet(u8),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Channel {
+    First,
+    Second,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[repr(u8)]
+pub enum ScancodeSet {
+    Set1 = 1,
+    Set2 = 2,
+    Set3 = 3,
+}
+
+///What a device says it is when asked to identify itself
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceType {
+    AtKeyboard,
+    Mf2Keyboard,
+    Mouse,
+    ScrollMouse,
+    FiveButtonMouse,
+    Unknown(u8),
+}
+
+impl DeviceType {
+    fn from_id(id: &[u8]) -> DeviceType {
+        match id {
+            [] => DeviceType::AtKeyboard,
+            [0xAB, ..] => DeviceType::Mf2Keyboard,
+            [0x00, ..] => DeviceType::Mouse,
+            [0x03, ..] => DeviceType::ScrollMouse,
+            [0x04, ..] => DeviceType::FiveButtonMouse,
+            [other, ..] => DeviceType::Unknown(*other),
+        }
+    }
+
+    pub fn is_keyboard(&self) -> bool {
+        matches!(self, DeviceType::AtKeyboard | DeviceType::Mf2Keyboard)
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Leds {
+    pub scroll_lock: bool,
+    pub num_lock: bool,
+    pub caps_lock: bool,
+}
+
+impl Leds {
+    pub fn bits(&self) -> u8 {
+        self.scroll_lock as u8 | (self.num_lock as u8) << 1 | (self.caps_lock as u8) << 2
+    }
+}
+
+///How long a key has to be held before it repeats and how fast it repeats after that
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Typematic {
+    byte: u8,
+}
+
+impl Typematic {
+    ///What a keyboard uses after a reset, 10.9 repeats a second after half a second
+    pub const DEFAULT: Typematic = Typematic { byte: 0x2B };
+
+    ///Picks the closest setting the keyboard has, the delay is between 250 and 1000 ms
+    ///in steps of 250 and the rate between 2 and 30 repeats a second
+    pub fn new(delay_ms: u32, repeats_per_second: u32) -> Typematic {
+        let delay = ((delay_ms + 125) / 250).clamp(1, 4) as u8 - 1;
+        let repeats_per_second = repeats_per_second.clamp(2, 30) as u64;
+        let rate = (0..32u8)
+            .min_by_key(|rate| {
+                let interval_us = Typematic { byte: *rate }.interval_us();
+                (interval_us * repeats_per_second).abs_diff(1_000_000)
+            })
+            .unwrap_or(0);
+        Typematic {
+            byte: delay << 5 | rate,
+        }
+    }
+
+    pub fn delay_ms(&self) -> u32 {
+        (((self.byte >> 5) & 0b11) as u32 + 1) * 250
+    }
+
+    ///Time between repeats in microseconds, the rate field counts in 4.17 ms steps
+    pub fn interval_us(&self) -> u64 {
+        let mantissa = (self.byte & 0b111) as u64 + 8;
+        let exponent = (self.byte >> 3) & 0b11;
+        (mantissa << exponent) * 4170
+    }
+
+    pub fn byte(&self) -> u8 {
+        self.byte
+    }
+}
+
+impl Default for Typematic {
+    fn default() -> Self {
+        Typematic::DEFAULT
+    }
+}
+
+pub struct Controller<P> {
+    ports: P,
+    devices: [Option<DeviceType>; 2],
+}
+
+impl<P: PortIo> Controller<P> {
+    pub const fn with_ports(ports: P) -> Controller<P> {
+        Controller {
+            ports,
+            devices: [None; 2],
+        }
+    }
+
+    pub fn status(&mut self) -> Status {
+        STATUS.read(&mut self.ports)
+    }
+
+    ///The device that was found on a port, `None` before `initialize`
+    pub fn device(&self, channel: Channel) -> Option<DeviceType> {
+        self.devices[channel as usize]
+    }
+
+    fn wait_for(&mut self, ready: impl Fn(Status) -> bool, timeout: usize) -> Result<(), Ps2Error> {
+        for _ in 0..timeout {
+            if ready(self.status()) {
+                return Ok(());
+            }
+            core::hint::spin_loop();
+        }
+        Err(Ps2Error::Timeout)
+    }
+
+    fn read_data_within(&mut self, timeout: usize) -> Result<u8, Ps2Error> {
+        self.wait_for(|status| status.get(Status::OUTPUT_FULL), timeout)?;
+        Ok(DATA.read(&mut self.ports))
+    }
+
+    pub fn read_data(&mut self) -> Result<u8, Ps2Error> {
+        self.read_data_within(TIMEOUT)
+    }
+
+    pub fn write_data(&mut self, byte: u8) -> Result<(), Ps2Error> {
+        self.wait_for(|status| !status.get(Status::INPUT_FULL), TIMEOUT)?;
+        DATA.write(&mut self.ports, byte);
+        Ok(())
+    }
+
+    pub fn write_command(&mut self, command: u8) -> Result<(), Ps2Error> {
+        self.wait_for(|status| !status.get(Status::INPUT_FULL), TIMEOUT)?;
+        COMMAND.write(&mut self.ports, command);
+        Ok(())
+    }
+
+    ///Throws away whatever the devices sent that nobody read
+    pub fn flush(&mut self) {
+        for _ in 0..16 {
+            if !self.status().get(Status::OUTPUT_FULL) {
+                return;
+            }
+            DATA.read(&mut self.ports);
+        }
+    }
+
+    pub fn config(&mut self) -> Result<Config, Ps2Error> {
+        self.write_command(READ_CONFIG)?;
+        self.read_data().map(Config)
+    }
+
+    pub fn set_config(&mut self, config: Config) -> Result<(), Ps2Error> {
+        self.write_command(WRITE_CONFIG)?;
+        self.write_data(config.0)
+    }
+
+    fn update_config(&mut self, update: impl FnOnce(Config) -> Config) -> Result<Config, Ps2Error> {
+        let config = update(self.config()?);
+        self.set_config(config)?;
+        Ok(config)
+    }
+
+    ///Writes a byte to the device on a port without waiting for its answer
+    pub fn write_device(&mut self, channel: Channel, byte: u8) -> Result<(), Ps2Error> {
+        if channel == Channel::Second {
+            self.write_command(WRITE_PORT2)?;
+        }
+        self.write_data(byte)
+    }
+
+    ///Sends a byte to a device until it is acknowledged
+    pub fn send(&mut self, channel: Channel, byte: u8) -> Result<(), Ps2Error> {
+        for _ in 0..RETRIES {
+            self.write_device(channel, byte)?;
+            match self.read_data()? {
+                ACK => return Ok(()),
+                RESEND => continue,
+                other => return Err(Ps2Error::UnexpectedResponse(other)),
+            }
+        }
+        Err(Ps2Error::Resend(byte))
+    }
+
+    fn send_with_argument(
+        &mut self,
+        channel: Channel,
+        command: u8,
+        argument: u8,
+    ) -> Result<(), Ps2Error> {
+        self.send(channel, command)?;
+        self.send(channel, argument)
+    }
+
+    ///Resets a device and waits for it to pass its self test
+    pub fn reset_device(&mut self, channel: Channel) -> Result<(), Ps2Error> {
+        self.send(channel, RESET)
+            .map_err(|_| Ps2Error::NoDevice(channel))?;
+        match self.read_data_within(RESET_TIMEOUT)? {
+            RESET_PASSED => (),
+            other => return Err(Ps2Error::ResetFailed(channel, other)),
+        }
+        //mice follow up with their id
+        let _ = self.read_data();
+        Ok(())
+    }
+
+    ///Asks a device what it is, scanning has to be disabled for the answer to make sense
+    pub fn identify(&mut self, channel: Channel) -> Result<DeviceType, Ps2Error> {
+        self.send(channel, IDENTIFY)?;
+        let mut id = [0; 2];
+        let mut len = 0;
+        while len < id.len() {
+            match self.read_data() {
+                Ok(byte) => id[len] = byte,
+                Err(_) => break,
+            }
+            len += 1;
+        }
+        Ok(DeviceType::from_id(&id[..len]))
+    }
+
+    pub fn enable_scanning(&mut self, channel: Channel) -> Result<(), Ps2Error> {
+        self.send(channel, ENABLE_SCANNING)
+    }
+
+    pub fn disable_scanning(&mut self, channel: Channel) -> Result<(), Ps2Error> {
+        self.send(channel, DISABLE_SCANNING)
+    }
+
+    pub fn set_scancode_set(&mut self, set: ScancodeSet) -> Result<(), Ps2Error> {
+        self.send_with_argument(Channel::First, SCANCODE_SET, set as u8)
+    }
+
+    ///The scancode set the keyboard sends, only right with the translation turned off
+    pub fn scancode_set(&mut self) -> Result<ScancodeSet, Ps2Error> {
+        self.send_with_argument(Channel::First, SCANCODE_SET, 0)?;
+        match self.read_data()? {
+            1 => Ok(ScancodeSet::Set1),
+            2 => Ok(ScancodeSet::Set2),
+            3 => Ok(ScancodeSet::Set3),
+            other => Err(Ps2Error::UnsupportedScancodeSet(other)),
+        }
+    }
+
+    pub fn set_typematic(&mut self, typematic: Typematic) -> Result<(), Ps2Error> {
+        self.send_with_argument(Channel::First, SET_TYPEMATIC, typematic.byte())
+    }
+
+    ///Sets the keyboard leds and waits for the keyboard to take them, use `update_leds`
+    ///once the keyboard interrupt is running
+    pub fn set_leds(&mut self, leds: Leds) -> Result<(), Ps2Error> {
+        self.send_with_argument(Channel::First, SET_LEDS, leds.bits())
+    }
+
+    fn test_port(&mut self, channel: Channel) -> Result<(), Ps2Error> {
+        let command = match channel {
+            Channel::First => TEST_PORT1,
+            Channel::Second => TEST_PORT2,
+        };
+        self.write_command(command)?;
+        match self.read_data()? {
+            PORT_TEST_PASSED => Ok(()),
+            other => Err(Ps2Error::PortTestFailed(channel, other)),
+        }
+    }
+
+    //the second port is there if its clock follows the enable command
+    fn has_second_port(&mut self) -> Result<bool, Ps2Error> {
+        self.write_command(ENABLE_PORT2)?;
+        let present = !self.config()?.get(Config::PORT2_CLOCK_DISABLED);
+        self.write_command(DISABLE_PORT2)?;
+        Ok(present)
+    }
+
+    //resets whatever is on a tested port and asks it what it is
+    fn probe(&mut self, channel: Channel) -> Result<DeviceType, Ps2Error> {
+        self.reset_device(channel)?;
+        self.disable_scanning(channel)?;
+        self.identify(channel)
+    }
+
+    ///Takes the controller over from the firmware, the keyboard is left scanning in
+    ///scancode set 2 with its interrupt on. A device on the second port is reset and
+    ///identified but left disabled.
+    pub fn initialize(&mut self, typematic: Typematic, leds: Leds) -> Result<(), Ps2Error> {
+        self.devices = [None; 2];
+        self.write_command(DISABLE_PORT1)?;
+        self.write_command(DISABLE_PORT2)?;
+        self.flush();
+
+        let config = self.update_config(|config| {
+            config
+                .with(Config::PORT1_INTERRUPT, false)
+                .with(Config::PORT2_INTERRUPT, false)
+                .with(Config::TRANSLATION, false)
+        })?;
+
+        self.write_command(SELF_TEST)?;
+        match self.read_data()? {
+            SELF_TEST_PASSED => (),
+            other => return Err(Ps2Error::SelfTestFailed(other)),
+        }
+        //some controllers reset themselves in the self test
+        self.set_config(config)?;
+
+        let dual_channel = self.has_second_port()?;
+        self.test_port(Channel::First)?;
+
+        self.write_command(ENABLE_PORT1)?;
+        let keyboard = self.probe(Channel::First)?;
+        if !keyboard.is_keyboard() {
+            return Err(Ps2Error::NoDevice(Channel::First));
+        }
+        self.devices[Channel::First as usize] = Some(keyboard);
+
+        self.set_scancode_set(ScancodeSet::Set2)?;
+        match self.scancode_set()? {
+            ScancodeSet::Set2 => (),
+            other => return Err(Ps2Error::UnsupportedScancodeSet(other as u8)),
+        }
+        self.set_typematic(typematic)?;
+        self.set_leds(leds)?;
+
+        if dual_channel && self.test_port(Channel::Second).is_ok() {
+            self.write_command(ENABLE_PORT2)?;
+            self.devices[Channel::Second as usize] = self.probe(Channel::Second).ok();
+            self.write_command(DISABLE_PORT2)?;
+        }
+
+        self.enable_scanning(Channel::First)?;
+        self.flush();
+        self.update_config(|config| config.with(Config::PORT1_INTERRUPT, true))?;
+        Ok(())
+    }
+
+    pub fn set_sample_rate(&mut self, channel: Channel, rate: u8) -> Result<(), Ps2Error> {
+        self.send_with_argument(channel, SET_SAMPLE_RATE, rate)
+    }
+
+    ///Sets up the mouse found by `initialize` and turns on its interrupt. The sample rates
+    ///200, 100, 80 are the knock that turns on the scroll wheel of an IntelliMouse, the
+    ///mouse says it is a `ScrollMouse` afterwards if it has one.
+    pub fn initialize_mouse(&mut self) -> Result<DeviceType, Ps2Error> {
+        match self.device(Channel::Second) {
+            Some(device) if !device.is_keyboard() => (),
+            _ => return Err(Ps2Error::NoDevice(Channel::Second)),
+        }
+
+        self.write_command(ENABLE_PORT2)?;
+        self.send(Channel::Second, SET_DEFAULTS)?;
+        for rate in [200, 100, 80] {
+            self.set_sample_rate(Channel::Second, rate)?;
+        }
+        let mouse = self.identify(Channel::Second)?;
+        self.devices[Channel::Second as usize] = Some(mouse);
+
+        self.enable_scanning(Channel::Second)?;
+        self.flush();
+        self.update_config(|config| config.with(Config::PORT2_INTERRUPT, true))?;
+        Ok(mouse)
+    }
+
+    ///Gives the keyboard back the way the firmware left it, with translation to set 1
+    fn restore_firmware_setup(&mut self) {
+        let _ = self.write_command(ENABLE_PORT1);
+        let _ = self.update_config(|config| {
+            config
+                .with(Config::PORT1_INTERRUPT, true)
+                .with(Config::TRANSLATION, true)
+                .with(Config::PORT1_CLOCK_DISABLED, false)
+        });
+        let _ = self.enable_scanning(Channel::First);
+        self.flush();
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LedState {
+    Idle,
+    //waiting for the keyboard to take the set leds command
+    Command,
+    //waiting for the keyboard to take the led bits
+    Bits,
+}
+
+///What to do with a byte the keyboard sent while an led update is going on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum LedReply {
+    //not an answer to us, it is a scancode
+    Scancode,
+    Consumed,
+    Send(u8),
+}
+
+///Sends the led updates from the keyboard interrupt without waiting for the answers,
+///they arrive through the interrupt like the scancodes do
+#[derive(Debug)]
+struct LedSender {
+    state: LedState,
+    sent: Leds,
+    wanted: Leds,
+    retries: usize,
+}
+
+impl LedSender {
+    const fn new() -> LedSender {
+        let leds = Leds {
+            scroll_lock: false,
+            num_lock: false,
+            caps_lock: false,
+        };
+        LedSender {
+            state: LedState::Idle,
+            sent: leds,
+            wanted: leds,
+            retries: 0,
+        }
+    }
+
+    //returns the byte to start the update with if one has to be sent now
+    fn update(&mut self, leds: Leds) -> Option<u8> {
+        self.wanted = leds;
+        if self.state == LedState::Idle && self.wanted != self.sent {
+            self.state = LedState::Command;
+            self.retries = 0;
+            return Some(SET_LEDS);
+        }
+        None
+    }
+
+    fn reply(&mut self, byte: u8) -> LedReply {
+        match (self.state, byte) {
+            (LedState::Idle, _) => LedReply::Scancode,
+            (LedState::Command, ACK) => {
+                self.state = LedState::Bits;
+                self.sent = self.wanted;
+                LedReply::Send(self.sent.bits())
+            }
+            (LedState::Bits, ACK) => {
+                self.state = LedState::Idle;
+                match self.update(self.wanted) {
+                    Some(byte) => LedReply::Send(byte),
+                    None => LedReply::Consumed,
+                }
+            }
+            (state, RESEND) if self.retries < RETRIES => {
+                self.retries += 1;
+                match state {
+                    LedState::Bits => LedReply::Send(self.sent.bits()),
+                    _ => LedReply::Send(SET_LEDS),
+                }
+            }
+            (_, RESEND) => {
+                self.state = LedState::Idle;
+                LedReply::Consumed
+            }
+            _ => LedReply::Scancode,
+        }
+    }
+}
+
+///Whether anything answers at the status port, without a controller it reads all ones
+pub fn is_present(status: &mut PortRange) -> bool {
+    STATUS.read(status).0 != 0xFF
+}
+
+///Takes the controller over and sets up the keyboard, the firmware setup is put back if
+///anything fails. Call it with interrupts disabled.
+pub fn init(ports: PortSet<2>, typematic: Typematic, leds: Leds) -> Result<(), Ps2Error> {
+    let mut controller = CONTROLLER.lock();
+    let controller = controller.insert(Controller::with_ports(ports));
+
+    match controller.initialize(typematic, leds) {
+        Ok(()) => {
+            let mut sender = LEDS.lock();
+            sender.sent = leds;
+            sender.wanted = leds;
+            INITIALIZED.store(true, Ordering::Relaxed);
+            Ok(())
+        }
+        Err(error) => {
+            controller.restore_firmware_setup();
+            Err(error)
+        }
+    }
+}
+
+///Pulls the reset line of the processor, which restarts the machine
+pub fn pulse_reset_line() {
+    //nothing else runs anymore when this is used, whoever held the lock won't let go
+    unsafe { CONTROLLER.force_unlock() };
+    if let Some(controller) = CONTROLLER.lock().as_mut() {
+        let _ = controller.write_command(PULSE_RESET);
+    }
+}
+
+///The byte that raised a keyboard or mouse interrupt, the interrupt says it is there.
+///`None` before `init`.
+pub fn interrupt_byte() -> Option<u8> {
+    //everyone else holds the controller with interrupts disabled
+    CONTROLLER
+        .lock()
+        .as_mut()
+        .map(|controller| DATA.read(&mut controller.ports))
+}
+
+///Whether the keyboard is in scancode set 2 instead of the translated set 1
+pub fn is_initialized() -> bool {
+    INITIALIZED.load(Ordering::Relaxed)
+}
+
+///Turns on the mouse on the second port, call it with interrupts disabled after `init`
+pub fn init_mouse() -> Result<DeviceType, Ps2Error> {
+    if !is_initialized() {
+        return Err(Ps2Error::NoDevice(Channel::Second));
+    }
+    match CONTROLLER.lock().as_mut() {
+        Some(controller) => controller.initialize_mouse(),
+        None => Err(Ps2Error::NoDevice(Channel::Second)),
+    }
+}
+
+pub fn device(channel: Channel) -> Option<DeviceType> {
+    without_interrupts(|| CONTROLLER.lock().as_ref()?.device(channel))
+}
+
+///Starts changing the keyboard leds without waiting for the keyboard, safe to call from
+///the keyboard interrupt
+pub fn update_leds(leds: Leds) {
+    if !is_initialized() {
+        return;
+    }
+    without_interrupts(|| {
+        if let Some(byte) = LEDS.lock().update(leds) {
+            if let Some(controller) = CONTROLLER.lock().as_mut() {
+                let _ = controller.write_data(byte);
+            }
+        }
+    });
+}
+
+///Called with every byte from the keyboard before it is decoded, returns true if the byte
+///was an answer to an led update
+pub fn handle_keyboard_response(byte: u8) -> bool {
+    if !is_initialized() {
+        return false;
+    }
+    without_interrupts(|| match LEDS.lock().reply(byte) {
+        LedReply::Scancode => false,
+        LedReply::Consumed => true,
+        LedReply::Send(byte) => {
+            if let Some(controller) = CONTROLLER.lock().as_mut() {
+                let _ = controller.write_data(byte);
+            }
+            true
+        }
+    })
+}
+
+#[test_case]
+fn test_typematic() {
+    assert_eq!(Typematic::new(500, 11), Typematic::DEFAULT);
+    assert_eq!(Typematic::new(250, 30).byte(), 0x00);
+    assert_eq!(Typematic::new(5000, 1).byte(), 0x7F);
+
+    let typematic = Typematic::new(750, 20);
+    assert_eq!(typematic.delay_ms(), 750);
+    assert_eq!(typematic.interval_us(), 50_040);
+}
+
+#[test_case]
+fn test_led_updates() {
+    let mut sender = LedSender::new();
+    let caps = Leds {
+        caps_lock: true,
+        ..Leds::default()
+    };
+    let caps_and_num = Leds {
+        num_lock: true,
+        ..caps
+    };
+
+    assert_eq!(sender.update(caps), Some(SET_LEDS));
+    //a second change while the first is on its way is sent after it
+    assert_eq!(sender.update(caps_and_num), None);
+    assert_eq!(sender.reply(0x1E), LedReply::Scancode);
+    assert_eq!(sender.reply(ACK), LedReply::Send(0b110));
+    assert_eq!(sender.reply(RESEND), LedReply::Send(0b110));
+    assert_eq!(sender.reply(ACK), LedReply::Consumed);
+    assert_eq!(sender.update(caps_and_num), None);
+    assert_eq!(sender.reply(ACK), LedReply::Scancode);
+}
+
+#[test_case]
+fn test_resend() {
+    use rost_core::mock::MockPorts;
+
+    let mut controller = Controller::with_ports(MockPorts::new());
+    //there is always a byte to read and room to write one
+    controller.ports.set(0x64, 1);
+    //the keyboard asks for the command again once and then takes it
+    controller.ports.queue_read(0x60, RESEND.into());
+    controller.ports.queue_read(0x60, ACK.into());
+    assert_eq!(controller.enable_scanning(Channel::First), Ok(()));
+    let sent = controller.ports.log().filter(|access| {
+        *access
+            == rost_core::mock::Access::Write {
+                port: 0x60,
+                value: ENABLE_SCANNING.into(),
+            }
+    });
+    assert_eq!(sent.count(), 2);
+    assert_eq!(controller.ports.queued_reads(), 0);
+}
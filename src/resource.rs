@@ -0,0 +1,495 @@
+//! The table of which driver owns which ports, IRQ lines and memory
+//!
+//! `claim_ports`, `claim_irq` and `claim_memory` give back a guard that holds the claim
+//! until it is dropped, `keep` holds it for good. The drivers that are there from boot to
+//! shutdown list what they use in a `RESOURCES` constant that `driver::init` reserves, so
+//! two drivers wanting the same port show up in the log right at boot. The reservation is
+//! only kept when the device is set up, a device that isn't there gives everything back.
+//!
+//! The ports are only reached through the guards, a driver can't touch a port it didn't
+//! claim.
+
+use core::mem;
+
+use rost_core::port::PortIo;
+use rost_core::resource::{ClaimId, ResourceTable};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+use x86_64::instructions::port::Port;
+
+use crate::mmio::MmioRegion;
+use crate::println;
+
+pub use rost_core::resource::{Claim, Resource, ResourceError};
+
+pub const MAX_CLAIMS: usize = 64;
+///How many resources one reservation can hold
+pub const MAX_RESERVED: usize = 8;
+
+static TABLE: Mutex<ResourceTable<MAX_CLAIMS>> = Mutex::new(ResourceTable::new());
+
+fn claim(owner: &'static str, resource: Resource) -> Result<ClaimId, ResourceError> {
+    without_interrupts(|| TABLE.lock().claim(owner, resource))
+}
+
+fn release(id: ClaimId) {
+    without_interrupts(|| TABLE.lock().release(id));
+}
+
+//runs `in` and `out` on whatever port it is given, the guards only hand it the ports
+//they hold
+struct IoPorts;
+
+impl PortIo for IoPorts {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        unsafe { Port::new(port).read() }
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        unsafe { Port::new(port).write(value) }
+    }
+
+    fn read_u16(&mut self, port: u16) -> u16 {
+        unsafe { Port::new(port).read() }
+    }
+
+    fn write_u16(&mut self, port: u16, value: u16) {
+        unsafe { Port::new(port).write(value) }
+    }
+
+    fn read_u32(&mut self, port: u16) -> u32 {
+        unsafe { Port::new(port).read() }
+    }
+
+    fn write_u32(&mut self, port: u16, value: u32) {
+        unsafe { Port::new(port).write(value) }
+    }
+}
+
+///Ports that belong to whoever holds this, they are given back when it is dropped
+#[derive(Debug)]
+pub struct PortRange {
+    id: Option<ClaimId>,
+    start: u16,
+    count: u16,
+}
+
+impl PortRange {
+    pub fn start(&self) -> u16 {
+        self.start
+    }
+
+    pub fn count(&self) -> u16 {
+        self.count
+    }
+
+    pub fn contains(&self, port: u16, width: u16) -> bool {
+        Resource::ports(self.start, self.count).contains(&Resource::ports(port, width))
+    }
+
+    ///Never gives the ports back
+    pub fn keep(self) {
+        mem::forget(self)
+    }
+
+    //the ports past the range are someone else's
+    fn check(&self, port: u16, width: u16) {
+        assert!(
+            self.contains(port, width),
+            "port {:#x} is outside of the claimed {}",
+            port,
+            Resource::ports(self.start, self.count)
+        );
+    }
+}
+
+impl PortIo for PortRange {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        self.check(port, 1);
+        IoPorts.read_u8(port)
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        self.check(port, 1);
+        IoPorts.write_u8(port, value)
+    }
+
+    fn read_u16(&mut self, port: u16) -> u16 {
+        self.check(port, 2);
+        IoPorts.read_u16(port)
+    }
+
+    fn write_u16(&mut self, port: u16, value: u16) {
+        self.check(port, 2);
+        IoPorts.write_u16(port, value)
+    }
+
+    fn read_u32(&mut self, port: u16) -> u32 {
+        self.check(port, 4);
+        IoPorts.read_u32(port)
+    }
+
+    fn write_u32(&mut self, port: u16, value: u32) {
+        self.check(port, 4);
+        IoPorts.write_u32(port, value)
+    }
+}
+
+impl Drop for PortRange {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            release(id)
+        }
+    }
+}
+
+///Port ranges used as one, for a device whose ports are not next to each other
+#[derive(Debug)]
+pub struct PortSet<const N: usize>([PortRange; N]);
+
+impl<const N: usize> PortSet<N> {
+    pub fn new(ranges: [PortRange; N]) -> PortSet<N> {
+        PortSet(ranges)
+    }
+
+    //the range `port` is in, the ports in none of them are someone else's
+    fn range(&mut self, port: u16, width: u16) -> &mut PortRange {
+        self.0
+            .iter_mut()
+            .find(|range| range.contains(port, width))
+            .unwrap_or_else(|| panic!("port {:#x} is outside of the claimed ports", port))
+    }
+}
+
+impl<const N: usize> PortIo for PortSet<N> {
+    fn read_u8(&mut self, port: u16) -> u8 {
+        self.range(port, 1).read_u8(port)
+    }
+
+    fn write_u8(&mut self, port: u16, value: u8) {
+        self.range(port, 1).write_u8(port, value)
+    }
+
+    fn read_u16(&mut self, port: u16) -> u16 {
+        self.range(port, 2).read_u16(port)
+    }
+
+    fn write_u16(&mut self, port: u16, value: u16) {
+        self.range(port, 2).write_u16(port, value)
+    }
+
+    fn read_u32(&mut self, port: u16) -> u32 {
+        self.range(port, 4).read_u32(port)
+    }
+
+    fn write_u32(&mut self, port: u16, value: u32) {
+        self.range(port, 4).write_u32(port, value)
+    }
+}
+
+///An irq line that belongs to whoever holds this
+#[derive(Debug)]
+pub struct IrqLine {
+    id: Option<ClaimId>,
+    line: u8,
+}
+
+impl IrqLine {
+    pub fn line(&self) -> u8 {
+        self.line
+    }
+
+    ///Lets the line through the PICs
+    pub fn unmask(&self) {
+        crate::interrupts::unmask_irq(self.line)
+    }
+
+    ///Never gives the line back
+    pub fn keep(self) {
+        mem::forget(self)
+    }
+}
+
+impl Drop for IrqLine {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            release(id)
+        }
+    }
+}
+
+///Physical memory of a device that belongs to whoever holds this
+#[derive(Debug)]
+pub struct MemoryRange {
+    id: Option<ClaimId>,
+    start: u64,
+    size: u64,
+}
+
+impl MemoryRange {
+    pub fn start(&self) -> u64 {
+        self.start
+    }
+
+    pub fn size(&self) -> u64 {
+        self.size
+    }
+
+    ///The memory as a window to read and write the device through
+    ///
+    ///# Safety
+    ///
+    ///The memory has to be mapped at its physical address, which the bootloader only
+    ///does for the first megabyte, and the region must not outlive the claim
+    pub unsafe fn region(&self) -> MmioRegion {
+        MmioRegion::new(self.start as usize, self.size as usize)
+    }
+
+    ///Never gives the memory back
+    pub fn keep(self) {
+        mem::forget(self)
+    }
+}
+
+impl Drop for MemoryRange {
+    fn drop(&mut self) {
+        if let Some(id) = self.id.take() {
+            release(id)
+        }
+    }
+}
+
+pub fn claim_ports(
+    owner: &'static str,
+    start: u16,
+    count: u16,
+) -> Result<PortRange, ResourceError> {
+    let id = claim(owner, Resource::ports(start, count))?;
+    Ok(PortRange {
+        id: Some(id),
+        start,
+        count,
+    })
+}
+
+pub fn claim_irq(owner: &'static str, line: u8) -> Result<IrqLine, ResourceError> {
+    let id = claim(owner, Resource::Irq(line))?;
+    Ok(IrqLine { id: Some(id), line })
+}
+
+pub fn claim_memory(
+    owner: &'static str,
+    start: u64,
+    size: u64,
+) -> Result<MemoryRange, ResourceError> {
+    let id = claim(owner, Resource::memory(start, size))?;
+    Ok(MemoryRange {
+        id: Some(id),
+        start,
+        size,
+    })
+}
+
+///The guard of any kind of resource
+#[derive(Debug)]
+pub enum Guard {
+    Ports(PortRange),
+    Irq(IrqLine),
+    Memory(MemoryRange),
+}
+
+impl Guard {
+    pub fn claim(owner: &'static str, resource: Resource) -> Result<Guard, ResourceError> {
+        match resource {
+            Resource::Ports { start, count } => claim_ports(owner, start, count).map(Guard::Ports),
+            Resource::Irq(line) => claim_irq(owner, line).map(Guard::Irq),
+            Resource::Memory { start, size } => claim_memory(owner, start, size).map(Guard::Memory),
+        }
+    }
+
+    pub fn keep(self) {
+        match self {
+            Guard::Ports(ports) => ports.keep(),
+            Guard::Irq(irq) => irq.keep(),
+            Guard::Memory(memory) => memory.keep(),
+        }
+    }
+}
+
+///The guards `reserve` gives back, everything is given back when it is dropped
+#[derive(Debug)]
+pub struct Reservation {
+    guards: [Option<Guard>; MAX_RESERVED],
+}
+
+impl Reservation {
+    pub fn new() -> Reservation {
+        Reservation {
+            guards: core::array::from_fn(|_| None),
+        }
+    }
+
+    ///The ports starting at `start`, for looking at the device while the reservation is
+    ///held
+    pub fn ports(&mut self, start: u16) -> Option<&mut PortRange> {
+        self.guards
+            .iter_mut()
+            .flatten()
+            .find_map(|guard| match guard {
+                Guard::Ports(ports) if ports.start == start => Some(ports),
+                _ => None,
+            })
+    }
+
+    ///Takes the ports starting at `start` out, whoever takes them holds them from then on
+    pub fn take_ports(&mut self, start: u16) -> Option<PortRange> {
+        let slot = self
+            .guards
+            .iter_mut()
+            .find(|slot| matches!(slot, Some(Guard::Ports(ports)) if ports.start == start))?;
+        match slot.take() {
+            Some(Guard::Ports(ports)) => Some(ports),
+            _ => None,
+        }
+    }
+
+    pub fn irq(&self, line: u8) -> Option<&IrqLine> {
+        self.guards.iter().flatten().find_map(|guard| match guard {
+            Guard::Irq(irq) if irq.line == line => Some(irq),
+            _ => None,
+        })
+    }
+
+    ///Never gives anything back
+    pub fn keep(self) {
+        for guard in self.guards.into_iter().flatten() {
+            guard.keep();
+        }
+    }
+}
+
+impl Default for Reservation {
+    fn default() -> Self {
+        Reservation::new()
+    }
+}
+
+///Claims everything in `resources`, each conflict is logged and the first one is returned
+///after giving back what could be claimed
+pub fn reserve(owner: &'static str, resources: &[Resource]) -> Result<Reservation, ResourceError> {
+    if resources.len() > MAX_RESERVED {
+        return Err(ResourceError::Full);
+    }
+
+    let mut reservation = Reservation::new();
+    let mut result = Ok(());
+    for (slot, resource) in reservation.guards.iter_mut().zip(resources) {
+        match Guard::claim(owner, *resource) {
+            Ok(guard) => *slot = Some(guard),
+            Err(error) => {
+                log::error!("{}", error);
+                result = result.and(Err(error));
+            }
+        }
+    }
+    result.map(|()| reservation)
+}
+
+///The claim that has some of `resource`, if there is one
+pub fn owner_of(resource: Resource) -> Option<Claim> {
+    without_interrupts(|| TABLE.lock().owner_of(&resource))
+}
+
+///Prints the claims, the ports first and everything in order
+pub fn list() {
+    let mut claims = [None; MAX_CLAIMS];
+    let count = without_interrupts(|| {
+        let table = TABLE.lock();
+        for (slot, claim) in claims.iter_mut().zip(table.iter()) {
+            *slot = Some(claim);
+        }
+        table.len()
+    });
+
+    let claims = &mut claims[..count];
+    claims.sort_unstable_by_key(|claim| {
+        claim.map(|claim| match claim.resource {
+            Resource::Ports { start, .. } => (0, u64::from(start)),
+            Resource::Irq(line) => (1, u64::from(line)),
+            Resource::Memory { start, .. } => (2, start),
+        })
+    });
+    for claim in claims.iter().flatten() {
+        println!("{:<12}{}", claim.owner, claim.resource);
+    }
+}
+
+#[test_case]
+fn test_guards_give_back_their_claims() {
+    let ports = claim_ports("test", 0x2000, 4).unwrap();
+    let error = claim_ports("other test", 0x2003, 1).unwrap_err();
+    assert!(matches!(
+        error,
+        ResourceError::Conflict { owned, .. } if owned.owner == "test"
+    ));
+    drop(ports);
+
+    let ports = claim_ports("other test", 0x2003, 1).unwrap();
+    assert_eq!(
+        owner_of(Resource::ports(0x2003, 1)).map(|claim| claim.owner),
+        Some("other test")
+    );
+    drop(ports);
+    assert_eq!(owner_of(Resource::ports(0x2000, 4)), None);
+}
+
+#[test_case]
+fn test_failed_reservation_gives_back_its_claims() {
+    let ports = claim_ports("test", 0x2010, 1).unwrap();
+    let wanted = [Resource::ports(0x2000, 4), Resource::ports(0x2010, 1)];
+    assert!(reserve("other test", &wanted).is_err());
+    assert_eq!(owner_of(Resource::ports(0x2000, 4)), None);
+    drop(ports);
+
+    let reservation = reserve("other test", &wanted).unwrap();
+    assert_eq!(
+        owner_of(Resource::ports(0x2010, 1)).map(|claim| claim.owner),
+        Some("other test")
+    );
+    drop(reservation);
+    assert_eq!(owner_of(Resource::ports(0x2010, 1)), None);
+}
+
+#[test_case]
+fn test_reservation_hands_out_its_guards() {
+    let wanted = [Resource::ports(0x2000, 4), Resource::ports(0x2010, 1)];
+    let mut reservation = reserve("test", &wanted).unwrap();
+    assert_eq!(
+        reservation.ports(0x2010).map(|ports| ports.count()),
+        Some(1)
+    );
+
+    let mut ports = PortSet::new([
+        reservation.take_ports(0x2000).unwrap(),
+        reservation.take_ports(0x2010).unwrap(),
+    ]);
+    assert!(reservation.take_ports(0x2000).is_none());
+    drop(reservation);
+    assert!(owner_of(Resource::ports(0x2010, 1)).is_some());
+
+    //nothing is behind them, this only goes to the right range
+    ports.read_u8(0x2010);
+    ports.write_u16(0x2002, 0);
+    drop(ports);
+    assert_eq!(owner_of(Resource::ports(0x2010, 1)), None);
+}
+
+#[test_case]
+fn test_legacy_devices_are_reserved() {
+    let owner = |resource| owner_of(resource).map(|claim| claim.owner);
+    assert_eq!(owner(Resource::ports(0x60, 1)), Some("ps2"));
+    assert_eq!(owner(Resource::ports(0x3D4, 2)), Some("vga"));
+    assert_eq!(owner(Resource::Irq(0)), Some("pit"));
+    assert!(claim_irq("test", 1).is_err());
+    assert!(claim_memory("test", 0xB8000, 0x1000).is_err());
+}
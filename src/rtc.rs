@@ -0,0 +1,177 @@
+//! The real time clock in the CMOS chip, it keeps the date while the machine is off
+
+use core::fmt;
+
+use rost_core::bitfield;
+use rost_core::port::PortIo;
+use rost_core::register::IndexedPorts;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::resource::{PortRange, Resource};
+
+pub const RESOURCES: [Resource; 1] = [Resource::ports(0x70, 2)];
+
+const CMOS: IndexedPorts = IndexedPorts::new(0x70, 0x71);
+
+//the index and the data port, set by `init`
+static PORTS: Mutex<Option<PortRange>> = Mutex::new(None);
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+bitfield! {
+    struct StatusA(u8) {
+        //the clock is changing its registers
+        const UPDATE_IN_PROGRESS: bool = 7;
+    }
+}
+
+bitfield! {
+    struct StatusB(u8) {
+        const HOURS_24: bool = 1;
+        const BINARY: bool = 2;
+    }
+}
+
+//the hours register in 12 hour mode
+const PM: u8 = 1 << 7;
+
+///A date and time as the clock keeps it, usually in UTC
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+}
+
+impl DateTime {
+    ///Turns the raw registers into a date, `status_b` says if they are bcd or binary
+    ///and if the hours are 12 or 24 hour. The clock only keeps two digits of the year,
+    ///they are taken to be in this century.
+    fn from_registers(registers: [u8; 6], status_b: StatusB) -> DateTime {
+        let decode = |value: u8| {
+            if status_b.get(StatusB::BINARY) {
+                value
+            } else {
+                (value >> 4) * 10 + (value & 0x0F)
+            }
+        };
+        let [second, minute, hour, day, month, year] = registers;
+
+        let mut hours = decode(hour & !PM);
+        if !status_b.get(StatusB::HOURS_24) {
+            hours %= 12;
+            if hour & PM != 0 {
+                hours += 12;
+            }
+        }
+
+        DateTime {
+            year: 2000 + decode(year) as u16,
+            month: decode(month),
+            day: decode(day),
+            hour: hours,
+            minute: decode(minute),
+            second: decode(second),
+        }
+    }
+}
+
+impl fmt::Display for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            self.year, self.month, self.day, self.hour, self.minute, self.second
+        )
+    }
+}
+
+fn read_registers(io: &mut impl PortIo) -> [u8; 6] {
+    while StatusA(CMOS.read(io, STATUS_A)).get(StatusA::UPDATE_IN_PROGRESS) {
+        core::hint::spin_loop();
+    }
+    [SECONDS, MINUTES, HOURS, DAY, MONTH, YEAR].map(|register| CMOS.read(io, register))
+}
+
+//the registers are read until two reads agree so an update half way through can't give
+//a mixed up time
+fn read_clock(io: &mut impl PortIo) -> DateTime {
+    let mut registers = read_registers(io);
+    loop {
+        let again = read_registers(io);
+        if again == registers {
+            break;
+        }
+        registers = again;
+    }
+    DateTime::from_registers(registers, StatusB(CMOS.read(io, STATUS_B)))
+}
+
+///Takes the ports of the CMOS chip
+pub fn init(ports: PortRange) {
+    without_interrupts(|| *PORTS.lock() = Some(ports));
+}
+
+///Reads the clock, `None` before `init`
+pub fn now() -> Option<DateTime> {
+    without_interrupts(|| PORTS.lock().as_mut().map(read_clock))
+}
+
+#[test_case]
+fn test_bcd_registers() {
+    let status_b = StatusB::default().with(StatusB::HOURS_24, true);
+    let date = DateTime::from_registers([0x59, 0x07, 0x23, 0x18, 0x10, 0x26], status_b);
+    assert_eq!(
+        date,
+        DateTime {
+            year: 2026,
+            month: 10,
+            day: 18,
+            hour: 23,
+            minute: 7,
+            second: 59,
+        }
+    );
+}
+
+#[test_case]
+fn test_binary_12_hour_registers() {
+    let binary = StatusB::default().with(StatusB::BINARY, true);
+    let date = DateTime::from_registers([0, 30, PM | 12, 1, 2, 24], binary);
+    assert_eq!((date.hour, date.minute), (12, 30));
+    let date = DateTime::from_registers([0, 30, 12, 1, 2, 24], binary);
+    assert_eq!(date.hour, 0);
+}
+
+#[test_case]
+fn test_read_clock() {
+    use rost_core::mock::MockPorts;
+
+    let mut cmos = MockPorts::new().with_indexed(0x70, 0x71);
+    let registers = [
+        (SECONDS, 0x05),
+        (MINUTES, 0x04),
+        (HOURS, 0x03),
+        (DAY, 0x02),
+        (MONTH, 0x01),
+        (YEAR, 0x27),
+        (STATUS_B, 0b10),
+    ];
+    for (register, value) in registers {
+        CMOS.write(&mut cmos, register, value);
+    }
+    let date = read_clock(&mut cmos);
+    assert_eq!((date.year, date.month, date.day), (2027, 1, 2));
+    assert_eq!((date.hour, date.minute, date.second), (3, 4, 5));
+}
@@ -0,0 +1,261 @@
+//! Using COM1 as a terminal
+//!
+//! With the console enabled received bytes are decoded into keys and end up in the same
+//! input buffer as the PS/2 keyboard, and everything printed on the screen is also sent
+//! to the serial port. This makes rost usable over `-serial stdio` without a display.
+
+use core::fmt::{self, Write};
+use core::sync::atomic::{AtomicBool, Ordering};
+
+use pc_keyboard::{DecodedKey, KeyCode};
+use spin::Mutex;
+
+use super::SERIAL1;
+
+const ESCAPE: u8 = 0x1B;
+const DELETE: u8 = 0x7F;
+const BACKSPACE: u8 = 0x08;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static DECODER: Mutex<Decoder> = Mutex::new(Decoder::new());
+
+pub fn set_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_enabled() -> bool {
+    ENABLED.load(Ordering::SeqCst)
+}
+
+///Feeds a received byte to the decoder, finished keys go into the input buffer
+pub fn receive(byte: u8) {
+    DECODER
+        .lock()
+        .advance(byte, |key| crate::io::INPUTBUFFER.write().write_key(key));
+}
+
+///Sends printed text to the terminal when the console is enabled
+pub fn mirror(args: fmt::Arguments) {
+    if is_enabled() {
+        let mut serial = SERIAL1.lock();
+        let _ = CrLf(&mut *serial).write_fmt(args);
+    }
+}
+
+//terminals expect a carriage return before every line feed
+struct CrLf<'a, W: Write>(&'a mut W);
+
+impl<W: Write> Write for CrLf<'_, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for (i, line) in s.split('\n').enumerate() {
+            if i > 0 {
+                self.0.write_str("\r\n")?;
+            }
+            self.0.write_str(line)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    Ground,
+    Escape,
+    //ESC [ followed by an optional number
+    Csi(u8),
+    //ESC O, some terminals send the arrow keys like this
+    Ss3,
+    //a multi byte utf-8 character with this many bytes still missing
+    Utf8(u8),
+}
+
+///Turns the bytes a terminal sends into keys
+#[derive(Debug, Clone, Copy)]
+pub struct Decoder {
+    state: State,
+    last_was_cr: bool,
+    character: u32,
+}
+
+impl Decoder {
+    pub const fn new() -> Decoder {
+        Decoder {
+            state: State::Ground,
+            last_was_cr: false,
+            character: 0,
+        }
+    }
+
+    ///Takes the next byte, `emit` gets every key that has been received whole with it
+    pub fn advance(&mut self, byte: u8, mut emit: impl FnMut(DecodedKey)) {
+        let last_was_cr = core::mem::replace(&mut self.last_was_cr, byte == b'\r');
+
+        //the escape key was pressed on its own, the byte after it is the next key
+        if self.state == State::Escape && byte != b'[' && byte != b'O' {
+            self.state = State::Ground;
+            emit(DecodedKey::RawKey(KeyCode::Escape));
+        }
+        if let Some(key) = self.decode(byte, last_was_cr) {
+            emit(key);
+        }
+    }
+
+    fn decode(&mut self, byte: u8, last_was_cr: bool) -> Option<DecodedKey> {
+        match self.state {
+            State::Ground => self.ground(byte, last_was_cr),
+            State::Escape => {
+                self.state = match byte {
+                    b'[' => State::Csi(0),
+                    _ => State::Ss3,
+                };
+                None
+            }
+            State::Csi(parameter) => match byte {
+                b'0'..=b'9' => {
+                    let digit = byte - b'0';
+                    self.state = State::Csi(parameter.saturating_mul(10).saturating_add(digit));
+                    None
+                }
+                //modifiers like ESC [ 1 ; 5 A are ignored
+                b';' => None,
+                b'~' => {
+                    self.state = State::Ground;
+                    tilde_key(parameter).map(DecodedKey::RawKey)
+                }
+                0x40..=0x7E => {
+                    self.state = State::Ground;
+                    final_key(byte).map(DecodedKey::RawKey)
+                }
+                _ => {
+                    self.state = State::Ground;
+                    None
+                }
+            },
+            State::Ss3 => {
+                self.state = State::Ground;
+                final_key(byte).map(DecodedKey::RawKey)
+            }
+            State::Utf8(missing) => {
+                if byte & 0xC0 != 0x80 {
+                    // a broken character, start over with this byte
+                    self.state = State::Ground;
+                    return self.ground(byte, last_was_cr);
+                }
+
+                self.character = self.character << 6 | (byte & 0x3F) as u32;
+                if missing > 1 {
+                    self.state = State::Utf8(missing - 1);
+                    None
+                } else {
+                    self.state = State::Ground;
+                    char::from_u32(self.character).map(DecodedKey::Unicode)
+                }
+            }
+        }
+    }
+
+    fn ground(&mut self, byte: u8, last_was_cr: bool) -> Option<DecodedKey> {
+        match byte {
+            ESCAPE => {
+                self.state = State::Escape;
+                None
+            }
+            b'\r' => Some(DecodedKey::Unicode('\n')),
+            // enter is CR LF on some terminals, the CR already was the newline
+            b'\n' if last_was_cr => None,
+            DELETE | BACKSPACE => Some(DecodedKey::Unicode('\u{8}')),
+            0x00..=0x7F => Some(DecodedKey::Unicode(byte as char)),
+            0xC0..=0xDF => self.start_utf8(byte & 0x1F, 1),
+            0xE0..=0xEF => self.start_utf8(byte & 0x0F, 2),
+            0xF0..=0xF7 => self.start_utf8(byte & 0x07, 3),
+            _ => None,
+        }
+    }
+
+    fn start_utf8(&mut self, bits: u8, missing: u8) -> Option<DecodedKey> {
+        self.character = bits as u32;
+        self.state = State::Utf8(missing);
+        None
+    }
+}
+
+impl Default for Decoder {
+    fn default() -> Self {
+        Decoder::new()
+    }
+}
+
+fn final_key(byte: u8) -> Option<KeyCode> {
+    match byte {
+        b'A' => Some(KeyCode::ArrowUp),
+        b'B' => Some(KeyCode::ArrowDown),
+        b'C' => Some(KeyCode::ArrowRight),
+        b'D' => Some(KeyCode::ArrowLeft),
+        b'H' => Some(KeyCode::Home),
+        b'F' => Some(KeyCode::End),
+        _ => None,
+    }
+}
+
+fn tilde_key(parameter: u8) -> Option<KeyCode> {
+    match parameter {
+        1 | 7 => Some(KeyCode::Home),
+        2 => Some(KeyCode::Insert),
+        3 => Some(KeyCode::Delete),
+        4 | 8 => Some(KeyCode::End),
+        5 => Some(KeyCode::PageUp),
+        6 => Some(KeyCode::PageDown),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+fn decode_all(bytes: &[u8], keys: &mut [Option<DecodedKey>]) -> usize {
+    let mut decoder = Decoder::new();
+    let mut count = 0;
+    for byte in bytes {
+        decoder.advance(*byte, |key| {
+            keys[count] = Some(key);
+            count += 1;
+        });
+    }
+    count
+}
+
+#[test_case]
+fn test_decode_text() {
+    let mut keys = [None; 8];
+    assert_eq!(decode_all(b"a\r\nb\x7f", &mut keys), 4);
+    assert_eq!(keys[0], Some(DecodedKey::Unicode('a')));
+    assert_eq!(keys[1], Some(DecodedKey::Unicode('\n')));
+    assert_eq!(keys[2], Some(DecodedKey::Unicode('b')));
+    assert_eq!(keys[3], Some(DecodedKey::Unicode('\u{8}')));
+}
+
+#[test_case]
+fn test_decode_escape_sequences() {
+    let mut keys = [None; 8];
+    assert_eq!(decode_all(b"\x1b[A\x1bOD\x1b[3~\x1b[1;5H", &mut keys), 4);
+    assert_eq!(keys[0], Some(DecodedKey::RawKey(KeyCode::ArrowUp)));
+    assert_eq!(keys[1], Some(DecodedKey::RawKey(KeyCode::ArrowLeft)));
+    assert_eq!(keys[2], Some(DecodedKey::RawKey(KeyCode::Delete)));
+    assert_eq!(keys[3], Some(DecodedKey::RawKey(KeyCode::Home)));
+}
+
+#[test_case]
+fn test_decode_lone_escape() {
+    let mut keys = [None; 8];
+    assert_eq!(decode_all(b"\x1bx\x1b\x1b[B", &mut keys), 4);
+    assert_eq!(keys[0], Some(DecodedKey::RawKey(KeyCode::Escape)));
+    assert_eq!(keys[1], Some(DecodedKey::Unicode('x')));
+    assert_eq!(keys[2], Some(DecodedKey::RawKey(KeyCode::Escape)));
+    assert_eq!(keys[3], Some(DecodedKey::RawKey(KeyCode::ArrowDown)));
+}
+
+#[test_case]
+fn test_decode_utf8() {
+    let mut keys = [None; 8];
+    assert_eq!(decode_all("ä€".as_bytes(), &mut keys), 2);
+    assert_eq!(keys[0], Some(DecodedKey::Unicode('ä')));
+    assert_eq!(keys[1], Some(DecodedKey::Unicode('€')));
+}
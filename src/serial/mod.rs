@@ -0,0 +1,125 @@
+use spin::Mutex;
+use lazy_static::lazy_static;
+use x86_64::instructions::interrupts;
+
+use crate::io::RingBuffer;
+use crate::resource::{IrqLine, PortRange};
+use uart::{ComPort, LineConfig, Uart};
+
+pub mod console;
+pub mod uart;
+
+pub const COM1: u16 = ComPort::Com1.base();
+///The PIC line COM1 raises when it has received something
+pub const COM1_IRQ: u8 = ComPort::Com1.irq();
+
+///Bytes received on COM1 that nobody has read yet, the oldest bytes are dropped when full.
+///The console decoding them into keys doesn't take them out of here.
+static RECEIVED: Mutex<RingBuffer<u8, 256>> = Mutex::new(RingBuffer::new(0));
+
+lazy_static!(
+    ///COM1, the interrupt handler uses it too so only lock it with interrupts disabled. Its
+    ///ports are claimed the first time it is used, which may be before the drivers start.
+    pub static ref SERIAL1: Mutex<Uart<PortRange>> = {
+        let ports = ComPort::Com1.claim().expect("COM1 is already claimed");
+        let mut serial_port = Uart::with_ports(ports, COM1);
+        serial_port
+            .init(LineConfig::DEFAULT)
+            .expect("Default line config is invalid");
+        Mutex::new(serial_port)
+    };
+);
+
+///Sets up COM1 and turns on its receive interrupt on `irq`
+pub fn init(irq: &IrqLine) {
+    interrupts::without_interrupts(|| SERIAL1.lock().enable_receive_interrupt());
+    irq.unmask();
+}
+
+///Whether there is a uart behind COM1
+pub fn is_present() -> bool {
+    interrupts::without_interrupts(|| SERIAL1.lock().is_present())
+}
+
+///Empties the uart receive buffer, called from the COM1 interrupt handler
+pub fn handle_interrupt() {
+    let mut serial = SERIAL1.lock();
+
+    while let Some(byte) = serial.try_receive() {
+        if console::is_enabled() {
+            console::receive(byte);
+        }
+        RECEIVED.lock().push_overwrite(byte);
+    }
+}
+
+///Takes the oldest received byte if there is one
+pub fn try_read_byte() -> Option<u8> {
+    interrupts::without_interrupts(|| RECEIVED.lock().pop())
+}
+
+///Waits until a byte is received, interrupts have to be enabled
+pub fn read_byte() -> u8 {
+    loop {
+        interrupts::disable();
+        if let Some(byte) = RECEIVED.lock().pop() {
+            interrupts::enable();
+            return byte;
+        }
+        // enabling and halting together means the interrupt can not slip in between
+        interrupts::enable_and_hlt();
+    }
+}
+
+
+#[doc(hidden)]
+pub fn _print(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    interrupts::without_interrupts(|| {         // new
+        SERIAL1
+            .lock()
+            .write_fmt(args)
+            .expect("Printing to serial failed");
+    });
+}
+
+///Prints to COM1 even when `SERIAL1` is held, for when whoever holds it will never let go
+pub fn write_unlocked(args: ::core::fmt::Arguments) {
+    use core::fmt::Write;
+
+    unsafe { SERIAL1.force_unlock() };
+    let _ = SERIAL1.lock().write_fmt(args);
+}
+
+/// Prints to the host through the serial interface.
+#[macro_export]
+macro_rules! serial_print {
+    ($($arg:tt)*) => {
+        $crate::serial::_print(format_args!($($arg)*))
+    };
+}
+
+/// Prints to the host through the serial interface, appending a newline.
+#[macro_export]
+macro_rules! serial_println {
+    () => ($crate::serial_print!("\n"));
+    ($fmt:expr) => ($crate::serial_print!(concat!($fmt, "\n")));
+    ($fmt:expr, $($arg:tt)*) => ($crate::serial_print!(
+        concat!($fmt, "\n"), $($arg)*));
+}
+
+#[test_case]
+fn test_received_bytes_in_order() {
+    interrupts::without_interrupts(|| {
+        let mut received = RECEIVED.lock();
+        received.clear();
+        for byte in b"ok" {
+            received.push_overwrite(*byte);
+        }
+    });
+
+    assert_eq!(try_read_byte(), Some(b'o'));
+    assert_eq!(read_byte(), b'k');
+    assert_eq!(try_read_byte(), None);
+}
@@ -0,0 +1,537 @@
+//! A driver for the 16550 compatible uarts behind the four standard COM ports
+
+use core::fmt;
+
+use rost_core::port::PortIo;
+
+use crate::resource::{self, PortRange, ResourceError};
+
+//register offsets from the base port
+const DATA: u16 = 0;
+const INTERRUPT_ENABLE: u16 = 1;
+//the two divisor bytes share the first two registers while DLAB is set
+const DIVISOR_LOW: u16 = 0;
+const DIVISOR_HIGH: u16 = 1;
+const INTERRUPT_IDENTIFICATION: u16 = 2;
+const FIFO_CONTROL: u16 = 2;
+const LINE_CONTROL: u16 = 3;
+const MODEM_CONTROL: u16 = 4;
+const LINE_STATUS: u16 = 5;
+const MODEM_STATUS: u16 = 6;
+const SCRATCH: u16 = 7;
+///How many ports the registers take up
+pub const REGISTER_COUNT: u16 = 8;
+
+const DLAB: u8 = 0x80;
+const RECEIVED_DATA_INTERRUPT: u8 = 0x01;
+//DTR, RTS and OUT2 which connects the uart interrupt to the PIC
+const MODEM_CONTROL_DEFAULT: u8 = 0x0B;
+//enable and clear both fifos
+const FIFO_ENABLE: u8 = 0x07;
+
+///The clock all baud rates are divided from
+pub const MAX_BAUD_RATE: u32 = 115200;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ComPort {
+    Com1,
+    Com2,
+    Com3,
+    Com4,
+}
+
+impl ComPort {
+    pub const ALL: [ComPort; 4] = [ComPort::Com1, ComPort::Com2, ComPort::Com3, ComPort::Com4];
+
+    pub const fn base(self) -> u16 {
+        match self {
+            ComPort::Com1 => 0x3F8,
+            ComPort::Com2 => 0x2F8,
+            ComPort::Com3 => 0x3E8,
+            ComPort::Com4 => 0x2E8,
+        }
+    }
+
+    pub const fn name(self) -> &'static str {
+        match self {
+            ComPort::Com1 => "com1",
+            ComPort::Com2 => "com2",
+            ComPort::Com3 => "com3",
+            ComPort::Com4 => "com4",
+        }
+    }
+
+    ///The PIC line of the port, COM3 and COM4 share theirs with COM1 and COM2
+    pub const fn irq(self) -> u8 {
+        match self {
+            ComPort::Com1 | ComPort::Com3 => 4,
+            ComPort::Com2 | ComPort::Com4 => 3,
+        }
+    }
+
+    ///Claims the registers of the port for `Uart::with_ports`
+    pub fn claim(self) -> Result<PortRange, ResourceError> {
+        resource::claim_ports(self.name(), self.base(), REGISTER_COUNT)
+    }
+}
+
+///The ports that have a uart behind them, the ones claimed by a driver are in use so
+///they are there
+pub fn detect_ports() -> impl Iterator<Item = ComPort> {
+    ComPort::ALL.into_iter().filter(|port| match port.claim() {
+        Ok(ports) => Uart::with_ports(ports, port.base()).is_present(),
+        Err(_) => true,
+    })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DataBits {
+    Five,
+    Six,
+    Seven,
+    Eight,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parity {
+    None,
+    Odd,
+    Even,
+    ///The parity bit is always 1
+    Mark,
+    ///The parity bit is always 0
+    Space,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopBits {
+    One,
+    ///One and a half with 5 data bits
+    Two,
+}
+
+///How many bytes the receive fifo collects before raising an interrupt
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FifoTrigger {
+    Bytes1,
+    Bytes4,
+    Bytes8,
+    Bytes14,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum UartError {
+    NotPresent,
+    ///Another driver has the ports
+    Busy(ResourceError),
+    ///The baud rate has to divide 115200 evenly
+    InvalidBaudRate(u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineConfig {
+    pub baud_rate: u32,
+    pub data_bits: DataBits,
+    pub parity: Parity,
+    pub stop_bits: StopBits,
+    ///None leaves the uart without fifos, every byte then causes its own interrupt
+    pub fifo_trigger: Option<FifoTrigger>,
+}
+
+impl LineConfig {
+    ///38400 baud 8N1
+    pub const DEFAULT: LineConfig = LineConfig {
+        baud_rate: 38400,
+        data_bits: DataBits::Eight,
+        parity: Parity::None,
+        stop_bits: StopBits::One,
+        fifo_trigger: Some(FifoTrigger::Bytes14),
+    };
+
+    pub const fn with_baud_rate(mut self, baud_rate: u32) -> LineConfig {
+        self.baud_rate = baud_rate;
+        self
+    }
+
+    pub fn divisor(&self) -> Result<u16, UartError> {
+        let invalid = UartError::InvalidBaudRate(self.baud_rate);
+
+        if self.baud_rate == 0 || !MAX_BAUD_RATE.is_multiple_of(self.baud_rate) {
+            return Err(invalid);
+        }
+        u16::try_from(MAX_BAUD_RATE / self.baud_rate).map_err(|_| invalid)
+    }
+
+    ///The line control register value without DLAB
+    pub fn line_control(&self) -> u8 {
+        let data_bits = match self.data_bits {
+            DataBits::Five => 0b00,
+            DataBits::Six => 0b01,
+            DataBits::Seven => 0b10,
+            DataBits::Eight => 0b11,
+        };
+        let stop_bits = match self.stop_bits {
+            StopBits::One => 0,
+            StopBits::Two => 1 << 2,
+        };
+        let parity = match self.parity {
+            Parity::None => 0b000,
+            Parity::Odd => 0b001,
+            Parity::Even => 0b011,
+            Parity::Mark => 0b101,
+            Parity::Space => 0b111,
+        } << 3;
+
+        data_bits | stop_bits | parity
+    }
+
+    fn fifo_control(&self) -> u8 {
+        match self.fifo_trigger {
+            None => 0,
+            Some(trigger) => FIFO_ENABLE | (trigger as u8) << 6,
+        }
+    }
+}
+
+impl Default for LineConfig {
+    fn default() -> Self {
+        LineConfig::DEFAULT
+    }
+}
+
+///The line status register
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LineStatus(pub u8);
+
+impl LineStatus {
+    pub fn data_ready(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    ///A byte came in before the previous one was read and got lost
+    pub fn overrun_error(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    pub fn parity_error(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    ///The stop bit was missing, usually a baud rate mismatch
+    pub fn framing_error(&self) -> bool {
+        self.0 & 0x08 != 0
+    }
+
+    ///The line was held low for longer than a whole byte
+    pub fn break_interrupt(&self) -> bool {
+        self.0 & 0x10 != 0
+    }
+
+    ///The transmitter can take another byte
+    pub fn transmitter_ready(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+
+    ///Everything has been sent
+    pub fn transmitter_empty(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    pub fn has_error(&self) -> bool {
+        self.0 & 0x1E != 0
+    }
+}
+
+///The modem status register, the delta bits tell which lines changed since the last read
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ModemStatus(pub u8);
+
+impl ModemStatus {
+    pub fn clear_to_send_changed(&self) -> bool {
+        self.0 & 0x01 != 0
+    }
+
+    pub fn data_set_ready_changed(&self) -> bool {
+        self.0 & 0x02 != 0
+    }
+
+    ///Ring indicator went from on to off
+    pub fn ring_ended(&self) -> bool {
+        self.0 & 0x04 != 0
+    }
+
+    pub fn carrier_detect_changed(&self) -> bool {
+        self.0 & 0x08 != 0
+    }
+
+    pub fn clear_to_send(&self) -> bool {
+        self.0 & 0x10 != 0
+    }
+
+    pub fn data_set_ready(&self) -> bool {
+        self.0 & 0x20 != 0
+    }
+
+    pub fn ring_indicator(&self) -> bool {
+        self.0 & 0x40 != 0
+    }
+
+    pub fn carrier_detect(&self) -> bool {
+        self.0 & 0x80 != 0
+    }
+}
+
+///How many times each line error has happened since the uart was set up
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct LineErrors {
+    pub overrun: u32,
+    pub parity: u32,
+    pub framing: u32,
+    pub breaks: u32,
+}
+
+impl LineErrors {
+    fn record(&mut self, status: LineStatus) {
+        let count = |counter: &mut u32, happened: bool| {
+            if happened {
+                *counter = counter.saturating_add(1);
+            }
+        };
+
+        count(&mut self.overrun, status.overrun_error());
+        count(&mut self.parity, status.parity_error());
+        count(&mut self.framing, status.framing_error());
+        count(&mut self.breaks, status.break_interrupt());
+    }
+
+    pub fn total(&self) -> u32 {
+        self.overrun
+            .saturating_add(self.parity)
+            .saturating_add(self.framing)
+            .saturating_add(self.breaks)
+    }
+}
+
+#[derive(Debug)]
+pub struct Uart<P> {
+    ports: P,
+    base: u16,
+    config: LineConfig,
+    errors: LineErrors,
+}
+
+impl Uart<PortRange> {
+    ///Claims the ports of a COM port, probes it and sets it up with `config`. The ports
+    ///are given back when the uart is dropped.
+    pub fn open(port: ComPort, config: LineConfig) -> Result<Uart<PortRange>, UartError> {
+        let ports = port.claim().map_err(UartError::Busy)?;
+        let mut uart = Uart::with_ports(ports, port.base());
+        if !uart.is_present() {
+            return Err(UartError::NotPresent);
+        }
+        uart.init(config)?;
+        Ok(uart)
+    }
+}
+
+impl<P: PortIo> Uart<P> {
+    ///Creates a uart whose registers start at `base` in `ports`, without touching them
+    pub const fn with_ports(ports: P, base: u16) -> Uart<P> {
+        Uart {
+            ports,
+            base,
+            config: LineConfig::DEFAULT,
+            errors: LineErrors {
+                overrun: 0,
+                parity: 0,
+                framing: 0,
+                breaks: 0,
+            },
+        }
+    }
+
+    pub fn base(&self) -> u16 {
+        self.base
+    }
+
+    ///Checks for a uart by writing to its scratch register and reading the value back
+    pub fn is_present(&mut self) -> bool {
+        [0x5A, 0xA5].iter().all(|pattern| {
+            self.write_register(SCRATCH, *pattern);
+            self.read_register(SCRATCH) == *pattern
+        })
+    }
+
+    ///Resets the uart with interrupts off and the modem control lines up
+    pub fn init(&mut self, config: LineConfig) -> Result<(), UartError> {
+        self.write_register(INTERRUPT_ENABLE, 0);
+        self.configure(config)?;
+        self.write_register(MODEM_CONTROL, MODEM_CONTROL_DEFAULT);
+        self.errors = LineErrors::default();
+        Ok(())
+    }
+
+    ///Changes the line settings, an invalid config leaves the old one in place
+    pub fn configure(&mut self, config: LineConfig) -> Result<(), UartError> {
+        let divisor = config.divisor()?;
+        let [low, high] = divisor.to_le_bytes();
+
+        self.write_register(LINE_CONTROL, DLAB);
+        self.write_register(DIVISOR_LOW, low);
+        self.write_register(DIVISOR_HIGH, high);
+        self.write_register(LINE_CONTROL, config.line_control());
+        self.write_register(FIFO_CONTROL, config.fifo_control());
+
+        self.config = config;
+        Ok(())
+    }
+
+    pub fn config(&self) -> LineConfig {
+        self.config
+    }
+
+    ///Reads the divisor back from the hardware
+    pub fn divisor(&mut self) -> u16 {
+        let line_control = self.read_register(LINE_CONTROL);
+
+        self.write_register(LINE_CONTROL, line_control | DLAB);
+        let low = self.read_register(DIVISOR_LOW);
+        let high = self.read_register(DIVISOR_HIGH);
+        self.write_register(LINE_CONTROL, line_control);
+
+        u16::from_le_bytes([low, high])
+    }
+
+    ///Whether the fifos are really on, a plain 8250 or 16450 does not have them
+    pub fn fifo_enabled(&mut self) -> bool {
+        self.read_register(INTERRUPT_IDENTIFICATION) & 0xC0 == 0xC0
+    }
+
+    ///Lets the uart raise its irq whenever data has been received
+    pub fn enable_receive_interrupt(&mut self) {
+        self.write_register(INTERRUPT_ENABLE, RECEIVED_DATA_INTERRUPT);
+    }
+
+    pub fn disable_interrupts(&mut self) {
+        self.write_register(INTERRUPT_ENABLE, 0);
+    }
+
+    ///Reads the line status, any errors it reports are counted
+    pub fn line_status(&mut self) -> LineStatus {
+        let status = LineStatus(self.read_register(LINE_STATUS));
+        self.errors.record(status);
+        status
+    }
+
+    pub fn modem_status(&mut self) -> ModemStatus {
+        ModemStatus(self.read_register(MODEM_STATUS))
+    }
+
+    pub fn errors(&self) -> LineErrors {
+        self.errors
+    }
+
+    pub fn send(&mut self, byte: u8) {
+        while !self.line_status().transmitter_ready() {
+            core::hint::spin_loop();
+        }
+        self.write_register(DATA, byte);
+    }
+
+    pub fn try_receive(&mut self) -> Option<u8> {
+        if self.line_status().data_ready() {
+            Some(self.read_register(DATA))
+        } else {
+            None
+        }
+    }
+
+    pub fn receive(&mut self) -> u8 {
+        loop {
+            if let Some(byte) = self.try_receive() {
+                return byte;
+            }
+            core::hint::spin_loop();
+        }
+    }
+
+    fn read_register(&mut self, register: u16) -> u8 {
+        self.ports.read_u8(self.base + register)
+    }
+
+    fn write_register(&mut self, register: u16, value: u8) {
+        self.ports.write_u8(self.base + register, value)
+    }
+}
+
+impl<P: PortIo> fmt::Write for Uart<P> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for byte in s.bytes() {
+            self.send(byte);
+        }
+        Ok(())
+    }
+}
+
+#[test_case]
+fn test_line_control_bits() {
+    let config = LineConfig {
+        data_bits: DataBits::Seven,
+        parity: Parity::Even,
+        stop_bits: StopBits::Two,
+        ..LineConfig::DEFAULT
+    };
+    assert_eq!(LineConfig::DEFAULT.line_control(), 0x03);
+    assert_eq!(config.line_control(), 0x1E);
+}
+
+#[test_case]
+fn test_divisor() {
+    assert_eq!(LineConfig::DEFAULT.divisor(), Ok(3));
+    assert_eq!(LineConfig::DEFAULT.with_baud_rate(50).divisor(), Ok(2304));
+    assert_eq!(
+        LineConfig::DEFAULT.with_baud_rate(40000).divisor(),
+        Err(UartError::InvalidBaudRate(40000))
+    );
+    assert_eq!(
+        LineConfig::DEFAULT.with_baud_rate(1).divisor(),
+        Err(UartError::InvalidBaudRate(1))
+    );
+}
+
+#[test_case]
+fn test_configure() {
+    use rost_core::mock::MockPorts;
+
+    let mut uart = Uart::with_ports(MockPorts::new(), 0x3F8);
+    uart.configure(LineConfig::DEFAULT.with_baud_rate(9600))
+        .unwrap();
+    //the divisor is written with DLAB set, which the line control write clears again
+    assert_eq!(uart.ports.get(0x3F8), 12);
+    assert_eq!(uart.ports.get(0x3F9), 0);
+    assert_eq!(uart.ports.get(0x3FB), 0x03);
+    assert_eq!(uart.ports.get(0x3FA), 0xC7);
+    assert_eq!(
+        uart.configure(LineConfig::DEFAULT.with_baud_rate(7)),
+        Err(UartError::InvalidBaudRate(7))
+    );
+}
+
+#[test_case]
+fn test_com1_detected() {
+    assert!(super::is_present());
+    assert_eq!(detect_ports().next(), Some(ComPort::Com1));
+}
+
+#[test_case]
+fn test_configure_round_trip() {
+    x86_64::instructions::interrupts::without_interrupts(|| {
+        let mut serial = super::SERIAL1.lock();
+        let original = serial.config();
+
+        serial.configure(original.with_baud_rate(9600)).unwrap();
+        assert_eq!(serial.divisor(), 12);
+        serial.configure(original).unwrap();
+        assert_eq!(serial.divisor(), original.divisor().unwrap());
+    });
+}
@@ -0,0 +1,347 @@
+//! The commands the shell comes with
+
+use core::time::Duration;
+
+use super::commands::{self, Command, CommandError};
+use crate::pc_speaker::rtttl::Melody;
+use crate::pc_speaker::{self, Note, SpeakerError};
+use crate::vga_driver::Color;
+use crate::{print, println};
+
+pub const BUILTINS: [Command; 17] = [
+    Command {
+        name: "help",
+        usage: "[command]",
+        help: "lists the commands or shows how to use one",
+        run: help,
+    },
+    Command {
+        name: "clear",
+        usage: "",
+        help: "clears the screen",
+        run: clear,
+    },
+    Command {
+        name: "echo",
+        usage: "[text...]",
+        help: "prints its arguments",
+        run: echo,
+    },
+    Command {
+        name: "color",
+        usage: "<foreground> [background]",
+        help: "changes the text colors",
+        run: color,
+    },
+    Command {
+        name: "uptime",
+        usage: "",
+        help: "shows how long the kernel has been running",
+        run: uptime,
+    },
+    Command {
+        name: "date",
+        usage: "",
+        help: "shows the date and time of the real time clock",
+        run: date,
+    },
+    Command {
+        name: "mem",
+        usage: "",
+        help: "shows how much physical memory there is",
+        run: mem,
+    },
+    Command {
+        name: "lsres",
+        usage: "",
+        help: "shows which driver owns which ports, irq lines and memory",
+        run: lsres,
+    },
+    Command {
+        name: "lsdev",
+        usage: "",
+        help: "shows the device tree and the state of every device",
+        run: lsdev,
+    },
+    Command {
+        name: "lspci",
+        usage: "[-v]",
+        help: "lists the pci devices, -v adds their bars and capabilities",
+        run: lspci,
+    },
+    Command {
+        name: "beep",
+        usage: "[frequency] [milliseconds]",
+        help: "plays a tone on the pc speaker",
+        run: beep,
+    },
+    Command {
+        name: "play",
+        usage: "<rtttl melody> | stop",
+        help: "plays a melody like tune:d=4,o=5,b=120:c,e,g in the background",
+        run: play,
+    },
+    Command {
+        name: "dmesg",
+        usage: "[-c]",
+        help: "shows the kernel log, -c clears it afterwards",
+        run: dmesg,
+    },
+    Command {
+        name: "layout",
+        usage: "[name]",
+        help: "shows or changes the keyboard layout",
+        run: layout,
+    },
+    Command {
+        name: "reboot",
+        usage: "",
+        help: "restarts the machine",
+        run: reboot,
+    },
+    Command {
+        name: "shutdown",
+        usage: "",
+        help: "turns the machine off",
+        run: shutdown,
+    },
+    Command {
+        name: "panic",
+        usage: "[message...]",
+        help: "panics the kernel on purpose",
+        run: panic,
+    },
+];
+
+const COLOR_NAMES: [(&str, Color); 16] = [
+    ("black", Color::Black),
+    ("blue", Color::Blue),
+    ("green", Color::Green),
+    ("cyan", Color::Cyan),
+    ("red", Color::Red),
+    ("magenta", Color::Magenta),
+    ("brown", Color::Brown),
+    ("lightgray", Color::LightGray),
+    ("darkgray", Color::DarkGray),
+    ("lightblue", Color::LightBlue),
+    ("lightgreen", Color::LightGreen),
+    ("lightcyan", Color::LightCyan),
+    ("lightred", Color::LightRed),
+    ("pink", Color::Pink),
+    ("yellow", Color::Yellow),
+    ("white", Color::White),
+];
+
+fn help(args: &[&str]) -> Result<(), CommandError> {
+    match args {
+        [] => commands::with_registry(|registry| {
+            for command in registry.iter() {
+                println!("{:<10}{}", command.name, command.help);
+            }
+        }),
+        [name] => {
+            let command = commands::find(name).ok_or(CommandError::Failed("no such command"))?;
+            println!("{}", command.help);
+            println!("usage: {} {}", command.name, command.usage);
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn clear(_: &[&str]) -> Result<(), CommandError> {
+    crate::vga_driver::clear_screen();
+    //the screen is not mirrored byte by byte, so the terminal is cleared separately
+    if crate::serial::console::is_enabled() {
+        crate::serial_print!("\x1b[2J\x1b[H");
+    }
+    Ok(())
+}
+
+fn echo(args: &[&str]) -> Result<(), CommandError> {
+    for (i, arg) in args.iter().enumerate() {
+        if i > 0 {
+            print!(" ");
+        }
+        print!("{}", arg);
+    }
+    println!();
+    Ok(())
+}
+
+fn parse_color(name: &str) -> Result<Color, CommandError> {
+    COLOR_NAMES
+        .iter()
+        .find(|(color_name, _)| color_name.eq_ignore_ascii_case(name))
+        .map(|(_, color)| *color)
+        .ok_or(CommandError::Failed(
+            "unknown color, the colors are named like lightblue",
+        ))
+}
+
+fn color(args: &[&str]) -> Result<(), CommandError> {
+    let (foreground, background) = match args {
+        [foreground] => (parse_color(foreground)?, Color::Black),
+        [foreground, background] => (parse_color(foreground)?, parse_color(background)?),
+        _ => return Err(CommandError::Usage),
+    };
+    crate::colorchg(foreground, background);
+    Ok(())
+}
+
+fn uptime(_: &[&str]) -> Result<(), CommandError> {
+    let uptime = crate::interrupts::uptime();
+    let seconds = uptime.as_secs();
+    println!(
+        "up {}:{:02}:{:02}.{:02}",
+        seconds / 3600,
+        seconds / 60 % 60,
+        seconds % 60,
+        uptime.subsec_millis() / 10
+    );
+    Ok(())
+}
+
+fn date(_: &[&str]) -> Result<(), CommandError> {
+    let now = crate::rtc::now().ok_or(CommandError::Failed("there is no real time clock"))?;
+    println!("{}", now);
+    Ok(())
+}
+
+fn mem(_: &[&str]) -> Result<(), CommandError> {
+    let stats = crate::memory::stats().ok_or(CommandError::Failed(
+        "the bootloader did not pass a memory map",
+    ))?;
+    println!("total    {:>8} KiB", stats.total() / 1024);
+    println!("usable   {:>8} KiB", stats.usable / 1024);
+    println!("in use   {:>8} KiB", stats.in_use / 1024);
+    println!("reserved {:>8} KiB", stats.reserved / 1024);
+    Ok(())
+}
+
+fn lsres(_: &[&str]) -> Result<(), CommandError> {
+    crate::resource::list();
+    Ok(())
+}
+
+fn lsdev(_: &[&str]) -> Result<(), CommandError> {
+    crate::driver::list();
+    Ok(())
+}
+
+fn lspci(args: &[&str]) -> Result<(), CommandError> {
+    let verbose = match args {
+        [] => false,
+        ["-v"] => true,
+        _ => return Err(CommandError::Usage),
+    };
+    crate::pci::list(verbose);
+    Ok(())
+}
+
+fn parse_number(arg: &str) -> Result<u32, CommandError> {
+    arg.parse().map_err(|_| CommandError::Usage)
+}
+
+fn beep(args: &[&str]) -> Result<(), CommandError> {
+    let (frequency, milliseconds) = match args {
+        [] => (440, 200),
+        [frequency] => (parse_number(frequency)?, 200),
+        [frequency, milliseconds] => (parse_number(frequency)?, parse_number(milliseconds)?),
+        _ => return Err(CommandError::Usage),
+    };
+
+    pc_speaker::beep(frequency, Duration::from_millis(milliseconds as u64)).map_err(speaker_error)
+}
+
+fn speaker_error(error: SpeakerError) -> CommandError {
+    match error {
+        SpeakerError::FrequencyOutOfRange(_) => {
+            CommandError::Failed("the frequency has to be between 20 and 20000 Hz")
+        }
+        SpeakerError::QueueFull => CommandError::Failed("the melody is too long to queue"),
+    }
+}
+
+fn play(args: &[&str]) -> Result<(), CommandError> {
+    let text = match args {
+        ["stop"] => {
+            pc_speaker::stop();
+            return Ok(());
+        }
+        [text] => text,
+        _ => return Err(CommandError::Usage),
+    };
+
+    let melody = Melody::parse(text).map_err(|_| CommandError::Usage)?;
+    let mut notes = [Note::rest(Duration::ZERO); pc_speaker::QUEUE_LENGTH];
+    let mut count = 0;
+    for note in melody.notes() {
+        let note = note.map_err(|_| CommandError::Failed("a note could not be read"))?;
+        *notes
+            .get_mut(count)
+            .ok_or(speaker_error(SpeakerError::QueueFull))? = note;
+        count += 1;
+    }
+    pc_speaker::play(&notes[..count]).map_err(speaker_error)
+}
+
+fn dmesg(args: &[&str]) -> Result<(), CommandError> {
+    match args {
+        [] => crate::logger::dmesg(),
+        ["-c"] => {
+            crate::logger::dmesg();
+            crate::logger::clear_dmesg();
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn layout(args: &[&str]) -> Result<(), CommandError> {
+    use crate::keyboard::{self, Layout};
+
+    match args {
+        [] => {
+            println!("current layout: {}", keyboard::layout().name());
+            print!("layouts:");
+            for layout in Layout::ALL {
+                print!(" {}", layout.name());
+            }
+            println!();
+        }
+        [name] => {
+            let layout = Layout::from_name(name).ok_or(CommandError::Failed("unknown layout"))?;
+            keyboard::set_layout(layout);
+        }
+        _ => return Err(CommandError::Usage),
+    }
+    Ok(())
+}
+
+fn reboot(_: &[&str]) -> Result<(), CommandError> {
+    crate::power::reboot();
+}
+
+fn shutdown(_: &[&str]) -> Result<(), CommandError> {
+    crate::power::shutdown();
+}
+
+fn panic(args: &[&str]) -> Result<(), CommandError> {
+    match args {
+        [] => panic!("panic requested from the shell"),
+        [first, rest @ ..] => {
+            let mut message = super::line::Line::from_text(first);
+            for arg in rest {
+                let _ = core::fmt::Write::write_fmt(&mut message, format_args!(" {}", arg));
+            }
+            panic!("{}", message.as_str())
+        }
+    }
+}
+
+#[test_case]
+fn test_parse_color() {
+    assert_eq!(parse_color("LightBlue"), Ok(Color::LightBlue));
+    assert!(parse_color("purple").is_err());
+}
@@ -0,0 +1,134 @@
+//! The commands the shell knows, other modules can add their own with `register`
+
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::builtins::BUILTINS;
+
+pub const MAX_COMMANDS: usize = 48;
+
+static REGISTRY: Mutex<Registry> = Mutex::new(Registry::with_builtins());
+
+///What a command gets: its arguments without the command name
+pub type CommandFn = fn(args: &[&str]) -> Result<(), CommandError>;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CommandError {
+    ///The arguments were wrong, the shell prints the usage line
+    Usage,
+    Failed(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RegistryError {
+    Full,
+    NameTaken,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Command {
+    pub name: &'static str,
+    ///The arguments part of the usage line, like `<foreground> [background]`
+    pub usage: &'static str,
+    pub help: &'static str,
+    pub run: CommandFn,
+}
+
+pub struct Registry {
+    commands: [Option<Command>; MAX_COMMANDS],
+}
+
+impl Registry {
+    pub const fn new() -> Registry {
+        Registry {
+            commands: [None; MAX_COMMANDS],
+        }
+    }
+
+    const fn with_builtins() -> Registry {
+        let mut registry = Registry::new();
+        let mut i = 0;
+        while i < BUILTINS.len() {
+            registry.commands[i] = Some(BUILTINS[i]);
+            i += 1;
+        }
+        registry
+    }
+
+    pub fn register(&mut self, command: Command) -> Result<(), RegistryError> {
+        if self.find(command.name).is_some() {
+            return Err(RegistryError::NameTaken);
+        }
+        let slot = self
+            .commands
+            .iter_mut()
+            .find(|slot| slot.is_none())
+            .ok_or(RegistryError::Full)?;
+        *slot = Some(command);
+        Ok(())
+    }
+
+    pub fn find(&self, name: &str) -> Option<Command> {
+        self.iter().find(|command| command.name == name)
+    }
+
+    ///The commands in the order they were registered
+    pub fn iter(&self) -> impl Iterator<Item = Command> + '_ {
+        self.commands.iter().flatten().copied()
+    }
+
+    ///The names starting with `prefix`
+    pub fn completions<'a>(&'a self, prefix: &'a str) -> impl Iterator<Item = &'static str> + 'a {
+        self.iter()
+            .map(|command| command.name)
+            .filter(move |name| name.starts_with(prefix))
+    }
+}
+
+impl Default for Registry {
+    fn default() -> Self {
+        Registry::new()
+    }
+}
+
+///Adds a command to the shell
+pub fn register(command: Command) -> Result<(), RegistryError> {
+    without_interrupts(|| REGISTRY.lock().register(command))
+}
+
+pub fn find(name: &str) -> Option<Command> {
+    without_interrupts(|| REGISTRY.lock().find(name))
+}
+
+///Runs `f` with the registry locked, don't run commands from inside it
+pub fn with_registry<R>(f: impl FnOnce(&Registry) -> R) -> R {
+    without_interrupts(|| f(&REGISTRY.lock()))
+}
+
+#[test_case]
+fn test_registry() {
+    fn nothing(_: &[&str]) -> Result<(), CommandError> {
+        Ok(())
+    }
+    let command = |name| Command {
+        name,
+        usage: "",
+        help: "",
+        run: nothing,
+    };
+
+    let mut registry = Registry::new();
+    registry.register(command("cat")).unwrap();
+    registry.register(command("clear")).unwrap();
+    registry.register(command("date")).unwrap();
+    assert_eq!(
+        registry.register(command("cat")).err(),
+        Some(RegistryError::NameTaken)
+    );
+
+    let mut completions = registry.completions("c");
+    assert_eq!(completions.next(), Some("cat"));
+    assert_eq!(completions.next(), Some("clear"));
+    assert_eq!(completions.next(), None);
+    assert!(registry.find("date").is_some());
+}
@@ -0,0 +1,106 @@
+//! The lines run before, browsed with the up and down arrows
+
+use super::line::Line;
+use crate::io::RingBuffer;
+
+pub const HISTORY_LENGTH: usize = 32;
+
+#[derive(Debug)]
+pub struct History {
+    lines: RingBuffer<Line, HISTORY_LENGTH>,
+    //how many lines back from the newest is shown, `None` when not browsing
+    position: Option<usize>,
+    //the line that was being typed when browsing started
+    draft: Line,
+}
+
+impl History {
+    pub const fn new() -> History {
+        History {
+            lines: RingBuffer::new(Line::new()),
+            position: None,
+            draft: Line::new(),
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.lines.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lines.is_empty()
+    }
+
+    ///Remembers a line that was run, blank lines and repeats of the last line are skipped
+    pub fn push(&mut self, line: &Line) {
+        self.position = None;
+        if line.as_str().trim().is_empty() {
+            return;
+        }
+        if self.newest(0) == Some(*line) {
+            return;
+        }
+        self.lines.push_overwrite(*line);
+    }
+
+    ///The line before the one shown, `current` is kept to come back to
+    pub fn older(&mut self, current: &Line) -> Option<Line> {
+        let position = match self.position {
+            None => {
+                self.draft = *current;
+                0
+            }
+            Some(position) => position + 1,
+        };
+        let line = self.newest(position)?;
+        self.position = Some(position);
+        Some(line)
+    }
+
+    ///The line after the one shown, or the draft after the newest one
+    pub fn newer(&mut self) -> Option<Line> {
+        match self.position? {
+            0 => {
+                self.position = None;
+                Some(self.draft)
+            }
+            position => {
+                self.position = Some(position - 1);
+                self.newest(position - 1)
+            }
+        }
+    }
+
+    ///Goes through the lines from the oldest to the newest
+    pub fn iter(&self) -> impl Iterator<Item = Line> + '_ {
+        self.lines.iter()
+    }
+
+    fn newest(&self, back: usize) -> Option<Line> {
+        let index = self.lines.len().checked_sub(back + 1)?;
+        self.lines.get(index)
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+#[test_case]
+fn test_history_browsing() {
+    let mut history = History::new();
+    for line in ["ls", "ls", "", "echo hi"] {
+        history.push(&Line::from_text(line));
+    }
+    assert_eq!(history.len(), 2);
+
+    let draft = Line::from_text("ec");
+    assert_eq!(history.older(&draft), Some(Line::from_text("echo hi")));
+    assert_eq!(history.older(&draft), Some(Line::from_text("ls")));
+    assert_eq!(history.older(&draft), None);
+    assert_eq!(history.newer(), Some(Line::from_text("echo hi")));
+    assert_eq!(history.newer(), Some(draft));
+    assert_eq!(history.newer(), None);
+}
@@ -0,0 +1,266 @@
+//! The line being typed, with the cursor somewhere inside it
+//!
+//! The editor writes every change to the screen itself. The screen cursor has to be where
+//! the line cursor is, only backspace (which moves left without erasing) and plain
+//! characters are used so the same output works on the screen and on a terminal.
+
+use core::fmt::{self, Write};
+
+///Fits on an 80 column row together with the prompt
+pub const MAX_LINE: usize = 72;
+
+const BACKSPACE: char = '\u{8}';
+
+///Text in a fixed size buffer, what doesn't fit is dropped
+#[derive(Debug, Clone, Copy)]
+pub struct Text<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+pub type Line = Text<MAX_LINE>;
+
+impl<const N: usize> Text<N> {
+    pub const fn new() -> Self {
+        Text {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    ///Copies as much of `text` as fits
+    pub fn from_text(text: &str) -> Self {
+        let mut line = Text::new();
+        let _ = line.write_str(text);
+        line
+    }
+
+    pub fn as_str(&self) -> &str {
+        //only whole characters are ever written
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn is_full(&self) -> bool {
+        self.len == N
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    fn insert(&mut self, index: usize, byte: u8) {
+        self.bytes.copy_within(index..self.len, index + 1);
+        self.bytes[index] = byte;
+        self.len += 1;
+    }
+
+    fn remove(&mut self, index: usize) {
+        self.bytes.copy_within(index + 1..self.len, index);
+        self.len -= 1;
+    }
+}
+
+//the bytes past the end are left over from earlier text
+impl<const N: usize> PartialEq for Text<N> {
+    fn eq(&self, other: &Self) -> bool {
+        self.as_bytes() == other.as_bytes()
+    }
+}
+
+impl<const N: usize> Eq for Text<N> {}
+
+impl<const N: usize> Default for Text<N> {
+    fn default() -> Self {
+        Text::new()
+    }
+}
+
+impl<const N: usize> Write for Text<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for character in s.chars() {
+            let mut encoded = [0; 4];
+            let encoded = character.encode_utf8(&mut encoded).as_bytes();
+            if self.len + encoded.len() > N {
+                break;
+            }
+            self.bytes[self.len..self.len + encoded.len()].copy_from_slice(encoded);
+            self.len += encoded.len();
+        }
+        Ok(())
+    }
+}
+
+///A line of printable ascii and the cursor in it
+#[derive(Debug, Clone, Copy, Default)]
+pub struct LineEditor {
+    line: Line,
+    cursor: usize,
+}
+
+impl LineEditor {
+    pub const fn new() -> LineEditor {
+        LineEditor {
+            line: Line::new(),
+            cursor: 0,
+        }
+    }
+
+    pub fn line(&self) -> &Line {
+        &self.line
+    }
+
+    pub fn cursor(&self) -> usize {
+        self.cursor
+    }
+
+    ///Inserts a character at the cursor, returns false when it isn't printable ascii or
+    ///the line is full
+    pub fn insert(&mut self, character: char, out: &mut impl Write) -> bool {
+        if !(' '..='~').contains(&character) || self.line.is_full() {
+            return false;
+        }
+        self.line.insert(self.cursor, character as u8);
+        self.cursor += 1;
+        let _ = out.write_char(character);
+        self.redraw_tail(0, out);
+        true
+    }
+
+    pub fn insert_str(&mut self, text: &str, out: &mut impl Write) {
+        for character in text.chars() {
+            if !self.insert(character, out) {
+                return;
+            }
+        }
+    }
+
+    ///Removes the character left of the cursor
+    pub fn backspace(&mut self, out: &mut impl Write) {
+        if self.cursor == 0 {
+            return;
+        }
+        self.cursor -= 1;
+        self.line.remove(self.cursor);
+        let _ = out.write_char(BACKSPACE);
+        self.redraw_tail(1, out);
+    }
+
+    ///Removes the character under the cursor
+    pub fn delete(&mut self, out: &mut impl Write) {
+        if self.cursor == self.line.len() {
+            return;
+        }
+        self.line.remove(self.cursor);
+        self.redraw_tail(1, out);
+    }
+
+    pub fn left(&mut self, out: &mut impl Write) {
+        if self.cursor > 0 {
+            self.cursor -= 1;
+            let _ = out.write_char(BACKSPACE);
+        }
+    }
+
+    pub fn right(&mut self, out: &mut impl Write) {
+        if self.cursor < self.line.len() {
+            let _ = out.write_char(self.line.as_bytes()[self.cursor] as char);
+            self.cursor += 1;
+        }
+    }
+
+    pub fn home(&mut self, out: &mut impl Write) {
+        move_back(self.cursor, out);
+        self.cursor = 0;
+    }
+
+    pub fn end(&mut self, out: &mut impl Write) {
+        let _ = out.write_str(&self.line.as_str()[self.cursor..]);
+        self.cursor = self.line.len();
+    }
+
+    ///Swaps the whole line for another one, leaving the cursor at its end
+    pub fn replace(&mut self, line: &Line, out: &mut impl Write) {
+        let old_len = self.line.len();
+        self.home(out);
+        self.line.clear();
+        self.insert_str(line.as_str(), out);
+
+        let leftover = old_len.saturating_sub(self.line.len());
+        for _ in 0..leftover {
+            let _ = out.write_char(' ');
+        }
+        move_back(leftover, out);
+    }
+
+    ///Takes the finished line, leaving the editor empty
+    pub fn take(&mut self) -> Line {
+        let line = self.line;
+        self.line.clear();
+        self.cursor = 0;
+        line
+    }
+
+    //writes the line from the cursor on and `erase` spaces over what is left of the old
+    //line, then moves back to the cursor
+    fn redraw_tail(&self, erase: usize, out: &mut impl Write) {
+        let tail = &self.line.as_str()[self.cursor..];
+        let _ = out.write_str(tail);
+        for _ in 0..erase {
+            let _ = out.write_char(' ');
+        }
+        move_back(tail.len() + erase, out);
+    }
+}
+
+fn move_back(columns: usize, out: &mut impl Write) {
+    for _ in 0..columns {
+        let _ = out.write_char(BACKSPACE);
+    }
+}
+
+#[test_case]
+fn test_line_editing() {
+    let mut editor = LineEditor::new();
+    let mut out = Text::<64>::new();
+
+    editor.insert_str("ecoh", &mut out);
+    editor.left(&mut out);
+    editor.backspace(&mut out);
+    editor.end(&mut out);
+    editor.insert('o', &mut out);
+    assert_eq!(editor.line().as_str(), "echo");
+    assert!(!editor.insert('\u{e4}', &mut out));
+
+    editor.home(&mut out);
+    editor.delete(&mut out);
+    assert_eq!(editor.line().as_str(), "cho");
+    assert_eq!(editor.cursor(), 0);
+}
+
+#[test_case]
+fn test_line_editor_output() {
+    let mut editor = LineEditor::new();
+    editor.insert_str("abc", &mut Text::<8>::new());
+    editor.left(&mut Text::<8>::new());
+
+    let mut out = Text::<16>::new();
+    editor.backspace(&mut out);
+    assert_eq!(out.as_str(), "\u{8}c \u{8}\u{8}");
+
+    let mut out = Text::<16>::new();
+    editor.replace(&Line::from_text("x"), &mut out);
+    assert_eq!(out.as_str(), "\u{8}x \u{8}");
+    assert_eq!(editor.cursor(), 1);
+}
@@ -0,0 +1,252 @@
+//! An interactive shell on the screen and the serial console
+//!
+//! Keys come from the input buffer, which the keyboard and the serial console both feed,
+//! and everything is printed with `print!`, which the serial console mirrors. The line
+//! can be edited with the arrows, Home, End, backspace and delete, the up and down arrows
+//! go through the history and tab completes command names.
+
+use core::fmt::{self, Write};
+
+use pc_keyboard::{DecodedKey, KeyCode};
+use x86_64::instructions::interrupts;
+
+use crate::io::INPUTBUFFER;
+use crate::{print, println};
+use history::History;
+use line::{LineEditor, MAX_LINE};
+
+pub mod builtins;
+pub mod commands;
+pub mod history;
+pub mod line;
+
+pub use commands::{register, Command, CommandError, RegistryError};
+
+pub const PROMPT: &str = "rost> ";
+///Arguments past this are an error
+pub const MAX_ARGS: usize = 16;
+
+//sends the editor output to the screen and the serial console
+struct Console;
+
+impl Write for Console {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        print!("{}", s);
+        Ok(())
+    }
+}
+
+pub struct Shell {
+    editor: LineEditor,
+    history: History,
+}
+
+impl Shell {
+    pub const fn new() -> Shell {
+        Shell {
+            editor: LineEditor::new(),
+            history: History::new(),
+        }
+    }
+
+    pub fn prompt(&self) {
+        print!("{}", PROMPT);
+    }
+
+    ///Handles one key, enter runs the line
+    pub fn handle_key(&mut self, key: DecodedKey) {
+        let out = &mut Console;
+
+        match key {
+            DecodedKey::Unicode('\n') => self.enter(),
+            DecodedKey::Unicode('\u{8}') => self.editor.backspace(out),
+            DecodedKey::Unicode('\u{7f}') | DecodedKey::RawKey(KeyCode::Delete) => {
+                self.editor.delete(out)
+            }
+            DecodedKey::Unicode('\t') => self.complete(),
+            DecodedKey::Unicode(character) => {
+                self.editor.insert(character, out);
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => self.editor.left(out),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => self.editor.right(out),
+            DecodedKey::RawKey(KeyCode::Home) => self.editor.home(out),
+            DecodedKey::RawKey(KeyCode::End) => self.editor.end(out),
+            DecodedKey::RawKey(KeyCode::ArrowUp) => {
+                if let Some(line) = self.history.older(self.editor.line()) {
+                    self.editor.replace(&line, out);
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowDown) => {
+                if let Some(line) = self.history.newer() {
+                    self.editor.replace(&line, out);
+                }
+            }
+            DecodedKey::RawKey(_) => (),
+        }
+    }
+
+    fn enter(&mut self) {
+        let line = self.editor.take();
+        println!();
+        self.history.push(&line);
+        execute(line.as_str());
+        self.prompt();
+    }
+
+    //completes the command name under the cursor as far as all matching names agree,
+    //lists the names if that adds nothing
+    fn complete(&mut self) {
+        let line = *self.editor.line();
+        let typed = &line.as_str()[..self.editor.cursor()];
+        if typed.contains(' ') {
+            return;
+        }
+
+        let (count, common) = commands::with_registry(|registry| {
+            let mut count = 0;
+            let mut common: Option<&'static str> = None;
+            for name in registry.completions(typed) {
+                count += 1;
+                common = Some(match common {
+                    None => name,
+                    Some(common) => &common[..common_prefix(common, name)],
+                });
+            }
+            (count, common.unwrap_or(""))
+        });
+
+        match count {
+            0 => (),
+            1 => {
+                self.editor.insert_str(&common[typed.len()..], &mut Console);
+                self.editor.insert(' ', &mut Console);
+            }
+            _ if common.len() > typed.len() => {
+                self.editor.insert_str(&common[typed.len()..], &mut Console);
+            }
+            _ => {
+                println!();
+                commands::with_registry(|registry| {
+                    for name in registry.completions(typed) {
+                        print!("{}  ", name);
+                    }
+                });
+                println!();
+                self.prompt();
+                print!("{}", line.as_str());
+                for _ in self.editor.cursor()..line.len() {
+                    print!("\u{8}");
+                }
+            }
+        }
+    }
+}
+
+impl Default for Shell {
+    fn default() -> Self {
+        Shell::new()
+    }
+}
+
+fn common_prefix(a: &str, b: &str) -> usize {
+    a.bytes().zip(b.bytes()).take_while(|(a, b)| a == b).count()
+}
+
+///Splits a line at whitespace, `None` if there are more than `MAX_ARGS` words
+pub fn split_args(line: &str) -> Option<([&str; MAX_ARGS], usize)> {
+    let mut args = [""; MAX_ARGS];
+    let mut count = 0;
+    for word in line.split_whitespace() {
+        *args.get_mut(count)? = word;
+        count += 1;
+    }
+    Some((args, count))
+}
+
+///Runs a command line and prints what went wrong if anything did
+pub fn execute(line: &str) {
+    let Some((args, count)) = split_args(line) else {
+        println!("too many arguments, at most {} are taken", MAX_ARGS - 1);
+        return;
+    };
+    let Some((name, args)) = args[..count].split_first() else {
+        return;
+    };
+    let Some(command) = commands::find(name) else {
+        println!("{}: command not found, try help", name);
+        return;
+    };
+
+    match (command.run)(args) {
+        Ok(()) => (),
+        Err(CommandError::Usage) => println!("usage: {} {}", command.name, command.usage),
+        Err(CommandError::Failed(reason)) => println!("{}: {}", command.name, reason),
+    }
+}
+
+//takes the keys typed since the last call, oldest first
+fn read_keys(mut handle: impl FnMut(DecodedKey)) {
+    let input = interrupts::without_interrupts(|| {
+        let mut input = INPUTBUFFER.write();
+        let copy = *input;
+        input.unread_char_count = 0;
+        copy
+    });
+
+    let unread = input.unread_char_count.min(input.buffer.len());
+    //the newest key is at the front of the buffer
+    for key in input.buffer[..unread].iter().rev() {
+        handle(*key);
+    }
+}
+
+///Runs the shell forever, interrupts have to be enabled
+pub fn run() -> ! {
+    let mut shell = Shell::new();
+    println!(
+        "type help for a list of commands, lines can be up to {} characters",
+        MAX_LINE
+    );
+    shell.prompt();
+
+    loop {
+        read_keys(|key| shell.handle_key(key));
+        interrupts::without_interrupts(|| crate::vga_driver::WRITER.lock().show_cursor_at_writer());
+
+        interrupts::disable();
+        if INPUTBUFFER.read().unread_char_count == 0 {
+            // enabling and halting together means a key can not slip in between
+            interrupts::enable_and_hlt();
+        } else {
+            interrupts::enable();
+        }
+    }
+}
+
+#[test_case]
+fn test_split_args() {
+    let (args, count) = split_args("  echo  hello world ").unwrap();
+    assert_eq!(&args[..count], ["echo", "hello", "world"]);
+    assert!(split_args("a b c d e f g h i j k l m n o p q").is_none());
+}
+
+#[test_case]
+fn test_execute_registered_command() {
+    use core::sync::atomic::{AtomicUsize, Ordering};
+
+    static ARGS: AtomicUsize = AtomicUsize::new(0);
+    fn count_args(args: &[&str]) -> Result<(), CommandError> {
+        ARGS.store(args.len(), Ordering::SeqCst);
+        Ok(())
+    }
+
+    register(Command {
+        name: "test-count-args",
+        usage: "[args...]",
+        help: "counts its arguments",
+        run: count_args,
+    })
+    .unwrap();
+    execute("test-count-args one two");
+    assert_eq!(ARGS.load(Ordering::SeqCst), 2);
+}
@@ -0,0 +1,93 @@
+//! The options of the test runner, taken from the kernel command line
+//!
+//! Like with libtest, words are filters and only the tests whose names contain one of
+//! them run. `--exact` makes a name have to be the same as a filter, `--list` prints the
+//! names of the tests without running them and `--bench` times the benchmarks. For example
+//! `cargo test -- -fw_cfg name=opt/rost/cmdline,string=keyboard` runs the keyboard tests.
+
+use core::fmt;
+
+pub const MAX_FILTERS: usize = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArgsError<'a> {
+    UnknownOption(&'a str),
+    TooManyFilters,
+}
+
+impl fmt::Display for ArgsError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ArgsError::UnknownOption(option) => write!(f, "unknown option {}", option),
+            ArgsError::TooManyFilters => write!(f, "at most {} filters are taken", MAX_FILTERS),
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct TestArgs<'a> {
+    filters: [&'a str; MAX_FILTERS],
+    filter_count: usize,
+    pub exact: bool,
+    pub list: bool,
+    ///Times the benchmarks and skips the tests
+    pub bench: bool,
+}
+
+impl<'a> TestArgs<'a> {
+    pub fn parse(args: impl Iterator<Item = &'a str>) -> Result<TestArgs<'a>, ArgsError<'a>> {
+        let mut test_args = TestArgs::default();
+        for arg in args {
+            match arg {
+                "--exact" => test_args.exact = true,
+                "--list" => test_args.list = true,
+                "--bench" => test_args.bench = true,
+                option if option.starts_with("--") => return Err(ArgsError::UnknownOption(option)),
+                filter => {
+                    *test_args
+                        .filters
+                        .get_mut(test_args.filter_count)
+                        .ok_or(ArgsError::TooManyFilters)? = filter;
+                    test_args.filter_count += 1;
+                }
+            }
+        }
+        Ok(test_args)
+    }
+
+    pub fn filters(&self) -> &[&'a str] {
+        &self.filters[..self.filter_count]
+    }
+
+    ///True if the test runs, all of them do without filters
+    pub fn matches(&self, name: &str) -> bool {
+        let filters = self.filters();
+        filters.is_empty()
+            || filters.iter().any(|filter| {
+                if self.exact {
+                    name == *filter
+                } else {
+                    name.contains(filter)
+                }
+            })
+    }
+}
+
+#[test_case]
+fn test_test_args() {
+    let args = TestArgs::parse("keyboard --list mouse".split_whitespace()).unwrap();
+    assert!(args.list);
+    assert_eq!(args.filters(), ["keyboard", "mouse"]);
+    assert!(args.matches("rost::keyboard::test_key_events"));
+    assert!(!args.matches("rost::pit::test_divisor_for"));
+
+    let args = TestArgs::parse("--exact --bench rost::pit::test".split_whitespace()).unwrap();
+    assert!(args.bench);
+    assert!(!args.matches("rost::pit::test_divisor_for"));
+    assert!(TestArgs::default().matches("anything"));
+
+    assert_eq!(
+        TestArgs::parse(["--nocapture"].into_iter()),
+        Err(ArgsError::UnknownOption("--nocapture"))
+    );
+}
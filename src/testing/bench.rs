@@ -0,0 +1,313 @@
+//! Benchmarks timed with the time stamp counter
+//!
+//! A benchmark is a function taking a `Bencher`, described with `bench_descriptor!`:
+//!
+//! ```ignore
+//! #[test_case]
+//! const BENCH_NEW_LINE: BenchDescriptor = bench_descriptor!(bench_new_line);
+//! ```
+//!
+//! The code being measured goes in a closure given to `Bencher::iter`. Like with libtest a
+//! benchmark runs the closure once as a test, `--bench` on the command line times the
+//! benchmarks instead and reports the tests as ignored. Each benchmark is timed over
+//! `SAMPLES` samples of about a millisecond with interrupts disabled, the report has the
+//! median and the spread like libtest and the mean and percentiles on a line of its own.
+
+use core::cell::Cell;
+use core::fmt;
+use core::hint::black_box;
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::{Outcome, TestOptions};
+use crate::{tsc, Testable};
+
+///How many times the iterations of a benchmark are timed
+pub const SAMPLES: usize = 100;
+///How long a sample takes at least, unless that needs more than `MAX_ITERATIONS`
+const SAMPLE_NANOS: u64 = 1_000_000;
+const MAX_ITERATIONS: u64 = 1_000_000;
+
+///Runs the code being measured, given to every benchmark
+pub struct Bencher {
+    measure: bool,
+    statistics: Option<Statistics>,
+}
+
+impl Bencher {
+    //runs the closure once, for benchmarks run as tests
+    fn once() -> Bencher {
+        Bencher {
+            measure: false,
+            statistics: None,
+        }
+    }
+
+    fn measuring() -> Bencher {
+        Bencher {
+            measure: true,
+            statistics: None,
+        }
+    }
+
+    ///Times `routine`, what it returns is kept from being optimized away
+    pub fn iter<T>(&mut self, mut routine: impl FnMut() -> T) {
+        if self.measure {
+            self.statistics = Some(measure(&mut routine));
+        } else {
+            black_box(routine());
+        }
+    }
+
+    ///What the last call to `iter` measured
+    pub fn statistics(&self) -> Option<Statistics> {
+        self.statistics
+    }
+}
+
+//the cycles `iterations` calls to `routine` took
+fn sample<T>(routine: &mut impl FnMut() -> T, iterations: u64) -> u64 {
+    without_interrupts(|| {
+        let start = tsc::read();
+        for _ in 0..iterations {
+            black_box(routine());
+        }
+        tsc::read() - start
+    })
+}
+
+fn measure<T>(routine: &mut impl FnMut() -> T) -> Statistics {
+    let frequency = tsc::frequency();
+    let sample_cycles = SAMPLE_NANOS * frequency / 1_000_000_000;
+
+    //doubling the iterations until they take a tenth of a sample also warms up the caches
+    let mut iterations = 1;
+    let mut cycles = sample(routine, iterations);
+    while cycles < sample_cycles / 10 && iterations < MAX_ITERATIONS {
+        iterations *= 2;
+        cycles = sample(routine, iterations);
+    }
+    let iterations = (iterations * sample_cycles / cycles.max(1)).clamp(1, MAX_ITERATIONS);
+
+    let mut samples = [0; SAMPLES];
+    for cycles in samples.iter_mut() {
+        *cycles = sample(routine, iterations) / iterations;
+    }
+    Statistics::from_samples(&mut samples, iterations, frequency)
+}
+
+///Cycles per iteration of a benchmark
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Statistics {
+    pub mean: u64,
+    pub median: u64,
+    pub p90: u64,
+    pub p99: u64,
+    pub min: u64,
+    pub max: u64,
+    ///The spread of the middle 90% of the samples, libtest prints it after the median
+    pub deviation: u64,
+    ///Iterations in every sample
+    pub iterations: u64,
+    ///Of the time stamp counter, in hertz
+    pub frequency: u64,
+}
+
+impl Statistics {
+    ///`samples` are the cycles per iteration of each sample, they end up sorted
+    pub fn from_samples(samples: &mut [u64], iterations: u64, frequency: u64) -> Statistics {
+        assert!(!samples.is_empty(), "no samples");
+        samples.sort_unstable();
+        let percentile = |percent: usize| samples[(samples.len() - 1) * percent / 100];
+        let sum: u128 = samples.iter().map(|cycles| *cycles as u128).sum();
+
+        Statistics {
+            mean: (sum / samples.len() as u128) as u64,
+            median: percentile(50),
+            p90: percentile(90),
+            p99: percentile(99),
+            min: samples[0],
+            max: samples[samples.len() - 1],
+            deviation: percentile(95) - percentile(5),
+            iterations,
+            frequency,
+        }
+    }
+
+    pub fn nanos(&self, cycles: u64) -> u64 {
+        tsc::cycles_to_nanos(cycles, self.frequency)
+    }
+}
+
+///A number with commas between the thousands, like libtest prints the times
+pub struct Thousands(pub u64);
+
+impl fmt::Display for Thousands {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        //formatted into a buffer first so that the whole number can be padded
+        let mut digits = [0; 20];
+        let mut len = 0;
+        let mut rest = self.0;
+        loop {
+            digits[len] = b'0' + (rest % 10) as u8;
+            len += 1;
+            rest /= 10;
+            if rest == 0 {
+                break;
+            }
+        }
+
+        let mut text = [0; 26];
+        let mut text_len = 0;
+        for (i, digit) in digits[..len].iter().rev().enumerate() {
+            if i > 0 && (len - i).is_multiple_of(3) {
+                text[text_len] = b',';
+                text_len += 1;
+            }
+            text[text_len] = *digit;
+            text_len += 1;
+        }
+        f.pad(core::str::from_utf8(&text[..text_len]).unwrap_or(""))
+    }
+}
+
+///A benchmark function, made with `bench_descriptor!`
+#[derive(Debug, Clone, Copy)]
+pub struct BenchDescriptor {
+    pub name: &'static str,
+    pub run: fn(&mut Bencher),
+}
+
+impl BenchDescriptor {
+    pub const fn new(name: &'static str, run: fn(&mut Bencher)) -> BenchDescriptor {
+        BenchDescriptor { name, run }
+    }
+}
+
+impl Testable for BenchDescriptor {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self) {
+        (self.run)(&mut Bencher::once())
+    }
+
+    fn bench(&self) -> Option<fn(&mut Bencher)> {
+        Some(self.run)
+    }
+}
+
+///Describes a benchmark function, named like test functions are
+#[macro_export]
+macro_rules! bench_descriptor {
+    ($function:ident) => {
+        $crate::testing::bench::BenchDescriptor::new(
+            concat!(module_path!(), "::", stringify!($function)),
+            $function,
+        )
+    };
+}
+
+//a benchmark being timed, run by `run_test` so that a panic in it ends only the benchmark
+struct Measurement {
+    name: &'static str,
+    bench: fn(&mut Bencher),
+    options: TestOptions,
+    statistics: Cell<Option<Statistics>>,
+}
+
+impl Testable for Measurement {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self) {
+        let mut bencher = Bencher::measuring();
+        (self.bench)(&mut bencher);
+        self.statistics.set(bencher.statistics());
+    }
+
+    fn options(&self) -> TestOptions {
+        self.options
+    }
+}
+
+///Times a benchmark, tests are run like `run_test` does. A benchmark that never calls
+///`Bencher::iter` passes without being measured.
+pub fn run_bench(test: &dyn Testable) -> Outcome {
+    let Some(bench) = test.bench() else {
+        return super::run_test(test);
+    };
+    let measurement = Measurement {
+        name: test.name(),
+        bench,
+        options: test.options(),
+        statistics: Cell::new(None),
+    };
+    match super::run_test(&measurement) {
+        Outcome::Passed => measurement
+            .statistics
+            .get()
+            .map_or(Outcome::Passed, Outcome::Measured),
+        outcome => outcome,
+    }
+}
+
+#[cfg(test)]
+fn bench_black_box(bencher: &mut Bencher) {
+    let mut count = 0u64;
+    bencher.iter(|| {
+        count += 1;
+        count
+    });
+}
+
+#[test_case]
+const BENCH_BLACK_BOX: BenchDescriptor = bench_descriptor!(bench_black_box);
+
+#[test_case]
+fn test_statistics() {
+    let mut samples = [0; SAMPLES];
+    for (i, cycles) in samples.iter_mut().enumerate() {
+        //backwards to check that they get sorted
+        *cycles = (SAMPLES - i) as u64 * 10;
+    }
+    let statistics = Statistics::from_samples(&mut samples, 4, 2_000_000_000);
+    assert_eq!(statistics.min, 10);
+    assert_eq!(statistics.max, 1000);
+    assert_eq!(statistics.median, 500);
+    assert_eq!(statistics.p90, 900);
+    assert_eq!(statistics.p99, 990);
+    assert_eq!(statistics.mean, 505);
+    assert_eq!(statistics.deviation, 950 - 50);
+    assert_eq!(statistics.nanos(statistics.median), 250);
+}
+
+#[test_case]
+fn test_thousands() {
+    use crate::shell::line::Text;
+    use core::fmt::Write;
+
+    let mut text = Text::<64>::new();
+    write!(
+        text,
+        "{}|{}|{}|{:>8}",
+        Thousands(0),
+        Thousands(999),
+        Thousands(1_234_567),
+        Thousands(1000)
+    )
+    .unwrap();
+    assert_eq!(text.as_str(), "0|999|1,234,567|   1,000");
+}
+
+#[test_case]
+fn test_run_bench() {
+    assert!(matches!(
+        run_bench(&bench_descriptor!(bench_black_box)),
+        Outcome::Measured(statistics) if statistics.iterations > 1
+    ));
+    fn not_a_bench() {}
+    assert_eq!(run_bench(&not_a_bench), Outcome::Passed);
+}
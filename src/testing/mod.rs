@@ -0,0 +1,375 @@
+//! Test options and running tests so that a panic ends only the test it happened in
+//!
+//! A plain `#[test_case]` function passes when it returns. For anything else the test is
+//! described with a `TestDescriptor`, which `#[test_case]` takes just as well:
+//!
+//! ```ignore
+//! #[test_case]
+//! const TEST_OVERFLOW: TestDescriptor = test_descriptor!(overflows).should_panic();
+//! ```
+//!
+//! The runner saves its stack pointer before calling a test and the panic handler jumps
+//! back to it. Nothing the test had on its stack is dropped, so a lock it held stays
+//! locked. How the results are printed is in `report`, which tests run is in `args`,
+//! timing benchmarks is in `bench`.
+
+use core::arch::global_asm;
+use core::fmt::{self, Write};
+use core::panic::PanicInfo;
+use core::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use core::time::Duration;
+
+use spin::Mutex;
+use x86_64::instructions::interrupts;
+
+use crate::Testable;
+
+pub mod args;
+pub mod bench;
+pub mod report;
+
+///How much of a panic message is kept for matching and printing
+const MESSAGE_LENGTH: usize = 512;
+
+//the stack pointer saved by `rost_run_test`, zero when no test runs
+static RETURN_STACK: AtomicU64 = AtomicU64::new(0);
+//the uptime in nanoseconds when the running test times out, zero when it can't
+static DEADLINE: AtomicU64 = AtomicU64::new(0);
+//set when the panic that ended a test came from the timeout
+static TIMED_OUT: AtomicBool = AtomicBool::new(false);
+static PANIC_MESSAGE: Mutex<Message> = Mutex::new(Message::new());
+
+global_asm!(
+    ".global rost_run_test",
+    ".global rost_abandon_test",
+    //calls rsi with rdx after saving the registers the caller expects back, rdi points to
+    //where the stack pointer is kept for `rost_abandon_test`
+    "rost_run_test:",
+    "    push rbp",
+    "    push rbx",
+    "    push r12",
+    "    push r13",
+    "    push r14",
+    "    push r15",
+    "    mov [rdi], rsp",
+    "    mov rdi, rdx",
+    //the stack has to be 16 byte aligned at the call
+    "    sub rsp, 8",
+    "    call rsi",
+    "    add rsp, 8",
+    "    xor eax, eax",
+    ".Lrost_test_return:",
+    "    pop r15",
+    "    pop r14",
+    "    pop r13",
+    "    pop r12",
+    "    pop rbx",
+    "    pop rbp",
+    "    ret",
+    //returns 1 from `rost_run_test` on the stack in rdi, whatever is above it is left behind
+    "rost_abandon_test:",
+    "    mov rsp, rdi",
+    "    mov eax, 1",
+    "    jmp .Lrost_test_return",
+);
+
+extern "C" {
+    fn rost_run_test(return_stack: *mut u64, run: extern "C" fn(*const ()), data: *const ())
+        -> u64;
+    fn rost_abandon_test(return_stack: u64) -> !;
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ShouldPanic {
+    No,
+    Yes,
+    ///Passes if the panic message contains this text
+    WithMessage(&'static str),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct TestOptions {
+    pub should_panic: ShouldPanic,
+    ///Skipped and reported as ignored
+    pub ignore: bool,
+    ///Ends the test as timed out if it runs longer, checked on every timer tick
+    pub timeout: Option<Duration>,
+}
+
+impl TestOptions {
+    pub const DEFAULT: TestOptions = TestOptions {
+        should_panic: ShouldPanic::No,
+        ignore: false,
+        timeout: None,
+    };
+
+    ///How long the watchdog lets the test run, past its timeout the test should have ended
+    ///itself unless it hung with interrupts disabled
+    pub fn watchdog_budget(&self) -> Duration {
+        self.timeout
+            .map_or(crate::watchdog::DEFAULT_BUDGET, |timeout| {
+                timeout + Duration::from_secs(1)
+            })
+    }
+}
+
+impl Default for TestOptions {
+    fn default() -> Self {
+        TestOptions::DEFAULT
+    }
+}
+
+///A test function with options, made with `test_descriptor!`
+#[derive(Debug, Clone, Copy)]
+pub struct TestDescriptor {
+    pub name: &'static str,
+    pub run: fn(),
+    pub options: TestOptions,
+}
+
+impl TestDescriptor {
+    pub const fn new(name: &'static str, run: fn()) -> TestDescriptor {
+        TestDescriptor {
+            name,
+            run,
+            options: TestOptions::DEFAULT,
+        }
+    }
+
+    pub const fn should_panic(mut self) -> TestDescriptor {
+        self.options.should_panic = ShouldPanic::Yes;
+        self
+    }
+
+    pub const fn should_panic_with(mut self, message: &'static str) -> TestDescriptor {
+        self.options.should_panic = ShouldPanic::WithMessage(message);
+        self
+    }
+
+    pub const fn ignore(mut self) -> TestDescriptor {
+        self.options.ignore = true;
+        self
+    }
+
+    pub const fn timeout(mut self, timeout: Duration) -> TestDescriptor {
+        self.options.timeout = Some(timeout);
+        self
+    }
+}
+
+impl Testable for TestDescriptor {
+    fn name(&self) -> &'static str {
+        self.name
+    }
+
+    fn run(&self) {
+        (self.run)()
+    }
+
+    fn options(&self) -> TestOptions {
+        self.options
+    }
+}
+
+///Describes a test function, named like plain test functions are
+#[macro_export]
+macro_rules! test_descriptor {
+    ($function:ident) => {
+        $crate::testing::TestDescriptor::new(
+            concat!(module_path!(), "::", stringify!($function)),
+            $function,
+        )
+    };
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    Passed,
+    Ignored,
+    Failed(Failure),
+    ///Ran past its timeout, whether it should panic or not
+    TimedOut,
+    ///A benchmark that was timed
+    Measured(bench::Statistics),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Failure {
+    ///The panic message is in `with_panic_message`
+    Panicked,
+    DidNotPanic,
+    ///It panicked but the message did not contain this
+    WrongMessage(&'static str),
+}
+
+//the start of a panic message, cut at a character boundary when it is too long
+struct Message {
+    bytes: [u8; MESSAGE_LENGTH],
+    len: usize,
+}
+
+impl Message {
+    const fn new() -> Message {
+        Message {
+            bytes: [0; MESSAGE_LENGTH],
+            len: 0,
+        }
+    }
+
+    fn as_str(&self) -> &str {
+        core::str::from_utf8(&self.bytes[..self.len]).unwrap_or("")
+    }
+
+    fn clear(&mut self) {
+        self.len = 0;
+    }
+}
+
+impl Write for Message {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        for character in s.chars() {
+            let end = self.len + character.len_utf8();
+            if end > MESSAGE_LENGTH {
+                break;
+            }
+            character.encode_utf8(&mut self.bytes[self.len..end]);
+            self.len = end;
+        }
+        Ok(())
+    }
+}
+
+extern "C" fn call_test(test: *const ()) {
+    let test = unsafe { *(test as *const &dyn Testable) };
+    test.run();
+}
+
+///Runs a test, a panic or running past the timeout ends the test instead of the kernel
+pub fn run_test(test: &dyn Testable) -> Outcome {
+    let options = test.options();
+    if options.ignore {
+        return Outcome::Ignored;
+    }
+
+    interrupts::without_interrupts(|| PANIC_MESSAGE.lock().clear());
+    let interrupts_enabled = interrupts::are_enabled();
+    //a test can run tests of its own, the outer one is set back up afterwards
+    let outer_stack = RETURN_STACK.load(Ordering::SeqCst);
+    let outer_deadline = DEADLINE.load(Ordering::SeqCst);
+    let deadline = options.timeout.map_or(0, |timeout| {
+        (crate::interrupts::uptime() + timeout).as_nanos() as u64
+    });
+    DEADLINE.store(deadline, Ordering::SeqCst);
+    TIMED_OUT.store(false, Ordering::SeqCst);
+
+    let panicked = unsafe {
+        rost_run_test(
+            RETURN_STACK.as_ptr(),
+            call_test,
+            &test as *const &dyn Testable as *const (),
+        )
+    } != 0;
+
+    DEADLINE.store(outer_deadline, Ordering::SeqCst);
+    RETURN_STACK.store(outer_stack, Ordering::SeqCst);
+    //a test ended from an interrupt handler comes back with interrupts disabled
+    if interrupts_enabled {
+        interrupts::enable();
+    }
+
+    if panicked && TIMED_OUT.swap(false, Ordering::SeqCst) {
+        return Outcome::TimedOut;
+    }
+    match (options.should_panic, panicked) {
+        (ShouldPanic::No, false) => Outcome::Passed,
+        (ShouldPanic::No, true) => Outcome::Failed(Failure::Panicked),
+        (_, false) => Outcome::Failed(Failure::DidNotPanic),
+        (ShouldPanic::Yes, true) => Outcome::Passed,
+        (ShouldPanic::WithMessage(expected), true) => {
+            if with_panic_message(|message| message.contains(expected)) {
+                Outcome::Passed
+            } else {
+                Outcome::Failed(Failure::WrongMessage(expected))
+            }
+        }
+    }
+}
+
+///Gives `f` what the last test that panicked printed about its panic
+pub fn with_panic_message<R>(f: impl FnOnce(&str) -> R) -> R {
+    interrupts::without_interrupts(|| f(PANIC_MESSAGE.lock().as_str()))
+}
+
+///True while `run_test` is running a test
+pub fn is_running() -> bool {
+    RETURN_STACK.load(Ordering::SeqCst) != 0
+}
+
+///Goes back to `run_test` if a test is running, called by the panic handler
+pub fn abandon_test(info: &PanicInfo) {
+    let return_stack = RETURN_STACK.swap(0, Ordering::SeqCst);
+    if return_stack == 0 {
+        return;
+    }
+    DEADLINE.store(0, Ordering::SeqCst);
+    //the test may have panicked while writing the message, then it stays empty
+    if let Some(mut message) = PANIC_MESSAGE.try_lock() {
+        message.clear();
+        let _ = write!(message, "{}", info);
+    }
+    unsafe { rost_abandon_test(return_stack) }
+}
+
+///Panics if the running test is past its timeout, called from the timer interrupt after
+///the end of interrupt has been sent
+pub(crate) fn check_timeout(now: Duration) {
+    let deadline = DEADLINE.load(Ordering::SeqCst);
+    if deadline != 0 && now.as_nanos() as u64 >= deadline {
+        DEADLINE.store(0, Ordering::SeqCst);
+        TIMED_OUT.store(true, Ordering::SeqCst);
+        panic!("test ran past its timeout");
+    }
+}
+
+#[cfg(test)]
+fn panics() {
+    panic!("this panic is expected");
+}
+
+#[cfg(test)]
+fn never_returns() {
+    crate::hlt_loop();
+}
+
+#[test_case]
+const TEST_SHOULD_PANIC: TestDescriptor = test_descriptor!(panics).should_panic();
+
+#[test_case]
+const TEST_SHOULD_PANIC_WITH: TestDescriptor =
+    test_descriptor!(panics).should_panic_with("is expected");
+
+#[test_case]
+const TEST_IGNORED: TestDescriptor = test_descriptor!(never_returns).ignore();
+
+#[test_case]
+fn test_outcomes() {
+    let wrong_message = test_descriptor!(panics).should_panic_with("something else");
+    assert_eq!(
+        run_test(&wrong_message),
+        Outcome::Failed(Failure::WrongMessage("something else"))
+    );
+    assert!(with_panic_message(|message| message.contains("is expected")));
+
+    fn returns() {}
+    let should_panic = test_descriptor!(returns).should_panic();
+    assert_eq!(
+        run_test(&should_panic),
+        Outcome::Failed(Failure::DidNotPanic)
+    );
+
+    //a test that should panic still times out rather than passing
+    let hangs = test_descriptor!(never_returns)
+        .timeout(Duration::from_millis(50))
+        .should_panic();
+    assert_eq!(run_test(&hangs), Outcome::TimedOut);
+}
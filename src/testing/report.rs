@@ -0,0 +1,404 @@
+//! Printing test results like libtest does
+//!
+//! The text goes to COM1 in the format of `cargo test`, so tools reading that output
+//! work on the kernel tests too. When there is a COM3 the same results go to it as
+//! libtest JSON events, one per line, for example with QEMU started with
+//! `-serial stdio -serial null -serial file:results.json`. COM2 is left to the gdb stub.
+//!
+//! A timed benchmark gets the libtest line with the median and the spread in nanoseconds,
+//! followed by a line with the cycles the same way for every benchmark so that the
+//! output of two commits can be compared with diff.
+
+use core::fmt::{self, Write};
+use core::time::Duration;
+
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::bench::{Statistics, Thousands};
+use super::{Failure, Outcome};
+use crate::resource::PortRange;
+use crate::serial::uart::{ComPort, LineConfig, Uart};
+use crate::{serial_print, serial_println};
+
+///How many failed tests are named again in the summary
+const MAX_LISTED_FAILURES: usize = 32;
+
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Summary {
+    pub passed: usize,
+    ///Failed by panicking or by not panicking, timeouts are counted separately
+    pub failed: usize,
+    pub ignored: usize,
+    pub timed_out: usize,
+    ///Benchmarks that were timed
+    pub measured: usize,
+    ///Left out by the filters on the command line
+    pub filtered_out: usize,
+}
+
+impl Summary {
+    pub fn record(&mut self, outcome: Outcome) {
+        match outcome {
+            Outcome::Passed => self.passed += 1,
+            Outcome::Ignored => self.ignored += 1,
+            Outcome::Failed(_) => self.failed += 1,
+            Outcome::TimedOut => self.timed_out += 1,
+            Outcome::Measured(_) => self.measured += 1,
+        }
+    }
+
+    pub fn is_success(&self) -> bool {
+        self.failed == 0 && self.timed_out == 0
+    }
+
+    ///The last line of the report, libtest counts a timeout as a failure
+    pub fn write_result(&self, out: &mut impl Write, elapsed: Duration) -> fmt::Result {
+        write!(
+            out,
+            "test result: {}. {} passed; {} failed; {} ignored; {} measured; {} filtered out; \
+             finished in {}s",
+            if self.is_success() { "ok" } else { "FAILED" },
+            self.passed,
+            self.failed + self.timed_out,
+            self.ignored,
+            self.measured,
+            self.filtered_out,
+            Seconds(elapsed)
+        )
+    }
+
+    ///The suite event that ends the JSON output
+    pub fn write_json(&self, out: &mut impl Write, elapsed: Duration) -> fmt::Result {
+        write!(
+            out,
+            r#"{{ "type": "suite", "event": "{}", "passed": {}, "failed": {}, "ignored": {}, "#,
+            if self.is_success() { "ok" } else { "failed" },
+            self.passed,
+            self.failed + self.timed_out,
+            self.ignored
+        )?;
+        write!(
+            out,
+            r#""measured": {}, "filtered_out": {}, "timed_out": {}, "exec_time": {} }}"#,
+            self.measured,
+            self.filtered_out,
+            self.timed_out,
+            Seconds(elapsed)
+        )
+    }
+}
+
+//seconds with three decimals like libtest prints them
+struct Seconds(Duration);
+
+impl fmt::Display for Seconds {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}.{:03}", self.0.as_secs(), self.0.subsec_millis())
+    }
+}
+
+///A string escaped to go between the quotes of a JSON string
+pub struct JsonStr<'a>(pub &'a str);
+
+impl fmt::Display for JsonStr<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for character in self.0.chars() {
+            match character {
+                '"' => f.write_str("\\\"")?,
+                '\\' => f.write_str("\\\\")?,
+                '\n' => f.write_str("\\n")?,
+                '\t' => f.write_str("\\t")?,
+                character if character.is_control() => write!(f, "\\u{:04x}", character as u32)?,
+                character => f.write_char(character)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+///Writes the results of one run of the tests as they come in
+pub struct Report {
+    json: Option<Uart<PortRange>>,
+    summary: Summary,
+    failures: [&'static str; MAX_LISTED_FAILURES],
+    failure_count: usize,
+    suite_started: Duration,
+    test_started: Duration,
+}
+
+impl Report {
+    ///Prints the header, the JSON goes to COM3 if it is there
+    pub fn start(test_count: usize, filtered_out: usize) -> Report {
+        let json = Uart::open(ComPort::Com3, LineConfig::DEFAULT).ok();
+        let now = crate::interrupts::uptime();
+        let mut report = Report {
+            json,
+            summary: Summary {
+                filtered_out,
+                ..Summary::default()
+            },
+            failures: [""; MAX_LISTED_FAILURES],
+            failure_count: 0,
+            suite_started: now,
+            test_started: now,
+        };
+
+        serial_println!();
+        serial_println!(
+            "running {} test{}",
+            test_count,
+            if test_count == 1 { "" } else { "s" }
+        );
+        report.json_line(format_args!(
+            r#"{{ "type": "suite", "event": "started", "test_count": {} }}"#,
+            test_count
+        ));
+        report
+    }
+
+    pub fn test_started(&mut self, name: &str) {
+        self.test_started = crate::interrupts::uptime();
+        serial_print!("test {} ... ", name);
+        self.json_line(format_args!(
+            r#"{{ "type": "test", "event": "started", "name": "{}" }}"#,
+            JsonStr(name)
+        ));
+    }
+
+    ///Prints how a test went, `message` is the panic message of a failed test
+    pub fn test_finished(&mut self, name: &'static str, outcome: Outcome, message: &str) {
+        let elapsed = crate::interrupts::uptime() - self.test_started;
+        self.summary.record(outcome);
+
+        let event = match outcome {
+            Outcome::Passed => {
+                serial_println!("ok");
+                "ok"
+            }
+            Outcome::Ignored => {
+                serial_println!("ignored");
+                "ignored"
+            }
+            Outcome::Failed(_) => {
+                serial_println!("FAILED");
+                "failed"
+            }
+            Outcome::TimedOut => {
+                serial_println!("FAILED (time limit exceeded)");
+                "failed"
+            }
+            //libtest has a bench event instead of a test event for them
+            Outcome::Measured(statistics) => {
+                serial_println!("{}", BenchLine(statistics));
+                serial_println!("{}", CycleLine(statistics));
+                self.json_line(format_args!(
+                    r#"{{ "type": "bench", "name": "{}", {} }}"#,
+                    JsonStr(name),
+                    BenchJson(statistics)
+                ));
+                return;
+            }
+        };
+        let failed = event == "failed";
+
+        let reason = Reason(outcome, message);
+        if failed {
+            serial_println!("---- {} stdout ----", name);
+            serial_println!("{}", reason);
+            serial_println!();
+            if let Some(slot) = self.failures.get_mut(self.failure_count) {
+                *slot = name;
+            }
+            self.failure_count += 1;
+        }
+
+        self.json_line(format_args!(
+            r#"{{ "type": "test", "name": "{}", "event": "{}", "exec_time": {}, "stdout": "{}" }}"#,
+            JsonStr(name),
+            event,
+            Seconds(elapsed),
+            JsonEscaped(reason)
+        ));
+    }
+
+    ///Prints the summary, true if no test failed
+    pub fn finish(mut self) -> bool {
+        let elapsed = crate::interrupts::uptime() - self.suite_started;
+
+        if self.failure_count > 0 {
+            serial_println!();
+            serial_println!("failures:");
+            for name in &self.failures[..self.failure_count.min(MAX_LISTED_FAILURES)] {
+                serial_println!("    {}", name);
+            }
+            if self.failure_count > MAX_LISTED_FAILURES {
+                serial_println!("    and {} more", self.failure_count - MAX_LISTED_FAILURES);
+            }
+        }
+        serial_println!();
+        without_interrupts(|| {
+            let mut serial = crate::serial::SERIAL1.lock();
+            let _ = self.summary.write_result(&mut *serial, elapsed);
+            let _ = serial.write_str("\n\n");
+        });
+
+        let summary = self.summary;
+        if let Some(json) = self.json.as_mut() {
+            let _ = summary.write_json(json, elapsed);
+            let _ = json.write_char('\n');
+        }
+        summary.is_success()
+    }
+
+    pub fn summary(&self) -> Summary {
+        self.summary
+    }
+
+    fn json_line(&mut self, line: fmt::Arguments) {
+        if let Some(json) = self.json.as_mut() {
+            let _ = json.write_fmt(line);
+            let _ = json.write_char('\n');
+        }
+    }
+}
+
+//why a test failed, empty for tests that did not
+struct Reason<'a>(Outcome, &'a str);
+
+impl fmt::Display for Reason<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.0 {
+            Outcome::Failed(Failure::Panicked) => f.write_str(self.1),
+            Outcome::Failed(Failure::DidNotPanic) => {
+                f.write_str("note: test did not panic as expected")
+            }
+            Outcome::Failed(Failure::WrongMessage(expected)) => write!(
+                f,
+                "{}\nnote: panic did not contain expected string\n      expected substring: {:?}",
+                self.1, expected
+            ),
+            Outcome::TimedOut => f.write_str("note: test ran past its timeout"),
+            Outcome::Passed | Outcome::Ignored | Outcome::Measured(_) => Ok(()),
+        }
+    }
+}
+
+//what libtest prints for a benchmark
+struct BenchLine(Statistics);
+
+impl fmt::Display for BenchLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let statistics = self.0;
+        write!(
+            f,
+            "bench: {:>11} ns/iter (+/- {})",
+            Thousands(statistics.nanos(statistics.median)),
+            Thousands(statistics.nanos(statistics.deviation))
+        )
+    }
+}
+
+//the cycles per iteration, which do not change with the calibration
+struct CycleLine(Statistics);
+
+impl fmt::Display for CycleLine {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let statistics = self.0;
+        write!(
+            f,
+            "    cycles/iter: median {} mean {} p90 {} p99 {} min {} max {} ({} x {} iters)",
+            Thousands(statistics.median),
+            Thousands(statistics.mean),
+            Thousands(statistics.p90),
+            Thousands(statistics.p99),
+            Thousands(statistics.min),
+            Thousands(statistics.max),
+            super::bench::SAMPLES,
+            Thousands(statistics.iterations)
+        )
+    }
+}
+
+//the fields of a libtest bench event, with the percentiles and the cycles added
+struct BenchJson(Statistics);
+
+impl fmt::Display for BenchJson {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let statistics = self.0;
+        let nanos = |cycles| statistics.nanos(cycles);
+        write!(
+            f,
+            r#""median": {}, "deviation": {}, "mean": {}, "p90": {}, "p99": {}, "min": {}, "max": {}, "#,
+            nanos(statistics.median),
+            nanos(statistics.deviation),
+            nanos(statistics.mean),
+            nanos(statistics.p90),
+            nanos(statistics.p99),
+            nanos(statistics.min),
+            nanos(statistics.max)
+        )?;
+        write!(
+            f,
+            r#""cycles": {{ "median": {}, "deviation": {}, "mean": {}, "p90": {}, "p99": {}, "min": {}, "max": {} }}, "iterations": {}, "samples": {}, "tsc_frequency": {}"#,
+            statistics.median,
+            statistics.deviation,
+            statistics.mean,
+            statistics.p90,
+            statistics.p99,
+            statistics.min,
+            statistics.max,
+            statistics.iterations,
+            super::bench::SAMPLES,
+            statistics.frequency
+        )
+    }
+}
+
+//escapes everything a `Display` writes
+struct JsonEscaped<T>(T);
+
+impl<T: fmt::Display> fmt::Display for JsonEscaped<T> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        struct Escaper<'a, 'b>(&'a mut fmt::Formatter<'b>);
+
+        impl Write for Escaper<'_, '_> {
+            fn write_str(&mut self, s: &str) -> fmt::Result {
+                write!(self.0, "{}", JsonStr(s))
+            }
+        }
+
+        write!(Escaper(f), "{}", self.0)
+    }
+}
+
+#[test_case]
+fn test_summary() {
+    let mut summary = Summary {
+        filtered_out: 3,
+        ..Summary::default()
+    };
+    summary.record(Outcome::Passed);
+    summary.record(Outcome::Passed);
+    summary.record(Outcome::Ignored);
+    summary.record(Outcome::Measured(Statistics::from_samples(&mut [5], 1, 1)));
+    assert!(summary.is_success());
+    summary.record(Outcome::TimedOut);
+    assert!(!summary.is_success());
+
+    let mut line = super::Message::new();
+    summary
+        .write_result(&mut line, Duration::from_millis(1250))
+        .unwrap();
+    assert_eq!(
+        line.as_str(),
+        "test result: FAILED. 2 passed; 1 failed; 1 ignored; 1 measured; 3 filtered out; \
+         finished in 1.250s"
+    );
+}
+
+#[test_case]
+fn test_json_escaping() {
+    let mut line = super::Message::new();
+    write!(line, "{}", JsonStr("a \"quoted\"\\path\n\u{1}")).unwrap();
+    assert_eq!(line.as_str(), r#"a \"quoted\"\\path\n\u0001"#);
+}
@@ -0,0 +1,82 @@
+//! The time stamp counter, a cycle counter of the cpu read with `rdtsc`
+//!
+//! How fast it counts is not reported anywhere dependable, so it is measured against the
+//! PIT the first time `frequency` is called. QEMU keeps the counter going at a steady
+//! rate, on old hardware it may slow down with the cpu.
+
+use core::arch::asm;
+use core::sync::atomic::{AtomicU64, Ordering};
+use core::time::Duration;
+
+use crate::pit;
+
+///How many PIT ticks the calibration spins for, about 50ms
+const CALIBRATION_TICKS: u16 = 59659;
+
+//zero until calibrated
+static FREQUENCY: AtomicU64 = AtomicU64::new(0);
+
+///The current count, instructions before it have finished when it is read
+pub fn read() -> u64 {
+    let low: u32;
+    let high: u32;
+    unsafe {
+        asm!(
+            "lfence",
+            "rdtsc",
+            out("eax") low,
+            out("edx") high,
+            options(nomem, nostack, preserves_flags)
+        );
+    }
+    u64::from(high) << 32 | u64::from(low)
+}
+
+///Measures how many cycles the counter goes through in a second, takes about 50ms
+pub fn calibrate() -> u64 {
+    let start = read();
+    pit::busy_wait(CALIBRATION_TICKS);
+    let cycles = read() - start;
+
+    let frequency = cycles as u128 * pit::FREQUENCY as u128 / CALIBRATION_TICKS as u128;
+    frequency as u64
+}
+
+///Cycles per second, calibrated on the first call
+pub fn frequency() -> u64 {
+    match FREQUENCY.load(Ordering::Relaxed) {
+        0 => {
+            let frequency = calibrate().max(1);
+            FREQUENCY.store(frequency, Ordering::Relaxed);
+            frequency
+        }
+        frequency => frequency,
+    }
+}
+
+pub fn cycles_to_nanos(cycles: u64, frequency: u64) -> u64 {
+    (cycles as u128 * 1_000_000_000 / frequency as u128) as u64
+}
+
+pub fn cycles_to_duration(cycles: u64) -> Duration {
+    Duration::from_nanos(cycles_to_nanos(cycles, frequency()))
+}
+
+#[test_case]
+fn test_calibrate() {
+    let frequency = frequency();
+    //anything slower than 100MHz is not a cpu QEMU emulates
+    assert!(frequency > 100_000_000, "{} Hz", frequency);
+
+    let start = read();
+    crate::interrupts::sleep(Duration::from_millis(20));
+    let slept = cycles_to_duration(read() - start);
+    assert!(slept >= Duration::from_millis(15), "{:?}", slept);
+    assert!(slept < Duration::from_millis(200), "{:?}", slept);
+}
+
+#[test_case]
+fn test_cycles_to_nanos() {
+    assert_eq!(cycles_to_nanos(3_000, 3_000_000_000), 1_000);
+    assert_eq!(cycles_to_nanos(1, 1_000_000_000), 1);
+}
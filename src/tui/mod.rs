@@ -0,0 +1,531 @@
+//! A small text mode window system drawn on top of the vga text buffer
+//!
+//! Windows are drawn back to front so the window at the top of the z-order
+//! always ends up visible, and everything a window draws is clipped to the
+//! window and the screen. Without a heap everything lives in fixed size arrays.
+
+pub mod widgets;
+
+use pc_keyboard::DecodedKey;
+
+use crate::vga_driver::code_page_737_definitions::Symbols;
+use crate::vga_driver::{Color, CursorPosition, Point, Writer};
+use widgets::Widget;
+
+pub const MAX_WINDOWS: usize = 8;
+pub const MAX_WIDGETS: usize = 8;
+
+///A rectangle on the screen in columns (x) and rows (y)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl Rect {
+    pub const fn new(x: usize, y: usize, width: usize, height: usize) -> Rect {
+        Rect {
+            x,
+            y,
+            width,
+            height,
+        }
+    }
+
+    pub fn right(&self) -> usize {
+        self.x + self.width
+    }
+
+    pub fn bottom(&self) -> usize {
+        self.y + self.height
+    }
+
+    pub fn contains(&self, x: usize, y: usize) -> bool {
+        x >= self.x && x < self.right() && y >= self.y && y < self.bottom()
+    }
+
+    ///The overlapping part of two rectangles, empty rectangles come out with a width or height of 0
+    pub fn intersection(&self, other: &Rect) -> Rect {
+        let x = self.x.max(other.x);
+        let y = self.y.max(other.y);
+        let right = self.right().min(other.right());
+        let bottom = self.bottom().min(other.bottom());
+
+        Rect::new(x, y, right.saturating_sub(x), bottom.saturating_sub(y))
+    }
+
+    ///The rectangle shrunk by one cell on every side
+    pub fn inner(&self) -> Rect {
+        Rect::new(
+            self.x + 1,
+            self.y + 1,
+            self.width.saturating_sub(2),
+            self.height.saturating_sub(2),
+        )
+    }
+}
+
+///The foreground and background color of a cell
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Style {
+    pub foreground: Color,
+    pub background: Color,
+}
+
+impl Style {
+    pub const fn new(foreground: Color, background: Color) -> Style {
+        Style {
+            foreground,
+            background,
+        }
+    }
+
+    pub const fn inverted(&self) -> Style {
+        Style::new(self.background, self.foreground)
+    }
+}
+
+///Draws into a clipped region of the screen, coordinates are relative to the origin
+pub struct Canvas<'a> {
+    writer: &'a mut Writer,
+    origin: (usize, usize),
+    clip: Rect,
+}
+
+impl<'a> Canvas<'a> {
+    pub fn new(writer: &'a mut Writer, origin: (usize, usize), clip: Rect) -> Canvas<'a> {
+        let screen = Rect::new(0, 0, writer.width(), writer.height());
+        Canvas {
+            writer,
+            origin,
+            clip: clip.intersection(&screen),
+        }
+    }
+
+    pub fn put(&mut self, x: usize, y: usize, character: u8, style: Style) {
+        let (x, y) = (self.origin.0 + x, self.origin.1 + y);
+        if self.clip.contains(x, y) {
+            self.writer
+                .write_char_at(Point(y, x), character, style.foreground, style.background);
+        }
+    }
+
+    ///Writes bytes in a row, anything past the clip rectangle is cut off
+    pub fn write_bytes(&mut self, x: usize, y: usize, bytes: &[u8], style: Style) {
+        for (i, byte) in bytes.iter().enumerate() {
+            self.put(x + i, y, *byte, style);
+        }
+    }
+
+    pub fn fill(&mut self, rect: Rect, character: u8, style: Style) {
+        for y in rect.y..rect.bottom() {
+            for x in rect.x..rect.right() {
+                self.put(x, y, character, style);
+            }
+        }
+    }
+}
+
+///How the edge of a window is drawn
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Border {
+    None,
+    Single,
+    Double,
+}
+
+impl Border {
+    //corners in the order top left, top right, bottom left, bottom right
+    fn symbols(&self) -> Option<(Symbols, Symbols, [Symbols; 4])> {
+        match self {
+            Border::None => None,
+            Border::Single => Some((
+                Symbols::LineHorizontal,
+                Symbols::LineVertical,
+                [
+                    Symbols::LineDownRight,
+                    Symbols::LineDownLeft,
+                    Symbols::LineRightUp,
+                    Symbols::LineLeftUp,
+                ],
+            )),
+            Border::Double => Some((
+                Symbols::PipeHorizontal,
+                Symbols::PipeVertical,
+                [
+                    Symbols::PipeDownRight,
+                    Symbols::PipeDownLeft,
+                    Symbols::PipeRightUp,
+                    Symbols::PipeLeftUp,
+                ],
+            )),
+        }
+    }
+}
+
+///Identifies a window on a desktop
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WindowId(usize);
+
+///Identifies a widget inside a window
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct WidgetId {
+    pub window: WindowId,
+    index: usize,
+}
+
+#[derive(Clone, Copy)]
+pub struct Window {
+    pub rect: Rect,
+    pub title: &'static str,
+    pub border: Border,
+    pub style: Style,
+    pub focused_style: Style,
+    widgets: [Option<Widget>; MAX_WIDGETS],
+    focused_widget: Option<usize>,
+}
+
+impl Window {
+    pub fn new(rect: Rect, title: &'static str, style: Style) -> Window {
+        Window {
+            rect,
+            title,
+            border: Border::Double,
+            style,
+            focused_style: Style::new(Color::Yellow, style.background),
+            widgets: [None; MAX_WIDGETS],
+            focused_widget: None,
+        }
+    }
+
+    ///The part of the window widgets are placed in, widget rectangles are relative to it
+    pub fn content_rect(&self) -> Rect {
+        match self.border {
+            Border::None => self.rect,
+            _ => self.rect.inner(),
+        }
+    }
+
+    ///Moves focus to the next focusable widget, returns false when there are no more left in the window
+    fn focus_next(&mut self) -> bool {
+        let start = self.focused_widget.map_or(0, |i| i + 1);
+        let next = (start..MAX_WIDGETS).find(|i| match &self.widgets[*i] {
+            Some(widget) => widget.is_focusable(),
+            None => false,
+        });
+
+        self.focused_widget = next;
+        next.is_some()
+    }
+
+    fn draw(&self, writer: &mut Writer, focused: bool) {
+        let frame_style = if focused {
+            self.focused_style
+        } else {
+            self.style
+        };
+        let mut canvas = Canvas::new(writer, (self.rect.x, self.rect.y), self.rect);
+        let local = Rect::new(0, 0, self.rect.width, self.rect.height);
+
+        canvas.fill(local, b' ', self.style);
+
+        if let Some((horizontal, vertical, corners)) = self.border.symbols() {
+            let (right, bottom) = (
+                local.width.saturating_sub(1),
+                local.height.saturating_sub(1),
+            );
+            for x in 1..right {
+                canvas.put(x, 0, horizontal as u8, frame_style);
+                canvas.put(x, bottom, horizontal as u8, frame_style);
+            }
+            for y in 1..bottom {
+                canvas.put(0, y, vertical as u8, frame_style);
+                canvas.put(right, y, vertical as u8, frame_style);
+            }
+            canvas.put(0, 0, corners[0] as u8, frame_style);
+            canvas.put(right, 0, corners[1] as u8, frame_style);
+            canvas.put(0, bottom, corners[2] as u8, frame_style);
+            canvas.put(right, bottom, corners[3] as u8, frame_style);
+
+            // the title sits in the top border with a space on each side
+            let title = self.title.as_bytes();
+            let space = local.width.saturating_sub(4);
+            if space > 0 && !title.is_empty() {
+                let title = &title[..title.len().min(space)];
+                canvas.put(1, 0, b' ', frame_style);
+                canvas.write_bytes(2, 0, title, frame_style);
+                canvas.put(2 + title.len(), 0, b' ', frame_style);
+            }
+        }
+
+        let content = self.content_rect();
+        for (i, widget) in self.widgets.iter().enumerate() {
+            if let Some(widget) = widget {
+                let mut canvas = Canvas::new(canvas.writer, (content.x, content.y), content);
+                widget.draw(&mut canvas, focused && self.focused_widget == Some(i));
+            }
+        }
+    }
+}
+
+///Holds every window and the order they are stacked in
+pub struct Desktop {
+    windows: [Option<Window>; MAX_WINDOWS],
+    //indices into windows from the bottom to the top of the stack
+    z_order: [usize; MAX_WINDOWS],
+    window_count: usize,
+    pub background: Style,
+}
+
+impl Desktop {
+    pub const fn new(background: Style) -> Desktop {
+        const NO_WINDOW: Option<Window> = None;
+        Desktop {
+            windows: [NO_WINDOW; MAX_WINDOWS],
+            z_order: [0; MAX_WINDOWS],
+            window_count: 0,
+            background,
+        }
+    }
+
+    ///Adds a window on top of the others and focuses it, returns None if there is no room left
+    pub fn add_window(&mut self, window: Window) -> Option<WindowId> {
+        let slot = self.windows.iter().position(|w| w.is_none())?;
+        self.windows[slot] = Some(window);
+        self.z_order[self.window_count] = slot;
+        self.window_count += 1;
+        Some(WindowId(slot))
+    }
+
+    pub fn remove_window(&mut self, id: WindowId) {
+        if let Some(position) = self.z_position(id) {
+            self.z_order[position..self.window_count].rotate_left(1);
+            self.window_count -= 1;
+            self.windows[id.0] = None;
+        }
+    }
+
+    pub fn window(&self, id: WindowId) -> Option<&Window> {
+        self.windows.get(id.0)?.as_ref()
+    }
+
+    pub fn window_mut(&mut self, id: WindowId) -> Option<&mut Window> {
+        self.windows.get_mut(id.0)?.as_mut()
+    }
+
+    ///Adds a widget to a window, the first focusable widget gets focus
+    pub fn add_widget(&mut self, window: WindowId, widget: Widget) -> Option<WidgetId> {
+        let target = self.window_mut(window)?;
+        let index = target.widgets.iter().position(|w| w.is_none())?;
+        let focusable = widget.is_focusable();
+
+        target.widgets[index] = Some(widget);
+        if focusable && target.focused_widget.is_none() {
+            target.focused_widget = Some(index);
+        }
+
+        Some(WidgetId { window, index })
+    }
+
+    pub fn widget(&self, id: WidgetId) -> Option<&Widget> {
+        self.window(id.window)?.widgets[id.index].as_ref()
+    }
+
+    pub fn widget_mut(&mut self, id: WidgetId) -> Option<&mut Widget> {
+        self.window_mut(id.window)?.widgets[id.index].as_mut()
+    }
+
+    ///The window at the top of the stack which receives keyboard input
+    pub fn focused_window(&self) -> Option<WindowId> {
+        match self.window_count {
+            0 => None,
+            count => Some(WindowId(self.z_order[count - 1])),
+        }
+    }
+
+    pub fn focused_widget(&self) -> Option<WidgetId> {
+        let window = self.focused_window()?;
+        let index = self.window(window)?.focused_widget?;
+        Some(WidgetId { window, index })
+    }
+
+    ///Moves a window to the top of the stack and focuses it
+    pub fn raise(&mut self, id: WindowId) {
+        if let Some(position) = self.z_position(id) {
+            self.z_order[position..self.window_count].rotate_left(1);
+        }
+    }
+
+    ///The topmost window covering a cell on the screen
+    pub fn window_at(&self, x: usize, y: usize) -> Option<WindowId> {
+        self.z_order[..self.window_count]
+            .iter()
+            .rev()
+            .map(|slot| WindowId(*slot))
+            .find(|id| match self.window(*id) {
+                Some(window) => window.rect.contains(x, y),
+                None => false,
+            })
+    }
+
+    ///Tab moves focus through the widgets of the focused window and then on to the
+    ///next window, every other key goes to the focused widget. Returns true if the key was used
+    pub fn handle_key(&mut self, key: DecodedKey) -> bool {
+        let focused = match self.focused_window() {
+            Some(focused) => focused,
+            None => return false,
+        };
+
+        if key == DecodedKey::Unicode('\t') {
+            let has_next = self.window_mut(focused).is_some_and(|w| w.focus_next());
+            if !has_next && self.window_count > 1 {
+                // the bottom window comes to the top which cycles through all of them
+                let next = WindowId(self.z_order[0]);
+                self.raise(next);
+                if let Some(window) = self.window_mut(next) {
+                    window.focused_widget = None;
+                    window.focus_next();
+                }
+            } else if !has_next {
+                if let Some(window) = self.window_mut(focused) {
+                    window.focus_next();
+                }
+            }
+            return true;
+        }
+
+        match self.focused_widget() {
+            Some(id) => self.widget_mut(id).is_some_and(|w| w.handle_key(key)),
+            None => false,
+        }
+    }
+
+    ///Draws the background and every window from the bottom up and moves the hardware
+    ///cursor to the focused text input
+    pub fn render(&self, writer: &mut Writer) {
+        let screen = Rect::new(0, 0, writer.width(), writer.height());
+        Canvas::new(writer, (0, 0), screen).fill(screen, b' ', self.background);
+
+        let focused = self.focused_window();
+        for slot in &self.z_order[..self.window_count] {
+            if let Some(window) = &self.windows[*slot] {
+                window.draw(writer, focused == Some(WindowId(*slot)));
+            }
+        }
+
+        let cursor = self.focused_widget().and_then(|id| {
+            let content = self.window(id.window)?.content_rect();
+            let (x, y) = self.widget(id)?.cursor_position()?;
+            Some((content.x + x, content.y + y))
+        });
+        if let Some((x, y)) = cursor {
+            if screen.contains(x, y) {
+                writer.set_cursor_pos(CursorPosition {
+                    x: x as u8,
+                    y: y as u8,
+                });
+            }
+        }
+    }
+
+    fn z_position(&self, id: WindowId) -> Option<usize> {
+        self.z_order[..self.window_count]
+            .iter()
+            .position(|slot| *slot == id.0)
+    }
+}
+
+#[cfg(test)]
+fn test_style() -> Style {
+    Style::new(Color::White, Color::Blue)
+}
+
+#[test_case]
+fn test_rect_intersection() {
+    let a = Rect::new(0, 0, 10, 10);
+    let b = Rect::new(5, 8, 10, 10);
+    assert_eq!(a.intersection(&b), Rect::new(5, 8, 5, 2));
+    assert_eq!(a.intersection(&Rect::new(20, 20, 5, 5)).width, 0);
+}
+
+#[test_case]
+fn test_window_z_order() {
+    let mut desktop = Desktop::new(test_style());
+    let back = desktop
+        .add_window(Window::new(Rect::new(0, 0, 10, 5), "back", test_style()))
+        .unwrap();
+    let front = desktop
+        .add_window(Window::new(Rect::new(5, 2, 10, 5), "front", test_style()))
+        .unwrap();
+
+    assert_eq!(desktop.window_at(6, 3), Some(front));
+    desktop.raise(back);
+    assert_eq!(desktop.window_at(6, 3), Some(back));
+    assert_eq!(desktop.focused_window(), Some(back));
+}
+
+#[test_case]
+fn test_tab_moves_focus() {
+    use widgets::TextInput;
+
+    let mut desktop = Desktop::new(test_style());
+    let first_window = desktop
+        .add_window(Window::new(Rect::new(0, 0, 20, 5), "first", test_style()))
+        .unwrap();
+    let second_window = desktop
+        .add_window(Window::new(Rect::new(0, 10, 20, 6), "second", test_style()))
+        .unwrap();
+
+    let input = TextInput::new(Rect::new(0, 0, 10, 1), test_style(), test_style());
+    let first = desktop
+        .add_widget(first_window, Widget::TextInput(input))
+        .unwrap();
+    let second = desktop
+        .add_widget(second_window, Widget::TextInput(input))
+        .unwrap();
+    let third = desktop
+        .add_widget(second_window, Widget::TextInput(input))
+        .unwrap();
+
+    assert_eq!(desktop.focused_widget(), Some(second));
+    desktop.handle_key(DecodedKey::Unicode('\t'));
+    assert_eq!(desktop.focused_widget(), Some(third));
+    desktop.handle_key(DecodedKey::Unicode('\t'));
+    assert_eq!(desktop.focused_widget(), Some(first));
+
+    desktop.handle_key(DecodedKey::Unicode('a'));
+    match desktop.widget(first) {
+        Some(Widget::TextInput(input)) => assert_eq!(input.as_str(), "a"),
+        _ => panic!("widget changed type"),
+    }
+}
+
+#[test_case]
+fn test_render_clips_to_screen() {
+    use crate::vga_driver::WRITER;
+    use x86_64::instructions::interrupts;
+
+    let mut desktop = Desktop::new(test_style());
+    desktop.add_window(Window::new(
+        Rect::new(75, 20, 10, 10),
+        "clipped",
+        test_style(),
+    ));
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        desktop.render(&mut writer);
+
+        assert_eq!(
+            writer.char_at(Point(20, 75)),
+            Some(Symbols::PipeDownRight as u8)
+        );
+        assert_eq!(
+            writer.char_at(Point(21, 75)),
+            Some(Symbols::PipeVertical as u8)
+        );
+        assert_eq!(writer.char_at(Point(24, 79)), Some(b' '));
+    });
+}
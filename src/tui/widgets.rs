@@ -0,0 +1,484 @@
+use core::fmt;
+
+use pc_keyboard::{DecodedKey, KeyCode};
+
+use super::{Canvas, Rect, Style};
+use crate::vga_driver::code_page_737_definitions::Symbols;
+
+pub const LABEL_CAPACITY: usize = 78;
+pub const TEXT_INPUT_CAPACITY: usize = 64;
+
+///A fixed capacity piece of text since we do not have a heap to put strings on
+#[derive(Clone, Copy)]
+pub struct TextBuffer<const N: usize> {
+    bytes: [u8; N],
+    len: usize,
+}
+
+impl<const N: usize> TextBuffer<N> {
+    pub const fn new() -> Self {
+        TextBuffer {
+            bytes: [0; N],
+            len: 0,
+        }
+    }
+
+    pub fn as_bytes(&self) -> &[u8] {
+        &self.bytes[..self.len]
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn clear(&mut self) {
+        self.len = 0;
+    }
+
+    ///Replaces the text, anything over the capacity is cut off
+    pub fn set(&mut self, s: &str) {
+        self.clear();
+        self.push_str(s);
+    }
+
+    ///Inserts a byte at an index, returns false if the buffer is full
+    pub fn insert(&mut self, index: usize, byte: u8) -> bool {
+        if self.len >= N || index > self.len {
+            return false;
+        }
+        self.bytes[index..=self.len].rotate_right(1);
+        self.bytes[index] = byte;
+        self.len += 1;
+        true
+    }
+
+    ///Removes the byte at an index
+    pub fn remove(&mut self, index: usize) {
+        if index >= self.len {
+            return;
+        }
+        self.bytes[index..self.len].rotate_left(1);
+        self.len -= 1;
+    }
+
+    fn push_str(&mut self, s: &str) {
+        for byte in s.bytes() {
+            if !self.insert(self.len, byte) {
+                break;
+            }
+        }
+    }
+}
+
+impl<const N: usize> Default for TextBuffer<N> {
+    fn default() -> Self {
+        TextBuffer::new()
+    }
+}
+
+impl<const N: usize> fmt::Write for TextBuffer<N> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.push_str(s);
+        Ok(())
+    }
+}
+
+///A piece of static text, writing to it with `write!` appends to the text
+#[derive(Clone, Copy)]
+pub struct Label {
+    pub rect: Rect,
+    pub style: Style,
+    pub text: TextBuffer<LABEL_CAPACITY>,
+}
+
+impl Label {
+    pub fn new(rect: Rect, text: &str, style: Style) -> Label {
+        let mut label = Label {
+            rect,
+            style,
+            text: TextBuffer::new(),
+        };
+        label.text.set(text);
+        label
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text.set(text);
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        canvas.fill(self.rect, b' ', self.style);
+        canvas.write_bytes(self.rect.x, self.rect.y, self.text.as_bytes(), self.style);
+    }
+}
+
+impl fmt::Write for Label {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.text.write_str(s)
+    }
+}
+
+///A horizontal bar filled according to value / max with the percentage printed in the middle
+#[derive(Clone, Copy)]
+pub struct ProgressBar {
+    pub rect: Rect,
+    pub style: Style,
+    pub fill_style: Style,
+    pub value: u32,
+    pub max: u32,
+}
+
+impl ProgressBar {
+    pub fn new(rect: Rect, max: u32, style: Style, fill_style: Style) -> ProgressBar {
+        ProgressBar {
+            rect,
+            style,
+            fill_style,
+            value: 0,
+            max,
+        }
+    }
+
+    ///Sets the value clamping it to the max of the bar
+    pub fn set_value(&mut self, value: u32) {
+        self.value = value.min(self.max);
+    }
+
+    pub fn percent(&self) -> u32 {
+        if self.max == 0 {
+            return 100;
+        }
+        (self.value as u64 * 100 / self.max as u64) as u32
+    }
+
+    fn draw(&self, canvas: &mut Canvas) {
+        let width = self.rect.width;
+        let filled = if self.max == 0 {
+            width
+        } else {
+            (width as u64 * self.value as u64 / self.max as u64) as usize
+        };
+
+        let mut label = TextBuffer::<5>::new();
+        let _ = fmt::write(&mut label, format_args!("{}%", self.percent()));
+        let label_start = width.saturating_sub(label.len()) / 2;
+
+        for row in 0..self.rect.height {
+            for col in 0..width {
+                let (byte, style) = if col < filled {
+                    (Symbols::FullBlock as u8, self.fill_style)
+                } else {
+                    (Symbols::ShadeLight as u8, self.style)
+                };
+
+                // the percentage goes on the middle row in inverted colors so it is readable over both halves
+                let on_label = row == self.rect.height / 2
+                    && col >= label_start
+                    && col - label_start < label.len();
+                let (x, y) = (self.rect.x + col, self.rect.y + row);
+
+                if on_label {
+                    canvas.put(x, y, label.as_bytes()[col - label_start], style.inverted());
+                } else {
+                    canvas.put(x, y, byte, style);
+                }
+            }
+        }
+    }
+}
+
+///A scrollable list of items where one item is selected
+#[derive(Clone, Copy)]
+pub struct List {
+    pub rect: Rect,
+    pub style: Style,
+    pub selected_style: Style,
+    pub items: &'static [&'static str],
+    pub selected: usize,
+    scroll: usize,
+}
+
+impl List {
+    pub fn new(
+        rect: Rect,
+        items: &'static [&'static str],
+        style: Style,
+        selected_style: Style,
+    ) -> List {
+        List {
+            rect,
+            style,
+            selected_style,
+            items,
+            selected: 0,
+            scroll: 0,
+        }
+    }
+
+    ///The currently selected item if the list is not empty
+    pub fn selected_item(&self) -> Option<&'static str> {
+        self.items.get(self.selected).copied()
+    }
+
+    pub fn select(&mut self, index: usize) {
+        if self.items.is_empty() {
+            return;
+        }
+        self.selected = index.min(self.items.len() - 1);
+
+        // keep the selected item inside the visible rows
+        if self.selected < self.scroll {
+            self.scroll = self.selected;
+        } else if self.rect.height > 0 && self.selected >= self.scroll + self.rect.height {
+            self.scroll = self.selected + 1 - self.rect.height;
+        }
+    }
+
+    fn handle_key(&mut self, key: DecodedKey) -> bool {
+        match key {
+            DecodedKey::RawKey(KeyCode::ArrowUp) => self.select(self.selected.saturating_sub(1)),
+            DecodedKey::RawKey(KeyCode::ArrowDown) => self.select(self.selected + 1),
+            DecodedKey::RawKey(KeyCode::Home) => self.select(0),
+            DecodedKey::RawKey(KeyCode::End) => self.select(usize::MAX),
+            _ => return false,
+        }
+        true
+    }
+
+    fn draw(&self, canvas: &mut Canvas, focused: bool) {
+        //there is no row to put the items or the arrows on
+        if self.rect.width == 0 || self.rect.height == 0 {
+            return;
+        }
+        canvas.fill(self.rect, b' ', self.style);
+
+        for row in 0..self.rect.height {
+            let index = self.scroll + row;
+            let item = match self.items.get(index) {
+                Some(item) => item,
+                None => break,
+            };
+
+            let style = if index == self.selected && focused {
+                self.selected_style
+            } else {
+                self.style
+            };
+            let line = Rect::new(self.rect.x, self.rect.y + row, self.rect.width, 1);
+            canvas.fill(line, b' ', style);
+
+            let marker = if index == self.selected { b'>' } else { b' ' };
+            canvas.put(line.x, line.y, marker, style);
+            canvas.write_bytes(line.x + 1, line.y, item.as_bytes(), style);
+        }
+
+        // show arrows on the right edge when there is more to scroll to
+        let right = self.rect.x + self.rect.width.saturating_sub(1);
+        if self.scroll > 0 {
+            canvas.put(right, self.rect.y, Symbols::ArrowUp as u8, self.style);
+        }
+        if self.scroll + self.rect.height < self.items.len() {
+            canvas.put(
+                right,
+                self.rect.y + self.rect.height - 1,
+                Symbols::ArrowDown as u8,
+                self.style,
+            );
+        }
+    }
+}
+
+///A single line of editable text
+#[derive(Clone, Copy)]
+pub struct TextInput {
+    pub rect: Rect,
+    pub style: Style,
+    pub focused_style: Style,
+    pub text: TextBuffer<TEXT_INPUT_CAPACITY>,
+    pub cursor: usize,
+    scroll: usize,
+}
+
+impl TextInput {
+    pub fn new(rect: Rect, style: Style, focused_style: Style) -> TextInput {
+        TextInput {
+            rect,
+            style,
+            focused_style,
+            text: TextBuffer::new(),
+            cursor: 0,
+            scroll: 0,
+        }
+    }
+
+    pub fn set_text(&mut self, text: &str) {
+        self.text.set(text);
+        self.cursor = self.text.len();
+        self.update_scroll();
+    }
+
+    ///Returns the current text, the input only accepts ascii so this can not fail
+    pub fn as_str(&self) -> &str {
+        core::str::from_utf8(self.text.as_bytes()).unwrap_or("")
+    }
+
+    fn handle_key(&mut self, key: DecodedKey) -> bool {
+        match key {
+            DecodedKey::Unicode('\u{8}') => {
+                if self.cursor > 0 {
+                    self.cursor -= 1;
+                    self.text.remove(self.cursor);
+                }
+            }
+            DecodedKey::Unicode('\u{7f}') | DecodedKey::RawKey(KeyCode::Delete) => {
+                self.text.remove(self.cursor)
+            }
+            DecodedKey::Unicode(character @ ' '..='~') => {
+                if self.text.insert(self.cursor, character as u8) {
+                    self.cursor += 1;
+                }
+            }
+            DecodedKey::RawKey(KeyCode::ArrowLeft) => self.cursor = self.cursor.saturating_sub(1),
+            DecodedKey::RawKey(KeyCode::ArrowRight) => {
+                self.cursor = (self.cursor + 1).min(self.text.len())
+            }
+            DecodedKey::RawKey(KeyCode::Home) => self.cursor = 0,
+            DecodedKey::RawKey(KeyCode::End) => self.cursor = self.text.len(),
+            _ => return false,
+        }
+        self.update_scroll();
+        true
+    }
+
+    fn update_scroll(&mut self) {
+        let width = self.rect.width.max(1);
+        if self.cursor < self.scroll {
+            self.scroll = self.cursor;
+        } else if self.cursor >= self.scroll + width {
+            self.scroll = self.cursor + 1 - width;
+        }
+    }
+
+    ///Where the hardware cursor should be shown relative to the window interior
+    fn cursor_position(&self) -> (usize, usize) {
+        (self.rect.x + self.cursor - self.scroll, self.rect.y)
+    }
+
+    fn draw(&self, canvas: &mut Canvas, focused: bool) {
+        let style = if focused {
+            self.focused_style
+        } else {
+            self.style
+        };
+        canvas.fill(self.rect, b' ', style);
+
+        let visible = &self.text.as_bytes()[self.scroll..];
+        let visible = &visible[..visible.len().min(self.rect.width)];
+        canvas.write_bytes(self.rect.x, self.rect.y, visible, style);
+    }
+}
+
+///Every widget a window can hold
+#[derive(Clone, Copy)]
+pub enum Widget {
+    Label(Label),
+    ProgressBar(ProgressBar),
+    List(List),
+    TextInput(TextInput),
+}
+
+impl Widget {
+    ///Whether the widget can take keyboard focus
+    pub fn is_focusable(&self) -> bool {
+        matches!(self, Widget::List(_) | Widget::TextInput(_))
+    }
+
+    ///Lets the widget react to a key, returns true if the key was used
+    pub fn handle_key(&mut self, key: DecodedKey) -> bool {
+        match self {
+            Widget::List(list) => list.handle_key(key),
+            Widget::TextInput(input) => input.handle_key(key),
+            _ => false,
+        }
+    }
+
+    pub fn cursor_position(&self) -> Option<(usize, usize)> {
+        match self {
+            Widget::TextInput(input) => Some(input.cursor_position()),
+            _ => None,
+        }
+    }
+
+    pub(super) fn draw(&self, canvas: &mut Canvas, focused: bool) {
+        match self {
+            Widget::Label(label) => label.draw(canvas),
+            Widget::ProgressBar(bar) => bar.draw(canvas),
+            Widget::List(list) => list.draw(canvas, focused),
+            Widget::TextInput(input) => input.draw(canvas, focused),
+        }
+    }
+}
+
+#[test_case]
+fn test_text_buffer_insert_remove() {
+    let mut text = TextBuffer::<4>::new();
+    text.set("abc");
+    assert!(text.insert(1, b'x'));
+    assert_eq!(text.as_bytes(), b"axbc");
+    assert!(!text.insert(0, b'y'));
+    text.remove(0);
+    assert_eq!(text.as_bytes(), b"xbc");
+}
+
+#[test_case]
+fn test_text_input_editing() {
+    use super::Color;
+    let style = Style::new(Color::White, Color::Black);
+    let mut input = TextInput::new(Rect::new(0, 0, 4, 1), style, style);
+
+    for character in "hello".chars() {
+        input.handle_key(DecodedKey::Unicode(character));
+    }
+    input.handle_key(DecodedKey::RawKey(KeyCode::Home));
+    input.handle_key(DecodedKey::RawKey(KeyCode::Delete));
+    input.handle_key(DecodedKey::RawKey(KeyCode::End));
+    input.handle_key(DecodedKey::Unicode('\u{8}'));
+
+    assert_eq!(input.as_str(), "ell");
+    assert_eq!(input.cursor_position(), (3, 0));
+}
+
+#[test_case]
+fn test_list_scrolls_to_selection() {
+    use super::Color;
+    static ITEMS: [&str; 5] = ["a", "b", "c", "d", "e"];
+    let style = Style::new(Color::White, Color::Black);
+    let mut list = List::new(Rect::new(0, 0, 10, 2), &ITEMS, style, style);
+
+    list.select(4);
+    assert_eq!(list.scroll, 3);
+    list.handle_key(DecodedKey::RawKey(KeyCode::Home));
+    assert_eq!(list.selected_item(), Some("a"));
+    assert_eq!(list.scroll, 0);
+}
+
+#[test_case]
+fn test_empty_list_draws_nothing() {
+    use super::Color;
+    use crate::vga_driver::WRITER;
+    use x86_64::instructions::interrupts;
+
+    static ITEMS: [&str; 2] = ["a", "b"];
+    let style = Style::new(Color::White, Color::Black);
+    let list = List::new(Rect::new(0, 0, 10, 0), &ITEMS, style, style);
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+        let mut canvas = Canvas::new(&mut writer, (0, 0), Rect::new(0, 0, 80, 25));
+        list.draw(&mut canvas, true);
+    });
+}
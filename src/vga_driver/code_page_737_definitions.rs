@@ -1,10 +1,23 @@
 //THIS IS NOT THE FULL CODE PAGE 737 ONLY THE SYMBOLS I HAVE NEEDED
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Symbols {
     Point = 0xFE,
-    PipeVertical = 0xBE,
+    PipeVertical = 0xBA,
     PipeHorizontal = 0xCD,
     PipeDownLeft = 0xBB,
     PipeLeftUp = 0xBC,
     PipeRightUp = 0xC8,
     PipeDownRight = 0xC9,
+    LineVertical = 0xB3,
+    LineHorizontal = 0xC4,
+    LineDownLeft = 0xBF,
+    LineLeftUp = 0xD9,
+    LineRightUp = 0xC0,
+    LineDownRight = 0xDA,
+    ShadeLight = 0xB0,
+    ShadeMedium = 0xB1,
+    ShadeDark = 0xB2,
+    FullBlock = 0xDB,
+    ArrowUp = 0x18,
+    ArrowDown = 0x19,
 }
@@ -0,0 +1,299 @@
+//! Loading text mode fonts into vga plane 2
+//!
+//! Plane 2 has room for 8 fonts of 256 glyphs each, every glyph takes 32 bytes of which
+//! only the first `height` rows are shown. The character map select register decides
+//! which of the 8 slots are used, bit 3 of a character's attribute picks between the
+//! two selected slots which is how 512 glyph fonts work.
+
+use rost_core::mmio::Mmio;
+
+use super::modes::{self, SEQUENCER};
+use crate::mmio::MmioRegion;
+
+pub const GLYPH_STRIDE: usize = 32;
+pub const SLOT_GLYPHS: usize = 256;
+
+const PSF1_MAGIC: [u8; 2] = [0x36, 0x04];
+const PSF1_MODE_512: u8 = 0x01;
+const PSF2_MAGIC: [u8; 4] = [0x72, 0xB5, 0x4A, 0x86];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FontError {
+    BadMagic,
+    Truncated,
+    ///Text mode glyphs are always 8 pixels wide
+    UnsupportedWidth(u32),
+    ///A glyph can be at most 32 rows tall
+    UnsupportedHeight(u32),
+}
+
+///A bitmap font, every glyph row is one byte with the leftmost pixel in the highest bit
+#[derive(Debug, Clone, Copy)]
+pub struct Font<'a> {
+    pub glyph_count: usize,
+    pub height: usize,
+    bytes_per_glyph: usize,
+    glyphs: &'a [u8],
+}
+
+impl<'a> Font<'a> {
+    ///Wraps raw glyph data, for example a font made with a `const` array
+    pub fn from_raw(glyphs: &'a [u8], height: usize) -> Result<Font<'a>, FontError> {
+        if height == 0 || height > GLYPH_STRIDE {
+            return Err(FontError::UnsupportedHeight(height as u32));
+        }
+
+        Ok(Font {
+            glyph_count: glyphs.len() / height,
+            height,
+            bytes_per_glyph: height,
+            glyphs,
+        })
+    }
+
+    ///Parses a PSF1 or PSF2 file, usually one embedded with `include_bytes!`
+    pub fn parse(data: &'a [u8]) -> Result<Font<'a>, FontError> {
+        if data.starts_with(&PSF2_MAGIC) {
+            Font::parse_psf2(data)
+        } else if data.starts_with(&PSF1_MAGIC) {
+            Font::parse_psf1(data)
+        } else {
+            Err(FontError::BadMagic)
+        }
+    }
+
+    fn parse_psf1(data: &'a [u8]) -> Result<Font<'a>, FontError> {
+        let mode = *data.get(2).ok_or(FontError::Truncated)?;
+        let height = *data.get(3).ok_or(FontError::Truncated)? as usize;
+        let glyph_count = if mode & PSF1_MODE_512 != 0 { 512 } else { 256 };
+
+        Font::with_glyphs(data, 4, glyph_count, height, height)
+    }
+
+    fn parse_psf2(data: &'a [u8]) -> Result<Font<'a>, FontError> {
+        let field = |index: usize| -> Result<u32, FontError> {
+            let bytes = data
+                .get(index * 4..index * 4 + 4)
+                .ok_or(FontError::Truncated)?;
+            Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+        };
+
+        let header_size = field(2)? as usize;
+        let glyph_count = field(4)? as usize;
+        let bytes_per_glyph = field(5)? as usize;
+        let height = field(6)?;
+        let width = field(7)?;
+
+        if width != 8 {
+            return Err(FontError::UnsupportedWidth(width));
+        }
+
+        Font::with_glyphs(
+            data,
+            header_size,
+            glyph_count,
+            height as usize,
+            bytes_per_glyph,
+        )
+    }
+
+    fn with_glyphs(
+        data: &'a [u8],
+        offset: usize,
+        glyph_count: usize,
+        height: usize,
+        bytes_per_glyph: usize,
+    ) -> Result<Font<'a>, FontError> {
+        if height == 0 || height > GLYPH_STRIDE || bytes_per_glyph < height {
+            return Err(FontError::UnsupportedHeight(height as u32));
+        }
+
+        //a malformed header can ask for more glyphs than fit in the address space
+        let end = glyph_count
+            .checked_mul(bytes_per_glyph)
+            .and_then(|size| size.checked_add(offset))
+            .ok_or(FontError::Truncated)?;
+        let glyphs = data.get(offset..end).ok_or(FontError::Truncated)?;
+
+        Ok(Font {
+            glyph_count,
+            height,
+            bytes_per_glyph,
+            glyphs,
+        })
+    }
+
+    ///The rows of a glyph or None if the font does not have that many glyphs
+    pub fn glyph(&self, index: usize) -> Option<&'a [u8]> {
+        if index >= self.glyph_count {
+            return None;
+        }
+        let start = index * self.bytes_per_glyph;
+        Some(&self.glyphs[start..start + self.height])
+    }
+}
+
+///One of the 8 places in plane 2 a 256 glyph font can be stored.
+///Only slots 0, 1, 4 and 5 are kept when switching to a graphics mode and back
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FontSlot(u8);
+
+impl FontSlot {
+    ///The slot the BIOS font is in at boot
+    pub const DEFAULT: FontSlot = FontSlot(0);
+
+    pub const fn new(slot: u8) -> Option<FontSlot> {
+        if slot < 8 {
+            Some(FontSlot(slot))
+        } else {
+            None
+        }
+    }
+
+    ///Where the slot starts in plane 2, slots 4-7 sit in the gaps between slots 0-3
+    pub fn offset(&self) -> usize {
+        let slot = self.0 as usize;
+        (slot & 0x03) * 0x4000 + (slot >> 2) * 0x2000
+    }
+
+    //the slot number as it is spread over the character map select register, map b is
+    //used for characters with bit 3 of the attribute clear and map a for the rest
+    fn map_b_bits(&self) -> u8 {
+        (self.0 & 0x03) | ((self.0 & 0x04) << 2)
+    }
+
+    fn map_a_bits(&self) -> u8 {
+        ((self.0 & 0x03) << 2) | ((self.0 & 0x04) << 3)
+    }
+}
+
+///Writes the first 256 glyphs of a font into a slot, glyph rows below the font height are cleared
+pub fn load_font(font: &Font, slot: FontSlot) {
+    load_glyphs(font, 0, slot);
+}
+
+///Writes a font with up to 512 glyphs into two slots and shows both of them, the second
+///half is used for characters with bit 3 of the attribute set
+pub fn load_font_512(font: &Font, low: FontSlot, high: FontSlot) {
+    load_glyphs(font, 0, low);
+    if font.glyph_count > SLOT_GLYPHS {
+        load_glyphs(font, SLOT_GLYPHS, high);
+        select_fonts_512(low, high);
+    } else {
+        select_font(low);
+    }
+}
+
+fn load_glyphs(font: &Font, first: usize, slot: FontSlot) {
+    modes::with_font_plane(|plane| {
+        for index in 0..SLOT_GLYPHS {
+            let glyph = font.glyph(first + index).unwrap_or(&[]);
+            write_glyph_rows(plane, slot, index as u8, glyph);
+        }
+    });
+}
+
+///Replaces a single glyph while the font is in use, rows past the end of `rows` are cleared
+pub fn set_glyph(slot: FontSlot, character: u8, rows: &[u8]) {
+    modes::with_font_plane(|plane| write_glyph_rows(plane, slot, character, rows));
+}
+
+///Reads the 32 rows stored for a glyph
+pub fn glyph(slot: FontSlot, character: u8) -> [u8; GLYPH_STRIDE] {
+    let mut rows = [0; GLYPH_STRIDE];
+
+    modes::with_font_plane(|plane| {
+        let start = slot.offset() + character as usize * GLYPH_STRIDE;
+        for (i, row) in rows.iter_mut().enumerate() {
+            *row = plane.read_u8(start + i);
+        }
+    });
+
+    rows
+}
+
+fn write_glyph_rows(plane: &mut MmioRegion, slot: FontSlot, character: u8, rows: &[u8]) {
+    let start = slot.offset() + character as usize * GLYPH_STRIDE;
+    for i in 0..GLYPH_STRIDE {
+        let row = rows.get(i).copied().unwrap_or(0);
+        plane.write_u8(start + i, row);
+    }
+}
+
+///Shows a single 256 glyph font no matter what bit 3 of the attribute is
+pub fn select_font(slot: FontSlot) {
+    modes::write_indexed(SEQUENCER, 0x03, slot.map_a_bits() | slot.map_b_bits());
+    // bit 3 goes back to meaning a bright foreground
+    modes::write_attribute(0x12, 0x0F);
+}
+
+///Shows two fonts at once, characters with bit 3 of the attribute clear use `low`
+pub fn select_fonts_512(low: FontSlot, high: FontSlot) {
+    modes::write_indexed(SEQUENCER, 0x03, high.map_a_bits() | low.map_b_bits());
+    // with bit 3 picking the font it can not also make the color bright
+    modes::write_attribute(0x12, 0x07);
+}
+
+#[test_case]
+fn test_parse_psf1() {
+    let mut data = [0u8; 4 + 256 * 16];
+    data[..4].copy_from_slice(&[0x36, 0x04, 0x00, 16]);
+    data[4 + 65 * 16] = 0x18;
+
+    let font = Font::parse(&data).expect("parsing failed");
+    assert_eq!(font.glyph_count, 256);
+    assert_eq!(font.height, 16);
+    assert_eq!(font.glyph(65).unwrap()[0], 0x18);
+    assert!(font.glyph(256).is_none());
+    assert_eq!(Font::parse(&data[..100]).err(), Some(FontError::Truncated));
+}
+
+#[test_case]
+fn test_parse_psf2() {
+    let mut data = [0u8; 32 + 2 * 8];
+    let header: [u32; 8] = [0x864A_B572, 0, 32, 0, 2, 8, 8, 8];
+    for (i, field) in header.iter().enumerate() {
+        data[i * 4..i * 4 + 4].copy_from_slice(&field.to_le_bytes());
+    }
+    data[32 + 8] = 0xFF;
+
+    let font = Font::parse(&data).expect("parsing failed");
+    assert_eq!(font.glyph_count, 2);
+    assert_eq!(font.glyph(1).unwrap(), &[0xFF, 0, 0, 0, 0, 0, 0, 0]);
+}
+
+#[test_case]
+fn test_parse_psf2_huge_header() {
+    let mut data = [0u8; 32 + 2 * 8];
+    let header: [u32; 8] = [0x864A_B572, 0, u32::MAX, 0, u32::MAX, u32::MAX, 8, 8];
+    for (i, field) in header.iter().enumerate() {
+        data[i * 4..i * 4 + 4].copy_from_slice(&field.to_le_bytes());
+    }
+
+    assert_eq!(Font::parse(&data).err(), Some(FontError::Truncated));
+}
+
+#[test_case]
+fn test_font_slot_offsets() {
+    assert_eq!(FontSlot::new(1).unwrap().offset(), 0x4000);
+    assert_eq!(FontSlot::new(4).unwrap().offset(), 0x2000);
+    assert_eq!(FontSlot::new(7).unwrap().offset(), 0xE000);
+    assert!(FontSlot::new(8).is_none());
+}
+
+#[test_case]
+fn test_font_slot_map_bits() {
+    let slot = FontSlot::new(5).unwrap();
+    assert_eq!(slot.map_b_bits(), 0b01_0001);
+    assert_eq!(slot.map_a_bits(), 0b10_0100);
+}
+
+#[test_case]
+fn test_set_glyph_round_trip() {
+    // slot 1 is not shown so this does not disturb the screen
+    let slot = FontSlot::new(1).unwrap();
+    set_glyph(slot, b'A', &[0x81, 0x42, 0x24, 0x18]);
+
+    let rows = glyph(slot, b'A');
+    assert_eq!(&rows[..5], &[0x81, 0x42, 0x24, 0x18, 0x00]);
+}
@@ -0,0 +1,397 @@
+//! Pixel graphics in the vga modes 13h (320x200, 256 colors) and 12h (640x480, 16 colors)
+//!
+//! Switching to a graphics mode overwrites the font and the text in video memory so
+//! both are saved when leaving text mode and put back by `enter_text_mode`.
+
+use rost_core::mmio::Mmio;
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::modes::{self, RegisterSet, CRTC, GRAPHICS, SEQUENCER};
+use super::palette::{self, Rgb, DAC_SIZE};
+use crate::mmio::MmioRegion;
+
+//the 64k window the graphics modes show
+const GRAPHICS_MEMORY: usize = 0xA0000;
+const GRAPHICS_MEMORY_SIZE: usize = 0x10000;
+
+///How much of plane 2 is saved, this covers the font slots used by the text modes
+const SAVED_FONT_SIZE: usize = 0x8000;
+///How much of the text buffer is saved, enough for every text mode we use
+const SAVED_TEXT_SIZE: usize = 0x2000;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VideoMode {
+    Text,
+    Graphics320x200x256,
+    Graphics640x480x16,
+}
+
+struct DisplayState {
+    mode: VideoMode,
+    font: [u8; SAVED_FONT_SIZE],
+    text: [u8; SAVED_TEXT_SIZE],
+    colors: [Rgb; DAC_SIZE],
+    cursor: (u8, u8),
+    //the character map select and color plane enable registers which pick the font
+    font_select: (u8, u8),
+}
+
+static STATE: Mutex<DisplayState> = Mutex::new(DisplayState {
+    mode: VideoMode::Text,
+    font: [0; SAVED_FONT_SIZE],
+    text: [0; SAVED_TEXT_SIZE],
+    colors: [Rgb::new(0, 0, 0); DAC_SIZE],
+    cursor: (0, 0),
+    font_select: (0, 0x0F),
+});
+
+pub fn current_mode() -> VideoMode {
+    STATE.lock().mode
+}
+
+///Switches to 320x200 with 256 colors and loads the default 256 color palette
+pub fn enter_mode_13h() -> Mode13h {
+    switch_to_graphics(VideoMode::Graphics320x200x256, &modes::GRAPHICS_320X200X256);
+    palette::load_colors(0, &palette::default_256_colors());
+
+    let mut framebuffer = Mode13h {
+        memory: graphics_memory(),
+    };
+    framebuffer.clear(0);
+    framebuffer
+}
+
+///Switches to 640x480 with 16 colors, the colors are the same as the text mode ones
+///since the first 16 entries of the default palette are the text colors
+pub fn enter_mode_12h() -> Mode12h {
+    switch_to_graphics(VideoMode::Graphics640x480x16, &modes::GRAPHICS_640X480X16);
+    palette::load_colors(0, &palette::default_256_colors());
+
+    // write mode 2 lets a single write set all four planes of the masked pixels
+    modes::write_indexed(GRAPHICS, 0x05, 0x02);
+
+    let mut framebuffer = Mode12h {
+        memory: graphics_memory(),
+    };
+    framebuffer.clear(0);
+    framebuffer
+}
+
+///Goes back to the text mode with the font, text, colors and cursor from before the switch
+pub fn enter_text_mode() {
+    let text_mode = without_interrupts(|| super::WRITER.lock().mode());
+    let mut state = STATE.lock();
+    if state.mode == VideoMode::Text {
+        return;
+    }
+
+    without_interrupts(|| {
+        modes::write_registers(text_mode.registers());
+        modes::write_indexed(SEQUENCER, 0x03, state.font_select.0);
+        modes::write_attribute(0x12, state.font_select.1);
+
+        modes::with_font_plane(|plane| {
+            for (i, byte) in state.font.iter().enumerate() {
+                plane.write_u8(i, *byte);
+            }
+        });
+
+        let mut text = super::text_memory();
+        for (i, byte) in state.text.iter().enumerate() {
+            text.write_u8(i, *byte);
+        }
+
+        palette::load_colors(0, &state.colors);
+
+        modes::write_indexed(CRTC, 0x0E, state.cursor.0);
+        modes::write_indexed(CRTC, 0x0F, state.cursor.1);
+    });
+
+    state.mode = VideoMode::Text;
+}
+
+fn switch_to_graphics(mode: VideoMode, registers: &RegisterSet) {
+    let mut state = STATE.lock();
+
+    without_interrupts(|| {
+        if state.mode == VideoMode::Text {
+            let text = super::text_memory();
+            for (i, byte) in state.text.iter_mut().enumerate() {
+                *byte = text.read_u8(i);
+            }
+
+            let font = &mut state.font;
+            modes::with_font_plane(|plane| {
+                for (i, byte) in font.iter_mut().enumerate() {
+                    *byte = plane.read_u8(i);
+                }
+            });
+
+            palette::read_colors(0, &mut state.colors);
+
+            state.cursor = (
+                modes::read_indexed(CRTC, 0x0E),
+                modes::read_indexed(CRTC, 0x0F),
+            );
+            state.font_select = (
+                modes::read_indexed(SEQUENCER, 0x03),
+                modes::read_attribute(0x12),
+            );
+        }
+
+        modes::write_registers(registers);
+    });
+
+    state.mode = mode;
+}
+
+///Anything pixels can be drawn on, coordinates outside the screen are clipped
+pub trait Framebuffer {
+    fn width(&self) -> usize;
+    fn height(&self) -> usize;
+
+    ///Sets a pixel that is known to be on the screen
+    fn write_pixel(&mut self, x: usize, y: usize, color: u8);
+
+    ///Reads a pixel that is known to be on the screen
+    fn read_pixel(&self, x: usize, y: usize) -> u8;
+
+    fn put_pixel(&mut self, x: i32, y: i32, color: u8) {
+        if x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height() {
+            self.write_pixel(x as usize, y as usize, color);
+        }
+    }
+
+    fn get_pixel(&self, x: i32, y: i32) -> Option<u8> {
+        if x >= 0 && y >= 0 && (x as usize) < self.width() && (y as usize) < self.height() {
+            Some(self.read_pixel(x as usize, y as usize))
+        } else {
+            None
+        }
+    }
+
+    fn clear(&mut self, color: u8) {
+        self.fill_rectangle(0, 0, self.width() as i32, self.height() as i32, color);
+    }
+
+    ///Draws a line with Bresenham's algorithm
+    fn line(&mut self, x0: i32, y0: i32, x1: i32, y1: i32, color: u8) {
+        let dx = (x1 - x0).abs();
+        let dy = -(y1 - y0).abs();
+        let step_x = if x0 < x1 { 1 } else { -1 };
+        let step_y = if y0 < y1 { 1 } else { -1 };
+        let mut error = dx + dy;
+        let (mut x, mut y) = (x0, y0);
+
+        loop {
+            self.put_pixel(x, y, color);
+            if x == x1 && y == y1 {
+                break;
+            }
+
+            let doubled = 2 * error;
+            if doubled >= dy {
+                error += dy;
+                x += step_x;
+            }
+            if doubled <= dx {
+                error += dx;
+                y += step_y;
+            }
+        }
+    }
+
+    ///Draws the outline of a rectangle
+    fn rectangle(&mut self, x: i32, y: i32, width: i32, height: i32, color: u8) {
+        if width <= 0 || height <= 0 {
+            return;
+        }
+        let (right, bottom) = (x + width - 1, y + height - 1);
+
+        self.line(x, y, right, y, color);
+        self.line(x, bottom, right, bottom, color);
+        self.line(x, y, x, bottom, color);
+        self.line(right, y, right, bottom, color);
+    }
+
+    fn fill_rectangle(&mut self, x: i32, y: i32, width: i32, height: i32, color: u8) {
+        let left = x.max(0);
+        let top = y.max(0);
+        let right = (x + width).min(self.width() as i32);
+        let bottom = (y + height).min(self.height() as i32);
+
+        for row in top..bottom {
+            for col in left..right {
+                self.write_pixel(col as usize, row as usize, color);
+            }
+        }
+    }
+
+    ///Draws the outline of a circle with the midpoint algorithm
+    fn circle(&mut self, center_x: i32, center_y: i32, radius: i32, color: u8) {
+        let (mut x, mut y) = (radius, 0);
+        let mut error = 1 - radius;
+
+        while x >= y {
+            for (dx, dy) in [
+                (x, y),
+                (y, x),
+                (-y, x),
+                (-x, y),
+                (-x, -y),
+                (-y, -x),
+                (y, -x),
+                (x, -y),
+            ] {
+                self.put_pixel(center_x + dx, center_y + dy, color);
+            }
+
+            y += 1;
+            if error < 0 {
+                error += 2 * y + 1;
+            } else {
+                x -= 1;
+                error += 2 * (y - x) + 1;
+            }
+        }
+    }
+
+    ///Copies an image made of rows `width` pixels long onto the screen
+    fn blit(&mut self, x: i32, y: i32, width: usize, pixels: &[u8]) {
+        if width == 0 {
+            return;
+        }
+
+        for (i, row) in pixels.chunks(width).enumerate() {
+            for (j, color) in row.iter().enumerate() {
+                self.put_pixel(x + j as i32, y + i as i32, *color);
+            }
+        }
+    }
+}
+
+fn graphics_memory() -> MmioRegion {
+    unsafe { MmioRegion::new(GRAPHICS_MEMORY, GRAPHICS_MEMORY_SIZE) }
+}
+
+///Mode 13h, every byte at 0xA0000 is one pixel
+pub struct Mode13h {
+    memory: MmioRegion,
+}
+
+impl Mode13h {
+    pub const WIDTH: usize = 320;
+    pub const HEIGHT: usize = 200;
+}
+
+impl Framebuffer for Mode13h {
+    fn width(&self) -> usize {
+        Self::WIDTH
+    }
+
+    fn height(&self) -> usize {
+        Self::HEIGHT
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, color: u8) {
+        self.memory.write_u8(y * Self::WIDTH + x, color);
+    }
+
+    fn read_pixel(&self, x: usize, y: usize) -> u8 {
+        self.memory.read_u8(y * Self::WIDTH + x)
+    }
+}
+
+///Mode 12h, every byte holds one bit of 8 pixels in each of the four planes
+pub struct Mode12h {
+    memory: MmioRegion,
+}
+
+impl Mode12h {
+    pub const WIDTH: usize = 640;
+    pub const HEIGHT: usize = 480;
+}
+
+impl Framebuffer for Mode12h {
+    fn width(&self) -> usize {
+        Self::WIDTH
+    }
+
+    fn height(&self) -> usize {
+        Self::HEIGHT
+    }
+
+    fn write_pixel(&mut self, x: usize, y: usize, color: u8) {
+        let offset = y * Self::WIDTH / 8 + x / 8;
+
+        without_interrupts(|| {
+            modes::write_indexed(GRAPHICS, 0x08, 0x80 >> (x % 8));
+            // the read loads the latches so the pixels outside the bit mask are kept
+            self.memory.read_u8(offset);
+            self.memory.write_u8(offset, color & 0x0F);
+        });
+    }
+
+    fn read_pixel(&self, x: usize, y: usize) -> u8 {
+        let offset = y * Self::WIDTH / 8 + x / 8;
+        let bit = 7 - (x % 8);
+
+        without_interrupts(|| {
+            let mut color = 0;
+            for plane in 0..4 {
+                modes::write_indexed(GRAPHICS, 0x04, plane);
+                let byte = self.memory.read_u8(offset);
+                color |= ((byte >> bit) & 1) << plane;
+            }
+            color
+        })
+    }
+
+    fn clear(&mut self, color: u8) {
+        without_interrupts(|| {
+            modes::write_indexed(GRAPHICS, 0x08, 0xFF);
+            for i in 0..Self::WIDTH * Self::HEIGHT / 8 {
+                self.memory.write_u8(i, color & 0x0F);
+            }
+        });
+    }
+}
+
+#[test_case]
+fn test_mode_13h_round_trip() {
+    use super::{Point, WRITER};
+
+    let before = without_interrupts(|| WRITER.lock().char_at(Point(0, 0)));
+
+    let mut framebuffer = enter_mode_13h();
+    assert_eq!(current_mode(), VideoMode::Graphics320x200x256);
+
+    framebuffer.line(0, 0, 319, 199, 40);
+    framebuffer.circle(160, 100, 50, 41);
+    framebuffer.blit(10, 10, 2, &[1, 2, 3, 4]);
+    assert_eq!(framebuffer.get_pixel(319, 199), Some(40));
+    assert_eq!(framebuffer.get_pixel(210, 100), Some(41));
+    assert_eq!(framebuffer.get_pixel(11, 11), Some(4));
+    assert_eq!(framebuffer.get_pixel(320, 0), None);
+
+    enter_text_mode();
+    assert_eq!(current_mode(), VideoMode::Text);
+    assert_eq!(
+        without_interrupts(|| WRITER.lock().char_at(Point(0, 0))),
+        before
+    );
+}
+
+#[test_case]
+fn test_mode_12h_pixels() {
+    let mut framebuffer = enter_mode_12h();
+
+    framebuffer.fill_rectangle(8, 8, 3, 3, 12);
+    framebuffer.put_pixel(9, 9, 5);
+    assert_eq!(framebuffer.get_pixel(8, 8), Some(12));
+    assert_eq!(framebuffer.get_pixel(9, 9), Some(5));
+    assert_eq!(framebuffer.get_pixel(11, 8), Some(0));
+
+    enter_text_mode();
+}
@@ -1,11 +1,20 @@
 pub mod code_page_737_definitions;
+pub mod font;
+pub mod graphics;
+pub mod modes;
+pub mod palette;
 
 use code_page_737_definitions::Symbols;
 use lazy_static::lazy_static;
+use rost_core::mmio::Mmio;
+use rost_core::vga::{ColorCode, Crtc, ScreenChar, TextBuffer, TextWriter};
 use spin::Mutex;
-use volatile::Volatile;
 use x86_64::instructions::interrupts::without_interrupts;
-use x86_64::instructions::port::Port;
+
+use crate::mmio::MmioRegion;
+use crate::resource::Resource;
+
+pub use rost_core::vga::{Color, CursorPosition, Point, TextStyle};
 
 #[macro_export]
 macro_rules! print {
@@ -25,6 +34,7 @@ pub fn _print(args: fmt::Arguments) {
 
     interrupts::without_interrupts(|| {
         WRITER.lock().write_fmt(args).unwrap();
+        crate::serial::console::mirror(args);
     });
 }
 
@@ -49,253 +59,326 @@ pub fn change_screen_color(foreground_color: Color, background_color: Color) {
     });
 }
 
-pub fn move_cursor_by(x: i8, y: i8) {
-    WRITER.lock().move_cursor_by(x, y)
+pub fn clear_screen() {
+    without_interrupts(|| WRITER.lock().clear());
 }
 
-#[derive(Debug)]
-pub struct CursorPosition {
-    x: u8,
-    y: u8,
+///Switches the screen to another text mode, see `Writer::set_mode`
+pub fn set_text_mode(mode: TextMode) {
+    without_interrupts(|| WRITER.lock().set_mode(mode));
 }
 
-#[derive(Default)]
-pub struct Point(pub usize, pub usize);
-
-//the interface for the vga text mode text buffer
-// 4 bit color table (using u8 since no u4 exsists)
-#[allow(dead_code)]
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(u8)]
-pub enum Color {
-    Black = 0,
-    Blue = 1,
-    Green = 2,
-    Cyan = 3,
-    Red = 4,
-    Magenta = 5,
-    Brown = 6,
-    LightGray = 7,
-    DarkGray = 8,
-    LightBlue = 9,
-    LightGreen = 10,
-    LightCyan = 11,
-    LightRed = 12,
-    Pink = 13,
-    Yellow = 14,
-    White = 15,
+pub fn move_cursor_by(x: i8, y: i8) {
+    WRITER.lock().move_cursor_by(x, y)
 }
 
 lazy_static! {
     pub static ref WRITER: Mutex<Writer> = Mutex::new(Writer {
-        column_position: 0,
-        color_code: ColorCode::new(Color::White, Color::Black),
-        buffer: unsafe { &mut *(0xb8000 as *mut Buffer) },
-        registers: Registers {
-            crtc_address: Port::new(0x3D4),
-            crtc_data: Port::new(0x3D5)
-        },
+        text: TextWriter::new(
+            Buffer::new(TextMode::Text80x25),
+            ColorCode::new(Color::White, Color::Black)
+        ),
+        mode: TextMode::Text80x25,
+    });
+}
+///Decides whether bit 7 of an attribute makes the character blink (the default after boot)
+///or selects one of the 8 bright background colors
+pub fn set_blink_enabled(enabled: bool) {
+    without_interrupts(|| {
+        let mode_control = modes::read_attribute(0x10);
+        let mode_control = if enabled {
+            mode_control | 0x08
+        } else {
+            mode_control & !0x08
+        };
+        modes::write_attribute(0x10, mode_control);
     });
 }
-//A text character color code repsented as an u8 containing both the foreground and background color
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(transparent)]
-struct ColorCode(u8);
 
-impl ColorCode {
-    fn new(foreground: Color, background: Color) -> ColorCode {
-        ColorCode((background as u8) << 4 | (foreground as u8))
-    }
+pub fn blink_enabled() -> bool {
+    without_interrupts(|| modes::read_attribute(0x10) & 0x08 != 0)
 }
 
-//A vga text mode character with a colour and character code
+///Moves the underline to the last row of the characters or hides it
+pub fn set_underline_enabled(enabled: bool) {
+    without_interrupts(|| {
+        let font_height = WRITER.lock().mode().font_height() as u8;
+        let location = modes::read_indexed(modes::CRTC, 0x14) & !0x1F;
+        let row = if enabled { font_height - 1 } else { 0x1F };
+        modes::write_indexed(modes::CRTC, 0x14, location | row);
+    });
+}
+
+///All of the memory of the card, the graphics modes use the 64k from 0xA0000 and the text
+///modes the 32k from 0xB8000. The registers are claimed by whatever prints first.
+pub const RESOURCES: [Resource; 1] = [Resource::memory(0xA0000, 0x20000)];
+
+///Sets up the writer and claims the registers if nothing has printed yet
+pub fn init() {
+    lazy_static::initialize(&WRITER);
+    modes::claim_ports();
+}
+
+//the vga text mode chracter buffer, the 32k window at 0xb8000 has room for 16384 characters
+const BUFFER_ADDRESS: usize = 0xb8000;
+const BUFFER_CELLS: usize = 0x4000;
+
+fn text_memory() -> MmioRegion {
+    unsafe { MmioRegion::new(BUFFER_ADDRESS, BUFFER_CELLS * 2) }
+}
+
+///The text modes the writer can switch between
 #[derive(Debug, Clone, Copy, PartialEq, Eq)]
-#[repr(C)]
-struct ScreenChar {
-    ascii_character: u8,
-    color_code: ColorCode,
+pub enum TextMode {
+    Text80x25,
+    Text80x50,
+    Text90x30,
+    Text40x25,
 }
 
-//the vga text mode 2 dimensional chracter buffer
-const BUFFER_HEIGHT: usize = 25;
-const BUFFER_WIDTH: usize = 80;
+impl TextMode {
+    pub fn width(&self) -> usize {
+        match self {
+            TextMode::Text80x25 | TextMode::Text80x50 => 80,
+            TextMode::Text90x30 => 90,
+            TextMode::Text40x25 => 40,
+        }
+    }
 
-#[repr(transparent)]
+    pub fn height(&self) -> usize {
+        match self {
+            TextMode::Text80x25 | TextMode::Text40x25 => 25,
+            TextMode::Text80x50 => 50,
+            TextMode::Text90x30 => 30,
+        }
+    }
+
+    ///How many pixel rows every character is tall
+    pub fn font_height(&self) -> usize {
+        match self {
+            TextMode::Text80x50 => 8,
+            _ => 16,
+        }
+    }
+
+    fn registers(&self) -> &'static modes::RegisterSet {
+        match self {
+            TextMode::Text80x25 => &modes::TEXT_80X25,
+            TextMode::Text80x50 => &modes::TEXT_80X50,
+            TextMode::Text90x30 => &modes::TEXT_90X30,
+            TextMode::Text40x25 => &modes::TEXT_40X25,
+        }
+    }
+}
+
+///A view of the text buffer with the dimensions of the current text mode
 struct Buffer {
-    chars: [[Volatile<ScreenChar>; BUFFER_WIDTH]; BUFFER_HEIGHT],
+    memory: MmioRegion,
+    width: usize,
+    height: usize,
+}
+
+impl Buffer {
+    fn new(mode: TextMode) -> Buffer {
+        Buffer {
+            memory: text_memory(),
+            width: mode.width(),
+            height: mode.height(),
+        }
+    }
+
+    //every character is its byte followed by its attribute, written at once as a u16
+    fn offset(&self, row: usize, col: usize) -> usize {
+        (row * self.width + col) * 2
+    }
 }
 
-struct Registers {
-    crtc_address: Port<u8>,
-    crtc_data: Port<u8>,
+impl TextBuffer for Buffer {
+    fn width(&self) -> usize {
+        self.width
+    }
+
+    fn height(&self) -> usize {
+        self.height
+    }
+
+    fn read(&self, row: usize, col: usize) -> ScreenChar {
+        let [ascii_character, color_code] =
+            self.memory.read_u16(self.offset(row, col)).to_le_bytes();
+        ScreenChar {
+            ascii_character,
+            color_code: ColorCode(color_code),
+        }
+    }
+
+    fn write(&mut self, row: usize, col: usize, character: ScreenChar) {
+        let cell = u16::from_le_bytes([character.ascii_character, character.color_code.0]);
+        self.memory.write_u16(self.offset(row, col), cell)
+    }
 }
 
 //external implementation for writing to screen
 pub struct Writer {
-    column_position: usize,
-    color_code: ColorCode,
-    buffer: &'static mut Buffer,
-    registers: Registers,
+    text: TextWriter<Buffer>,
+    mode: TextMode,
 }
 
-//let mut crtc_address_register = Port::new(0x3D4);
-//let mut crtc_data_register = Port::new(0x3D5);
+///The font slot the font for the 8 pixel tall text modes is generated in, slot 4 survives graphics mode switches
+const HALF_HEIGHT_FONT_SLOT: u8 = 4;
 
 impl Writer {
     // First we have the "fAncY" graphics shite
     pub fn draw_symbol(&mut self, symbol: Symbols, point: Point) {
-        self.buffer.chars[point.0][point.1].write(ScreenChar {
-            ascii_character: symbol as u8,
-            color_code: self.color_code,
-        })
+        let color_code = self.text.color_code();
+        self.text.write_char_at(point, symbol as u8, color_code)
+    }
+
+    ///Writes a single character with its own colors to a point on the screen, points outside the screen are ignored
+    pub fn write_char_at(
+        &mut self,
+        point: Point,
+        character: u8,
+        foreground_color: Color,
+        background_color: Color,
+    ) {
+        self.text.write_char_at(
+            point,
+            character,
+            ColorCode::new(foreground_color, background_color),
+        )
+    }
+
+    ///Returns the character currently shown at a point on the screen
+    pub fn char_at(&self, point: Point) -> Option<u8> {
+        self.text.char_at(point)
+    }
+
+    ///Returns the raw attribute byte of a point on the screen, background in the high nibble
+    pub fn attribute_at(&self, point: Point) -> Option<u8> {
+        self.text.attribute_at(point)
+    }
+
+    ///Swaps the foreground and background of a point on the screen, doing it twice puts the colors back
+    pub fn invert_at(&mut self, point: Point) {
+        self.text.invert_at(point)
+    }
+
+    ///The width of the screen in characters
+    pub fn width(&self) -> usize {
+        self.text.width()
+    }
+
+    ///The height of the screen in characters
+    pub fn height(&self) -> usize {
+        self.text.height()
+    }
+
+    pub fn mode(&self) -> TextMode {
+        self.mode
+    }
+
+    ///Reprograms the card for another text mode and clears the screen. Modes with 8 pixel
+    ///tall characters get a font made from every pair of rows of the font in slot 0
+    pub fn set_mode(&mut self, mode: TextMode) {
+        without_interrupts(|| {
+            modes::write_registers(mode.registers());
+
+            if mode.font_height() == 8 {
+                let source = font::FontSlot::DEFAULT;
+                let target = font::FontSlot::new(HALF_HEIGHT_FONT_SLOT).unwrap();
+                for character in 0..=255 {
+                    let rows = font::glyph(source, character);
+                    let mut halved = [0; 8];
+                    for (i, row) in halved.iter_mut().enumerate() {
+                        *row = rows[i * 2] | rows[i * 2 + 1];
+                    }
+                    font::set_glyph(target, character, &halved);
+                }
+                font::select_font(target);
+            } else {
+                font::select_font(font::FontSlot::DEFAULT);
+            }
+        });
+
+        self.mode = mode;
+        let buffer = self.text.buffer_mut();
+        buffer.width = mode.width();
+        buffer.height = mode.height();
+        self.text.clear();
+        self.set_cursor_pos(CursorPosition {
+            x: 0,
+            y: (mode.height() - 1) as u8,
+        });
     }
 
     ///Iterates over the whole screen buffer and changes the color of each symbol
     pub fn change_screen_color(&mut self, foreground_color: Color, background_color: Color) {
         self.change_color(foreground_color, background_color);
-
-        for row in 0..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let char = self.buffer.chars[row][col].read();
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: char.ascii_character,
-                    color_code: ColorCode::new(foreground_color, background_color),
-                })
-            }
-        }
+        self.text
+            .change_screen_color(ColorCode::new(foreground_color, background_color));
     }
 
     // The handler for writing text
     pub fn write_byte(&mut self, byte: u8) {
-        match byte {
-            b'\n' => self.new_line(),
-            byte => {
-                if self.column_position >= BUFFER_WIDTH {
-                    self.new_line();
-                }
-
-                let row = BUFFER_HEIGHT - 1;
-                let col = self.column_position;
-
-                let color_code = self.color_code;
-                self.buffer.chars[row][col].write(ScreenChar {
-                    ascii_character: byte,
-                    color_code,
-                });
-                self.column_position += 1;
-            }
-        }
+        self.text.write_byte(byte)
     }
 
     ///Writes a string to the bottom of the screen
     pub fn write_string(&mut self, s: &str) {
-        for byte in s.bytes() {
-            match byte {
-                // printable ASCII byte or newline
-                0x20..=0x7e | b'\n' => self.write_byte(byte),
-                // not part of printable ASCII range
-                _ => self.write_byte(0xfe),
-            }
-        }
+        self.text.write_string(s)
     }
 
     ///Changes the color of text currently printed by the writer
     pub fn change_color(&mut self, foreground_color: Color, background_color: Color) {
-        self.color_code = ColorCode::new(foreground_color, background_color);
+        self.text
+            .set_color_code(ColorCode::new(foreground_color, background_color));
     }
 
-    ///Writes to a specified crtc data bus
-    fn crtc_write(&mut self, address: u8, value: u8) {
-        without_interrupts(move || unsafe {
-            self.registers.crtc_address.write(address);
-            self.registers.crtc_data.write(value);
-        });
+    ///Like `change_color` but with the blink, intensity and underline bits of a style
+    pub fn set_style(&mut self, style: TextStyle) {
+        self.text.set_color_code(ColorCode::from_style(style));
     }
 
-    ///Reads a specified crtc data bus and returns the value
-    fn crtc_read(&mut self, address: u8) -> u8 {
-        without_interrupts(move || unsafe {
-            self.registers.crtc_address.write(address);
-            self.registers.crtc_data.read()
-        })
+    ///Runs `f` with a different foreground color and puts the old color back afterwards
+    pub fn with_foreground<R>(&mut self, foreground: Color, f: impl FnOnce(&mut Writer) -> R) -> R {
+        let saved = self.text.color_code();
+        self.text.set_color_code(saved.with_foreground(foreground));
+        let result = f(self);
+        self.text.set_color_code(saved);
+        result
     }
 
     ///Moves the cursor x and y and wraps the cursor around if it goes over the side
     pub fn move_cursor_by(&mut self, x: i8, y: i8) {
-        let mut cursor_pos = self.get_cursor_position();
-
-        //without_interrupts(|| {
-        //println!("{:#?}", cursor_pos);
-        //});
-
-        let mut new_x: i16 = (cursor_pos.x as i8 + x).into();
-        let new_y: i16 = (cursor_pos.y as i8 + y).into();
-
-        //without_interrupts(|| {
-        //println!("new_x: {} new_y: {}", new_x, new_y);
-        //});
-        //BUFFER_WIDTH as i16 - new_x
-        if new_x < 0 {
-            new_x = BUFFER_WIDTH as i16 + new_x;
-        } else if new_x > BUFFER_WIDTH as i16 + 1 {
-            new_x = new_x + BUFFER_WIDTH as i16;
-        }
-
-        cursor_pos.x = new_x as u8;
-        cursor_pos.y = new_y as u8;
-
-        self.set_cursor_pos(cursor_pos)
+        let cursor_pos = self.get_cursor_position();
+        let width = self.text.width();
+        self.set_cursor_pos(rost_core::vga::moved_cursor(cursor_pos, x, y, width))
     }
 
     ///Takes a Cursorposition and sets it as the current cursorposition
     pub fn set_cursor_pos(&mut self, cp: CursorPosition) {
-        let pos = cp.y as u16 * BUFFER_WIDTH as u16 + cp.x as u16;
-
-        let [high, low] = pos.to_be_bytes();
-
-        self.crtc_write(0x0E, high);
-        self.crtc_write(0x0F, low);
+        let offset = rost_core::vga::cursor_offset(cp, self.text.width());
+        modes::with_ports(|ports| Crtc::new(ports).set_cursor_offset(offset));
     }
 
     ///Gets the current curor position and returns a CursorPosition
     pub fn get_cursor_position(&mut self) -> CursorPosition {
-        //gets the first and last 8 bits of the cursor position by reading them from the vga cards crtc addresses
-        let first_bits = self.crtc_read(0x0E);
-        let last_bits = self.crtc_read(0x0F);
-
-        // connects the first and last bits into an u16
-        let cursor_distance = ((first_bits as u16) << 8) | last_bits as u16;
-
-        //performs math to extract the x and y position
-        CursorPosition {
-            x: (|| -> u8 {
-                (cursor_distance - ((cursor_distance / 80) * 80))
-                    .try_into()
-                    .unwrap()
-            })(),
-            y: (|| -> u8 { (cursor_distance / 80).try_into().unwrap() })(),
-        }
+        let offset = modes::with_ports(|ports| Crtc::new(ports).cursor_offset());
+        rost_core::vga::cursor_position(offset, self.text.width())
     }
 
-    fn new_line(&mut self) {
-        for row in 1..BUFFER_HEIGHT {
-            for col in 0..BUFFER_WIDTH {
-                let character = self.buffer.chars[row][col].read();
-                self.buffer.chars[row - 1][col].write(character);
-            }
-        }
-        self.clear_row(BUFFER_HEIGHT - 1);
-        self.column_position = 0;
+    ///Blanks the whole screen and starts writing from the beginning of the bottom row
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.show_cursor_at_writer();
     }
 
-    fn clear_row(&mut self, row: usize) {
-        let blank = ScreenChar {
-            ascii_character: b' ',
-            color_code: self.color_code,
-        };
-        for col in 0..BUFFER_WIDTH {
-            self.buffer.chars[row][col].write(blank);
-        }
+    ///Moves the blinking cursor to where the next character will be written
+    pub fn show_cursor_at_writer(&mut self) {
+        self.set_cursor_pos(CursorPosition {
+            x: self.text.column_position().min(self.text.width() - 1) as u8,
+            y: (self.text.height() - 1) as u8,
+        });
     }
 }
 
@@ -330,7 +413,7 @@ fn test_println_output() {
         let mut writer = WRITER.lock();
         writeln!(writer, "\n{}", s).expect("writeln failed");
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = writer.text.buffer().read(writer.height() - 2, i);
             assert_eq!(char::from(screen_char.ascii_character), c);
         }
     });
@@ -350,7 +433,7 @@ fn test_color_change() {
         writeln!(writer, "\n{}", s).expect("writeln failed");
 
         for (i, c) in s.chars().enumerate() {
-            let screen_char = writer.buffer.chars[BUFFER_HEIGHT - 2][i].read();
+            let screen_char = writer.text.buffer().read(writer.height() - 2, i);
             assert_eq!(char::from(screen_char.ascii_character), c);
             assert_eq!(
                 screen_char.color_code,
@@ -359,3 +442,58 @@ fn test_color_change() {
         }
     });
 }
+
+#[test_case]
+fn test_text_mode_switch() {
+    use core::fmt::Write;
+    use x86_64::instructions::interrupts;
+
+    interrupts::without_interrupts(|| {
+        let mut writer = WRITER.lock();
+
+        writer.set_mode(TextMode::Text80x50);
+        assert_eq!((writer.width(), writer.height()), (80, 50));
+        writeln!(writer, "\ntall").expect("writeln failed");
+        assert_eq!(writer.char_at(Point(48, 0)), Some(b't'));
+        assert_eq!(writer.char_at(Point(50, 0)), None);
+
+        // 41 characters wrap onto a second line in 40 columns
+        writer.set_mode(TextMode::Text40x25);
+        for _ in 0..41 {
+            writer.write_byte(b'w');
+        }
+        assert_eq!(writer.char_at(Point(23, 39)), Some(b'w'));
+        assert_eq!(writer.char_at(Point(24, 0)), Some(b'w'));
+        assert_eq!(writer.char_at(Point(24, 1)), Some(b' '));
+
+        writer.set_mode(TextMode::Text90x30);
+        writer.set_cursor_pos(CursorPosition { x: 89, y: 10 });
+        let cursor = writer.get_cursor_position();
+        assert_eq!((cursor.x, cursor.y), (89, 10));
+
+        writer.set_mode(TextMode::Text80x25);
+        assert_eq!((writer.width(), writer.height()), (80, 25));
+    });
+}
+
+#[test_case]
+fn test_blink_toggle() {
+    let original = blink_enabled();
+
+    set_blink_enabled(false);
+    assert!(!blink_enabled());
+    set_blink_enabled(true);
+    assert!(blink_enabled());
+
+    set_blink_enabled(original);
+}
+
+#[cfg(test)]
+fn bench_new_line(bencher: &mut crate::testing::bench::Bencher) {
+    //the mouse interrupt locks the writer too, so never hold it with interrupts on
+    bencher.iter(|| without_interrupts(|| WRITER.lock().text.new_line()));
+}
+
+#[test_case]
+const BENCH_NEW_LINE: crate::testing::bench::BenchDescriptor =
+    crate::bench_descriptor!(bench_new_line);
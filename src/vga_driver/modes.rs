@@ -0,0 +1,294 @@
+//! Raw register programming of the vga card
+//!
+//! The register dumps are the standard ones every vga compatible card (and qemu's
+//! std vga) understands, they are written in the order misc, sequencer, crtc,
+//! graphics controller and attribute controller. Both graphics modes map the 16
+//! attribute palette entries straight to the first 16 DAC colors.
+
+use lazy_static::lazy_static;
+use rost_core::port::PortIo;
+use rost_core::register::{IndexedPorts, PortRegister, ReadOnly, WriteOnly};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::mmio::MmioRegion;
+use crate::resource::{self, PortRange};
+
+pub const MISC_WRITE: PortRegister<u8, WriteOnly> = PortRegister::new(0x3C2);
+pub const MISC_READ: PortRegister<u8, ReadOnly> = PortRegister::new(0x3CC);
+pub const SEQUENCER: IndexedPorts = IndexedPorts::new(0x3C4, 0x3C5);
+pub const CRTC: IndexedPorts = IndexedPorts::new(0x3D4, 0x3D5);
+pub const GRAPHICS: IndexedPorts = IndexedPorts::new(0x3CE, 0x3CF);
+//the attribute controller takes the index and the value on the same port, one after the
+//other
+pub const ATTRIBUTE_INDEX: PortRegister<u8, WriteOnly> = PortRegister::new(0x3C0);
+pub const ATTRIBUTE_READ: PortRegister<u8, ReadOnly> = PortRegister::new(0x3C1);
+pub const INPUT_STATUS: PortRegister<u8, ReadOnly> = PortRegister::new(0x3DA);
+//set in the attribute index the palette goes back to the screen
+const PALETTE_ADDRESS_SOURCE: u8 = 0x20;
+
+pub const SEQUENCER_COUNT: usize = 5;
+pub const CRTC_COUNT: usize = 25;
+pub const GRAPHICS_COUNT: usize = 9;
+pub const ATTRIBUTE_COUNT: usize = 21;
+
+///Every register needed to put the card into a mode
+pub struct RegisterSet {
+    pub misc: u8,
+    pub sequencer: [u8; SEQUENCER_COUNT],
+    pub crtc: [u8; CRTC_COUNT],
+    pub graphics: [u8; GRAPHICS_COUNT],
+    pub attribute: [u8; ATTRIBUTE_COUNT],
+}
+
+pub const TEXT_80X25: RegisterSet = RegisterSet {
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00,
+        0x50, 0x9C, 0x0E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+        0x3F, 0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+pub const TEXT_80X50: RegisterSet = RegisterSet {
+    misc: 0x67,
+    sequencer: [0x03, 0x00, 0x03, 0x00, 0x02],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x55, 0x81, 0xBF, 0x1F, 0x00, 0x47, 0x06, 0x07, 0x00, 0x00, 0x01,
+        0x40, 0x9C, 0x8E, 0x8F, 0x28, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+        0x3F, 0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+pub const TEXT_90X30: RegisterSet = RegisterSet {
+    misc: 0xE7,
+    sequencer: [0x03, 0x01, 0x03, 0x00, 0x02],
+    crtc: [
+        0x6B, 0x59, 0x5A, 0x82, 0x60, 0x8D, 0x0B, 0x3E, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00,
+        0x00, 0xEA, 0x0C, 0xDF, 0x2D, 0x10, 0xE8, 0x05, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+        0x3F, 0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+pub const TEXT_40X25: RegisterSet = RegisterSet {
+    misc: 0x67,
+    sequencer: [0x03, 0x08, 0x03, 0x00, 0x02],
+    crtc: [
+        0x2D, 0x27, 0x28, 0x90, 0x2B, 0xA0, 0xBF, 0x1F, 0x00, 0x4F, 0x0D, 0x0E, 0x00, 0x00, 0x00,
+        0xA0, 0x9C, 0x8E, 0x8F, 0x14, 0x1F, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x10, 0x0E, 0x00, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x14, 0x07, 0x38, 0x39, 0x3A, 0x3B, 0x3C, 0x3D, 0x3E,
+        0x3F, 0x0C, 0x00, 0x0F, 0x08, 0x00,
+    ],
+};
+
+pub const GRAPHICS_320X200X256: RegisterSet = RegisterSet {
+    misc: 0x63,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x0E],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0xBF, 0x1F, 0x00, 0x41, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x9C, 0x0E, 0x8F, 0x28, 0x40, 0x96, 0xB9, 0xA3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x00, 0x40, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F, 0x41, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+pub const GRAPHICS_640X480X16: RegisterSet = RegisterSet {
+    misc: 0xE3,
+    sequencer: [0x03, 0x01, 0x0F, 0x00, 0x06],
+    crtc: [
+        0x5F, 0x4F, 0x50, 0x82, 0x54, 0x80, 0x0B, 0x3E, 0x00, 0x40, 0x00, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0xEA, 0x0C, 0xDF, 0x28, 0x00, 0xE7, 0x04, 0xE3, 0xFF,
+    ],
+    graphics: [0x00, 0x00, 0x00, 0x00, 0x03, 0x00, 0x05, 0x0F, 0xFF],
+    attribute: [
+        0x00, 0x01, 0x02, 0x03, 0x04, 0x05, 0x06, 0x07, 0x08, 0x09, 0x0A, 0x0B, 0x0C, 0x0D, 0x0E,
+        0x0F, 0x01, 0x00, 0x0F, 0x00, 0x00,
+    ],
+};
+
+lazy_static! {
+    //the registers of the card, claimed the first time they are used which is usually
+    //the first `println`, long before the drivers start
+    static ref PORTS: Mutex<PortRange> = Mutex::new(
+        resource::claim_ports("vga", 0x3C0, 0x20).expect("the vga ports are already claimed")
+    );
+}
+
+///Runs `f` with the registers of the card to itself
+pub(super) fn with_ports<T>(f: impl FnOnce(&mut PortRange) -> T) -> T {
+    without_interrupts(|| f(&mut PORTS.lock()))
+}
+
+///Claims the registers if nothing has used them yet
+pub(super) fn claim_ports() {
+    lazy_static::initialize(&PORTS);
+}
+
+///Writes to an indexed register like the sequencer or the graphics controller
+pub fn write_indexed(registers: IndexedPorts, index: u8, value: u8) {
+    with_ports(|ports| registers.write(ports, index, value))
+}
+
+///Reads an indexed register like the sequencer or the graphics controller
+pub fn read_indexed(registers: IndexedPorts, index: u8) -> u8 {
+    with_ports(|ports| registers.read(ports, index))
+}
+
+///Writes an attribute controller register, reading the input status resets the flip flop
+///so the first write to 0x3C0 is always taken as the index
+fn write_attribute_to(io: &mut impl PortIo, index: u8, value: u8) {
+    INPUT_STATUS.read(io);
+    ATTRIBUTE_INDEX.write(io, index);
+    ATTRIBUTE_INDEX.write(io, value);
+    // bit 5 has to be set again or the screen stays blank
+    INPUT_STATUS.read(io);
+    ATTRIBUTE_INDEX.write(io, PALETTE_ADDRESS_SOURCE);
+}
+
+fn read_attribute_from(io: &mut impl PortIo, index: u8) -> u8 {
+    INPUT_STATUS.read(io);
+    ATTRIBUTE_INDEX.write(io, index);
+    let value = ATTRIBUTE_READ.read(io);
+    INPUT_STATUS.read(io);
+    ATTRIBUTE_INDEX.write(io, PALETTE_ADDRESS_SOURCE);
+    value
+}
+
+pub fn write_attribute(index: u8, value: u8) {
+    with_ports(|ports| write_attribute_to(ports, index, value))
+}
+
+pub fn read_attribute(index: u8) -> u8 {
+    with_ports(|ports| read_attribute_from(ports, index))
+}
+
+fn write_registers_to(io: &mut impl PortIo, registers: &RegisterSet) {
+    MISC_WRITE.write(io, registers.misc);
+
+    for (i, value) in registers.sequencer.iter().enumerate() {
+        SEQUENCER.write(io, i as u8, *value);
+    }
+
+    // crtc registers 0-7 are write protected by bit 7 of register 0x11
+    let mut crtc = registers.crtc;
+    crtc[0x03] |= 0x80;
+    crtc[0x11] &= !0x80;
+    CRTC.modify(io, 0x03, |value| value | 0x80);
+    CRTC.modify(io, 0x11, |value| value & !0x80);
+    for (i, value) in crtc.iter().enumerate() {
+        CRTC.write(io, i as u8, *value);
+    }
+
+    for (i, value) in registers.graphics.iter().enumerate() {
+        GRAPHICS.write(io, i as u8, *value);
+    }
+
+    for (i, value) in registers.attribute.iter().enumerate() {
+        write_attribute_to(io, i as u8, *value);
+    }
+}
+
+///Programs every register in the set, this is what actually switches modes
+pub fn write_registers(registers: &RegisterSet) {
+    with_ports(|ports| write_registers_to(ports, registers));
+}
+
+///Start of the 64k window plane 2 is mapped to while it is being accessed
+const FONT_PLANE_WINDOW: usize = 0xA0000;
+const FONT_PLANE_SIZE: usize = 0x10000;
+
+///Maps vga plane 2, which holds the text mode font, flat at 0xA0000 for the duration
+///of `f` and puts the sequencer and graphics controller back afterwards
+pub fn with_font_plane<R>(f: impl FnOnce(&mut MmioRegion) -> R) -> R {
+    without_interrupts(|| {
+        let map_mask = read_indexed(SEQUENCER, 0x02);
+        let memory_mode = read_indexed(SEQUENCER, 0x04);
+        let read_map = read_indexed(GRAPHICS, 0x04);
+        let graphics_mode = read_indexed(GRAPHICS, 0x05);
+        let miscellaneous = read_indexed(GRAPHICS, 0x06);
+
+        // the sequencer is held in reset while the memory layout changes
+        write_indexed(SEQUENCER, 0x00, 0x01);
+        write_indexed(SEQUENCER, 0x02, 0x04);
+        write_indexed(SEQUENCER, 0x04, 0x07);
+        write_indexed(SEQUENCER, 0x00, 0x03);
+        write_indexed(GRAPHICS, 0x04, 0x02);
+        write_indexed(GRAPHICS, 0x05, 0x00);
+        write_indexed(GRAPHICS, 0x06, 0x04);
+
+        let result = f(&mut unsafe { MmioRegion::new(FONT_PLANE_WINDOW, FONT_PLANE_SIZE) });
+
+        write_indexed(SEQUENCER, 0x00, 0x01);
+        write_indexed(SEQUENCER, 0x02, map_mask);
+        write_indexed(SEQUENCER, 0x04, memory_mode);
+        write_indexed(SEQUENCER, 0x00, 0x03);
+        write_indexed(GRAPHICS, 0x04, read_map);
+        write_indexed(GRAPHICS, 0x05, graphics_mode);
+        write_indexed(GRAPHICS, 0x06, miscellaneous);
+
+        result
+    })
+}
+
+#[test_case]
+fn test_write_attribute() {
+    use rost_core::mock::{Access, MockPorts};
+
+    let mut ports = MockPorts::new();
+    write_attribute_to(&mut ports, 0x10, 0x0C);
+    let accesses = [
+        Access::Read {
+            port: 0x3DA,
+            value: 0,
+        },
+        Access::Write {
+            port: 0x3C0,
+            value: 0x10,
+        },
+        Access::Write {
+            port: 0x3C0,
+            value: 0x0C,
+        },
+        Access::Read {
+            port: 0x3DA,
+            value: 0,
+        },
+        Access::Write {
+            port: 0x3C0,
+            value: 0x20,
+        },
+    ];
+    assert!(ports.log().eq(accesses));
+}
+
+#[test_case]
+fn test_crtc_write_protection() {
+    use rost_core::mock::MockPorts;
+
+    let mut ports = MockPorts::new().with_indexed(0x3D4, 0x3D5);
+    write_registers_to(&mut ports, &TEXT_80X25);
+    //the protection is lifted for the write and the mode's registers land as they are
+    //apart from the bits that keep it lifted
+    assert_eq!(ports.indexed_register(0x11), TEXT_80X25.crtc[0x11] & !0x80);
+    assert_eq!(ports.indexed_register(0x03), TEXT_80X25.crtc[0x03] | 0x80);
+    assert_eq!(ports.indexed_register(0x0A), TEXT_80X25.crtc[0x0A]);
+}
@@ -0,0 +1,233 @@
+//! The vga DAC which turns color indices into the actual color shown on screen
+
+use rost_core::register::{PortRegister, WriteOnly};
+use x86_64::instructions::interrupts::without_interrupts;
+
+use super::modes;
+use super::Color;
+
+pub const DAC_READ_INDEX: PortRegister<u8, WriteOnly> = PortRegister::new(0x3C7);
+pub const DAC_WRITE_INDEX: PortRegister<u8, WriteOnly> = PortRegister::new(0x3C8);
+pub const DAC_DATA: PortRegister<u8> = PortRegister::new(0x3C9);
+
+pub const DAC_SIZE: usize = 256;
+
+///A DAC color, every component only uses the lower 6 bits (0-63)
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct Rgb {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Rgb {
+    pub const fn new(red: u8, green: u8, blue: u8) -> Rgb {
+        Rgb {
+            red: red & 0x3F,
+            green: green & 0x3F,
+            blue: blue & 0x3F,
+        }
+    }
+
+    ///Creates a color from normal 8 bit components by dropping the lowest 2 bits
+    pub const fn from_rgb8(red: u8, green: u8, blue: u8) -> Rgb {
+        Rgb::new(red >> 2, green >> 2, blue >> 2)
+    }
+}
+
+///The 16 colors the text mode uses in the order of `Color`
+pub const DEFAULT_TEXT_COLORS: [Rgb; 16] = [
+    Rgb::new(0, 0, 0),
+    Rgb::new(0, 0, 42),
+    Rgb::new(0, 42, 0),
+    Rgb::new(0, 42, 42),
+    Rgb::new(42, 0, 0),
+    Rgb::new(42, 0, 42),
+    Rgb::new(42, 21, 0),
+    Rgb::new(42, 42, 42),
+    Rgb::new(21, 21, 21),
+    Rgb::new(21, 21, 63),
+    Rgb::new(21, 63, 21),
+    Rgb::new(21, 63, 63),
+    Rgb::new(63, 21, 21),
+    Rgb::new(63, 21, 63),
+    Rgb::new(63, 63, 21),
+    Rgb::new(63, 63, 63),
+];
+
+///Sets a single DAC entry
+pub fn set_color(index: u8, color: Rgb) {
+    load_colors(index, &[color]);
+}
+
+///Reads a single DAC entry back
+pub fn color(index: u8) -> Rgb {
+    let mut colors = [Rgb::default()];
+    read_colors(index, &mut colors);
+    colors[0]
+}
+
+///Writes consecutive DAC entries starting at `start`, the DAC index auto increments after every blue
+pub fn load_colors(start: u8, colors: &[Rgb]) {
+    modes::with_ports(|ports| {
+        DAC_WRITE_INDEX.write(ports, start);
+        for color in colors.iter().take(DAC_SIZE - start as usize) {
+            DAC_DATA.write(ports, color.red);
+            DAC_DATA.write(ports, color.green);
+            DAC_DATA.write(ports, color.blue);
+        }
+    });
+}
+
+///Reads consecutive DAC entries starting at `start` into `colors`
+pub fn read_colors(start: u8, colors: &mut [Rgb]) {
+    modes::with_ports(|ports| {
+        DAC_READ_INDEX.write(ports, start);
+        for color in colors.iter_mut().take(DAC_SIZE - start as usize) {
+            color.red = DAC_DATA.read(ports) & 0x3F;
+            color.green = DAC_DATA.read(ports) & 0x3F;
+            color.blue = DAC_DATA.read(ports) & 0x3F;
+        }
+    });
+}
+
+///A full 256 color palette, the first 16 are the text mode colors followed by a 6x6x6
+///color cube and a 24 step gray ramp
+pub fn default_256_colors() -> [Rgb; DAC_SIZE] {
+    let mut colors = [Rgb::default(); DAC_SIZE];
+    colors[..16].copy_from_slice(&DEFAULT_TEXT_COLORS);
+
+    for i in 0..216 {
+        let level = |component: usize| (component * 63 / 5) as u8;
+        colors[16 + i] = Rgb::new(level(i / 36), level(i / 6 % 6), level(i % 6));
+    }
+
+    for i in 0..24 {
+        let gray = (i * 63 / 23) as u8;
+        colors[232 + i] = Rgb::new(gray, gray, gray);
+    }
+
+    colors
+}
+
+///The 16 text mode colors in the order of `Color`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub colors: [Rgb; 16],
+}
+
+pub const DEFAULT_THEME: Theme = Theme {
+    colors: DEFAULT_TEXT_COLORS,
+};
+
+///Every color as a shade of an old amber monitor, brighter colors give brighter shades
+pub const AMBER_THEME: Theme = Theme {
+    colors: [
+        Rgb::new(0, 0, 0),
+        Rgb::new(16, 8, 0),
+        Rgb::new(22, 11, 0),
+        Rgb::new(28, 14, 0),
+        Rgb::new(34, 17, 0),
+        Rgb::new(38, 19, 0),
+        Rgb::new(42, 21, 0),
+        Rgb::new(48, 24, 0),
+        Rgb::new(12, 6, 0),
+        Rgb::new(40, 22, 0),
+        Rgb::new(46, 25, 0),
+        Rgb::new(50, 28, 0),
+        Rgb::new(54, 31, 0),
+        Rgb::new(58, 34, 0),
+        Rgb::new(61, 38, 4),
+        Rgb::new(63, 44, 10),
+    ],
+};
+
+///The DAC entry a text color is shown with. The attribute palette register for the color
+///gives the low bits and the color select register fills in the rest
+pub fn text_color_index(color: Color) -> u8 {
+    without_interrupts(|| {
+        let palette_entry = modes::read_attribute(color as u8);
+        let mode_control = modes::read_attribute(0x10);
+        let color_select = modes::read_attribute(0x14);
+
+        if mode_control & 0x80 != 0 {
+            (color_select & 0x0F) << 4 | (palette_entry & 0x0F)
+        } else {
+            (color_select & 0x0C) << 4 | (palette_entry & 0x3F)
+        }
+    })
+}
+
+///Points a text color at one of the first 64 DAC entries (or the block of 64 picked by the
+///color select register), this is how the 16 text colors can use any color of the DAC
+pub fn map_text_color(color: Color, dac_index: u8) {
+    without_interrupts(|| modes::write_attribute(color as u8, dac_index & 0x3F));
+}
+
+///Changes what a text color looks like everywhere on the screen
+pub fn set_text_color(color: Color, rgb: Rgb) {
+    set_color(text_color_index(color), rgb);
+}
+
+pub fn apply_theme(theme: &Theme) {
+    for (i, rgb) in theme.colors.iter().enumerate() {
+        set_text_color(COLORS[i], *rgb);
+    }
+}
+
+///The theme that is currently loaded in the DAC
+pub fn current_theme() -> Theme {
+    let mut theme = DEFAULT_THEME;
+    for (i, rgb) in theme.colors.iter_mut().enumerate() {
+        *rgb = color(text_color_index(COLORS[i]));
+    }
+    theme
+}
+
+const COLORS: [Color; 16] = [
+    Color::Black,
+    Color::Blue,
+    Color::Green,
+    Color::Cyan,
+    Color::Red,
+    Color::Magenta,
+    Color::Brown,
+    Color::LightGray,
+    Color::DarkGray,
+    Color::LightBlue,
+    Color::LightGreen,
+    Color::LightCyan,
+    Color::LightRed,
+    Color::Pink,
+    Color::Yellow,
+    Color::White,
+];
+
+#[test_case]
+fn test_theme_round_trip() {
+    let original = current_theme();
+
+    apply_theme(&AMBER_THEME);
+    assert_eq!(current_theme(), AMBER_THEME);
+    //blue is an amber shade only in the amber theme
+    assert_eq!(color(text_color_index(Color::Blue)), Rgb::new(16, 8, 0));
+
+    apply_theme(&original);
+    assert_eq!(current_theme(), original);
+}
+
+#[test_case]
+fn test_default_text_color_indices() {
+    assert_eq!(text_color_index(Color::Brown), 0x14);
+    assert_eq!(text_color_index(Color::White), 0x3F);
+}
+
+#[test_case]
+fn test_dac_round_trip() {
+    let original = color(200);
+
+    set_color(200, Rgb::new(12, 34, 56));
+    assert_eq!(color(200), Rgb::new(12, 34, 56));
+
+    set_color(200, original);
+}
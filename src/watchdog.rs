@@ -0,0 +1,241 @@
+//! Notices code that stops making progress, like a test stuck on a lock
+//!
+//! `arm` gives the code a budget and `disarm` ends it. The timer interrupt checks the
+//! budget on every tick, which works as long as the stuck code leaves interrupts enabled.
+//! Code stuck with interrupts disabled, like a deadlock inside `without_interrupts`, is
+//! caught by the ib700 watchdog device of QEMU started with
+//! `-device ib700 -action watchdog=inject-nmi`, which raises a non maskable interrupt a
+//! little after the budget runs out. The ib700 counts at most 30 seconds, longer budgets
+//! are only checked by the timer interrupt.
+//!
+//! Either way the name given to `arm`, the registers and the top of the stack are printed
+//! to COM1 without waiting for the serial lock and QEMU exits with `QemuExitCode::Hung`.
+
+use core::fmt::{self, Write};
+use core::time::Duration;
+
+use rost_core::register::{PortRegister, WriteOnly};
+use spin::Mutex;
+use x86_64::instructions::interrupts::without_interrupts;
+
+use crate::interrupts::TrapFrame;
+use crate::resource::{PortSet, Resource};
+use crate::QemuExitCode;
+
+///The budget of a test without a timeout of its own
+pub const DEFAULT_BUDGET: Duration = Duration::from_secs(10);
+///How many words of the stack are printed
+const STACK_WORDS: usize = 16;
+
+pub const RESOURCES: [Resource; 2] = [Resource::ports(0x441, 1), Resource::ports(0x443, 1)];
+
+//writing the start port sets the timeout and restarts the countdown, the stop port stops it
+const IB700_START: PortRegister<u8, WriteOnly> = PortRegister::new(0x443);
+const IB700_STOP: PortRegister<u8, WriteOnly> = PortRegister::new(0x441);
+//the timeout in seconds for each value written to the start port
+const IB700_TIMEOUTS: [u64; 16] = [30, 28, 26, 24, 22, 20, 18, 16, 14, 12, 10, 8, 6, 4, 2, 0];
+
+static WATCH: Mutex<Option<Watch>> = Mutex::new(None);
+//the stop and the start port, set by `init`
+static IB700: Mutex<Option<PortSet<2>>> = Mutex::new(None);
+
+#[derive(Debug, Clone, Copy)]
+struct Watch {
+    name: &'static str,
+    budget: Duration,
+    deadline: Duration,
+}
+
+///Everything printed about a hang
+struct HangReport<'a> {
+    watch: Watch,
+    frame: &'a TrapFrame,
+    stack: &'a [u8],
+}
+
+impl fmt::Display for HangReport<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let frame = self.frame;
+        writeln!(
+            f,
+            "watchdog: {} ran past its budget of {:?}",
+            self.watch.name, self.watch.budget
+        )?;
+
+        writeln!(f, "registers:")?;
+        let registers = [
+            ("rax", frame.rax),
+            ("rbx", frame.rbx),
+            ("rcx", frame.rcx),
+            ("rdx", frame.rdx),
+            ("rsi", frame.rsi),
+            ("rdi", frame.rdi),
+            ("rbp", frame.rbp),
+            ("rsp", frame.rsp),
+            ("r8", frame.r8),
+            ("r9", frame.r9),
+            ("r10", frame.r10),
+            ("r11", frame.r11),
+            ("r12", frame.r12),
+            ("r13", frame.r13),
+            ("r14", frame.r14),
+            ("r15", frame.r15),
+            ("rip", frame.rip),
+            ("rflags", frame.rflags),
+            ("cs", frame.cs),
+            ("ss", frame.ss),
+        ];
+        for (i, (name, value)) in registers.iter().enumerate() {
+            write!(f, "{:>6} {:#018x}", name, value)?;
+            f.write_str(if i % 4 == 3 { "\n" } else { " " })?;
+        }
+
+        writeln!(f, "stack:")?;
+        for (i, word) in self.stack.chunks_exact(8).enumerate() {
+            if i % 4 == 0 {
+                write!(f, "{:#018x}:", self.frame.rsp + i as u64 * 8)?;
+            }
+            let word = u64::from_le_bytes(word.try_into().unwrap());
+            write!(f, " {:#018x}", word)?;
+            if i % 4 == 3 {
+                f.write_char('\n')?;
+            }
+        }
+        if !self.stack.len().is_multiple_of(32) {
+            f.write_char('\n')?;
+        }
+        Ok(())
+    }
+}
+
+//the ib700 value for the shortest timeout at least two seconds past `budget`, so the
+//timer interrupt gets to report the hang first when it can. None when even the longest
+//timeout would run out before the budget does.
+fn ib700_setting(budget: Duration) -> Option<u8> {
+    let seconds = budget.as_secs() + 2 + (budget.subsec_nanos() > 0) as u64;
+    (0..IB700_TIMEOUTS.len())
+        .rev()
+        .find(|i| IB700_TIMEOUTS[*i] >= seconds)
+        .map(|i| i as u8)
+}
+
+///Takes the ports of the ib700, without them only the timer interrupt watches
+pub fn init(ports: PortSet<2>) {
+    without_interrupts(|| *IB700.lock() = Some(ports));
+}
+
+//writes to the ib700 if `init` got its ports
+fn write_ib700(register: PortRegister<u8, WriteOnly>, value: u8) {
+    without_interrupts(|| {
+        if let Some(ports) = IB700.lock().as_mut() {
+            register.write(ports, value);
+        }
+    });
+}
+
+///Starts watching whatever is called `name`, which has to call `disarm` within `budget`
+pub fn arm(name: &'static str, budget: Duration) {
+    let deadline = crate::interrupts::uptime() + budget;
+    without_interrupts(|| {
+        *WATCH.lock() = Some(Watch {
+            name,
+            budget,
+            deadline,
+        });
+        match ib700_setting(budget) {
+            Some(setting) => write_ib700(IB700_START, setting),
+            //a countdown left from an earlier budget would go off too early
+            None => write_ib700(IB700_STOP, 0),
+        }
+    });
+}
+
+pub fn disarm() {
+    without_interrupts(|| {
+        write_ib700(IB700_STOP, 0);
+        *WATCH.lock() = None;
+    });
+}
+
+pub fn is_armed() -> bool {
+    without_interrupts(|| WATCH.lock().is_some())
+}
+
+fn hang(watch: Watch, frame: &TrapFrame) -> ! {
+    //held by `arm` or `disarm` when the non maskable interrupt came in, QEMU exits
+    //before the countdown runs out again anyway
+    if let Some(Some(ports)) = IB700.try_lock().as_deref_mut() {
+        IB700_STOP.write(ports, 0);
+    }
+
+    //the stack may end before the words printed do, what can't be read is left out
+    let mut stack = [0; STACK_WORDS * 8];
+    let read = crate::gdb::memory::read(frame.rsp, &mut stack);
+    let report = HangReport {
+        watch,
+        frame,
+        stack: &stack[..read - read % 8],
+    };
+    crate::serial::write_unlocked(format_args!("\n{}\n", report));
+    crate::exit_qemu(QemuExitCode::Hung);
+    crate::hlt_loop();
+}
+
+///Reports a hang if the budget ran out, called from the timer interrupt
+pub(crate) fn check(now: Duration, frame: &TrapFrame) {
+    //held by `arm` or `disarm` on this cpu, they are not stuck
+    let Some(watch) = WATCH.try_lock().map(|watch| *watch) else {
+        return;
+    };
+    if let Some(watch) = watch {
+        if now >= watch.deadline {
+            hang(watch, frame);
+        }
+    }
+}
+
+///The ib700 raises a non maskable interrupt when its countdown runs out
+pub(crate) fn handle_nmi(frame: &TrapFrame) {
+    match WATCH.try_lock().map(|watch| *watch) {
+        Some(Some(watch)) => hang(watch, frame),
+        _ => crate::serial::write_unlocked(format_args!("unexpected non maskable interrupt\n")),
+    }
+}
+
+#[test_case]
+fn test_ib700_setting() {
+    let timeout = |budget| ib700_setting(budget).map(|setting| IB700_TIMEOUTS[setting as usize]);
+    assert_eq!(timeout(DEFAULT_BUDGET), Some(12));
+    assert_eq!(timeout(Duration::from_millis(50)), Some(4));
+    assert_eq!(timeout(Duration::from_secs(28)), Some(30));
+    assert_eq!(timeout(Duration::from_millis(28_001)), None);
+    assert_eq!(timeout(Duration::from_secs(60)), None);
+}
+
+#[test_case]
+fn test_hang_report() {
+    use crate::shell::line::Text;
+
+    let frame = TrapFrame {
+        rip: 0xdead_beef,
+        rsp: 0x1000,
+        ..crate::gdb::test_frame()
+    };
+    let stack = 0x1234u64.to_le_bytes();
+    let report = HangReport {
+        watch: Watch {
+            name: "rost::example::test_hangs",
+            budget: Duration::from_secs(2),
+            deadline: Duration::ZERO,
+        },
+        frame: &frame,
+        stack: &stack,
+    };
+
+    let mut text = Text::<2048>::new();
+    write!(text, "{}", report).unwrap();
+    let text = text.as_str();
+    assert!(text.starts_with("watchdog: rost::example::test_hangs ran past its budget of 2s\n"));
+    assert!(text.contains("   rip 0x00000000deadbeef"));
+    assert!(text.contains("0x0000000000001000: 0x0000000000001234"));
+}